@@ -1,28 +1,246 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::ArgAction;
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+use clap_complete::env::CompleteEnv;
+use clap_complete::Shell;
+use console::{measure_text_width, pad_str, style, Alignment};
 use fphoto_renamer_core::{
-    app_paths, apply_plan_with_options, generate_plan, generate_plan_for_jpg_files, load_config,
-    parse_template, undo_last, ApplyOptions, PlanOptions, DEFAULT_TEMPLATE,
+    app_paths, apply_plan_with_progress, cleanup_backups, collect_folder_stats, export_config,
+    generate_plan_chunked, generate_plan_for_jpg_files, generate_plan_for_jpg_files_chunked,
+    generate_plan_for_jpg_files_with_control, generate_plan_with_control, import_config,
+    inspect_jpg_file, list_undo_sessions, load_config, load_plan_file, parse_template,
+    parse_time_shift, parse_utc_offset, pending_apply, preview_undo, recover_interrupted_apply,
+    redo_last, save_config, save_plan_file, undo_last_files, undo_last_with_options,
+    undo_session_with_options, validate_plan_against_fs, ApplyMode, ApplyOptions, ApplyOutcome,
+    BackupFormat, BackupRetention, CollisionPolicy, ConfigImportMode, DateZone, FieldSource,
+    MetadataBackend, PlanControl, PlanOptions, ProgressSink, RawMatchMethod, RecoverAction,
+    RenameCandidate, RenamePlan, SourceFilter, TimeShift, UndoOptions, DEFAULT_TEMPLATE,
+    TEMPLATE_TOKENS,
 };
-use std::path::PathBuf;
+use indicatif::{ProgressBar, ProgressStyle};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::mpsc;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
 
 const EXIFTOOL_PATH_ENV: &str = "FPHOTO_EXIFTOOL_PATH";
+const PLAN_CHUNK_SIZE: usize = 200;
+
+// スクリプトから分岐できるよう終了コードを分ける。2 は clap の引数エラーと揃えている
+const EXIT_FAILURE: u8 = 1;
+const EXIT_INVALID_INPUT: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Success = 0,
+    NothingToRename = 3,
+    PartialFailure = 4,
+}
+
+// テンプレートやプランファイルなど、入力内容の誤りを示すマーカー
+#[derive(Debug)]
+struct InvalidInput;
+
+impl fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("入力内容が不正です")
+    }
+}
+
+fn invalid_input(err: impl Into<anyhow::Error>) -> anyhow::Error {
+    err.into().context(InvalidInput)
+}
+
+fn exit_code_for_error(err: &anyhow::Error) -> u8 {
+    if err.downcast_ref::<InvalidInput>().is_some() {
+        EXIT_INVALID_INPUT
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+fn changes_outcome(changed: usize) -> Outcome {
+    if changed == 0 {
+        Outcome::NothingToRename
+    } else {
+        Outcome::Success
+    }
+}
 
 #[derive(Debug, Parser)]
 #[command(name = "fphoto-renamer-cli")]
 #[command(about = "JPG写真のファイル名をテンプレートで一括リネームします")]
 #[command(version)]
 struct Cli {
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    #[arg(short, long, global = true)]
+    quiet: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
+impl Cli {
+    fn log_level(&self) -> log::LevelFilter {
+        if self.quiet {
+            return log::LevelFilter::Error;
+        }
+        match self.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
     Rename(RenameArgs),
-    Undo,
+    Plan(PlanArgs),
+    Apply(ApplyPlanArgs),
+    Inspect(InspectArgs),
+    Stats(StatsArgs),
+    Watch(WatchArgs),
+    Undo(UndoArgs),
+    Redo,
+    Recover(RecoverArgs),
+    CleanupBackups(CleanupBackupsArgs),
     Config(ConfigArgs),
+    Completions(CompletionsArgs),
+}
+
+#[derive(Debug, Args)]
+struct CompletionsArgs {
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+#[derive(Debug, Args)]
+struct InspectArgs {
+    #[arg(value_name = "PHOTO")]
+    path: String,
+    #[arg(long)]
+    raw_input: Option<String>,
+    #[arg(long, default_value_t = false)]
+    raw_parent_if_missing: bool,
+    #[arg(
+        long,
+        default_value = DEFAULT_TEMPLATE,
+        add = ArgValueCompleter::new(complete_template)
+    )]
+    template: String,
+    #[arg(long, allow_hyphen_values = true)]
+    exclude: Vec<String>,
+    #[arg(long = "dedupe-same-maker", default_value_t = true, action = ArgAction::Set)]
+    dedupe_same_maker: bool,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    dedupe_model_maker: Option<bool>,
+    #[arg(long, value_name = "OFFSET", value_parser = validate_timezone)]
+    timezone: Option<String>,
+    #[arg(long, default_value_t = false)]
+    utc: bool,
+    #[arg(long, value_name = "[MODEL=]OFFSET", value_parser = parse_time_shift_arg, allow_hyphen_values = true)]
+    shift_time: Vec<TimeShift>,
+    #[arg(long, default_value_t = false)]
+    prefer_gps_time: bool,
+}
+
+#[derive(Debug, Args)]
+struct StatsArgs {
+    #[arg(value_name = "FOLDER")]
+    path: String,
+    #[arg(long)]
+    raw_input: Option<String>,
+    #[arg(long, default_value_t = false)]
+    raw_parent_if_missing: bool,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    recursive: Option<bool>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    include_hidden: Option<bool>,
+    #[arg(long, value_parser = clap::value_parser!(i8).range(-1..=5))]
+    min_rating: Option<i8>,
+    #[arg(long, default_value_t = false)]
+    include_videos: bool,
+    #[arg(long, value_name = "[MODEL=]OFFSET", value_parser = parse_time_shift_arg, allow_hyphen_values = true)]
+    shift_time: Vec<TimeShift>,
+    #[arg(long, default_value_t = false)]
+    prefer_gps_time: bool,
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    progress: Option<ProgressFormat>,
+    #[arg(long, default_value_t = false)]
+    json: bool,
+    #[arg(long, default_value_t = false)]
+    no_cache: bool,
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    #[arg(value_name = "FOLDER")]
+    path: String,
+    #[arg(long)]
+    raw_input: Option<String>,
+    #[arg(long, default_value_t = false)]
+    raw_parent_if_missing: bool,
+    #[arg(long, add = ArgValueCompleter::new(complete_template))]
+    template: Option<String>,
+    #[arg(long, allow_hyphen_values = true)]
+    exclude: Vec<String>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    recursive: Option<bool>,
+    #[arg(long, default_value_t = 2000)]
+    debounce_ms: u64,
+    #[arg(long, default_value_t = false)]
+    apply: bool,
+    #[command(flatten)]
+    apply_options: ApplyArgs,
+}
+
+#[derive(Debug, Args)]
+struct CleanupBackupsArgs {
+    #[arg(long)]
+    keep_sessions: Option<usize>,
+    #[arg(long)]
+    keep_days: Option<u32>,
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+struct UndoArgs {
+    #[command(subcommand)]
+    action: Option<UndoAction>,
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+    #[arg(long, default_value_t = false)]
+    force: bool,
+    #[arg(long = "file", conflicts_with = "dry_run")]
+    files: Vec<String>,
+    #[arg(long, conflicts_with_all = ["dry_run", "files"])]
+    id: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum UndoAction {
+    List,
+}
+
+#[derive(Debug, Args)]
+struct RecoverArgs {
+    #[arg(long, default_value_t = false)]
+    rollback: bool,
 }
 
 #[derive(Debug, Args)]
@@ -34,172 +252,856 @@ struct ConfigArgs {
 #[derive(Debug, Subcommand)]
 enum ConfigAction {
     Show,
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        #[arg(required = true, num_args = 1.., allow_hyphen_values = true)]
+        values: Vec<String>,
+    },
+    Unset {
+        key: String,
+    },
+    Export {
+        file: String,
+    },
+    Import {
+        file: String,
+        #[arg(long, default_value_t = false)]
+        overwrite: bool,
+    },
 }
 
 #[derive(Debug, Args)]
 struct RenameArgs {
-    #[arg(long, required = true, num_args = 1..)]
+    #[command(flatten)]
+    scan: ScanArgs,
+    #[arg(long, default_value_t = false)]
+    apply: bool,
+    #[command(flatten)]
+    apply_options: ApplyArgs,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+    #[arg(long, default_value_t = false)]
+    no_color: bool,
+}
+
+#[derive(Debug, Args)]
+struct PlanArgs {
+    #[command(flatten)]
+    scan: ScanArgs,
+    #[arg(long)]
+    out: String,
+}
+
+#[derive(Debug, Args)]
+struct ApplyPlanArgs {
+    #[arg(long)]
+    plan: String,
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    progress: Option<ProgressFormat>,
+    #[arg(long, value_enum)]
+    only_source: Option<OnlySource>,
+    #[command(flatten)]
+    apply_options: ApplyArgs,
+}
+
+#[derive(Debug, Args)]
+struct ScanArgs {
+    #[arg(long, num_args = 1.., required_unless_present_any = ["paths", "files_from"])]
     jpg_input: Vec<String>,
+    #[arg(value_name = "PATH")]
+    paths: Vec<String>,
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<String>,
+    #[arg(
+        short = '0',
+        long = "null",
+        default_value_t = false,
+        requires = "files_from"
+    )]
+    null_separated: bool,
     #[arg(long)]
     raw_input: Option<String>,
     #[arg(long, default_value_t = false)]
     raw_parent_if_missing: bool,
-    #[arg(long, default_value_t = false)]
-    apply: bool,
     #[arg(
         long,
-        default_value = DEFAULT_TEMPLATE
+        default_value = DEFAULT_TEMPLATE,
+        add = ArgValueCompleter::new(complete_template)
     )]
     template: String,
     #[arg(long, allow_hyphen_values = true)]
     exclude: Vec<String>,
+    #[arg(long, value_name = "FILE")]
+    exclude_file: Option<String>,
     #[arg(long = "dedupe-same-maker", default_value_t = true, action = ArgAction::Set)]
     dedupe_same_maker: bool,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    dedupe_model_maker: Option<bool>,
+    #[arg(long, value_name = "OFFSET", value_parser = validate_timezone)]
+    timezone: Option<String>,
+    #[arg(long, default_value_t = false)]
+    utc: bool,
+    #[arg(long, value_name = "[MODEL=]OFFSET", value_parser = parse_time_shift_arg, allow_hyphen_values = true)]
+    shift_time: Vec<TimeShift>,
+    #[arg(long, default_value_t = false)]
+    prefer_gps_time: bool,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    recursive: Option<bool>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    include_hidden: Option<bool>,
+    #[arg(long, value_parser = clap::value_parser!(u16).range(16..=255))]
+    max_filename_len: Option<u16>,
+    #[arg(long, value_parser = clap::value_parser!(i8).range(-1..=5))]
+    min_rating: Option<i8>,
+    #[arg(long, default_value_t = 0)]
+    min_file_size: u64,
+    #[arg(long, default_value_t = false)]
+    include_videos: bool,
+    #[arg(long, default_value_t = false)]
+    raw_primary: bool,
+    #[arg(long = "primary-ext")]
+    primary_ext: Vec<String>,
+    #[arg(long = "raw-ext")]
+    raw_ext: Vec<String>,
+    #[arg(long, value_name = "MS", num_args = 0..=1, require_equals = true, default_missing_value = "1000")]
+    match_raw_by_time: Option<u64>,
+    #[arg(long, default_value_t = false)]
+    match_raw_by_id: bool,
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    progress: Option<ProgressFormat>,
+    #[arg(long, default_value_t = false)]
+    strict_metadata: bool,
+    #[arg(long, default_value_t = false)]
+    changed_only: bool,
+    #[arg(long, default_value_t = false)]
+    rename_companions: bool,
+    #[arg(long, value_enum)]
+    only_source: Option<OnlySource>,
+    #[arg(long, default_value_t = false)]
+    no_cache: bool,
+}
+
+impl ScanArgs {
+    // --jpg-input・位置引数・--files-from は、フォルダ・ファイルを混在して指定できる
+    fn jpg_inputs(&self) -> Result<Vec<PathBuf>> {
+        let mut inputs: Vec<PathBuf> = self
+            .jpg_input
+            .iter()
+            .chain(&self.paths)
+            .map(PathBuf::from)
+            .collect();
+        if let Some(source) = &self.files_from {
+            let raw = if source == "-" {
+                let mut raw = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut raw)
+                    .context("標準入力からファイル一覧を読めませんでした")?;
+                raw
+            } else {
+                fs::read_to_string(source)
+                    .with_context(|| format!("ファイル一覧を読めませんでした: {source}"))?
+            };
+            inputs.extend(parse_file_list(&raw, self.null_separated));
+        }
+        if inputs.is_empty() {
+            bail!("--jpg-input・パス・--files-from のいずれかで対象を1件以上指定してください");
+        }
+        Ok(inputs)
+    }
+
+    // 設定の exclude_strings・--exclude-file・--exclude の順に重複を除いてまとめる
+    fn exclusions(&self, config_terms: &[String]) -> Result<Vec<String>> {
+        let file_terms = match &self.exclude_file {
+            Some(path) => parse_exclusion_file(
+                &fs::read_to_string(path)
+                    .with_context(|| format!("除外文字列ファイルを読めませんでした: {path}"))?,
+            ),
+            None => Vec::new(),
+        };
+        let mut terms = Vec::new();
+        for term in config_terms.iter().chain(&file_terms).chain(&self.exclude) {
+            if !terms.contains(term) {
+                terms.push(term.clone());
+            }
+        }
+        Ok(terms)
+    }
+}
+
+// 1行1件。空行と # で始まる行は読み飛ばす
+fn parse_exclusion_file(raw: &str) -> Vec<String> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+// カメラの時計のタイムゾーン (--timezone) は clap の段階で形式を確かめる
+fn validate_timezone(raw: &str) -> Result<String, String> {
+    parse_utc_offset(raw)
+        .map(|_| raw.to_string())
+        .ok_or_else(|| format!("+09:00 / -0500 / UTC のように指定してください: {raw}"))
+}
+
+fn parse_time_shift_arg(raw: &str) -> Result<TimeShift, String> {
+    parse_time_shift(raw).map_err(|err| err.to_string())
+}
+
+fn date_zone(timezone: Option<&str>, utc: bool) -> DateZone {
+    DateZone {
+        timezone: timezone.and_then(parse_utc_offset),
+        utc,
+    }
+}
+
+// 改行区切りではCRLFも受け付ける。-0 指定時はNUL区切りとして空白や改行を含むパスをそのまま扱う
+fn parse_file_list(raw: &str, null_separated: bool) -> Vec<PathBuf> {
+    let separator = if null_separated { '\0' } else { '\n' };
+    raw.split(separator)
+        .map(|line| {
+            if null_separated {
+                line
+            } else {
+                line.strip_suffix('\r').unwrap_or(line)
+            }
+        })
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+#[derive(Debug, Args)]
+struct ApplyArgs {
     #[arg(long, default_value_t = false)]
     backup_originals: bool,
-    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
-    output: OutputFormat,
+    #[arg(long)]
+    backup_dir: Option<String>,
+    #[arg(long, default_value_t = false, conflicts_with = "trash_originals")]
+    backup_zip: bool,
+    #[arg(long, default_value_t = false, conflicts_with = "backup_dir")]
+    trash_originals: bool,
+    #[arg(long, default_value_t = false)]
+    clear_readonly: bool,
+    #[arg(long, default_value_t = false)]
+    set_file_times: bool,
+    #[arg(long, conflicts_with = "hardlink_to")]
+    copy_to: Option<String>,
+    #[arg(long)]
+    hardlink_to: Option<String>,
+    #[arg(long, default_value_t = false)]
+    continue_on_error: bool,
+    #[arg(long, value_enum, default_value_t = OnCollision::Fail)]
+    on_collision: OnCollision,
+    #[arg(long)]
+    limit: Option<usize>,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum OutputFormat {
     Table,
     Json,
+    Ndjson,
+    Markdown,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgressFormat {
+    Text,
+    Json,
+    Bar,
+}
 
-    match cli.command {
-        Commands::Rename(args) => cmd_rename(args),
-        Commands::Undo => cmd_undo(),
-        Commands::Config(config) => match config.action {
-            ConfigAction::Show => cmd_config_show(),
-        },
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnCollision {
+    Fail,
+    Suffix,
+    Skip,
 }
 
-fn cmd_rename(args: RenameArgs) -> Result<()> {
-    configure_exiftool_path();
-    parse_template(&args.template)?;
+impl From<OnCollision> for CollisionPolicy {
+    fn from(value: OnCollision) -> Self {
+        match value {
+            OnCollision::Fail => CollisionPolicy::Fail,
+            OnCollision::Suffix => CollisionPolicy::Suffix,
+            OnCollision::Skip => CollisionPolicy::Skip,
+        }
+    }
+}
 
-    let jpg_inputs: Vec<PathBuf> = args.jpg_input.iter().map(PathBuf::from).collect();
-    let primary_jpg_input = jpg_inputs
-        .first()
-        .cloned()
-        .context("--jpg-input を最低1件指定してください")?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnlySource {
+    Xmp,
+    Raw,
+    Jpg,
+}
 
-    let options = PlanOptions {
-        jpg_input: primary_jpg_input,
-        raw_input: args.raw_input.map(Into::into),
-        raw_from_jpg_parent_when_missing: args.raw_parent_if_missing,
-        recursive: false,
-        include_hidden: false,
-        template: args.template,
-        dedupe_same_maker: args.dedupe_same_maker,
-        exclusions: args.exclude,
-        max_filename_len: 240,
-    };
+impl From<OnlySource> for SourceFilter {
+    fn from(value: OnlySource) -> Self {
+        match value {
+            OnlySource::Xmp => SourceFilter::Xmp,
+            OnlySource::Raw => SourceFilter::Raw,
+            OnlySource::Jpg => SourceFilter::Jpg,
+        }
+    }
+}
 
-    let plan = if jpg_inputs.len() == 1 {
-        generate_plan(&options)?
-    } else {
-        generate_plan_for_jpg_files(&options, &jpg_inputs)?
-    };
+struct StderrProgress;
 
-    match args.output {
-        OutputFormat::Json => {
-            println!("{}", serde_json::to_string_pretty(&plan)?);
+impl ProgressSink for StderrProgress {
+    fn files_scanned(&self, scanned_files: usize) {
+        if scanned_files.is_multiple_of(500) {
+            eprintln!("走査中: {scanned_files}件");
         }
-        OutputFormat::Table => {
-            print_table(&plan);
+    }
+
+    fn scan_finished(&self, scanned_files: usize) {
+        eprintln!("走査完了: {scanned_files}件");
+    }
+
+    fn metadata_read(&self, done: usize, total: usize) {
+        if done.is_multiple_of(100) || done == total {
+            eprintln!("メタデータ読込: {done}/{total}");
         }
     }
 
-    if args.apply {
-        let result = apply_plan_with_options(
-            &plan,
-            &ApplyOptions {
-                backup_originals: args.backup_originals,
-            },
-        )?;
-        eprintln!(
-            "適用完了: {}件 (変更なし {}件)",
-            result.applied, result.unchanged
-        );
-    } else {
-        eprintln!("dry-run: リネームは未実行です。実行する場合は --apply を指定してください。");
+    fn files_applied(&self, done: usize, total: usize) {
+        if done.is_multiple_of(100) || done == total {
+            eprintln!("適用: {done}/{total}");
+        }
     }
+}
 
-    Ok(())
+// 自動処理向けに1行1イベントのJSONをstderrへ出す。stdoutの表・JSON出力とは混ざらない
+struct JsonProgress;
+
+impl JsonProgress {
+    fn emit(&self, event: serde_json::Value) {
+        eprintln!("{event}");
+    }
 }
 
-fn configure_exiftool_path() {
-    if std::env::var_os(EXIFTOOL_PATH_ENV).is_some() {
-        return;
+impl ProgressSink for JsonProgress {
+    fn files_scanned(&self, scanned_files: usize) {
+        if scanned_files.is_multiple_of(500) {
+            self.emit(serde_json::json!({ "event": "scanned", "count": scanned_files }));
+        }
     }
 
-    for candidate in exiftool_path_candidates() {
-        if candidate.is_file() {
-            std::env::set_var(EXIFTOOL_PATH_ENV, candidate);
-            return;
+    fn scan_finished(&self, scanned_files: usize) {
+        self.emit(serde_json::json!({ "event": "scan_done", "count": scanned_files }));
+    }
+
+    fn metadata_read(&self, done: usize, total: usize) {
+        if done.is_multiple_of(100) || done == total {
+            self.emit(
+                serde_json::json!({ "event": "metadata_read", "done": done, "total": total }),
+            );
+        }
+    }
+
+    fn files_applied(&self, done: usize, total: usize) {
+        if done.is_multiple_of(100) || done == total {
+            self.emit(serde_json::json!({ "event": "applied", "done": done, "total": total }));
         }
     }
 }
 
-fn exiftool_path_candidates() -> Vec<PathBuf> {
-    let mut candidates = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProgressPhase {
+    Scan,
+    Metadata,
+    Apply,
+}
 
-    #[cfg(target_os = "windows")]
-    let binary_name = "exiftool.exe";
-    #[cfg(not(target_os = "windows"))]
-    let binary_name = "exiftool";
+// 走査・メタデータ読込・適用の各段階ごとにバーを切り替えて表示する
+struct BarProgress {
+    current: Mutex<Option<(ProgressPhase, ProgressBar)>>,
+}
 
-    if let Some(path) = find_in_path(binary_name) {
-        candidates.push(path);
+impl BarProgress {
+    const fn new() -> Self {
+        Self {
+            current: Mutex::new(None),
+        }
     }
 
-    if let Ok(exe_path) = std::env::current_exe() {
-        if let Some(exe_dir) = exe_path.parent() {
-            #[cfg(target_os = "windows")]
-            {
-                candidates.push(exe_dir.join("resources/bin/windows/exiftool.exe"));
-                candidates.push(exe_dir.join("exiftool.exe"));
-            }
-            #[cfg(target_os = "macos")]
-            {
-                candidates.push(exe_dir.join("resources/bin/macos/exiftool"));
-                candidates.push(exe_dir.join("exiftool"));
-            }
-            #[cfg(target_os = "linux")]
-            {
-                candidates.push(exe_dir.join("resources/bin/linux/exiftool"));
-                candidates.push(exe_dir.join("exiftool"));
+    fn bar(&self, phase: ProgressPhase, total: usize) -> ProgressBar {
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((current_phase, bar)) = current.as_ref() {
+            if *current_phase == phase {
+                if phase != ProgressPhase::Scan {
+                    bar.set_length(total as u64);
+                }
+                return bar.clone();
             }
+            bar.finish();
         }
+
+        let bar = if phase == ProgressPhase::Scan {
+            ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{spinner} {msg}: {pos}件 ({per_sec})")
+                    .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+            )
+        } else {
+            ProgressBar::new(total as u64).with_style(
+                ProgressStyle::with_template(
+                    "{msg}: [{bar:30}] {pos}/{len} ({per_sec}, 残り{eta})",
+                )
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+                .progress_chars("=> "),
+            )
+        };
+        bar.set_message(match phase {
+            ProgressPhase::Scan => "走査中",
+            ProgressPhase::Metadata => "メタデータ読込",
+            ProgressPhase::Apply => "適用",
+        });
+        *current = Some((phase, bar.clone()));
+        bar
     }
 
-    #[cfg(target_os = "windows")]
-    {
-        candidates.push(
-            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-                .join("../gui/src-tauri/resources/bin/windows/exiftool.exe"),
-        );
+    fn advance(&self, phase: ProgressPhase, done: usize, total: usize) {
+        let bar = self.bar(phase, total);
+        bar.set_position(done as u64);
+        if done == total {
+            bar.finish();
+        }
     }
-    #[cfg(target_os = "macos")]
-    {
-        candidates.push(
-            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-                .join("../gui/src-tauri/resources/bin/macos/exiftool"),
-        );
-        candidates.push(PathBuf::from("/opt/homebrew/bin/exiftool"));
-        candidates.push(PathBuf::from("/usr/local/bin/exiftool"));
-        candidates.push(PathBuf::from("/usr/bin/exiftool"));
+}
+
+impl ProgressSink for BarProgress {
+    fn files_scanned(&self, scanned_files: usize) {
+        let bar = self.bar(ProgressPhase::Scan, 0);
+        bar.set_position(scanned_files as u64);
+        bar.tick();
     }
-    #[cfg(target_os = "linux")]
-    {
-        candidates.push(
-            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+
+    fn scan_finished(&self, scanned_files: usize) {
+        let bar = self.bar(ProgressPhase::Scan, 0);
+        bar.set_position(scanned_files as u64);
+        bar.finish();
+    }
+
+    fn metadata_read(&self, done: usize, total: usize) {
+        self.advance(ProgressPhase::Metadata, done, total);
+    }
+
+    fn files_applied(&self, done: usize, total: usize) {
+        self.advance(ProgressPhase::Apply, done, total);
+    }
+}
+
+fn progress_sink(format: Option<ProgressFormat>) -> Option<&'static dyn ProgressSink> {
+    static TEXT: StderrProgress = StderrProgress;
+    static JSON: JsonProgress = JsonProgress;
+    static BAR: BarProgress = BarProgress::new();
+    match format? {
+        ProgressFormat::Text => Some(&TEXT),
+        ProgressFormat::Json => Some(&JSON),
+        ProgressFormat::Bar => Some(&BAR),
+    }
+}
+
+fn main() -> ExitCode {
+    // COMPLETE=<shell> で起動された場合は補完候補を返して終了する
+    CompleteEnv::with_factory(Cli::command).complete();
+    let cli = Cli::parse();
+    // RUST_LOG が指定されていればフラグより優先する
+    env_logger::Builder::new()
+        .filter_level(cli.log_level())
+        .parse_default_env()
+        .init();
+
+    match run(cli.command) {
+        Ok(outcome) => ExitCode::from(outcome as u8),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(exit_code_for_error(&err))
+        }
+    }
+}
+
+fn run(command: Commands) -> Result<Outcome> {
+    let result = match command {
+        Commands::Rename(args) => return cmd_rename(args),
+        Commands::Plan(args) => return cmd_plan(args),
+        Commands::Apply(args) => return cmd_apply(args),
+        Commands::Inspect(args) => cmd_inspect(args),
+        Commands::Stats(args) => cmd_stats(args),
+        Commands::Watch(args) => cmd_watch(args),
+        Commands::Undo(args) => cmd_undo(args),
+        Commands::Redo => cmd_redo(),
+        Commands::Recover(args) => cmd_recover(args),
+        Commands::CleanupBackups(args) => cmd_cleanup_backups(args),
+        Commands::Completions(args) => {
+            clap_complete::generate(
+                args.shell,
+                &mut Cli::command(),
+                "fphoto-renamer-cli",
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+        Commands::Config(config) => match config.action {
+            ConfigAction::Show => cmd_config_show(),
+            ConfigAction::Get { key } => cmd_config_get(&key),
+            ConfigAction::Set { key, values } => cmd_config_set(&key, &values),
+            ConfigAction::Unset { key } => cmd_config_unset(&key),
+            ConfigAction::Export { file } => cmd_config_export(&file),
+            ConfigAction::Import { file, overwrite } => cmd_config_import(&file, overwrite),
+        },
+    };
+    result.map(|()| Outcome::Success)
+}
+
+// 閉じていない `{` の後ろをトークン名で補完する
+fn complete_template(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let Some(current) = current.to_str() else {
+        return Vec::new();
+    };
+    let Some(open) = current.rfind('{') else {
+        return Vec::new();
+    };
+    if current[open..].contains('}') {
+        return Vec::new();
+    }
+    let (head, partial) = (&current[..=open], &current[open + 1..]);
+    TEMPLATE_TOKENS
+        .iter()
+        .filter(|token| token.starts_with(partial))
+        .map(|token| CompletionCandidate::new(format!("{head}{token}}}")))
+        .collect()
+}
+
+fn cmd_rename(args: RenameArgs) -> Result<Outcome> {
+    configure_exiftool_path();
+    parse_template(&args.scan.template).map_err(invalid_input)?;
+
+    let jpg_inputs = args.scan.jpg_inputs()?;
+    let options = plan_options(&args.scan, &jpg_inputs)?;
+    let control = PlanControl {
+        progress: progress_sink(args.scan.progress),
+        cancel: None,
+    };
+    // dry-run のJSON以外の形式は、完成したプランを区切って順に表示する
+    if !args.apply && !matches!(args.output, OutputFormat::Json) {
+        let output = args.output;
+        let color = color_enabled(args.no_color);
+        print_plan_header(output);
+        let print_batch = |batch: Vec<RenameCandidate>| print_plan_rows(output, &batch, color);
+        let summary = if jpg_inputs.len() == 1 {
+            generate_plan_chunked(&options, control, PLAN_CHUNK_SIZE, print_batch)?
+        } else {
+            generate_plan_for_jpg_files_chunked(
+                &options,
+                &jpg_inputs,
+                control,
+                PLAN_CHUNK_SIZE,
+                print_batch,
+            )?
+        };
+        print_plan_footer(output, &summary)?;
+        warn_if_exiftool_missing(&summary, color);
+        report_orphaned_companions(&summary);
+        eprintln!("dry-run: リネームは未実行です。実行する場合は --apply を指定してください。");
+        return Ok(changes_outcome(
+            summary.stats.planned - summary.stats.unchanged,
+        ));
+    }
+
+    let plan = generate_plan_for_inputs(&options, &jpg_inputs, control)?;
+
+    if matches!(args.output, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+    } else {
+        print_plan_header(args.output);
+        print_plan_rows(args.output, &plan.candidates, color_enabled(args.no_color))?;
+        print_plan_footer(args.output, &plan)?;
+    }
+    warn_if_exiftool_missing(&plan, color_enabled(args.no_color));
+    report_orphaned_companions(&plan);
+
+    if args.apply {
+        run_apply(
+            &plan,
+            &args.apply_options,
+            false,
+            progress_sink(args.scan.progress),
+        )
+    } else {
+        eprintln!("dry-run: リネームは未実行です。実行する場合は --apply を指定してください。");
+        Ok(changes_outcome(plan.stats.planned - plan.stats.unchanged))
+    }
+}
+
+fn cmd_plan(args: PlanArgs) -> Result<Outcome> {
+    configure_exiftool_path();
+    parse_template(&args.scan.template).map_err(invalid_input)?;
+
+    let jpg_inputs = args.scan.jpg_inputs()?;
+    let options = plan_options(&args.scan, &jpg_inputs)?;
+    let control = PlanControl {
+        progress: progress_sink(args.scan.progress),
+        cancel: None,
+    };
+    let plan = generate_plan_for_inputs(&options, &jpg_inputs, control)?;
+    save_plan_file(&plan, Path::new(&args.out))?;
+    let changed = plan
+        .candidates
+        .iter()
+        .filter(|candidate| candidate.changed)
+        .count();
+    eprintln!(
+        "プランを保存しました: {} (対象 {}件, 変更 {}件)",
+        args.out,
+        plan.candidates.len(),
+        changed
+    );
+    Ok(changes_outcome(changed))
+}
+
+// 保存したプランは生成後にファイルが変わっている可能性があるため、古い場合は適用しない
+fn cmd_apply(args: ApplyPlanArgs) -> Result<Outcome> {
+    let mut plan = load_plan_file(Path::new(&args.plan)).map_err(invalid_input)?;
+    if let Some(only_source) = args.only_source {
+        let filter = SourceFilter::from(only_source);
+        plan.candidates
+            .retain(|candidate| filter.matches(candidate.metadata.source));
+    }
+    let report = validate_plan_against_fs(&plan);
+    if let Some(first) = report.stale.first() {
+        return Err(invalid_input(anyhow::anyhow!(
+            "プラン作成後に変更されたファイルがあるため適用できません ({}件): {}",
+            report.stale.len(),
+            first.path.display()
+        )));
+    }
+    run_apply(
+        &plan,
+        &args.apply_options,
+        true,
+        progress_sink(args.progress),
+    )
+}
+
+fn plan_options(args: &ScanArgs, jpg_inputs: &[PathBuf]) -> Result<PlanOptions> {
+    let primary_jpg_input = jpg_inputs
+        .first()
+        .cloned()
+        .context("--jpg-input またはパスを最低1件指定してください")?;
+
+    let config = load_config()?;
+    let primary_extensions = if args.primary_ext.is_empty() {
+        config.primary_extensions
+    } else {
+        args.primary_ext.clone()
+    };
+    let raw_extensions = if args.raw_ext.is_empty() {
+        config.raw_extensions
+    } else {
+        args.raw_ext.clone()
+    };
+
+    Ok(PlanOptions {
+        jpg_input: primary_jpg_input,
+        raw_input: args.raw_input.as_ref().map(Into::into),
+        raw_from_jpg_parent_when_missing: args.raw_parent_if_missing,
+        recursive: args.recursive.unwrap_or(config.recursive_default),
+        include_hidden: args.include_hidden.unwrap_or(config.include_hidden_default),
+        follow_symlinks: false,
+        template: args.template.clone(),
+        dedupe_same_maker: args.dedupe_same_maker,
+        exclusions: args.exclusions(&config.exclude_strings)?,
+        max_filename_len: args
+            .max_filename_len
+            .map_or(config.max_filename_len, usize::from),
+        min_rating: args.min_rating,
+        min_file_size: args.min_file_size,
+        include_videos: args.include_videos,
+        raw_primary: args.raw_primary,
+        primary_extensions,
+        raw_extensions,
+        strict_metadata: args.strict_metadata,
+        changed_only: args.changed_only,
+        rename_companions: args.rename_companions,
+        only_source: args.only_source.map(Into::into),
+        metadata_cache: !args.no_cache,
+        maker_aliases: config.maker_aliases,
+        extra_exif_tags: config.extra_exif_tags,
+        dedupe_model_maker: args.dedupe_model_maker.unwrap_or(config.dedupe_model_maker),
+        date_zone: date_zone(args.timezone.as_deref(), args.utc),
+        time_shifts: args.shift_time.clone(),
+        prefer_gps_time: args.prefer_gps_time,
+        raw_time_tolerance: args.match_raw_by_time.map(Duration::from_millis),
+        match_raw_by_unique_id: args.match_raw_by_id,
+    })
+}
+
+fn generate_plan_for_inputs(
+    options: &PlanOptions,
+    jpg_inputs: &[PathBuf],
+    control: PlanControl<'_>,
+) -> Result<RenamePlan> {
+    if jpg_inputs.len() == 1 {
+        generate_plan_with_control(options, control)
+    } else {
+        generate_plan_for_jpg_files_with_control(options, jpg_inputs, control)
+    }
+}
+
+fn run_apply(
+    plan: &RenamePlan,
+    args: &ApplyArgs,
+    reject_stale_plan: bool,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<Outcome> {
+    let backup_retention = load_config()?.backup_retention;
+    // プラン表示は全件のまま、適用対象だけを先頭N枚に絞る
+    let limited;
+    let plan = match args.limit {
+        Some(limit) => {
+            limited = plan.limit_changed(limit);
+            eprintln!("--limit: 変更のある先頭{limit}枚のみ適用します");
+            &limited
+        }
+        None => plan,
+    };
+    let result = apply_plan_with_progress(
+        plan,
+        &ApplyOptions {
+            // --backup-dir / --backup-zip / --trash-originals の指定だけでもバックアップを有効にする
+            backup_originals: args.backup_originals
+                || args.backup_dir.is_some()
+                || args.backup_zip
+                || args.trash_originals,
+            reject_stale_plan,
+            mode: apply_mode(args),
+            continue_on_error: args.continue_on_error,
+            collision_policy: args.on_collision.into(),
+            backup_dir: args.backup_dir.as_deref().map(PathBuf::from),
+            backup_format: if args.backup_zip {
+                BackupFormat::Zip
+            } else if args.trash_originals {
+                BackupFormat::Trash
+            } else {
+                BackupFormat::Files
+            },
+            backup_retention,
+            clear_readonly: args.clear_readonly,
+            set_file_times: args.set_file_times,
+        },
+        progress,
+    )?;
+    eprintln!(
+        "適用完了: {}件 (変更なし {}件)",
+        result.applied, result.unchanged
+    );
+    let mut failed = 0;
+    for file in &result.files {
+        if let ApplyOutcome::Failed { error } = &file.outcome {
+            eprintln!("失敗: {}: {error}", file.original_path.display());
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Ok(Outcome::PartialFailure);
+    }
+    Ok(changes_outcome(result.applied))
+}
+
+fn apply_mode(args: &ApplyArgs) -> ApplyMode {
+    if let Some(output_dir) = &args.copy_to {
+        return ApplyMode::CopyTo(PathBuf::from(output_dir));
+    }
+    if let Some(output_dir) = &args.hardlink_to {
+        return ApplyMode::HardlinkTo(PathBuf::from(output_dir));
+    }
+    ApplyMode::Rename
+}
+
+fn configure_exiftool_path() {
+    if std::env::var_os(EXIFTOOL_PATH_ENV).is_some() {
+        return;
+    }
+
+    for candidate in exiftool_path_candidates() {
+        if candidate.is_file() {
+            log::debug!("exiftoolの候補を採用: {}", candidate.display());
+            std::env::set_var(EXIFTOOL_PATH_ENV, candidate);
+            return;
+        }
+    }
+}
+
+fn exiftool_path_candidates() -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+
+    #[cfg(target_os = "windows")]
+    let binary_name = "exiftool.exe";
+    #[cfg(not(target_os = "windows"))]
+    let binary_name = "exiftool";
+
+    if let Some(path) = find_in_path(binary_name) {
+        candidates.push(path);
+    }
+
+    if let Ok(exe_path) = std::env::current_exe() {
+        if let Some(exe_dir) = exe_path.parent() {
+            #[cfg(target_os = "windows")]
+            {
+                candidates.push(exe_dir.join("resources/bin/windows/exiftool.exe"));
+                candidates.push(exe_dir.join("exiftool.exe"));
+            }
+            #[cfg(target_os = "macos")]
+            {
+                candidates.push(exe_dir.join("resources/bin/macos/exiftool"));
+                candidates.push(exe_dir.join("exiftool"));
+            }
+            #[cfg(target_os = "linux")]
+            {
+                candidates.push(exe_dir.join("resources/bin/linux/exiftool"));
+                candidates.push(exe_dir.join("exiftool"));
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        candidates.push(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("../gui/src-tauri/resources/bin/windows/exiftool.exe"),
+        );
+    }
+    #[cfg(target_os = "macos")]
+    {
+        candidates.push(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("../gui/src-tauri/resources/bin/macos/exiftool"),
+        );
+        candidates.push(PathBuf::from("/opt/homebrew/bin/exiftool"));
+        candidates.push(PathBuf::from("/usr/local/bin/exiftool"));
+        candidates.push(PathBuf::from("/usr/bin/exiftool"));
+    }
+    #[cfg(target_os = "linux")]
+    {
+        candidates.push(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
                 .join("../gui/src-tauri/resources/bin/linux/exiftool"),
         );
         candidates.push(PathBuf::from("/usr/local/bin/exiftool"));
@@ -217,108 +1119,1370 @@ fn find_in_path(binary_name: &str) -> Option<PathBuf> {
             return Some(candidate);
         }
     }
-    None
-}
-
-fn cmd_undo() -> Result<()> {
-    let result = undo_last()?;
-    println!("取り消し完了: {}件", result.restored);
-    Ok(())
-}
+    None
+}
+
+fn cmd_inspect(args: InspectArgs) -> Result<()> {
+    configure_exiftool_path();
+    parse_template(&args.template).map_err(invalid_input)?;
+
+    let config = load_config()?;
+    let jpg_path = PathBuf::from(&args.path);
+    let options = PlanOptions {
+        jpg_input: jpg_path.clone(),
+        raw_input: args.raw_input.as_ref().map(Into::into),
+        raw_from_jpg_parent_when_missing: args.raw_parent_if_missing,
+        recursive: config.recursive_default,
+        template: args.template.clone(),
+        dedupe_same_maker: args.dedupe_same_maker,
+        exclusions: args.exclude.clone(),
+        max_filename_len: config.max_filename_len,
+        primary_extensions: config.primary_extensions,
+        raw_extensions: config.raw_extensions,
+        maker_aliases: config.maker_aliases,
+        extra_exif_tags: config.extra_exif_tags,
+        dedupe_model_maker: args.dedupe_model_maker.unwrap_or(config.dedupe_model_maker),
+        date_zone: date_zone(args.timezone.as_deref(), args.utc),
+        time_shifts: args.shift_time.clone(),
+        prefer_gps_time: args.prefer_gps_time,
+        ..PlanOptions::default()
+    };
+    let inspection = inspect_jpg_file(&options, &jpg_path)?;
+    let candidate = &inspection.candidate;
+    let metadata = &candidate.metadata;
+    let provenance = &candidate.provenance;
+    let path_or_none = |path: &Option<PathBuf>| {
+        path.as_ref()
+            .map_or_else(|| "なし".to_string(), |path| path.display().to_string())
+    };
+
+    println!("ファイル: {}", candidate.original_path.display());
+    println!("XMP: {}", path_or_none(&inspection.xmp_path));
+    println!("RAW: {}", path_or_none(&inspection.raw_path));
+    for raw_path in candidate
+        .raw_paths
+        .iter()
+        .filter(|path| Some(*path) != inspection.raw_path.as_ref())
+    {
+        println!("RAW (同名の別形式): {}", raw_path.display());
+    }
+    if let Some(method) = candidate.raw_match {
+        println!("RAW対応付け: {}", raw_match_label(method));
+    }
+    println!("取得元: {}", candidate.source_label);
+    println!(
+        "EXIF読み込み: {}",
+        metadata_backend_label(provenance.backend)
+    );
+    let mut fields = vec![
+        (
+            "date",
+            Some(metadata.date.format("%Y-%m-%d %H:%M:%S %:z").to_string()),
+            provenance.date,
+        ),
+        (
+            "camera_make",
+            metadata.camera_make.clone(),
+            provenance.camera_make,
+        ),
+        (
+            "camera_model",
+            metadata.camera_model.clone(),
+            provenance.camera_model,
+        ),
+        (
+            "lens_make",
+            metadata.lens_make.clone(),
+            provenance.lens_make,
+        ),
+        (
+            "lens_model",
+            metadata.lens_model.clone(),
+            provenance.lens_model,
+        ),
+        ("film_sim", metadata.film_sim.clone(), provenance.film_sim),
+        (
+            "rating",
+            metadata.rating.map(|rating| rating.to_string()),
+            provenance.rating,
+        ),
+        (
+            "dynamic_range",
+            metadata.dynamic_range.clone(),
+            provenance.dynamic_range,
+        ),
+        (
+            "grain_effect",
+            metadata.grain_effect.clone(),
+            provenance.grain_effect,
+        ),
+        (
+            "color_chrome",
+            metadata.color_chrome_effect.clone(),
+            provenance.color_chrome_effect,
+        ),
+        ("wb_shift", metadata.wb_shift.clone(), provenance.wb_shift),
+        (
+            "focal_length_eq",
+            metadata.focal_length_eq.clone(),
+            provenance.focal_length_eq,
+        ),
+        (
+            "exposure_comp",
+            metadata.exposure_compensation.clone(),
+            provenance.exposure_compensation,
+        ),
+        (
+            "flash",
+            metadata
+                .flash_fired
+                .map(|fired| if fired { "fired" } else { "off" }.to_string()),
+            provenance.flash_fired,
+        ),
+        (
+            "image_unique_id",
+            metadata.image_unique_id.clone(),
+            provenance.image_unique_id,
+        ),
+        ("label", metadata.label.clone(), provenance.label),
+        (
+            "title",
+            metadata.title(None).map(str::to_string),
+            provenance.title,
+        ),
+        (
+            "description",
+            metadata.description(None).map(str::to_string),
+            provenance.description,
+        ),
+        (
+            "keywords",
+            (!metadata.keywords.is_empty()).then(|| metadata.keywords.join(", ")),
+            provenance.keywords,
+        ),
+    ];
+    fields.extend(metadata.extra_tags.iter().map(|(tag, value)| {
+        (
+            tag.as_str(),
+            Some(value.clone()),
+            provenance.extra_tags.get(tag).copied(),
+        )
+    }));
+    for (name, value, source) in fields {
+        println!(
+            "  {name}: {} ({})",
+            value.as_deref().unwrap_or("-"),
+            field_source_label(source)
+        );
+    }
+    println!("新ファイル名: {}", candidate.target_path.display());
+    for warning in &candidate.warnings {
+        println!("警告: {warning}");
+    }
+    Ok(())
+}
+
+// 撮影中の取り込みフォルダ向け。書き込みが落ち着くまで待ってから、新しく届いた写真だけをプランする
+fn cmd_watch(args: WatchArgs) -> Result<()> {
+    configure_exiftool_path();
+
+    let config = load_config()?;
+    let template = args.template.clone().unwrap_or(config.template);
+    parse_template(&template).map_err(invalid_input)?;
+    let jpg_root = PathBuf::from(&args.path);
+    if !jpg_root.is_dir() {
+        return Err(invalid_input(anyhow::anyhow!(
+            "監視するフォルダが存在しません: {}",
+            jpg_root.display()
+        )));
+    }
+    let mut exclusions = config.exclude_strings;
+    exclusions.extend(args.exclude.iter().cloned());
+    let options = PlanOptions {
+        jpg_input: jpg_root.clone(),
+        raw_input: args.raw_input.as_ref().map(Into::into),
+        raw_from_jpg_parent_when_missing: args.raw_parent_if_missing,
+        recursive: args.recursive.unwrap_or(config.recursive_default),
+        template,
+        dedupe_same_maker: config.dedupe_same_maker,
+        exclusions,
+        max_filename_len: config.max_filename_len,
+        primary_extensions: config.primary_extensions,
+        raw_extensions: config.raw_extensions,
+        metadata_cache: true,
+        maker_aliases: config.maker_aliases,
+        extra_exif_tags: config.extra_exif_tags,
+        dedupe_model_maker: config.dedupe_model_maker,
+        ..PlanOptions::default()
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context("フォルダ監視を開始できませんでした")?;
+    let mode = if options.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    let mut watched = vec![jpg_root.clone()];
+    watched.extend(
+        options
+            .raw_input
+            .iter()
+            .filter(|raw| **raw != jpg_root)
+            .cloned(),
+    );
+    for folder in &watched {
+        watcher
+            .watch(folder, mode)
+            .with_context(|| format!("フォルダを監視できませんでした: {}", folder.display()))?;
+    }
+    eprintln!(
+        "監視を開始しました: {} (Ctrl+C で終了)",
+        watched
+            .iter()
+            .map(|folder| folder.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    // 自分でリネームした結果のファイルを再びプランすると {orig_name} が重なっていくため除外する
+    let mut produced = HashSet::<PathBuf>::new();
+    let debounce = Duration::from_millis(args.debounce_ms);
+    while let Ok(event) = receiver.recv() {
+        let mut changed_paths = BTreeSet::new();
+        changed_paths.extend(watch_event_paths(event));
+        // RAW/XMPがJPGより遅れて届く場合もあるので、イベントが止むまで待つ
+        while let Ok(event) = receiver.recv_timeout(debounce) {
+            changed_paths.extend(watch_event_paths(event));
+        }
+        let arrivals = changed_paths
+            .into_iter()
+            .filter(|path| {
+                !produced.contains(path) && path.is_file() && options.is_scan_target(path)
+            })
+            .collect::<Vec<_>>();
+        if arrivals.is_empty() {
+            continue;
+        }
+
+        let plan = match generate_plan_for_jpg_files(&options, &arrivals) {
+            Ok(plan) => plan,
+            Err(err) => {
+                eprintln!("プラン作成に失敗しました: {err:#}");
+                continue;
+            }
+        };
+        print_table_header();
+        print_candidate_rows(&plan.candidates, color_enabled(false));
+        if !args.apply {
+            eprintln!(
+                "dry-run: リネームは未実行です。自動で適用する場合は --apply を指定してください。"
+            );
+            continue;
+        }
+        produced.extend(
+            plan.candidates
+                .iter()
+                .filter(|candidate| candidate.changed)
+                .map(|candidate| candidate.target_path.clone()),
+        );
+        if let Err(err) = run_apply(&plan, &args.apply_options, true, None) {
+            eprintln!("適用に失敗しました: {err:#}");
+        }
+    }
+    Ok(())
+}
+
+fn watch_event_paths(event: notify::Result<notify::Event>) -> Vec<PathBuf> {
+    match event {
+        Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+            event.paths
+        }
+        Ok(_) => Vec::new(),
+        Err(err) => {
+            log::warn!("フォルダ監視のイベントを受け取れませんでした: {err}");
+            Vec::new()
+        }
+    }
+}
+
+fn cmd_stats(args: StatsArgs) -> Result<()> {
+    configure_exiftool_path();
+
+    let config = load_config()?;
+    let options = PlanOptions {
+        jpg_input: PathBuf::from(&args.path),
+        raw_input: args.raw_input.as_ref().map(Into::into),
+        raw_from_jpg_parent_when_missing: args.raw_parent_if_missing,
+        recursive: args.recursive.unwrap_or(config.recursive_default),
+        include_hidden: args.include_hidden.unwrap_or(config.include_hidden_default),
+        min_rating: args.min_rating,
+        include_videos: args.include_videos,
+        primary_extensions: config.primary_extensions,
+        raw_extensions: config.raw_extensions,
+        metadata_cache: !args.no_cache,
+        time_shifts: args.shift_time,
+        prefer_gps_time: args.prefer_gps_time,
+        ..PlanOptions::default()
+    };
+    let control = PlanControl {
+        progress: progress_sink(args.progress),
+        cancel: None,
+    };
+    let stats = collect_folder_stats(&options, control)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    println!(
+        "集計: scanned={} photos={} rating_skip={}",
+        stats.scanned_files, stats.photos, stats.skipped_below_rating
+    );
+    if let (Some(earliest), Some(latest)) = (stats.earliest, stats.latest) {
+        println!(
+            "撮影期間: {} 〜 {}",
+            earliest.format("%Y-%m-%d %H:%M:%S"),
+            latest.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    print_breakdown(
+        "カメラ別",
+        stats.by_camera.iter().map(|(k, v)| (k.clone(), *v)),
+    );
+    print_breakdown(
+        "レンズ別",
+        stats.by_lens.iter().map(|(k, v)| (k.clone(), *v)),
+    );
+    print_breakdown(
+        "フィルムシミュレーション別",
+        stats.by_film_sim.iter().map(|(k, v)| (k.clone(), *v)),
+    );
+    print_breakdown(
+        "取得元別",
+        stats.by_source.iter().map(|(k, v)| (k.to_string(), *v)),
+    );
+    Ok(())
+}
+
+fn field_source_label(source: Option<FieldSource>) -> &'static str {
+    match source {
+        Some(FieldSource::Xmp) => "xmp",
+        Some(FieldSource::RawExif) => "raw_exif",
+        Some(FieldSource::JpgExif) => "jpg_exif",
+        Some(FieldSource::Gps) => "gps",
+        Some(FieldSource::FileModified) => "file_modified",
+        None => "なし",
+    }
+}
+
+fn metadata_backend_label(backend: Option<MetadataBackend>) -> &'static str {
+    match backend {
+        Some(MetadataBackend::Exiftool) => "exiftool",
+        Some(MetadataBackend::Kamadak) => "kamadak-exif",
+        Some(MetadataBackend::EmbeddedXmp) => "埋め込みXMP",
+        None => "なし",
+    }
+}
+
+fn raw_match_label(method: RawMatchMethod) -> &'static str {
+    match method {
+        RawMatchMethod::Name => "ファイル名",
+        RawMatchMethod::ImageUniqueId => "ImageUniqueID",
+        RawMatchMethod::CaptureTime => "撮影日時",
+    }
+}
+
+fn cmd_undo(args: UndoArgs) -> Result<()> {
+    if let Some(UndoAction::List) = args.action {
+        return cmd_undo_list();
+    }
+    if args.dry_run {
+        let preview = preview_undo()?;
+        for entry in &preview.entries {
+            let missing = if entry.missing {
+                " (見つかりません)"
+            } else if entry.modified {
+                " (適用後に変更されています)"
+            } else {
+                ""
+            };
+            if preview.output_dir.is_some() {
+                println!("削除: {}{missing}", entry.current_path.display());
+            } else {
+                println!(
+                    "{} -> {}{missing}",
+                    entry.current_path.display(),
+                    entry.restore_path.display()
+                );
+            }
+        }
+        let missing = preview.entries.iter().filter(|entry| entry.missing).count();
+        eprintln!(
+            "dry-run: 取り消しは未実行です。対象 {}件 (見つからない {}件)",
+            preview.entries.len(),
+            missing
+        );
+        return Ok(());
+    }
+
+    let options = UndoOptions {
+        allow_modified: args.force,
+    };
+    let result = if let Some(id) = &args.id {
+        undo_session_with_options(id, &options)?
+    } else if args.files.is_empty() {
+        undo_last_with_options(&options)?
+    } else {
+        let files: Vec<PathBuf> = args.files.iter().map(PathBuf::from).collect();
+        undo_last_files(&files, &options)?
+    };
+    for path in &result.modified {
+        eprintln!(
+            "警告: 適用後に変更されたファイルを戻しました: {}",
+            path.display()
+        );
+    }
+    println!("取り消し完了: {}件", result.restored);
+    Ok(())
+}
+
+fn cmd_undo_list() -> Result<()> {
+    let sessions = list_undo_sessions()?;
+    if sessions.is_empty() {
+        println!("取り消し履歴はありません");
+        return Ok(());
+    }
+    for session in &sessions {
+        let roots = session
+            .roots
+            .iter()
+            .map(|root| root.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "{}  {}  {}件  {}",
+            session.id,
+            session.created_at.as_deref().unwrap_or("-"),
+            session.file_count,
+            session
+                .output_dir
+                .as_ref()
+                .map_or(roots, |dir| format!("出力先: {}", dir.display()))
+        );
+    }
+    Ok(())
+}
+
+fn cmd_redo() -> Result<()> {
+    let result = redo_last()?;
+    println!("やり直し完了: {}件", result.reapplied);
+    Ok(())
+}
+
+fn cmd_recover(args: RecoverArgs) -> Result<()> {
+    let Some(pending) = pending_apply()? else {
+        println!("中断された適用はありません");
+        return Ok(());
+    };
+    eprintln!(
+        "中断された適用: {}件 (一時ファイル {}件)",
+        pending.entries,
+        pending.leftover_temp_files.len()
+    );
+
+    let action = if args.rollback {
+        RecoverAction::Rollback
+    } else {
+        RecoverAction::Complete
+    };
+    let result = recover_interrupted_apply(action)?;
+    match result.action {
+        RecoverAction::Complete => println!("復旧完了 (適用を完了): {}件", result.recovered),
+        RecoverAction::Rollback => println!("復旧完了 (元に戻しました): {}件", result.recovered),
+    }
+    Ok(())
+}
+
+// 引数で指定しなかった条件は設定ファイルの保持設定を使う
+fn cmd_cleanup_backups(args: CleanupBackupsArgs) -> Result<()> {
+    let configured = load_config()?.backup_retention;
+    let retention = BackupRetention {
+        keep_sessions: args.keep_sessions.or(configured.keep_sessions),
+        keep_days: args.keep_days.or(configured.keep_days),
+    };
+    if retention.is_unlimited() {
+        bail!(
+            "保持条件がありません。--keep-sessions / --keep-days を指定するか、設定ファイルの backup_retention を設定してください"
+        );
+    }
+    let result = cleanup_backups(&retention)?;
+    println!(
+        "バックアップ削除完了: {}回分 ({}ファイル)",
+        result.removed_sessions, result.removed_files
+    );
+    Ok(())
+}
+
+fn cmd_config_show() -> Result<()> {
+    let config = load_config()?;
+    let paths = app_paths()?;
+    println!("設定ファイル: {}", paths.config_path.display());
+    println!("{}", toml::to_string_pretty(&config)?);
+    Ok(())
+}
+
+fn cmd_config_get(key: &str) -> Result<()> {
+    println!("{}", load_config()?.get_field(key).map_err(invalid_input)?);
+    Ok(())
+}
+
+fn cmd_config_set(key: &str, values: &[String]) -> Result<()> {
+    let mut config = load_config()?;
+    config.set_field(key, values).map_err(invalid_input)?;
+    save_config(&config)?;
+    eprintln!("設定を更新しました: {key}");
+    Ok(())
+}
+
+fn cmd_config_unset(key: &str) -> Result<()> {
+    let mut config = load_config()?;
+    config.unset_field(key).map_err(invalid_input)?;
+    save_config(&config)?;
+    eprintln!("設定を既定値に戻しました: {key}");
+    Ok(())
+}
+
+fn cmd_config_export(file: &str) -> Result<()> {
+    export_config(Path::new(file))?;
+    eprintln!("設定を書き出しました: {file}");
+    Ok(())
+}
+
+fn cmd_config_import(file: &str, overwrite: bool) -> Result<()> {
+    let mode = if overwrite {
+        ConfigImportMode::Overwrite
+    } else {
+        ConfigImportMode::Merge
+    };
+    let config = import_config(Path::new(file), mode).map_err(invalid_input)?;
+    eprintln!("設定を取り込みました: {file}");
+    println!("{}", toml::to_string_pretty(&config)?);
+    Ok(())
+}
+
+fn print_plan_header(output: OutputFormat) {
+    match output {
+        OutputFormat::Table => print_table_header(),
+        OutputFormat::Markdown => print_markdown_header(),
+        OutputFormat::Json | OutputFormat::Ndjson => {}
+    }
+}
+
+fn print_plan_rows(
+    output: OutputFormat,
+    candidates: &[RenameCandidate],
+    color: bool,
+) -> Result<()> {
+    match output {
+        OutputFormat::Table => print_candidate_rows(candidates, color),
+        OutputFormat::Markdown => print_markdown_rows(candidates),
+        OutputFormat::Ndjson => print_ndjson_candidates(candidates)?,
+        OutputFormat::Json => {}
+    }
+    Ok(())
+}
+
+fn print_plan_footer(output: OutputFormat, plan: &RenamePlan) -> Result<()> {
+    match output {
+        OutputFormat::Table => print_summary(plan),
+        OutputFormat::Markdown => print_markdown_summary(plan),
+        OutputFormat::Ndjson => print_ndjson_summary(plan)?,
+        OutputFormat::Json => {}
+    }
+    Ok(())
+}
+
+// JSON 出力を壊さないよう標準エラーに出す
+fn warn_if_exiftool_missing(plan: &RenamePlan, color: bool) {
+    if plan.exiftool_missing {
+        eprintln!(
+            "{}",
+            style("警告: exiftool が見つからないため kamadak-exif のみで読み込みました。フィルムシミュレーション等を取得できない場合があります (FPHOTO_EXIFTOOL_PATH で場所を指定できます)")
+                .yellow()
+                .bold()
+                .force_styling(color)
+        );
+    }
+}
+
+// JPG と対応しなかった RAW/XMP。多い場合は先頭だけ出して件数を添える
+fn report_orphaned_companions(plan: &RenamePlan) {
+    const SHOWN: usize = 20;
+    if plan.orphaned_companions.is_empty() {
+        return;
+    }
+    eprintln!(
+        "対応する JPG が無い RAW/XMP: {}件",
+        plan.orphaned_companions.len()
+    );
+    for path in plan.orphaned_companions.iter().take(SHOWN) {
+        eprintln!("  {}", path.display());
+    }
+    if plan.orphaned_companions.len() > SHOWN {
+        eprintln!("  ほか {}件", plan.orphaned_companions.len() - SHOWN);
+    }
+}
+
+fn print_table_header() {
+    println!("元ファイル -> 新ファイル (source)");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RowKind {
+    Unchanged,
+    Changed,
+    Collision,
+}
+
+// 連番が付いた (テンプレートの結果と名前が変わった) ものを衝突として強調する
+fn row_kind(changed: bool, target_path: &Path, rendered_base: &str) -> RowKind {
+    if !changed {
+        return RowKind::Unchanged;
+    }
+    let stem = target_path.file_stem().map(|stem| stem.to_string_lossy());
+    if stem.as_deref() == Some(rendered_base) {
+        RowKind::Changed
+    } else {
+        RowKind::Collision
+    }
+}
+
+fn file_label(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+// 別フォルダへ移動する場合だけ元フォルダからの相対パスを出す
+fn target_label(original_path: &Path, target_path: &Path) -> String {
+    match original_path.parent() {
+        Some(parent) if target_path.parent() == Some(parent) => file_label(target_path),
+        Some(parent) => target_path
+            .strip_prefix(parent)
+            .unwrap_or(target_path)
+            .display()
+            .to_string(),
+        None => target_path.display().to_string(),
+    }
+}
+
+fn color_enabled(no_color: bool) -> bool {
+    !no_color
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::io::stdout().is_terminal()
+}
+
+fn print_candidate_rows(candidates: &[RenameCandidate], color: bool) {
+    let labels = candidates
+        .iter()
+        .map(|candidate| {
+            (
+                file_label(&candidate.original_path),
+                target_label(&candidate.original_path, &candidate.target_path),
+            )
+        })
+        .collect::<Vec<_>>();
+    let original_width = labels
+        .iter()
+        .map(|(original, _)| measure_text_width(original))
+        .max()
+        .unwrap_or(0);
+    let target_width = labels
+        .iter()
+        .map(|(_, target)| measure_text_width(target))
+        .max()
+        .unwrap_or(0);
+
+    for (candidate, (original, target)) in candidates.iter().zip(&labels) {
+        let target = pad_str(target, target_width, Alignment::Left, None);
+        let target = match row_kind(
+            candidate.changed,
+            &candidate.target_path,
+            &candidate.rendered_base,
+        ) {
+            RowKind::Unchanged => style(target).dim(),
+            RowKind::Changed => style(target).green(),
+            RowKind::Collision => style(target).yellow().bold(),
+        }
+        .force_styling(color);
+        println!(
+            "{} -> {} ({})",
+            pad_str(original, original_width, Alignment::Left, None),
+            target,
+            candidate.source_label
+        );
+        for warning in &candidate.warnings {
+            println!(
+                "  {}",
+                style(format!("警告: {warning}")).red().force_styling(color)
+            );
+        }
+    }
+}
+
+fn print_summary(plan: &RenamePlan) {
+    let stats = &plan.stats;
+    println!(
+        "\n集計: scanned={} jpg={} non_jpg_skip={} small_skip={} rating_skip={} no_date_skip={} source_skip={} unchanged={}",
+        stats.scanned_files,
+        stats.jpg_files,
+        stats.skipped_non_jpg,
+        stats.skipped_small_files,
+        stats.skipped_below_rating,
+        stats.skipped_missing_date,
+        stats.skipped_other_source,
+        stats.unchanged
+    );
+    print_breakdown(
+        "カメラ別",
+        stats.by_camera.iter().map(|(k, v)| (k.clone(), *v)),
+    );
+    print_breakdown(
+        "フィルムシミュレーション別",
+        stats.by_film_sim.iter().map(|(k, v)| (k.clone(), *v)),
+    );
+    print_breakdown(
+        "取得元別",
+        stats.by_source.iter().map(|(k, v)| (k.to_string(), *v)),
+    );
+    if !plan.warnings.is_empty() {
+        println!("警告: {}件", plan.warnings.len());
+    }
+    let timings = &plan.timings;
+    println!(
+        "計測: scan={}ms metadata={}ms (exiftool={}ms kamadak={}ms) render={}ms total={}ms",
+        timings.scan_ms,
+        timings.metadata_ms,
+        timings.exiftool_ms,
+        timings.kamadak_ms,
+        timings.render_ms,
+        timings.total_ms
+    );
+}
+
+fn print_markdown_header() {
+    println!("| 元ファイル | 新ファイル | 取得元 |");
+    println!("| --- | --- | --- |");
+}
+
+// 新ファイルは元ファイルと同じフォルダなら名前だけ、フォルダ分けされる場合は元フォルダからの相対パスで表示する
+fn print_markdown_rows(candidates: &[RenameCandidate]) {
+    for candidate in candidates {
+        let original_name = candidate
+            .original_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let target = candidate
+            .original_path
+            .parent()
+            .and_then(|parent| candidate.target_path.strip_prefix(parent).ok())
+            .unwrap_or(&candidate.target_path);
+        println!(
+            "| {} | {} | {} |",
+            markdown_cell(&original_name),
+            markdown_cell(&target.to_string_lossy()),
+            markdown_cell(&candidate.source_label)
+        );
+    }
+}
+
+fn print_markdown_summary(plan: &RenamePlan) {
+    let stats = &plan.stats;
+    println!();
+    println!(
+        "**集計**: 走査 {}件 / 対象 {}件 / 変更なし {}件 / 警告 {}件",
+        stats.scanned_files,
+        stats.jpg_files,
+        stats.unchanged,
+        plan.warnings.len()
+    );
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+}
+
+// 1行1候補で出力し、最後に集計行を出す。jq では type で候補と集計を見分けられる
+fn print_ndjson_candidates(candidates: &[RenameCandidate]) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    for candidate in candidates {
+        let mut line = serde_json::to_value(candidate)?;
+        line["type"] = "candidate".into();
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+fn print_ndjson_summary(plan: &RenamePlan) -> Result<()> {
+    let line = serde_json::json!({
+        "type": "summary",
+        "stats": plan.stats,
+        "warnings": plan.warnings,
+        "exiftool_missing": plan.exiftool_missing,
+        "timings": plan.timings,
+    });
+    writeln!(std::io::stdout().lock(), "{line}")?;
+    Ok(())
+}
+
+fn print_breakdown(label: &str, entries: impl Iterator<Item = (String, usize)>) {
+    let line = entries
+        .map(|(key, count)| format!("{key}={count}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if !line.is_empty() {
+        println!("{label}: {line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_mode, changes_outcome, complete_template, date_zone, exit_code_for_error,
+        invalid_input, markdown_cell, parse_exclusion_file, parse_file_list, row_kind,
+        target_label, watch_event_paths, Cli, Commands, ConfigAction, OnCollision, Outcome,
+        OutputFormat, ProgressFormat, RowKind, UndoAction,
+    };
+    use anyhow::anyhow;
+    use clap::error::ErrorKind;
+    use clap::Parser;
+    use clap_complete::Shell;
+    use fphoto_renamer_core::{ApplyMode, DEFAULT_TEMPLATE};
+    use notify::event::{CreateKind, RemoveKind};
+    use notify::{Event, EventKind};
+    use std::ffi::OsStr;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn parse_rename_defaults() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "rename", "--jpg-input", "/tmp/jpg"])
+            .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Rename(args) => {
+                assert_eq!(args.scan.jpg_input, vec!["/tmp/jpg".to_string()]);
+                assert_eq!(args.scan.raw_input, None);
+                assert!(!args.scan.raw_parent_if_missing);
+                assert_eq!(args.scan.recursive, None);
+                assert_eq!(args.scan.include_hidden, None);
+                assert!(!args.apply);
+                assert_eq!(args.scan.template, DEFAULT_TEMPLATE);
+                assert!(args.scan.exclude.is_empty());
+                assert_eq!(args.scan.exclude_file, None);
+                assert!(args.scan.dedupe_same_maker);
+                assert!(!args.apply_options.backup_originals);
+                assert!(args.apply_options.backup_dir.is_none());
+                assert!(!args.apply_options.backup_zip);
+                assert_eq!(args.apply_options.copy_to, None);
+                assert_eq!(args.apply_options.hardlink_to, None);
+                assert!(!args.apply_options.continue_on_error);
+                assert_eq!(args.apply_options.on_collision, OnCollision::Fail);
+                assert_eq!(args.apply_options.limit, None);
+                assert_eq!(args.scan.min_rating, None);
+                assert_eq!(args.scan.min_file_size, 0);
+                assert!(!args.scan.include_videos);
+                assert!(!args.scan.raw_primary);
+                assert!(args.scan.primary_ext.is_empty());
+                assert_eq!(args.scan.progress, None);
+                assert!(!args.scan.strict_metadata);
+                assert!(!args.scan.changed_only);
+                assert!(!args.scan.rename_companions);
+                assert_eq!(args.scan.only_source, None);
+                assert!(!args.scan.no_cache);
+                assert!(matches!(args.output, OutputFormat::Table));
+            }
+            _ => panic!("rename command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_rename_with_explicit_values() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--raw-input",
+            "/tmp/raw",
+            "--raw-parent-if-missing",
+            "--apply",
+            "--template",
+            "{orig_name}",
+            "--exclude",
+            "-NR",
+            "--exclude",
+            "-DxO",
+            "--dedupe-same-maker=false",
+            "--backup-originals",
+            "--backup-dir",
+            "/mnt/nas/backups",
+            "--backup-zip",
+            "--copy-to",
+            "/tmp/export",
+            "--min-rating",
+            "3",
+            "--min-file-size",
+            "20000",
+            "--include-videos",
+            "--raw-primary",
+            "--primary-ext",
+            "jpg",
+            "--primary-ext",
+            "webp",
+            "--raw-ext",
+            "nef",
+            "--match-raw-by-time",
+            "--match-raw-by-id",
+            "--progress",
+            "--strict-metadata",
+            "--changed-only",
+            "--rename-companions",
+            "--on-collision",
+            "suffix",
+            "--output",
+            "json",
+        ])
+        .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Rename(args) => {
+                assert_eq!(args.scan.jpg_input, vec!["/tmp/jpg".to_string()]);
+                assert_eq!(args.scan.raw_input.as_deref(), Some("/tmp/raw"));
+                assert!(args.scan.raw_parent_if_missing);
+                assert!(args.apply);
+                assert_eq!(args.scan.template, "{orig_name}");
+                assert_eq!(
+                    args.scan.exclude,
+                    vec!["-NR".to_string(), "-DxO".to_string()]
+                );
+                assert!(!args.scan.dedupe_same_maker);
+                assert!(args.apply_options.backup_originals);
+                assert_eq!(
+                    args.apply_options.backup_dir.as_deref(),
+                    Some("/mnt/nas/backups")
+                );
+                assert!(args.apply_options.backup_zip);
+                assert!(!args.apply_options.trash_originals);
+                assert_eq!(args.apply_options.copy_to.as_deref(), Some("/tmp/export"));
+                assert_eq!(args.scan.min_rating, Some(3));
+                assert_eq!(args.scan.min_file_size, 20000);
+                assert!(args.scan.include_videos);
+                assert!(args.scan.raw_primary);
+                assert_eq!(
+                    args.scan.primary_ext,
+                    vec!["jpg".to_string(), "webp".to_string()]
+                );
+                assert_eq!(args.scan.raw_ext, vec!["nef".to_string()]);
+                assert_eq!(args.scan.match_raw_by_time, Some(1000));
+                assert!(args.scan.match_raw_by_id);
+                assert_eq!(args.scan.progress, Some(ProgressFormat::Text));
+                assert!(args.scan.strict_metadata);
+                assert!(args.scan.changed_only);
+                assert!(args.scan.rename_companions);
+                assert_eq!(args.apply_options.on_collision, OnCollision::Suffix);
+                assert!(matches!(args.output, OutputFormat::Json));
+            }
+            _ => panic!("rename command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_rename_ndjson_output() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--output",
+            "ndjson",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Rename(args) => assert!(matches!(args.output, OutputFormat::Ndjson)),
+            _ => panic!("rename command expected"),
+        }
+    }
+
+    #[test]
+    fn markdown_cell_escapes_table_syntax() {
+        assert_eq!(markdown_cell("a|b"), "a\\|b");
+        assert_eq!(markdown_cell("line\nbreak"), "line break");
+    }
+
+    #[test]
+    fn parse_rename_json_progress() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--progress=json",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Rename(args) => assert_eq!(args.scan.progress, Some(ProgressFormat::Json)),
+            _ => panic!("rename command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_progress_without_value_keeps_following_path() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "stats", "--progress", "/tmp/jpg"])
+            .expect("parse should succeed");
+        match cli.command {
+            Commands::Stats(args) => {
+                assert_eq!(args.progress, Some(ProgressFormat::Text));
+                assert_eq!(args.path, "/tmp/jpg");
+            }
+            _ => panic!("stats command expected"),
+        }
+    }
+
+    #[test]
+    fn table_rows_use_base_names_and_flag_collisions() {
+        let original = Path::new("/photos/IMG_0001.JPG");
+        assert_eq!(
+            target_label(original, Path::new("/photos/NEW.JPG")),
+            "NEW.JPG"
+        );
+        assert_eq!(
+            target_label(original, Path::new("/photos/2024/NEW.JPG")),
+            Path::new("2024").join("NEW.JPG").display().to_string()
+        );
+
+        assert_eq!(row_kind(false, original, "IMG_0001"), RowKind::Unchanged);
+        assert_eq!(
+            row_kind(true, Path::new("/photos/NEW.JPG"), "NEW"),
+            RowKind::Changed
+        );
+        assert_eq!(
+            row_kind(true, Path::new("/photos/NEW_001.JPG"), "NEW"),
+            RowKind::Collision
+        );
+    }
+
+    #[test]
+    fn parse_inspect_command() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "inspect",
+            "/tmp/jpg/DSC00001.JPG",
+            "--raw-input",
+            "/tmp/raw",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Inspect(args) => {
+                assert_eq!(args.path, "/tmp/jpg/DSC00001.JPG");
+                assert_eq!(args.raw_input.as_deref(), Some("/tmp/raw"));
+                assert_eq!(args.template, DEFAULT_TEMPLATE);
+            }
+            _ => panic!("inspect command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_timezone_and_utc_flags() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--timezone",
+            "+09:00",
+            "--utc",
+            "/tmp/jpg",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Rename(args) => {
+                let zone = date_zone(args.scan.timezone.as_deref(), args.scan.utc);
+                assert_eq!(zone.timezone.map(|tz| tz.local_minus_utc()), Some(9 * 3600));
+                assert!(zone.utc);
+            }
+            _ => panic!("rename command expected"),
+        }
+
+        assert!(Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--timezone",
+            "Tokyo",
+            "/tmp/jpg",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parse_prefer_gps_time_for_inspect_and_stats() {
+        for command in ["inspect", "stats"] {
+            let cli = Cli::try_parse_from([
+                "fphoto-renamer-cli",
+                command,
+                "--prefer-gps-time",
+                "/tmp/jpg",
+            ])
+            .expect("parse should succeed");
+            let prefer_gps_time = match cli.command {
+                Commands::Inspect(args) => args.prefer_gps_time,
+                Commands::Stats(args) => args.prefer_gps_time,
+                _ => panic!("inspect or stats command expected"),
+            };
+            assert!(prefer_gps_time);
+        }
+    }
+
+    #[test]
+    fn parse_shift_time_flags() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--shift-time",
+            "-1h3m",
+            "--shift-time",
+            "X-T5=+30s",
+            "/tmp/jpg",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Rename(args) => {
+                let shifts = &args.scan.shift_time;
+                assert_eq!(shifts.len(), 2);
+                assert_eq!(shifts[0].camera_model, None);
+                assert_eq!(shifts[0].offset.num_seconds(), -3780);
+                assert_eq!(shifts[1].camera_model.as_deref(), Some("X-T5"));
+                assert_eq!(shifts[1].offset.num_seconds(), 30);
+            }
+            _ => panic!("rename command expected"),
+        }
+
+        for command in ["inspect", "stats"] {
+            let cli = Cli::try_parse_from([
+                "fphoto-renamer-cli",
+                command,
+                "--shift-time",
+                "-1h",
+                "/tmp/jpg",
+            ])
+            .expect("parse should succeed");
+            let shifts = match cli.command {
+                Commands::Inspect(args) => args.shift_time,
+                Commands::Stats(args) => args.shift_time,
+                _ => panic!("inspect or stats command expected"),
+            };
+            assert_eq!(shifts.len(), 1);
+            assert_eq!(shifts[0].offset.num_seconds(), -3600);
+        }
+
+        assert!(Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--shift-time",
+            "1 hour",
+            "/tmp/jpg",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parse_recursive_and_include_hidden_flags() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--recursive",
+            "--include-hidden=false",
+            "/tmp/jpg",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Rename(args) => {
+                assert_eq!(args.scan.recursive, Some(true));
+                assert_eq!(args.scan.include_hidden, Some(false));
+                assert_eq!(args.scan.paths, vec!["/tmp/jpg".to_string()]);
+            }
+            _ => panic!("rename command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_max_filename_len_is_range_checked() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "/tmp/jpg",
+            "--max-filename-len",
+            "200",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Rename(args) => assert_eq!(args.scan.max_filename_len, Some(200)),
+            _ => panic!("rename command expected"),
+        }
+
+        assert!(Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "/tmp/jpg",
+            "--max-filename-len",
+            "300",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parse_stats_command() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "stats",
+            "/tmp/jpg",
+            "--min-rating",
+            "3",
+            "--json",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Stats(args) => {
+                assert_eq!(args.path, "/tmp/jpg");
+                assert_eq!(args.min_rating, Some(3));
+                assert!(args.json);
+                assert_eq!(args.progress, None);
+            }
+            _ => panic!("stats command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_config_set_accepts_multiple_values() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "config",
+            "set",
+            "exclude_strings",
+            "-NR",
+            "-DxO",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Config(config) => match config.action {
+                ConfigAction::Set { key, values } => {
+                    assert_eq!(key, "exclude_strings");
+                    assert_eq!(values, vec!["-NR".to_string(), "-DxO".to_string()]);
+                }
+                _ => panic!("config set expected"),
+            },
+            _ => panic!("config command expected"),
+        }
+    }
 
-fn cmd_config_show() -> Result<()> {
-    let config = load_config()?;
-    let paths = app_paths()?;
-    println!("設定ファイル: {}", paths.config_path.display());
-    println!("{}", toml::to_string_pretty(&config)?);
-    Ok(())
-}
+    #[test]
+    fn parse_config_import_defaults_to_merge() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "config", "import", "cfg.toml"])
+            .expect("parse should succeed");
+        match cli.command {
+            Commands::Config(config) => match config.action {
+                ConfigAction::Import { file, overwrite } => {
+                    assert_eq!(file, "cfg.toml");
+                    assert!(!overwrite);
+                }
+                _ => panic!("config import expected"),
+            },
+            _ => panic!("config command expected"),
+        }
+    }
 
-fn print_table(plan: &fphoto_renamer_core::RenamePlan) {
-    println!("元ファイル -> 新ファイル (source)");
-    for candidate in &plan.candidates {
-        println!(
-            "{} -> {} ({})",
-            candidate.original_path.display(),
-            candidate.target_path.display(),
-            candidate.source_label
+    #[test]
+    fn complete_template_suggests_tokens_after_open_brace() {
+        let values = complete_template(OsStr::new("{year}_{ca"))
+            .into_iter()
+            .map(|candidate| candidate.get_value().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec!["{year}_{camera_maker}", "{year}_{camera_model}"]
         );
+        assert!(complete_template(OsStr::new("{year}_")).is_empty());
     }
 
-    println!(
-        "\n集計: scanned={} jpg={} non_jpg_skip={} unchanged={}",
-        plan.stats.scanned_files,
-        plan.stats.jpg_files,
-        plan.stats.skipped_non_jpg,
-        plan.stats.unchanged
-    );
-}
-
-#[cfg(test)]
-mod tests {
-    use super::{Cli, Commands, OutputFormat};
-    use clap::error::ErrorKind;
-    use clap::Parser;
-    use fphoto_renamer_core::DEFAULT_TEMPLATE;
+    #[test]
+    fn parse_completions_shell() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "completions", "zsh"])
+            .expect("parse should succeed");
+        match cli.command {
+            Commands::Completions(args) => assert_eq!(args.shell, Shell::Zsh),
+            _ => panic!("completions command expected"),
+        }
+    }
 
     #[test]
-    fn parse_rename_defaults() {
-        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "rename", "--jpg-input", "/tmp/jpg"])
+    fn parse_undo_list_and_id() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "undo", "list"])
             .expect("parse should succeed");
+        match cli.command {
+            Commands::Undo(args) => assert!(matches!(args.action, Some(UndoAction::List))),
+            _ => panic!("undo command expected"),
+        }
 
+        let cli =
+            Cli::try_parse_from(["fphoto-renamer-cli", "undo", "--id", "20240101-120000-000"])
+                .expect("parse should succeed");
         match cli.command {
-            Commands::Rename(args) => {
-                assert_eq!(args.jpg_input, vec!["/tmp/jpg".to_string()]);
-                assert_eq!(args.raw_input, None);
-                assert!(!args.raw_parent_if_missing);
-                assert!(!args.apply);
-                assert_eq!(args.template, DEFAULT_TEMPLATE);
-                assert!(args.exclude.is_empty());
-                assert!(args.dedupe_same_maker);
-                assert!(!args.backup_originals);
-                assert!(matches!(args.output, OutputFormat::Table));
+            Commands::Undo(args) => {
+                assert!(args.action.is_none());
+                assert_eq!(args.id.as_deref(), Some("20240101-120000-000"));
             }
-            _ => panic!("rename command expected"),
+            _ => panic!("undo command expected"),
         }
+
+        let err = Cli::try_parse_from(["fphoto-renamer-cli", "undo", "--id", "x", "--dry-run"])
+            .expect_err("--id conflicts with --dry-run");
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
     }
 
     #[test]
-    fn parse_rename_with_explicit_values() {
+    fn parse_global_verbosity_flags() {
         let cli = Cli::try_parse_from([
             "fphoto-renamer-cli",
             "rename",
             "--jpg-input",
             "/tmp/jpg",
-            "--raw-input",
-            "/tmp/raw",
-            "--raw-parent-if-missing",
-            "--apply",
-            "--template",
-            "{orig_name}",
-            "--exclude",
-            "-NR",
-            "--exclude",
-            "-DxO",
-            "--dedupe-same-maker=false",
-            "--backup-originals",
-            "--output",
-            "json",
+            "-vv",
         ])
         .expect("parse should succeed");
+        assert_eq!(cli.log_level(), log::LevelFilter::Debug);
+
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "--quiet", "redo"])
+            .expect("parse should succeed");
+        assert_eq!(cli.log_level(), log::LevelFilter::Error);
 
+        assert!(Cli::try_parse_from(["fphoto-renamer-cli", "-v", "-q", "redo"]).is_err());
+    }
+
+    #[test]
+    fn parse_apply_bar_progress() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "apply",
+            "--plan",
+            "plan.json",
+            "--progress=bar",
+        ])
+        .expect("parse should succeed");
         match cli.command {
-            Commands::Rename(args) => {
-                assert_eq!(args.jpg_input, vec!["/tmp/jpg".to_string()]);
-                assert_eq!(args.raw_input.as_deref(), Some("/tmp/raw"));
-                assert!(args.raw_parent_if_missing);
-                assert!(args.apply);
-                assert_eq!(args.template, "{orig_name}");
-                assert_eq!(args.exclude, vec!["-NR".to_string(), "-DxO".to_string()]);
-                assert!(!args.dedupe_same_maker);
-                assert!(args.backup_originals);
-                assert!(matches!(args.output, OutputFormat::Json));
-            }
-            _ => panic!("rename command expected"),
+            Commands::Apply(args) => assert_eq!(args.progress, Some(ProgressFormat::Bar)),
+            _ => panic!("apply command expected"),
         }
     }
 
@@ -355,7 +2519,7 @@ mod tests {
         match cli.command {
             Commands::Rename(args) => {
                 assert_eq!(
-                    args.jpg_input,
+                    args.scan.jpg_input,
                     vec!["/tmp/a.JPG".to_string(), "/tmp/b.JPG".to_string()]
                 );
             }
@@ -363,6 +2527,320 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_rename_accepts_positional_paths_mixed_with_jpg_input() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/a",
+            "/tmp/b/IMG_0001.JPG",
+            "/tmp/c",
+        ])
+        .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Rename(args) => {
+                assert_eq!(
+                    args.scan.jpg_inputs().expect("inputs"),
+                    vec![
+                        PathBuf::from("/tmp/a"),
+                        PathBuf::from("/tmp/b/IMG_0001.JPG"),
+                        PathBuf::from("/tmp/c"),
+                    ]
+                );
+            }
+            _ => panic!("rename command expected"),
+        }
+
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "rename", "/tmp/a", "/tmp/b"])
+            .expect("positional paths alone should be accepted");
+        match cli.command {
+            Commands::Rename(args) => {
+                assert_eq!(args.scan.jpg_inputs().expect("inputs").len(), 2)
+            }
+            _ => panic!("rename command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_rename_files_from_stdin_with_null_separator() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "rename", "--files-from", "-", "-0"])
+            .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Rename(args) => {
+                assert_eq!(args.scan.files_from.as_deref(), Some("-"));
+                assert!(args.scan.null_separated);
+                assert!(args.scan.jpg_input.is_empty());
+            }
+            _ => panic!("rename command expected"),
+        }
+
+        let err = Cli::try_parse_from(["fphoto-renamer-cli", "rename", "/tmp/a", "-0"])
+            .expect_err("-0 requires --files-from");
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn parse_file_list_handles_newlines_and_nul_separators() {
+        assert_eq!(
+            parse_file_list("/tmp/a b.JPG\r\n\n/tmp/c.JPG\n", false),
+            vec![PathBuf::from("/tmp/a b.JPG"), PathBuf::from("/tmp/c.JPG")]
+        );
+        assert_eq!(
+            parse_file_list("/tmp/line\nbreak.JPG\0/tmp/d.JPG\0", true),
+            vec![
+                PathBuf::from("/tmp/line\nbreak.JPG"),
+                PathBuf::from("/tmp/d.JPG")
+            ]
+        );
+    }
+
+    #[test]
+    fn watch_event_paths_keeps_only_created_or_modified_files() {
+        let created = Event::new(EventKind::Create(CreateKind::File))
+            .add_path(PathBuf::from("/ingest/DSCF0001.JPG"));
+        assert_eq!(
+            watch_event_paths(Ok(created)),
+            vec![PathBuf::from("/ingest/DSCF0001.JPG")]
+        );
+        let removed = Event::new(EventKind::Remove(RemoveKind::File))
+            .add_path(PathBuf::from("/ingest/DSCF0002.JPG"));
+        assert!(watch_event_paths(Ok(removed)).is_empty());
+    }
+
+    #[test]
+    fn parse_watch_uses_config_template_unless_given() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "watch", "/ingest", "--apply"])
+            .expect("parse should succeed");
+        let Commands::Watch(args) = cli.command else {
+            panic!("watch command expected");
+        };
+        assert_eq!(args.path, "/ingest");
+        assert_eq!(args.template, None);
+        assert_eq!(args.debounce_ms, 2000);
+        assert!(args.apply);
+    }
+
+    #[test]
+    fn exit_codes_distinguish_invalid_input_and_empty_plans() {
+        let err =
+            invalid_input(anyhow!("テンプレートが不正です")).context("プラン作成に失敗しました");
+        assert_eq!(exit_code_for_error(&err), 2);
+        assert_eq!(exit_code_for_error(&anyhow!("I/O エラー")), 1);
+        assert_eq!(changes_outcome(0) as u8, 3);
+        assert_eq!(changes_outcome(5), Outcome::Success);
+        assert_eq!(Outcome::PartialFailure as u8, 4);
+    }
+
+    #[test]
+    fn parse_exclusion_file_skips_comments_and_blank_lines() {
+        assert_eq!(
+            parse_exclusion_file("# DxO\r\n-DxO_DeepPRIME\r\n\n  -NR  \n#-HDR\n"),
+            vec!["-DxO_DeepPRIME".to_string(), "-NR".to_string()]
+        );
+    }
+
+    #[test]
+    fn scan_exclusions_merge_config_and_flags_without_duplicates() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "/tmp/jpg",
+            "--exclude",
+            "-NR",
+            "--exclude",
+            "-HDR",
+        ])
+        .expect("parse should succeed");
+        let Commands::Rename(args) = cli.command else {
+            panic!("rename command expected");
+        };
+
+        let merged = args
+            .scan
+            .exclusions(&["-DxO".to_string(), "-NR".to_string()])
+            .expect("merge exclusions");
+        assert_eq!(
+            merged,
+            vec!["-DxO".to_string(), "-NR".to_string(), "-HDR".to_string()]
+        );
+    }
+
+    #[test]
+    fn parse_rename_hardlink_to_selects_hardlink_mode() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--hardlink-to",
+            "/tmp/links",
+        ])
+        .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Rename(args) => {
+                assert_eq!(
+                    apply_mode(&args.apply_options),
+                    ApplyMode::HardlinkTo(PathBuf::from("/tmp/links"))
+                );
+            }
+            _ => panic!("rename command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_copy_to_conflicts_with_hardlink_to() {
+        let err = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--copy-to",
+            "/tmp/export",
+            "--hardlink-to",
+            "/tmp/links",
+        ])
+        .expect_err("copy and hardlink modes are exclusive");
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn parse_trash_originals_conflicts_with_backup_zip() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--trash-originals",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Rename(args) => assert!(args.apply_options.trash_originals),
+            _ => panic!("rename command expected"),
+        }
+
+        let err = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "rename",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--trash-originals",
+            "--backup-zip",
+        ])
+        .expect_err("trash and zip backups are exclusive");
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn parse_undo_dry_run() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "undo", "--dry-run"])
+            .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Undo(args) => assert!(args.dry_run),
+            _ => panic!("undo command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_undo_selected_files() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "undo",
+            "--file",
+            "/tmp/jpg/A.JPG",
+            "--file",
+            "/tmp/jpg/B.JPG",
+        ])
+        .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Undo(args) => {
+                assert_eq!(args.files, vec!["/tmp/jpg/A.JPG", "/tmp/jpg/B.JPG"]);
+                assert!(!args.dry_run);
+            }
+            _ => panic!("undo command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_cleanup_backups() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "cleanup-backups",
+            "--keep-sessions",
+            "3",
+            "--keep-days",
+            "30",
+        ])
+        .expect("parse should succeed");
+
+        match cli.command {
+            Commands::CleanupBackups(args) => {
+                assert_eq!(args.keep_sessions, Some(3));
+                assert_eq!(args.keep_days, Some(30));
+            }
+            _ => panic!("cleanup-backups command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_plan_and_apply_plan_file() {
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "plan",
+            "--jpg-input",
+            "/tmp/jpg",
+            "--out",
+            "/tmp/plan.json",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Plan(args) => {
+                assert_eq!(args.scan.jpg_input, vec!["/tmp/jpg".to_string()]);
+                assert_eq!(args.out, "/tmp/plan.json");
+            }
+            _ => panic!("plan command expected"),
+        }
+
+        let cli = Cli::try_parse_from([
+            "fphoto-renamer-cli",
+            "apply",
+            "--plan",
+            "/tmp/plan.json",
+            "--backup-zip",
+        ])
+        .expect("parse should succeed");
+        match cli.command {
+            Commands::Apply(args) => {
+                assert_eq!(args.plan, "/tmp/plan.json");
+                assert!(args.apply_options.backup_zip);
+            }
+            _ => panic!("apply command expected"),
+        }
+    }
+
+    #[test]
+    fn parse_redo() {
+        let cli =
+            Cli::try_parse_from(["fphoto-renamer-cli", "redo"]).expect("parse should succeed");
+        assert!(matches!(cli.command, Commands::Redo));
+    }
+
+    #[test]
+    fn parse_recover_with_rollback() {
+        let cli = Cli::try_parse_from(["fphoto-renamer-cli", "recover", "--rollback"])
+            .expect("parse should succeed");
+
+        match cli.command {
+            Commands::Recover(args) => assert!(args.rollback),
+            _ => panic!("recover command expected"),
+        }
+    }
+
     #[test]
     fn parse_rename_missing_jpg_input_fails() {
         let err = Cli::try_parse_from(["fphoto-renamer-cli", "rename"])
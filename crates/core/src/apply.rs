@@ -1,12 +1,15 @@
-use crate::config::{app_paths, AppPaths};
-use crate::planner::{RenameCandidate, RenamePlan};
+use crate::config::{app_paths, AppPaths, BackupRetention};
+use crate::constants::MAX_UNDO_SESSIONS;
+use crate::planner::{FileFingerprint, ProgressSink, RenameCandidate, RenamePlan};
 use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Local};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,35 +23,327 @@ struct UndoLog {
     jpg_roots: Vec<PathBuf>,
     #[serde(default)]
     backup_paths: Vec<PathBuf>,
+    #[serde(default)]
+    follow_symlinks: bool,
+    #[serde(default)]
+    companion_roots: Vec<PathBuf>,
+    #[serde(default)]
+    created_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    output_dir: Option<PathBuf>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    backup_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct RenameOperation {
     from: PathBuf,
     to: PathBuf,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    hash: Option<String>,
+    #[serde(default)]
+    original_modified: Option<DateTime<Local>>,
+    #[serde(default)]
+    original_created: Option<DateTime<Local>>,
+}
+
+impl RenameOperation {
+    fn new(from: PathBuf, to: PathBuf) -> Self {
+        Self {
+            from,
+            to,
+            size: None,
+            hash: None,
+            original_modified: None,
+            original_created: None,
+        }
+    }
+
+    // リネーム後のサイズとハッシュを記録し、取り消し時に差し替えを検出できるようにする
+    fn recorded(from: PathBuf, to: PathBuf) -> Self {
+        let (size, hash) = match content_digest(&to) {
+            Ok((size, hash)) => (Some(size), Some(hash)),
+            Err(_) => (None, None),
+        };
+        Self {
+            from,
+            to,
+            size,
+            hash,
+            original_modified: None,
+            original_created: None,
+        }
+    }
+
+    // 撮影日時で上書きする前の日時を残し、取り消し時に戻せるようにする
+    fn with_original_times(mut self) -> Self {
+        if let Ok(metadata) = fs::metadata(&self.to) {
+            self.original_modified = metadata.modified().ok().map(DateTime::from);
+            self.original_created = metadata.created().ok().map(DateTime::from);
+        }
+        self
+    }
+
+    // 記録のない旧形式のログや、既に存在しないファイルは変更なしとみなす
+    fn content_changed(&self) -> bool {
+        let Some(size) = self.size else {
+            return false;
+        };
+        if !self.to.exists() {
+            return false;
+        }
+        match content_digest(&self.to) {
+            Ok((current_size, current_hash)) => {
+                current_size != size || self.hash.as_ref().is_some_and(|hash| *hash != current_hash)
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+const CONTENT_HASH_SAMPLE_BYTES: u64 = 64 * 1024;
+
+// 大きなRAWでも速いよう、先頭と末尾だけを FNV-1a でハッシュする
+fn content_digest(path: &Path) -> Result<(u64, String)> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("ファイルを開けませんでした: {}", path.display()))?;
+    let size = file.metadata()?.len();
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    feed(&size.to_le_bytes());
+
+    let mut buffer = Vec::new();
+    (&mut file)
+        .take(CONTENT_HASH_SAMPLE_BYTES)
+        .read_to_end(&mut buffer)?;
+    feed(&buffer);
+    if size > CONTENT_HASH_SAMPLE_BYTES {
+        let tail_start = size
+            .saturating_sub(CONTENT_HASH_SAMPLE_BYTES)
+            .max(CONTENT_HASH_SAMPLE_BYTES);
+        file.seek(SeekFrom::Start(tail_start))?;
+        buffer.clear();
+        file.read_to_end(&mut buffer)?;
+        feed(&buffer);
+    }
+    Ok((size, format!("{hash:016x}")))
 }
 
 #[derive(Debug, Clone)]
 struct ValidatedUndoLog {
     operations: Vec<RenameOperation>,
     jpg_roots: Vec<PathBuf>,
+    backup_roots: Vec<PathBuf>,
     backup_paths: Vec<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+    remove_targets: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApplyResult {
     pub applied: usize,
     pub unchanged: usize,
+    #[serde(default)]
+    pub files: Vec<ApplyFileResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ApplyOutcome {
+    Renamed,
+    Unchanged,
+    Skipped,
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ApplyFileResult {
+    pub original_path: PathBuf,
+    pub target_path: PathBuf,
+    pub outcome: ApplyOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyMode {
+    #[default]
+    Rename,
+    CopyTo(PathBuf),
+    HardlinkTo(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    #[default]
+    Fail,
+    Suffix,
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupFormat {
+    #[default]
+    Files,
+    Zip,
+    Trash,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ApplyOptions {
     pub backup_originals: bool,
+    #[serde(default)]
+    pub reject_stale_plan: bool,
+    #[serde(default)]
+    pub mode: ApplyMode,
+    #[serde(default)]
+    pub continue_on_error: bool,
+    #[serde(default)]
+    pub collision_policy: CollisionPolicy,
+    #[serde(default)]
+    pub backup_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub backup_format: BackupFormat,
+    #[serde(default)]
+    pub backup_retention: BackupRetention,
+    #[serde(default)]
+    pub clear_readonly: bool,
+    #[serde(default)]
+    pub set_file_times: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StaleReason {
+    Missing,
+    SizeChanged {
+        expected: u64,
+        actual: u64,
+    },
+    ModifiedChanged {
+        expected: Option<i64>,
+        actual: Option<i64>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StaleEntry {
+    pub path: PathBuf,
+    pub reason: StaleReason,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PlanValidationReport {
+    pub checked: usize,
+    pub stale: Vec<StaleEntry>,
+}
+
+impl PlanValidationReport {
+    pub fn is_fresh(&self) -> bool {
+        self.stale.is_empty()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UndoResult {
     pub restored: usize,
+    #[serde(default)]
+    pub modified: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UndoOptions {
+    pub allow_modified: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoPreview {
+    pub session_id: String,
+    pub created_at: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub entries: Vec<UndoPreviewEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UndoPreviewEntry {
+    pub current_path: PathBuf,
+    pub restore_path: PathBuf,
+    pub missing: bool,
+    #[serde(default)]
+    pub modified: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedoResult {
+    pub reapplied: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoSession {
+    pub id: String,
+    pub created_at: Option<String>,
+    pub file_count: usize,
+    pub roots: Vec<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+}
+
+const LEGACY_UNDO_SESSION_ID: &str = "legacy";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalPhase {
+    Staging,
+    Finalizing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApplyJournal {
+    phase: JournalPhase,
+    entries: Vec<StagedRename>,
+    undo: UndoLog,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingApply {
+    pub phase: JournalPhase,
+    pub entries: usize,
+    pub leftover_temp_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoverAction {
+    Complete,
+    Rollback,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupCleanupResult {
+    pub removed_sessions: usize,
+    pub removed_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackupSessionRecord {
+    created_at: String,
+    backup_roots: Vec<PathBuf>,
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoverResult {
+    pub action: RecoverAction,
+    pub recovered: usize,
 }
 
 pub fn apply_plan(plan: &RenamePlan) -> Result<ApplyResult> {
@@ -60,177 +355,974 @@ pub fn apply_plan_with_options(plan: &RenamePlan, options: &ApplyOptions) -> Res
     apply_plan_with_options_with_paths(plan, options, &paths)
 }
 
+pub fn apply_plan_with_progress(
+    plan: &RenamePlan,
+    options: &ApplyOptions,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<ApplyResult> {
+    let paths = app_paths()?;
+    apply_plan_with_progress_with_paths(plan, options, progress, &paths)
+}
+
+pub fn apply_plan_subset(
+    plan: &RenamePlan,
+    indices: &[usize],
+    options: &ApplyOptions,
+) -> Result<ApplyResult> {
+    let paths = app_paths()?;
+    apply_plan_subset_with_paths(plan, indices, options, &paths)
+}
+
+// 選択した行だけのプランとして適用し、選択外の行は結果にスキップとして残す
+fn apply_plan_subset_with_paths(
+    plan: &RenamePlan,
+    indices: &[usize],
+    options: &ApplyOptions,
+    paths: &AppPaths,
+) -> Result<ApplyResult> {
+    let mut selected = indices.to_vec();
+    selected.sort_unstable();
+    selected.dedup();
+    if let Some(index) = selected
+        .iter()
+        .find(|index| **index >= plan.candidates.len())
+    {
+        bail!("選択された行番号が範囲外です: {index}");
+    }
+
+    let mut subset = plan.clone();
+    subset.candidates = selected
+        .iter()
+        .map(|index| plan.candidates[*index].clone())
+        .collect();
+
+    let result = apply_plan_with_options_with_paths(&subset, options, paths)?;
+    let mut subset_files = result.files.into_iter();
+    let files = plan
+        .candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| {
+            if selected.binary_search(&index).is_ok() {
+                subset_files.next()
+            } else {
+                Some(ApplyFileResult {
+                    original_path: candidate.original_path.clone(),
+                    target_path: candidate.target_path.clone(),
+                    outcome: ApplyOutcome::Skipped,
+                })
+            }
+        })
+        .collect();
+
+    Ok(ApplyResult {
+        applied: result.applied,
+        unchanged: result.unchanged,
+        files,
+    })
+}
+
 fn apply_plan_with_options_with_paths(
     plan: &RenamePlan,
     options: &ApplyOptions,
     paths: &AppPaths,
 ) -> Result<ApplyResult> {
+    apply_plan_with_progress_with_paths(plan, options, None, paths)
+}
+
+fn apply_plan_with_progress_with_paths(
+    plan: &RenamePlan,
+    options: &ApplyOptions,
+    progress: Option<&dyn ProgressSink>,
+    paths: &AppPaths,
+) -> Result<ApplyResult> {
+    let _lock = match &options.mode {
+        ApplyMode::Rename => acquire_apply_lock(paths, &plan_jpg_roots(plan))?,
+        // 書き出しでは元のフォルダに何も作らない (書き込めないSDカードでも書き出せるように)。
+        // 出力先を作れない場合は書き出し側で具体的なエラーにする
+        ApplyMode::CopyTo(output_dir) | ApplyMode::HardlinkTo(output_dir) => {
+            let _ = fs::create_dir_all(output_dir);
+            let _lock = acquire_apply_lock(paths, std::slice::from_ref(output_dir))?;
+            return export_plan_to_output_dir(plan, options, output_dir, progress, paths);
+        }
+    };
+
     let candidates: Vec<&RenameCandidate> = plan.candidates.iter().filter(|c| c.changed).collect();
     if candidates.is_empty() {
         return Ok(ApplyResult {
             applied: 0,
             unchanged: plan.candidates.len(),
+            files: plan_file_results(plan, &HashMap::new()),
         });
     }
 
+    ensure_no_pending_journal(paths)?;
+    ensure_plan_is_fresh(plan, options)?;
     validate_apply_candidates(plan, &candidates)?;
 
+    let mut overrides = HashMap::<PathBuf, ApplyFileResult>::new();
+    let resolved = resolve_apply_collisions(&candidates, options.collision_policy, &mut overrides)?;
+    let candidates: Vec<&RenameCandidate> =
+        resolved.iter().map(|(candidate, _)| *candidate).collect();
+
     let backup_paths = if options.backup_originals {
-        backup_original_files(plan, &candidates)?
+        match options.backup_format {
+            BackupFormat::Files => {
+                backup_original_files(plan, &candidates, options.backup_dir.as_deref())?
+            }
+            BackupFormat::Zip => {
+                backup_original_files_to_zip(plan, &candidates, options.backup_dir.as_deref())?
+            }
+            // ゴミ箱へはリネーム完了後に送る
+            BackupFormat::Trash => Vec::new(),
+        }
     } else {
         Vec::new()
     };
 
-    let mut staged = Vec::<StagedRename>::with_capacity(candidates.len());
-    for (index, candidate) in candidates.iter().enumerate() {
-        let entry = StagedRename {
+    let planned: Vec<StagedRename> = resolved
+        .iter()
+        .enumerate()
+        .map(|(index, (candidate, target_path))| StagedRename {
             original_path: candidate.original_path.clone(),
-            target_path: candidate.target_path.clone(),
+            target_path: target_path.clone(),
             temp_path: temp_path_for(&candidate.original_path, index),
-        };
-        if let Err(err) = fs::rename(&entry.original_path, &entry.temp_path) {
+        })
+        .collect();
+
+    // 二段階リネームの前にジャーナルを書き、中断時に recover で完了/取り消しできるようにする
+    let mut journal = ApplyJournal {
+        phase: JournalPhase::Staging,
+        entries: planned.clone(),
+        undo: build_undo_log(&[], plan, options, &backup_paths, &[], None),
+    };
+    if let Err(err) = write_journal(&journal, paths) {
+        let err = err.context(
+            "取り消しログと同じ設定フォルダへ適用ジャーナルを保存できないため、適用を中止しました",
+        );
+        if let Err(cleanup_err) =
+            cleanup_created_backups_after_persist_failure(plan, options, &backup_paths)
+        {
+            return Err(err.context(format!(
+                "ジャーナル保存失敗後のバックアップ掃除にも失敗しました: {cleanup_err}"
+            )));
+        }
+        return Err(err);
+    }
+
+    // 読み取り専用属性は適用中だけ外し、関数を抜けるときに元の属性へ戻す
+    let _readonly_guard = if options.clear_readonly {
+        clear_readonly_attributes(&planned)?
+    } else {
+        ReadonlyGuard::default()
+    };
+
+    let mut staged = Vec::<StagedRename>::with_capacity(planned.len());
+    // 元の名前に残った (戻した) ファイル。これらを変更先にする候補は上書きになるため一緒に失敗させる
+    let mut failed_originals = HashSet::<PathBuf>::new();
+    for entry in planned {
+        if let Err(err) = move_file(&entry.original_path, &entry.temp_path) {
             let stage_err = anyhow::Error::from(err).context(format!(
-                "一時リネームに失敗しました: {} -> {}",
+                "一時リネームに失敗しました: {} -> {}{}",
                 entry.original_path.display(),
-                entry.temp_path.display()
+                entry.temp_path.display(),
+                readonly_hint(&entry.original_path)
             ));
+            if options.continue_on_error {
+                let error = format!("{stage_err:#}");
+                overrides.insert(entry.original_path.clone(), failed_result(&entry, error));
+                failed_originals.insert(entry.original_path.clone());
+                continue;
+            }
             if let Err(rollback_err) = rollback_staged_to_original_paths(&staged) {
                 return Err(stage_err.context(format!(
                     "一時リネーム失敗後のロールバックにも失敗しました: {rollback_err}"
                 )));
             }
-            return Err(stage_err);
+            return Err(discard_journal_after_rollback(stage_err, paths));
         }
         staged.push(entry);
     }
 
-    let mut operations = Vec::with_capacity(candidates.len());
+    // 失敗を許容する場合、フォルダ作成は最終リネームと合わせて1件ずつ行う
+    let mut created_dirs = Vec::<PathBuf>::new();
+    if !options.continue_on_error {
+        if let Err(err) = create_target_dirs(&staged, &mut created_dirs) {
+            let rollback_result = rollback_staged_to_original_paths(&staged)
+                .and_then(|()| remove_created_dirs(&created_dirs));
+            if let Err(rollback_err) = rollback_result {
+                return Err(err.context(format!(
+                    "フォルダ作成失敗後のロールバックにも失敗しました: {rollback_err}"
+                )));
+            }
+            return Err(discard_journal_after_rollback(err, paths));
+        }
+    }
+
+    journal.phase = JournalPhase::Finalizing;
+    journal.entries = staged.clone();
+    journal.undo.created_dirs = created_dirs.clone();
+    if let Err(err) = write_journal(&journal, paths) {
+        let rollback_result = rollback_staged_to_original_paths(&staged)
+            .and_then(|()| remove_created_dirs(&created_dirs));
+        if let Err(rollback_err) = rollback_result {
+            return Err(err.context(format!(
+                "ジャーナル更新失敗後のロールバックにも失敗しました: {rollback_err}"
+            )));
+        }
+        return Err(discard_journal_after_rollback(err, paths));
+    }
+
+    let mut operations = Vec::<RenameOperation>::with_capacity(candidates.len());
+    // 変更先 -> staged の位置。失敗したファイルを戻す時に、元の名前を塞いでいる適用済みのファイルを探す
+    let mut finalized_targets = HashMap::<PathBuf, usize>::new();
     for (finalized, entry) in staged.iter().enumerate() {
-        if let Err(err) = fs::rename(&entry.temp_path, &entry.target_path) {
+        report_applied(progress, finalized, staged.len());
+        if options.continue_on_error {
+            let result = if failed_originals.contains(&entry.target_path) {
+                Err(anyhow::anyhow!(
+                    "変更先の {} は失敗したファイルの元の名前のため、変更しませんでした",
+                    entry.target_path.display()
+                ))
+            } else {
+                create_missing_parent_dirs(&entry.target_path, &mut created_dirs).and_then(|()| {
+                    move_file(&entry.temp_path, &entry.target_path).with_context(|| {
+                        format!(
+                            "最終リネームに失敗しました: {} -> {}",
+                            entry.temp_path.display(),
+                            entry.target_path.display()
+                        )
+                    })
+                })
+            };
+            if let Err(err) = result {
+                let restored = restore_failed_chain(
+                    &staged,
+                    finalized,
+                    &mut finalized_targets,
+                    &mut failed_originals,
+                );
+                for (index, restore_error) in restored {
+                    let failed = &staged[index];
+                    let error = if index == finalized {
+                        format!("{err:#}")
+                    } else {
+                        format!(
+                            "{} の失敗で元の名前へ戻すため、取り消しました",
+                            entry.original_path.display()
+                        )
+                    };
+                    let error = match &restore_error {
+                        Some(restore_error) => format!("{error}; {restore_error}"),
+                        None => error,
+                    };
+                    overrides.insert(failed.original_path.clone(), failed_result(failed, error));
+                    operations.retain(|operation| operation.from != failed.original_path);
+                    // 元に戻したファイルは、中断時の取り消し対象から外す
+                    if restore_error.is_none() {
+                        journal
+                            .entries
+                            .retain(|journaled| journaled.original_path != failed.original_path);
+                    }
+                }
+                if let Err(err) = write_journal(&journal, paths) {
+                    let rollback_result = rollback_continued_apply(&staged, &finalized_targets)
+                        .and_then(|()| remove_created_dirs(&created_dirs));
+                    if let Err(rollback_err) = rollback_result {
+                        return Err(err.context(format!(
+                            "ジャーナル更新失敗後のロールバックにも失敗しました: {rollback_err}"
+                        )));
+                    }
+                    return Err(discard_journal_after_rollback(err, paths));
+                }
+                continue;
+            }
+            finalized_targets.insert(entry.target_path.clone(), finalized);
+        } else if let Err(err) = move_file(&entry.temp_path, &entry.target_path) {
             let apply_err = anyhow::Error::from(err).context(format!(
                 "最終リネームに失敗しました: {} -> {}",
                 entry.temp_path.display(),
                 entry.target_path.display()
             ));
-            if let Err(rollback_err) = rollback_after_final_rename_failure(&staged, finalized) {
+            let rollback_result = rollback_after_final_rename_failure(&staged, finalized)
+                .and_then(|()| remove_created_dirs(&created_dirs));
+            if let Err(rollback_err) = rollback_result {
                 return Err(apply_err.context(format!(
                     "最終リネーム失敗後のロールバックにも失敗しました: {rollback_err}"
                 )));
             }
-            return Err(apply_err);
+            return Err(discard_journal_after_rollback(apply_err, paths));
         }
 
-        operations.push(RenameOperation {
-            from: entry.original_path.clone(),
-            to: entry.target_path.clone(),
+        let operation =
+            RenameOperation::recorded(entry.original_path.clone(), entry.target_path.clone());
+        operations.push(if options.set_file_times {
+            operation.with_original_times()
+        } else {
+            operation
         });
     }
 
-    if let Err(persist_err) = persist_undo(&operations, plan, options, &backup_paths, paths) {
-        let rollback_result = rollback_after_undo_persist_failure(&operations);
+    report_applied(progress, staged.len(), staged.len());
+
+    // 全件失敗した場合は、直前の取り消しログを上書きしない
+    if operations.is_empty() {
+        remove_created_dirs(&created_dirs)?;
+    } else if let Err(persist_err) = persist_undo(
+        &operations,
+        plan,
+        options,
+        &backup_paths,
+        &created_dirs,
+        None,
+        paths,
+    ) {
+        let rollback_result = rollback_after_undo_persist_failure(&operations)
+            .and_then(|()| remove_created_dirs(&created_dirs));
+        let rolled_back = rollback_result.is_ok();
         let backup_cleanup_result =
-            cleanup_created_backups_after_persist_failure(plan, &backup_paths);
-        return Err(compose_persist_failure_error(
-            persist_err,
-            rollback_result,
-            backup_cleanup_result,
-        ));
+            cleanup_created_backups_after_persist_failure(plan, options, &backup_paths);
+        let err =
+            compose_persist_failure_error(persist_err, rollback_result, backup_cleanup_result);
+        if rolled_back {
+            return Err(discard_journal_after_rollback(err, paths));
+        }
+        return Err(err);
+    }
+    remove_journal(paths)?;
+
+    if options.set_file_times {
+        let capture_dates: HashMap<&Path, DateTime<Local>> = candidates
+            .iter()
+            .map(|candidate| {
+                (
+                    candidate.original_path.as_path(),
+                    candidate.metadata.date.with_timezone(&Local),
+                )
+            })
+            .collect();
+        set_capture_times(&operations, &capture_dates)
+            .context("適用は完了しましたが、ファイル日時を撮影日時に揃えられませんでした")?;
+    }
+
+    if options.backup_originals
+        && options.backup_format == BackupFormat::Trash
+        && !operations.is_empty()
+    {
+        send_original_copies_to_trash(&operations)
+            .context("適用は完了しましたが、元ファイルのコピーをゴミ箱へ送れませんでした")?;
+    }
+
+    if !operations.is_empty() && !backup_paths.is_empty() {
+        record_backup_session(plan, options, &backup_paths, paths)
+            .and_then(|()| enforce_backup_retention(&options.backup_retention, Local::now(), paths))
+            .context("適用は完了しましたが、バックアップの保持期間の処理に失敗しました")?;
     }
 
     Ok(ApplyResult {
         applied: operations.len(),
-        unchanged: plan.candidates.len().saturating_sub(operations.len()),
+        unchanged: plan.candidates.len().saturating_sub(candidates.len()),
+        files: plan_file_results(plan, &overrides),
     })
 }
 
-#[derive(Debug, Clone)]
-struct StagedRename {
-    original_path: PathBuf,
-    target_path: PathBuf,
-    temp_path: PathBuf,
-}
-
-fn plan_jpg_roots(plan: &RenamePlan) -> Vec<PathBuf> {
-    if plan.jpg_roots.is_empty() {
-        return vec![plan.jpg_root.clone()];
+fn report_applied(progress: Option<&dyn ProgressSink>, done: usize, total: usize) {
+    if let Some(progress) = progress {
+        progress.files_applied(done, total);
     }
-    plan.jpg_roots.clone()
 }
 
-fn canonicalize_jpg_roots(raw_roots: &[PathBuf]) -> Result<Vec<PathBuf>> {
-    if raw_roots.is_empty() {
-        bail!("JPGルートが指定されていません");
+// 失敗したファイルを一時ファイルから元の名前へ戻す。元の名前へ既に別のファイルを移していれば、
+// そちらも連鎖の奥から順に元へ戻す。上書きはせず、戻せなかったファイルは理由と共に返す
+fn restore_failed_chain(
+    staged: &[StagedRename],
+    failed: usize,
+    finalized_targets: &mut HashMap<PathBuf, usize>,
+    failed_originals: &mut HashSet<PathBuf>,
+) -> Vec<(usize, Option<String>)> {
+    let mut chain = vec![failed];
+    while let Some(next) = finalized_targets.remove(&staged[chain[chain.len() - 1]].original_path) {
+        chain.push(next);
     }
 
-    let mut seen = HashSet::<PathBuf>::new();
-    let mut out = Vec::<PathBuf>::new();
-    for root in raw_roots {
-        let canonical = fs::canonicalize(root)
-            .with_context(|| format!("JPGルートを解決できませんでした: {}", root.display()))?;
-        if !canonical.is_dir() {
-            bail!("JPGルートがフォルダではありません: {}", canonical.display());
-        }
-        if seen.insert(canonical.clone()) {
-            out.push(canonical);
+    let mut restored = Vec::with_capacity(chain.len());
+    for &index in chain.iter().rev() {
+        let entry = &staged[index];
+        let from = if index == failed {
+            &entry.temp_path
+        } else {
+            &entry.target_path
+        };
+        match move_file_no_replace(from, &entry.original_path) {
+            Ok(()) => {
+                failed_originals.insert(entry.original_path.clone());
+                restored.push((index, None));
+            }
+            Err(err) if index == failed => {
+                failed_originals.insert(entry.original_path.clone());
+                restored.push((
+                    index,
+                    Some(format!(
+                        "元の名前へ戻せなかったため一時ファイルのまま残っています: {} ({err})",
+                        from.display()
+                    )),
+                ));
+            }
+            // 戻せなかった適用済みのファイルは、変更後の名前のまま適用済みとして残す
+            Err(err) => {
+                log::warn!(
+                    "取り消せなかったため変更後の名前のまま残します: {} ({err})",
+                    from.display()
+                );
+                finalized_targets.insert(entry.target_path.clone(), index);
+            }
         }
     }
-    Ok(out)
+    restored
 }
 
-fn path_within_any_root(path: &Path, roots: &[PathBuf]) -> bool {
-    roots.iter().any(|root| path.starts_with(root))
+fn failed_result(entry: &StagedRename, error: String) -> ApplyFileResult {
+    ApplyFileResult {
+        original_path: entry.original_path.clone(),
+        target_path: entry.target_path.clone(),
+        outcome: ApplyOutcome::Failed { error },
+    }
 }
 
-fn pick_most_specific_root<'a>(path: &Path, roots: &'a [PathBuf]) -> Option<&'a PathBuf> {
-    roots
+// 既定の結果はプラン通り。衝突回避・スキップ・失敗したファイルだけ差し替える
+fn plan_file_results(
+    plan: &RenamePlan,
+    overrides: &HashMap<PathBuf, ApplyFileResult>,
+) -> Vec<ApplyFileResult> {
+    plan.candidates
         .iter()
-        .filter(|root| path.starts_with(root))
-        .max_by_key(|root| root.components().count())
+        .map(|candidate| {
+            overrides
+                .get(&candidate.original_path)
+                .cloned()
+                .unwrap_or_else(|| ApplyFileResult {
+                    original_path: candidate.original_path.clone(),
+                    target_path: candidate.target_path.clone(),
+                    outcome: if candidate.changed {
+                        ApplyOutcome::Renamed
+                    } else {
+                        ApplyOutcome::Unchanged
+                    },
+                })
+        })
+        .collect()
 }
 
-fn validate_apply_candidates(plan: &RenamePlan, candidates: &[&RenameCandidate]) -> Result<()> {
-    let jpg_roots = canonicalize_jpg_roots(&plan_jpg_roots(plan))?;
-    let mut seen_original_paths = HashSet::<PathBuf>::new();
-    let mut seen_target_paths = HashSet::<PathBuf>::new();
-
+// プラン作成後にリネーム先へ現れたファイルは、衝突時の方針に従って扱う
+fn resolve_apply_collisions<'a>(
+    candidates: &[&'a RenameCandidate],
+    policy: CollisionPolicy,
+    overrides: &mut HashMap<PathBuf, ApplyFileResult>,
+) -> Result<Vec<(&'a RenameCandidate, PathBuf)>> {
+    let mut originals: HashSet<&Path> = candidates
+        .iter()
+        .map(|candidate| candidate.original_path.as_path())
+        .collect();
+    // スキップしたファイルは元の名前に残るため、そこを変更先にする候補も衝突になる。
+    // 新たなスキップが出なくなるまで、スキップした元の名前を空かない名前として扱い直す
+    if policy == CollisionPolicy::Skip {
+        loop {
+            let skipped: Vec<&Path> = candidates
+                .iter()
+                .filter(|candidate| {
+                    originals.contains(candidate.original_path.as_path())
+                        && target_collides(candidate, &originals)
+                })
+                .map(|candidate| candidate.original_path.as_path())
+                .collect();
+            if skipped.is_empty() {
+                break;
+            }
+            for original in skipped {
+                originals.remove(original);
+            }
+        }
+    }
+    let mut reserved_paths: HashSet<PathBuf> = candidates
+        .iter()
+        .map(|candidate| candidate.target_path.clone())
+        .collect();
+    let mut resolved = Vec::with_capacity(candidates.len());
     for candidate in candidates {
-        let original_canonical = fs::canonicalize(&candidate.original_path).with_context(|| {
-            format!(
-                "元ファイルを解決できませんでした: {}",
-                candidate.original_path.display()
-            )
-        })?;
-        if !path_within_any_root(&original_canonical, &jpg_roots) {
-            bail!(
-                "JPGフォルダ外の元ファイルは適用できません: {}",
-                candidate.original_path.display()
-            );
+        if originals.contains(candidate.original_path.as_path())
+            && !target_collides(candidate, &originals)
+        {
+            resolved.push((*candidate, candidate.target_path.clone()));
+            continue;
         }
-        if !seen_original_paths.insert(original_canonical) {
-            bail!(
-                "重複した元ファイルが含まれています: {}",
-                candidate.original_path.display()
-            );
+        match policy {
+            CollisionPolicy::Fail => bail!(
+                "リネーム先に既にファイルが存在します: {}",
+                candidate.target_path.display()
+            ),
+            CollisionPolicy::Skip => {
+                overrides.insert(
+                    candidate.original_path.clone(),
+                    ApplyFileResult {
+                        original_path: candidate.original_path.clone(),
+                        target_path: candidate.target_path.clone(),
+                        outcome: ApplyOutcome::Skipped,
+                    },
+                );
+            }
+            CollisionPolicy::Suffix => {
+                let target_path =
+                    unique_path_with_reserved(candidate.target_path.clone(), &mut reserved_paths);
+                overrides.insert(
+                    candidate.original_path.clone(),
+                    ApplyFileResult {
+                        original_path: candidate.original_path.clone(),
+                        target_path: target_path.clone(),
+                        outcome: ApplyOutcome::Renamed,
+                    },
+                );
+                resolved.push((*candidate, target_path));
+            }
         }
+    }
+    Ok(resolved)
+}
 
-        let target_parent = candidate.target_path.parent().with_context(|| {
-            format!(
-                "リネーム先に親ディレクトリがありません: {}",
-                candidate.target_path.display()
-            )
-        })?;
-        let target_name = candidate.target_path.file_name().with_context(|| {
-            format!(
-                "リネーム先ファイル名が不正です: {}",
+fn target_collides(candidate: &RenameCandidate, originals: &HashSet<&Path>) -> bool {
+    let occupied = fs::symlink_metadata(&candidate.target_path)
+        .map(|metadata| !metadata.is_dir())
+        .unwrap_or(false);
+    if !occupied || originals.contains(candidate.target_path.as_path()) {
+        return false;
+    }
+    // 大文字小文字だけを変える場合、リネーム先は元ファイル自身に解決される
+    match (
+        fs::canonicalize(&candidate.original_path),
+        fs::canonicalize(&candidate.target_path),
+    ) {
+        (Ok(original), Ok(target)) => original != target,
+        _ => true,
+    }
+}
+
+fn ensure_plan_is_fresh(plan: &RenamePlan, options: &ApplyOptions) -> Result<()> {
+    if !options.reject_stale_plan {
+        return Ok(());
+    }
+    let report = validate_plan_against_fs(plan);
+    if let Some(first) = report.stale.first() {
+        bail!(
+            "プラン作成後に変更されたファイルがあるため適用できません ({}件): {}",
+            report.stale.len(),
+            first.path.display()
+        );
+    }
+    Ok(())
+}
+
+// 元ファイルには触れず、リネーム後の名前で出力フォルダへ複製(またはハードリンク)する
+fn export_plan_to_output_dir(
+    plan: &RenamePlan,
+    options: &ApplyOptions,
+    output_dir: &Path,
+    progress: Option<&dyn ProgressSink>,
+    paths: &AppPaths,
+) -> Result<ApplyResult> {
+    let candidates: Vec<&RenameCandidate> = plan.candidates.iter().collect();
+    if candidates.is_empty() {
+        return Ok(ApplyResult {
+            applied: 0,
+            unchanged: 0,
+            files: Vec::new(),
+        });
+    }
+
+    ensure_plan_is_fresh(plan, options)?;
+    validate_apply_candidates(plan, &candidates)?;
+
+    fs::create_dir_all(output_dir).with_context(|| {
+        format!(
+            "出力フォルダを作成できませんでした: {}",
+            output_dir.display()
+        )
+    })?;
+    let output_root = fs::canonicalize(output_dir).with_context(|| {
+        format!(
+            "出力フォルダを解決できませんでした: {}",
+            output_dir.display()
+        )
+    })?;
+
+    let allowed_roots = plan_allowed_roots(plan);
+    let mut seen_destinations = HashSet::<PathBuf>::new();
+    let mut jobs = Vec::<(RenameOperation, bool)>::with_capacity(candidates.len());
+    for candidate in &candidates {
+        let mut destination =
+            output_root.join(output_relative_path(&candidate.target_path, &allowed_roots));
+        if seen_destinations.contains(&destination) {
+            bail!("重複した出力先が含まれています: {}", destination.display());
+        }
+        let mut skip = false;
+        if fs::symlink_metadata(&destination).is_ok() {
+            match options.collision_policy {
+                CollisionPolicy::Fail => bail!(
+                    "出力先に同名のファイルが既に存在します: {}",
+                    destination.display()
+                ),
+                CollisionPolicy::Skip => skip = true,
+                CollisionPolicy::Suffix => {
+                    destination = unique_path_with_reserved(destination, &mut seen_destinations);
+                }
+            }
+        }
+        seen_destinations.insert(destination.clone());
+        jobs.push((
+            RenameOperation::new(candidate.original_path.clone(), destination),
+            skip,
+        ));
+    }
+
+    let hardlink = matches!(options.mode, ApplyMode::HardlinkTo(_));
+    let mut created_dirs = Vec::<PathBuf>::new();
+    let mut exported = Vec::<RenameOperation>::with_capacity(jobs.len());
+    let mut files = Vec::<ApplyFileResult>::with_capacity(jobs.len());
+    let total = jobs.len();
+    for (done, (job, skip)) in jobs.into_iter().enumerate() {
+        report_applied(progress, done, total);
+        if skip {
+            files.push(ApplyFileResult {
+                original_path: job.from,
+                target_path: job.to,
+                outcome: ApplyOutcome::Skipped,
+            });
+            continue;
+        }
+        let result = create_missing_parent_dirs(&job.to, &mut created_dirs).and_then(|()| {
+            if hardlink {
+                fs::hard_link(&job.from, &job.to).with_context(|| {
+                    format!(
+                        "ハードリンク作成に失敗しました: {} -> {}",
+                        job.from.display(),
+                        job.to.display()
+                    )
+                })
+            } else {
+                fs::copy(&job.from, &job.to).map(|_| ()).with_context(|| {
+                    format!(
+                        "コピーに失敗しました: {} -> {}",
+                        job.from.display(),
+                        job.to.display()
+                    )
+                })
+            }
+        });
+        if let Err(err) = result {
+            if options.continue_on_error {
+                // コピーは途中まで書き込まれた可能性があるため消しておく
+                if !hardlink {
+                    let _ = fs::remove_file(&job.to);
+                }
+                files.push(ApplyFileResult {
+                    original_path: job.from,
+                    target_path: job.to,
+                    outcome: ApplyOutcome::Failed {
+                        error: format!("{err:#}"),
+                    },
+                });
+                continue;
+            }
+            let rollback_result =
+                remove_exported_files(&exported).and_then(|_| remove_created_dirs(&created_dirs));
+            if let Err(rollback_err) = rollback_result {
+                return Err(err.context(format!(
+                    "出力失敗後のロールバックにも失敗しました: {rollback_err}"
+                )));
+            }
+            return Err(err);
+        }
+        files.push(ApplyFileResult {
+            original_path: job.from.clone(),
+            target_path: job.to.clone(),
+            outcome: ApplyOutcome::Renamed,
+        });
+        exported.push(RenameOperation::recorded(job.from, job.to));
+    }
+    report_applied(progress, total, total);
+
+    if exported.is_empty() {
+        remove_created_dirs(&created_dirs)?;
+    } else if let Err(persist_err) = persist_undo(
+        &exported,
+        plan,
+        options,
+        &[],
+        &created_dirs,
+        Some(&output_root),
+        paths,
+    ) {
+        let rollback_result =
+            remove_exported_files(&exported).and_then(|_| remove_created_dirs(&created_dirs));
+        return Err(compose_persist_failure_error(
+            persist_err,
+            rollback_result,
+            Ok(()),
+        ));
+    }
+
+    Ok(ApplyResult {
+        applied: exported.len(),
+        unchanged: 0,
+        files,
+    })
+}
+
+// 出力フォルダ内でも、JPG/RAWルートからの相対的なフォルダ構成を保つ
+fn output_relative_path(target_path: &Path, roots: &[PathBuf]) -> PathBuf {
+    if let Some(relative) = pick_most_specific_root(target_path, roots)
+        .and_then(|root| target_path.strip_prefix(root).ok())
+        .filter(|relative| {
+            !relative
+                .components()
+                .any(|component| matches!(component, Component::ParentDir))
+        })
+    {
+        return relative.to_path_buf();
+    }
+    target_path
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("file"))
+}
+
+fn remove_exported_files(operations: &[RenameOperation]) -> Result<usize> {
+    let mut removed = 0usize;
+    for operation in operations.iter().rev() {
+        if !operation.to.exists() {
+            continue;
+        }
+        fs::remove_file(&operation.to).with_context(|| {
+            format!(
+                "出力したファイルを削除できませんでした: {}",
+                operation.to.display()
+            )
+        })?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+pub fn validate_plan_against_fs(plan: &RenamePlan) -> PlanValidationReport {
+    let mut report = PlanValidationReport::default();
+    for candidate in &plan.candidates {
+        report.checked += 1;
+        let Some(current) = FileFingerprint::read(&candidate.original_path) else {
+            report.stale.push(StaleEntry {
+                path: candidate.original_path.clone(),
+                reason: StaleReason::Missing,
+            });
+            continue;
+        };
+        let Some(expected) = candidate.fingerprint else {
+            continue;
+        };
+        let reason = if current.size != expected.size {
+            StaleReason::SizeChanged {
+                expected: expected.size,
+                actual: current.size,
+            }
+        } else if current.modified_unix_ms != expected.modified_unix_ms {
+            StaleReason::ModifiedChanged {
+                expected: expected.modified_unix_ms,
+                actual: current.modified_unix_ms,
+            }
+        } else {
+            continue;
+        };
+        report.stale.push(StaleEntry {
+            path: candidate.original_path.clone(),
+            reason,
+        });
+    }
+    report
+}
+
+// 作成したフォルダは上位から順に記録し、取り消し時は逆順に空なら削除する
+fn create_target_dirs(staged: &[StagedRename], created_dirs: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in staged {
+        create_missing_parent_dirs(&entry.target_path, created_dirs)?;
+    }
+    Ok(())
+}
+
+fn create_missing_parent_dirs(path: &Path, created_dirs: &mut Vec<PathBuf>) -> Result<()> {
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    let missing = parent
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    for dir in missing.into_iter().rev() {
+        fs::create_dir(&dir).with_context(|| {
+            format!(
+                "リネーム先フォルダを作成できませんでした: {}",
+                dir.display()
+            )
+        })?;
+        created_dirs.push(dir);
+    }
+    Ok(())
+}
+
+fn remove_created_dirs(created_dirs: &[PathBuf]) -> Result<()> {
+    for dir in created_dirs.iter().rev() {
+        if !dir.is_dir() || !directory_is_empty(dir)? {
+            continue;
+        }
+        fs::remove_dir(dir).with_context(|| {
+            format!("作成したフォルダを削除できませんでした: {}", dir.display())
+        })?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StagedRename {
+    original_path: PathBuf,
+    target_path: PathBuf,
+    temp_path: PathBuf,
+}
+
+fn plan_jpg_roots(plan: &RenamePlan) -> Vec<PathBuf> {
+    if plan.jpg_roots.is_empty() {
+        return vec![plan.jpg_root.clone()];
+    }
+    plan.jpg_roots.clone()
+}
+
+// 連れ立ってリネームするRAW/XMPのフォルダも適用対象として扱う
+fn plan_allowed_roots(plan: &RenamePlan) -> Vec<PathBuf> {
+    let mut roots = plan_jpg_roots(plan);
+    roots.extend(plan.companion_roots.iter().cloned());
+    roots
+}
+
+fn canonicalize_jpg_roots(raw_roots: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    if raw_roots.is_empty() {
+        bail!("JPGルートが指定されていません");
+    }
+
+    let mut seen = HashSet::<PathBuf>::new();
+    let mut out = Vec::<PathBuf>::new();
+    for root in raw_roots {
+        let canonical = fs::canonicalize(root)
+            .with_context(|| format!("JPGルートを解決できませんでした: {}", root.display()))?;
+        if !canonical.is_dir() {
+            bail!("JPGルートがフォルダではありません: {}", canonical.display());
+        }
+        if seen.insert(canonical.clone()) {
+            out.push(canonical);
+        }
+    }
+    Ok(out)
+}
+
+fn path_within_any_root(path: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+fn extend_roots_with_symlinked_parents<'a>(
+    raw_roots: &[PathBuf],
+    roots: &mut Vec<PathBuf>,
+    paths: impl IntoIterator<Item = &'a Path>,
+) {
+    for path in paths {
+        if path
+            .components()
+            .any(|component| matches!(component, Component::ParentDir))
+        {
+            continue;
+        }
+        if !raw_roots.iter().any(|root| path.starts_with(root)) {
+            continue;
+        }
+        let Some(parent) = path.parent() else {
+            continue;
+        };
+        let Ok(canonical_parent) = fs::canonicalize(parent) else {
+            continue;
+        };
+        if !path_within_any_root(&canonical_parent, roots) {
+            roots.push(canonical_parent);
+        }
+    }
+}
+
+fn pick_most_specific_root<'a>(path: &Path, roots: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.components().count())
+}
+
+fn validate_apply_candidates(plan: &RenamePlan, candidates: &[&RenameCandidate]) -> Result<()> {
+    let raw_jpg_roots = plan_allowed_roots(plan);
+    let mut jpg_roots = canonicalize_jpg_roots(&raw_jpg_roots)?;
+    if plan.follow_symlinks {
+        extend_roots_with_symlinked_parents(
+            &raw_jpg_roots,
+            &mut jpg_roots,
+            candidates.iter().flat_map(|candidate| {
+                [
+                    candidate.original_path.as_path(),
+                    candidate.target_path.as_path(),
+                ]
+            }),
+        );
+    }
+    let mut seen_original_paths = HashSet::<PathBuf>::new();
+    let mut seen_target_paths = HashSet::<PathBuf>::new();
+
+    for candidate in candidates {
+        let original_canonical = fs::canonicalize(&candidate.original_path).with_context(|| {
+            format!(
+                "元ファイルを解決できませんでした: {}",
+                candidate.original_path.display()
+            )
+        })?;
+        if !path_within_any_root(&original_canonical, &jpg_roots) {
+            bail!(
+                "JPGフォルダ外の元ファイルは適用できません: {}",
+                candidate.original_path.display()
+            );
+        }
+        if !seen_original_paths.insert(original_canonical) {
+            bail!(
+                "重複した元ファイルが含まれています: {}",
+                candidate.original_path.display()
+            );
+        }
+
+        let target_parent = candidate.target_path.parent().with_context(|| {
+            format!(
+                "リネーム先に親ディレクトリがありません: {}",
                 candidate.target_path.display()
             )
         })?;
-        let target_parent_canonical = fs::canonicalize(target_parent).with_context(|| {
+        let target_name = candidate.target_path.file_name().with_context(|| {
             format!(
-                "リネーム先親ディレクトリを解決できませんでした: {}",
-                target_parent.display()
+                "リネーム先ファイル名が不正です: {}",
+                candidate.target_path.display()
             )
         })?;
+        let target_parent_canonical =
+            canonicalize_with_missing_dirs(target_parent).with_context(|| {
+                format!(
+                    "リネーム先親ディレクトリを解決できませんでした: {}",
+                    target_parent.display()
+                )
+            })?;
         if !path_within_any_root(&target_parent_canonical, &jpg_roots) {
             bail!(
                 "JPGフォルダ外のリネーム先は適用できません: {}",
@@ -249,12 +1341,32 @@ fn validate_apply_candidates(plan: &RenamePlan, candidates: &[&RenameCandidate])
     Ok(())
 }
 
+// まだ存在しないフォルダは、存在する祖先を正規化したうえで残りを連結する
+fn canonicalize_with_missing_dirs(path: &Path) -> Result<PathBuf> {
+    let mut missing = Vec::new();
+    let mut current = path;
+    while !current.exists() {
+        let name = current
+            .file_name()
+            .with_context(|| format!("フォルダ名が不正です: {}", current.display()))?;
+        missing.push(name.to_os_string());
+        current = current
+            .parent()
+            .with_context(|| format!("親ディレクトリがありません: {}", path.display()))?;
+    }
+    let mut canonical = fs::canonicalize(current)?;
+    for name in missing.into_iter().rev() {
+        canonical.push(name);
+    }
+    Ok(canonical)
+}
+
 fn rollback_staged_to_original_paths(staged: &[StagedRename]) -> Result<()> {
     for entry in staged.iter().rev() {
         if !entry.temp_path.exists() {
             continue;
         }
-        fs::rename(&entry.temp_path, &entry.original_path).with_context(|| {
+        move_file(&entry.temp_path, &entry.original_path).with_context(|| {
             format!(
                 "ロールバックに失敗しました: {} -> {}",
                 entry.temp_path.display(),
@@ -270,7 +1382,28 @@ fn rollback_after_final_rename_failure(staged: &[StagedRename], finalized: usize
         if !entry.target_path.exists() {
             continue;
         }
-        fs::rename(&entry.target_path, &entry.temp_path).with_context(|| {
+        move_file(&entry.target_path, &entry.temp_path).with_context(|| {
+            format!(
+                "ロールバック(退避)に失敗しました: {} -> {}",
+                entry.target_path.display(),
+                entry.temp_path.display()
+            )
+        })?;
+    }
+    rollback_staged_to_original_paths(staged)
+}
+
+// 失敗を許容する適用の途中で止める場合、適用済みのファイルだけを一時名へ戻してから全体を元に戻す。
+// 失敗して元の名前へ戻したファイルの変更先には別のファイルがあり得るため、触らない
+fn rollback_continued_apply(
+    staged: &[StagedRename],
+    finalized_targets: &HashMap<PathBuf, usize>,
+) -> Result<()> {
+    let mut finalized = finalized_targets.values().copied().collect::<Vec<_>>();
+    finalized.sort_unstable();
+    for &index in finalized.iter().rev() {
+        let entry = &staged[index];
+        move_file(&entry.target_path, &entry.temp_path).with_context(|| {
             format!(
                 "ロールバック(退避)に失敗しました: {} -> {}",
                 entry.target_path.display(),
@@ -286,7 +1419,7 @@ fn rollback_after_undo_persist_failure(operations: &[RenameOperation]) -> Result
         if !operation.to.exists() {
             continue;
         }
-        fs::rename(&operation.to, &operation.from).with_context(|| {
+        move_file(&operation.to, &operation.from).with_context(|| {
             format!(
                 "取り消しログ保存失敗後のロールバックに失敗しました: {} -> {}",
                 operation.to.display(),
@@ -299,16 +1432,21 @@ fn rollback_after_undo_persist_failure(operations: &[RenameOperation]) -> Result
 
 fn cleanup_created_backups_after_persist_failure(
     plan: &RenamePlan,
+    options: &ApplyOptions,
     backup_paths: &[PathBuf],
 ) -> Result<()> {
     if backup_paths.is_empty() {
         return Ok(());
     }
 
+    let jpg_roots = canonicalize_jpg_roots(&plan_allowed_roots(plan))?;
     let validated = ValidatedUndoLog {
         operations: Vec::new(),
-        jpg_roots: plan_jpg_roots(plan),
+        backup_roots: backup_roots_for(&jpg_roots, options.backup_dir.as_deref())?,
+        jpg_roots,
         backup_paths: backup_paths.to_vec(),
+        created_dirs: Vec::new(),
+        remove_targets: false,
     };
     cleanup_backup_if_needed(&validated)
 }
@@ -333,14 +1471,67 @@ fn compose_persist_failure_error(
     }
 }
 
+// 既定は各JPGルート直下の backup、指定時はそのフォルダ内にルートごとのフォルダを作る
+fn backup_roots_for(jpg_roots: &[PathBuf], backup_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+    let Some(backup_dir) = backup_dir else {
+        return Ok(jpg_roots.iter().map(|root| root.join("backup")).collect());
+    };
+    let backup_dir = if backup_dir.exists() {
+        fs::canonicalize(backup_dir).with_context(|| {
+            format!(
+                "バックアップ先フォルダを解決できませんでした: {}",
+                backup_dir.display()
+            )
+        })?
+    } else {
+        backup_dir.to_path_buf()
+    };
+    Ok(root_labels(jpg_roots)
+        .into_iter()
+        .map(|label| backup_dir.join(label))
+        .collect())
+}
+
+// JPGルートのフォルダ名。同名のルートがあれば連番を付けて区別する
+fn root_labels(jpg_roots: &[PathBuf]) -> Vec<OsString> {
+    let mut used = HashSet::<OsString>::new();
+    let mut labels = Vec::<OsString>::with_capacity(jpg_roots.len());
+    for jpg_root in jpg_roots {
+        let base = jpg_root
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_else(|| OsString::from("root"));
+        let mut name = base.clone();
+        let mut n = 2usize;
+        while !used.insert(name.clone()) {
+            name = base.clone();
+            name.push(format!("-{n}"));
+            n += 1;
+        }
+        labels.push(name);
+    }
+    labels
+}
+
 fn backup_original_files(
     plan: &RenamePlan,
     candidates: &[&RenameCandidate],
+    backup_dir: Option<&Path>,
 ) -> Result<Vec<PathBuf>> {
-    let jpg_roots = canonicalize_jpg_roots(&plan_jpg_roots(plan))?;
+    let jpg_roots = canonicalize_jpg_roots(&plan_allowed_roots(plan))?;
+    if let Some(backup_dir) = backup_dir {
+        fs::create_dir_all(backup_dir).with_context(|| {
+            format!(
+                "バックアップ先フォルダを作成できませんでした: {}",
+                backup_dir.display()
+            )
+        })?;
+    }
     let mut backup_roots = Vec::<(PathBuf, PathBuf)>::new();
-    for jpg_root in &jpg_roots {
-        let backup_root = jpg_root.join("backup");
+    for (jpg_root, backup_root) in jpg_roots
+        .iter()
+        .zip(backup_roots_for(&jpg_roots, backup_dir)?)
+    {
         fs::create_dir_all(&backup_root).with_context(|| {
             format!(
                 "バックアップフォルダを作成できませんでした: {}",
@@ -353,11 +1544,16 @@ fn backup_original_files(
                 backup_root.display()
             )
         })?;
-        if !backup_root_canonical.starts_with(jpg_root) {
-            bail!(
+        match backup_root.parent().filter(|_| backup_dir.is_some()) {
+            Some(backup_dir) if !backup_root_canonical.starts_with(backup_dir) => bail!(
+                "バックアップフォルダがバックアップ先フォルダ外を指しています: {}",
+                backup_root.display()
+            ),
+            None if !backup_root_canonical.starts_with(jpg_root) => bail!(
                 "バックアップフォルダがJPGフォルダ外を指しています: {}",
                 backup_root.display()
-            );
+            ),
+            _ => {}
         }
         backup_roots.push((jpg_root.clone(), backup_root_canonical));
     }
@@ -371,20 +1567,30 @@ fn backup_original_files(
                 candidate.original_path.display()
             )
         })?;
-        let Some(root) = backup_roots
+        let (root, original_for_backup) = match backup_roots
             .iter()
             .filter(|(jpg_root, _)| original_canonical.starts_with(jpg_root))
             .max_by_key(|(jpg_root, _)| jpg_root.components().count())
-        else {
-            bail!(
-                "バックアップ対象がJPGルート外です: {}",
-                candidate.original_path.display()
-            );
+        {
+            Some(root) => (root, original_canonical),
+            None => {
+                let Some(resolved) = plan
+                    .follow_symlinks
+                    .then(|| resolve_symlinked_backup_source(&backup_roots, plan, candidate))
+                    .flatten()
+                else {
+                    bail!(
+                        "バックアップ対象がJPGルート外です: {}",
+                        candidate.original_path.display()
+                    );
+                };
+                resolved
+            }
         };
         let backup_path = resolve_backup_path_with_reserved(
             &root.1,
             &root.0,
-            &original_canonical,
+            &original_for_backup,
             &mut reserved_paths,
         );
         backup_jobs.push((candidate.original_path.clone(), backup_path));
@@ -417,22 +1623,188 @@ fn backup_original_files(
         .collect())
 }
 
-#[cfg(test)]
-fn resolve_backup_path(backup_root: &Path, jpg_root: &Path, original_path: &Path) -> PathBuf {
-    let mut reserved_paths = HashSet::<PathBuf>::new();
-    resolve_backup_path_with_reserved(backup_root, jpg_root, original_path, &mut reserved_paths)
-}
-
-fn resolve_backup_path_with_reserved(
-    backup_root: &Path,
-    jpg_root: &Path,
+// 適用1回分の元ファイルを1つのzipへ書き出す。写真は圧縮が効かないため無圧縮で格納する
+fn backup_original_files_to_zip(
+    plan: &RenamePlan,
+    candidates: &[&RenameCandidate],
+    backup_dir: Option<&Path>,
+) -> Result<Vec<PathBuf>> {
+    let jpg_roots = canonicalize_jpg_roots(&plan_allowed_roots(plan))?;
+    if let Some(backup_dir) = backup_dir {
+        fs::create_dir_all(backup_dir).with_context(|| {
+            format!(
+                "バックアップ先フォルダを作成できませんでした: {}",
+                backup_dir.display()
+            )
+        })?;
+    }
+    let backup_roots = backup_roots_for(&jpg_roots, backup_dir)?;
+    let Some(backup_root) = backup_roots.first() else {
+        bail!("バックアップ先を決められませんでした");
+    };
+    fs::create_dir_all(backup_root).with_context(|| {
+        format!(
+            "バックアップフォルダを作成できませんでした: {}",
+            backup_root.display()
+        )
+    })?;
+
+    let stamp = Local::now().format("%Y%m%d-%H%M%S");
+    let mut reserved_paths = HashSet::<PathBuf>::new();
+    let archive_path = unique_path_with_reserved(
+        backup_root.join(format!("backup-{stamp}.zip")),
+        &mut reserved_paths,
+    );
+
+    let written = write_backup_zip(&archive_path, &jpg_roots, candidates);
+    if let Err(err) = written {
+        let _ = fs::remove_file(&archive_path);
+        return Err(err);
+    }
+    Ok(vec![archive_path])
+}
+
+fn write_backup_zip(
+    archive_path: &Path,
+    jpg_roots: &[PathBuf],
+    candidates: &[&RenameCandidate],
+) -> Result<()> {
+    let labels = root_labels(jpg_roots);
+    let file = fs::File::create(archive_path).with_context(|| {
+        format!(
+            "バックアップzipを作成できませんでした: {}",
+            archive_path.display()
+        )
+    })?;
+    let mut writer = zip::ZipWriter::new(file);
+    let mut entry_names = HashSet::<String>::new();
+    for candidate in candidates {
+        let original_canonical = fs::canonicalize(&candidate.original_path).with_context(|| {
+            format!(
+                "元ファイルを解決できませんでした: {}",
+                candidate.original_path.display()
+            )
+        })?;
+        // zip内ではJPGルートごとのフォルダ名で区別する
+        let entry_path = jpg_roots
+            .iter()
+            .zip(&labels)
+            .filter(|(jpg_root, _)| original_canonical.starts_with(jpg_root))
+            .max_by_key(|(jpg_root, _)| jpg_root.components().count())
+            .and_then(|(jpg_root, label)| {
+                let relative = original_canonical.strip_prefix(jpg_root).ok()?;
+                Some(Path::new(label).join(relative))
+            })
+            .or_else(|| candidate.original_path.file_name().map(PathBuf::from))
+            .with_context(|| {
+                format!(
+                    "バックアップ対象のファイル名が不正です: {}",
+                    candidate.original_path.display()
+                )
+            })?;
+        let base_name = entry_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let mut entry_name = base_name.clone();
+        let mut n = 1usize;
+        while !entry_names.insert(entry_name.clone()) {
+            entry_name = format!("{base_name}.{n}");
+            n += 1;
+        }
+
+        let mut source = fs::File::open(&candidate.original_path).with_context(|| {
+            format!(
+                "バックアップ元を開けませんでした: {}",
+                candidate.original_path.display()
+            )
+        })?;
+        let size = source.metadata()?.len();
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .large_file(size >= u64::from(u32::MAX));
+        writer
+            .start_file(entry_name.as_str(), options)
+            .with_context(|| format!("バックアップzipへの追加に失敗しました: {entry_name}"))?;
+        std::io::copy(&mut source, &mut writer).with_context(|| {
+            format!(
+                "バックアップに失敗しました: {} -> {}",
+                candidate.original_path.display(),
+                archive_path.display()
+            )
+        })?;
+    }
+    writer.finish().with_context(|| {
+        format!(
+            "バックアップzipの書き込みに失敗しました: {}",
+            archive_path.display()
+        )
+    })?;
+    Ok(())
+}
+
+// 元の名前でコピーを置いてからゴミ箱へ送り、OSのゴミ箱から元の場所・名前で戻せるようにする
+fn send_original_copies_to_trash(operations: &[RenameOperation]) -> Result<()> {
+    for operation in operations {
+        if fs::symlink_metadata(&operation.from).is_ok() {
+            continue;
+        }
+        fs::copy(&operation.to, &operation.from).with_context(|| {
+            format!(
+                "ゴミ箱用のコピー作成に失敗しました: {} -> {}",
+                operation.to.display(),
+                operation.from.display()
+            )
+        })?;
+        if let Err(err) = trash::delete(&operation.from) {
+            let _ = fs::remove_file(&operation.from);
+            return Err(anyhow::Error::from(err).context(format!(
+                "ゴミ箱へ送れませんでした: {}",
+                operation.from.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn resolve_symlinked_backup_source<'a>(
+    backup_roots: &'a [(PathBuf, PathBuf)],
+    plan: &RenamePlan,
+    candidate: &RenameCandidate,
+) -> Option<(&'a (PathBuf, PathBuf), PathBuf)> {
+    plan_allowed_roots(plan).iter().find_map(|raw_root| {
+        let relative = candidate.original_path.strip_prefix(raw_root).ok()?;
+        if relative
+            .components()
+            .any(|component| matches!(component, Component::ParentDir))
+        {
+            return None;
+        }
+        let canonical_root = fs::canonicalize(raw_root).ok()?;
+        let root = backup_roots
+            .iter()
+            .find(|(jpg_root, _)| jpg_root == &canonical_root)?;
+        Some((root, canonical_root.join(relative)))
+    })
+}
+
+#[cfg(test)]
+fn resolve_backup_path(backup_root: &Path, jpg_root: &Path, original_path: &Path) -> PathBuf {
+    let mut reserved_paths = HashSet::<PathBuf>::new();
+    resolve_backup_path_with_reserved(backup_root, jpg_root, original_path, &mut reserved_paths)
+}
+
+fn resolve_backup_path_with_reserved(
+    backup_root: &Path,
+    jpg_root: &Path,
     original_path: &Path,
     reserved_paths: &mut HashSet<PathBuf>,
 ) -> PathBuf {
     if let Ok(relative) = original_path.strip_prefix(jpg_root) {
         if !relative.as_os_str().is_empty() {
             let candidate = backup_root.join(relative);
-            return unique_backup_path_with_reserved(candidate, reserved_paths);
+            return unique_path_with_reserved(candidate, reserved_paths);
         }
     }
 
@@ -440,19 +1812,16 @@ fn resolve_backup_path_with_reserved(
         .file_name()
         .map(|v| v.to_os_string())
         .unwrap_or_else(|| OsString::from("file"));
-    unique_backup_path_with_reserved(backup_root.join(file_name), reserved_paths)
+    unique_path_with_reserved(backup_root.join(file_name), reserved_paths)
 }
 
 #[cfg(test)]
 fn unique_backup_path(candidate: PathBuf) -> PathBuf {
     let mut reserved_paths = HashSet::<PathBuf>::new();
-    unique_backup_path_with_reserved(candidate, &mut reserved_paths)
+    unique_path_with_reserved(candidate, &mut reserved_paths)
 }
 
-fn unique_backup_path_with_reserved(
-    candidate: PathBuf,
-    reserved_paths: &mut HashSet<PathBuf>,
-) -> PathBuf {
+fn unique_path_with_reserved(candidate: PathBuf, reserved_paths: &mut HashSet<PathBuf>) -> PathBuf {
     if !candidate.exists() && !reserved_paths.contains(&candidate) {
         reserved_paths.insert(candidate.clone());
         return candidate;
@@ -485,343 +1854,3209 @@ fn unique_backup_path_with_reserved(
 }
 
 pub fn undo_last() -> Result<UndoResult> {
+    undo_last_with_options(&UndoOptions::default())
+}
+
+pub fn undo_last_with_options(options: &UndoOptions) -> Result<UndoResult> {
     let paths = app_paths()?;
-    if !paths.undo_path.exists() {
-        anyhow::bail!("取り消し可能な履歴がありません");
-    }
+    undo_last_with_paths(options, &paths)
+}
 
-    let raw = fs::read_to_string(&paths.undo_path).with_context(|| {
-        format!(
-            "取り消しログを読めませんでした: {}",
-            paths.undo_path.display()
-        )
-    })?;
-    let log = serde_json::from_str::<UndoLog>(&raw).context("取り消しログが壊れています")?;
-    let validated = validate_undo_log(&log)?;
+fn undo_last_with_paths(options: &UndoOptions, paths: &AppPaths) -> Result<UndoResult> {
+    let Some((_, session_path)) = undo_session_paths(paths)?.pop() else {
+        bail!("取り消し可能な履歴がありません");
+    };
+    undo_session_file(&session_path, options, paths)
+}
 
-    let restored = restore_operations(&validated.operations)?;
+pub fn preview_undo() -> Result<UndoPreview> {
+    let paths = app_paths()?;
+    preview_undo_with_paths(&paths)
+}
 
-    cleanup_backup_if_needed(&validated)?;
+// ファイルシステムは変更せず、undo_last が行う復元内容だけを返す
+fn preview_undo_with_paths(paths: &AppPaths) -> Result<UndoPreview> {
+    let Some((session_id, session_path)) = undo_session_paths(paths)?.pop() else {
+        bail!("取り消し可能な履歴がありません");
+    };
+    let log = read_undo_log(&session_path)?;
+    let validated = validate_undo_log(&log)?;
+    let entries = validated
+        .operations
+        .iter()
+        .rev()
+        .map(|operation| UndoPreviewEntry {
+            current_path: operation.to.clone(),
+            restore_path: operation.from.clone(),
+            missing: !operation.to.exists(),
+            modified: operation.content_changed(),
+        })
+        .collect();
+    Ok(UndoPreview {
+        session_id,
+        created_at: log.created_at,
+        output_dir: log.output_dir,
+        entries,
+    })
+}
 
-    fs::remove_file(&paths.undo_path).with_context(|| {
-        format!(
-            "取り消しログ削除に失敗しました: {}",
-            paths.undo_path.display()
-        )
-    })?;
+pub fn undo_session(id: &str) -> Result<UndoResult> {
+    undo_session_with_options(id, &UndoOptions::default())
+}
 
-    Ok(UndoResult { restored })
+pub fn undo_session_with_options(id: &str, options: &UndoOptions) -> Result<UndoResult> {
+    let paths = app_paths()?;
+    undo_session_with_paths(id, options, &paths)
 }
 
-fn validate_undo_log(log: &UndoLog) -> Result<ValidatedUndoLog> {
-    let raw_jpg_roots = if !log.jpg_roots.is_empty() {
-        log.jpg_roots.clone()
-    } else if let Some(jpg_root) = log.jpg_root.as_ref() {
-        vec![jpg_root.clone()]
-    } else {
-        bail!("取り消しログにJPGルートが記録されていません");
+fn undo_session_with_paths(
+    id: &str,
+    options: &UndoOptions,
+    paths: &AppPaths,
+) -> Result<UndoResult> {
+    let Some((_, session_path)) = undo_session_paths(paths)?
+        .into_iter()
+        .find(|(session_id, _)| session_id == id)
+    else {
+        bail!("指定された取り消し履歴がありません: {id}");
     };
-    let jpg_roots = canonicalize_jpg_roots(&raw_jpg_roots)?;
-
-    let mut seen_from = HashSet::<PathBuf>::new();
-    let mut seen_to = HashSet::<PathBuf>::new();
-    let mut operations = Vec::<RenameOperation>::with_capacity(log.operations.len());
-    for operation in &log.operations {
-        let normalized_from =
-            normalize_path_within_roots(&operation.from, &jpg_roots, "取り消し元パス")?;
-        let normalized_to =
-            normalize_path_within_roots(&operation.to, &jpg_roots, "取り消し先パス")?;
-
-        if !seen_from.insert(normalized_from.clone()) {
-            bail!(
-                "取り消しログに重複した取り消し元パスがあります: {}",
-                normalized_from.display()
-            );
-        }
-        if !seen_to.insert(normalized_to.clone()) {
-            bail!(
-                "取り消しログに重複した取り消し先パスがあります: {}",
-                normalized_to.display()
-            );
-        }
+    undo_session_file(&session_path, options, paths)
+}
 
-        operations.push(RenameOperation {
-            from: normalized_from,
-            to: normalized_to,
-        });
-    }
+pub fn list_undo_sessions() -> Result<Vec<UndoSession>> {
+    let paths = app_paths()?;
+    list_undo_sessions_with_paths(&paths)
+}
 
-    if !log.backup_originals {
-        return Ok(ValidatedUndoLog {
-            operations,
-            jpg_roots,
-            backup_paths: Vec::new(),
+// 新しい順に返す
+fn list_undo_sessions_with_paths(paths: &AppPaths) -> Result<Vec<UndoSession>> {
+    let mut sessions = Vec::<UndoSession>::new();
+    for (id, path) in undo_session_paths(paths)?.into_iter().rev() {
+        let log = read_undo_log(&path)?;
+        let mut roots = if log.jpg_roots.is_empty() {
+            log.jpg_root.iter().cloned().collect::<Vec<_>>()
+        } else {
+            log.jpg_roots.clone()
+        };
+        roots.extend(log.companion_roots.iter().cloned());
+        sessions.push(UndoSession {
+            id,
+            created_at: log.created_at.clone(),
+            file_count: log.operations.len(),
+            roots,
+            output_dir: log.output_dir.clone(),
         });
     }
+    Ok(sessions)
+}
 
-    let backup_roots: Vec<PathBuf> = jpg_roots.iter().map(|root| root.join("backup")).collect();
+fn undo_history_dir(paths: &AppPaths) -> PathBuf {
+    paths.config_dir.join("undo-history")
+}
 
-    let mut backup_paths = Vec::<PathBuf>::new();
-    for backup_path in &log.backup_paths {
-        if !backup_path.exists() {
+// 古い順に返す。旧形式の undo-last.json があれば最も古い履歴として扱う
+fn undo_session_paths(paths: &AppPaths) -> Result<Vec<(String, PathBuf)>> {
+    let mut sessions = Vec::<(String, PathBuf)>::new();
+    if paths.undo_path.exists() {
+        sessions.push((LEGACY_UNDO_SESSION_ID.to_string(), paths.undo_path.clone()));
+    }
+    let history_dir = undo_history_dir(paths);
+    if !history_dir.is_dir() {
+        return Ok(sessions);
+    }
+    let mut history = Vec::<(String, PathBuf)>::new();
+    for entry in fs::read_dir(&history_dir).with_context(|| {
+        format!(
+            "取り消し履歴フォルダを読めませんでした: {}",
+            history_dir.display()
+        )
+    })? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
             continue;
         }
-        if backup_path.is_dir() {
-            bail!(
-                "取り消しログのバックアップパスがディレクトリです: {}",
-                backup_path.display()
-            );
-        }
-        backup_paths.push(normalize_path_within_roots(
-            backup_path,
-            &backup_roots,
-            "バックアップパス",
-        )?);
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        history.push((id.to_string(), path));
     }
+    history.sort_by(|a, b| a.0.cmp(&b.0));
+    sessions.extend(history);
+    Ok(sessions)
+}
 
-    Ok(ValidatedUndoLog {
-        operations,
-        jpg_roots,
-        backup_paths,
-    })
+fn read_undo_log(path: &Path) -> Result<UndoLog> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("取り消しログを読めませんでした: {}", path.display()))?;
+    serde_json::from_str::<UndoLog>(&raw)
+        .with_context(|| format!("取り消しログが壊れています: {}", path.display()))
 }
 
-fn normalize_path_within_roots(path: &Path, roots: &[PathBuf], label: &str) -> Result<PathBuf> {
-    let parent = path
-        .parent()
-        .with_context(|| format!("{label}に親ディレクトリがありません: {}", path.display()))?;
-    let file_name = path
-        .file_name()
-        .with_context(|| format!("{label}のファイル名が不正です: {}", path.display()))?;
-    let canonical_parent = fs::canonicalize(parent).with_context(|| {
-        format!(
-            "{label}の親ディレクトリを解決できませんでした: {}",
-            parent.display()
-        )
-    })?;
-    let Some(root) = pick_most_specific_root(&canonical_parent, roots) else {
-        bail!("{label}が許可範囲外です: {}", path.display());
-    };
-    if !canonical_parent.starts_with(root) {
-        bail!("{label}が許可範囲外です: {}", path.display());
+fn undo_session_file(
+    session_path: &Path,
+    options: &UndoOptions,
+    paths: &AppPaths,
+) -> Result<UndoResult> {
+    let log = read_undo_log(session_path)?;
+    let validated = validate_undo_log(&log)?;
+    let _lock = acquire_apply_lock(paths, &validated.jpg_roots)?;
+    ensure_undo_content_unchanged(&validated.operations, options)?;
+    let modified = modified_targets(&validated.operations);
+
+    let restored = if validated.remove_targets {
+        remove_exported_files(&validated.operations)?
+    } else {
+        restore_operations(&validated.operations)?
     };
-    Ok(canonical_parent.join(file_name))
-}
 
-fn restore_operations(operations: &[RenameOperation]) -> Result<usize> {
-    let mut restored = 0usize;
-    for op in operations.iter().rev() {
-        if !op.to.exists() {
-            continue;
-        }
-        fs::rename(&op.to, &op.from).with_context(|| {
-            format!(
-                "取り消しに失敗しました: {} -> {}",
-                op.to.display(),
-                op.from.display()
-            )
-        })?;
-        restored += 1;
+    cleanup_backup_if_needed(&validated)?;
+    remove_created_dirs(&validated.created_dirs)?;
+
+    fs::remove_file(session_path)
+        .with_context(|| format!("取り消しログ削除に失敗しました: {}", session_path.display()))?;
+
+    if validated.remove_targets {
+        remove_redo_log(paths)?;
+    } else {
+        write_redo_log(&log, paths)
+            .context("取り消しは完了しましたが、やり直し用の記録を保存できませんでした")?;
     }
-    Ok(restored)
+
+    Ok(UndoResult { restored, modified })
 }
 
-fn persist_undo(
-    operations: &[RenameOperation],
-    plan: &RenamePlan,
-    options: &ApplyOptions,
-    backup_paths: &[PathBuf],
+pub fn undo_last_files(files: &[PathBuf], options: &UndoOptions) -> Result<UndoResult> {
+    let paths = app_paths()?;
+    undo_last_files_with_paths(files, options, &paths)
+}
+
+// 直近の履歴から指定ファイルだけを戻し、残りの操作は取り消しログに書き戻す
+fn undo_last_files_with_paths(
+    files: &[PathBuf],
+    options: &UndoOptions,
     paths: &AppPaths,
-) -> Result<()> {
-    fs::create_dir_all(&paths.config_dir).with_context(|| {
-        format!(
-            "設定ディレクトリ作成に失敗しました: {}",
-            paths.config_dir.display()
-        )
-    })?;
+) -> Result<UndoResult> {
+    let Some((_, session_path)) = undo_session_paths(paths)?.pop() else {
+        bail!("取り消し可能な履歴がありません");
+    };
+    let mut log = read_undo_log(&session_path)?;
+    let validated = validate_undo_log(&log)?;
+    let _lock = acquire_apply_lock(paths, &validated.jpg_roots)?;
 
-    let log = UndoLog {
-        operations: operations.to_vec(),
-        backup_originals: options.backup_originals,
-        jpg_root: Some(plan.jpg_root.clone()),
-        jpg_roots: plan_jpg_roots(plan),
-        backup_paths: backup_paths.to_vec(),
+    let mut requested = HashSet::<PathBuf>::new();
+    for file in files {
+        let parent = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let file_name = file
+            .file_name()
+            .with_context(|| format!("ファイル名が不正です: {}", file.display()))?;
+        let canonical_parent = canonicalize_with_missing_dirs(parent)
+            .with_context(|| format!("パスを解決できませんでした: {}", file.display()))?;
+        requested.insert(canonical_parent.join(file_name));
+    }
+
+    let mut matched = HashSet::<PathBuf>::new();
+    let mut selected = Vec::<RenameOperation>::new();
+    let mut remaining = Vec::<RenameOperation>::new();
+    for (raw, operation) in log.operations.iter().zip(&validated.operations) {
+        let hit = [&operation.to, &operation.from]
+            .into_iter()
+            .find(|path| requested.contains(*path));
+        match hit {
+            Some(path) => {
+                matched.insert(path.clone());
+                selected.push(operation.clone());
+            }
+            None => remaining.push(raw.clone()),
+        }
+    }
+    if let Some(unknown) = requested.iter().find(|path| !matched.contains(*path)) {
+        bail!(
+            "直近の取り消し履歴に含まれないファイルが指定されました: {}",
+            unknown.display()
+        );
+    }
+    if remaining.is_empty() {
+        return undo_session_file(&session_path, options, paths);
+    }
+
+    ensure_undo_content_unchanged(&selected, options)?;
+    let modified = modified_targets(&selected);
+    let restored = if validated.remove_targets {
+        remove_exported_files(&selected)?
+    } else {
+        restore_operations(&selected)?
+    };
+    // 残りのファイルが入っているフォルダは空にならないため、ここでは消えない
+    remove_created_dirs(&validated.created_dirs)?;
+
+    let undone = UndoLog {
+        operations: selected,
+        ..log.clone()
     };
+    log.operations = remaining;
     let body =
         serde_json::to_string_pretty(&log).context("取り消しログのシリアライズに失敗しました")?;
-    write_file_atomically(&paths.undo_path, &body, "取り消しログ")?;
-    Ok(())
-}
+    write_file_atomically(&session_path, &body, "取り消しログ")?;
 
-fn write_file_atomically(target_path: &Path, body: &str, label: &str) -> Result<()> {
-    let file_name = target_path
-        .file_name()
-        .and_then(|v| v.to_str())
-        .unwrap_or("state");
-    let temp_path = target_path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+    if validated.remove_targets {
+        remove_redo_log(paths)?;
+    } else {
+        write_redo_log(&undone, paths)
+            .context("取り消しは完了しましたが、やり直し用の記録を保存できませんでした")?;
+    }
 
-    fs::write(&temp_path, body).with_context(|| {
+    Ok(UndoResult { restored, modified })
+}
+
+// リネーム後に編集・差し替えられたファイルを古い名前へ戻さないよう、既定では中止する
+fn ensure_undo_content_unchanged(
+    operations: &[RenameOperation],
+    options: &UndoOptions,
+) -> Result<()> {
+    if options.allow_modified {
+        return Ok(());
+    }
+    let modified = modified_targets(operations);
+    if modified.is_empty() {
+        return Ok(());
+    }
+    let listed = modified
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    bail!("適用後に内容が変更されたファイルがあるため取り消しを中止しました: {listed}");
+}
+
+fn modified_targets(operations: &[RenameOperation]) -> Vec<PathBuf> {
+    operations
+        .iter()
+        .filter(|operation| operation.content_changed())
+        .map(|operation| operation.to.clone())
+        .collect()
+}
+
+fn redo_path(paths: &AppPaths) -> PathBuf {
+    paths.config_dir.join("redo-last.json")
+}
+
+// バックアップと作成フォルダは取り消し時に片付けているため、やり直し用には記録しない
+fn write_redo_log(log: &UndoLog, paths: &AppPaths) -> Result<()> {
+    let mut redo = log.clone();
+    redo.backup_originals = false;
+    redo.backup_paths.clear();
+    redo.created_dirs.clear();
+    redo.created_at = None;
+    let body =
+        serde_json::to_string_pretty(&redo).context("やり直しログのシリアライズに失敗しました")?;
+    write_file_atomically(&redo_path(paths), &body, "やり直しログ")
+}
+
+fn remove_redo_log(paths: &AppPaths) -> Result<()> {
+    let path = redo_path(paths);
+    if !path.exists() {
+        return Ok(());
+    }
+    fs::remove_file(&path)
+        .with_context(|| format!("やり直しログの削除に失敗しました: {}", path.display()))
+}
+
+pub fn redo_last() -> Result<RedoResult> {
+    let paths = app_paths()?;
+    redo_last_with_paths(&paths)
+}
+
+// 直前に取り消した適用を、パスを検証し直してから二段階リネームで再適用する
+fn redo_last_with_paths(paths: &AppPaths) -> Result<RedoResult> {
+    let path = redo_path(paths);
+    if !path.exists() {
+        bail!("やり直し可能な履歴がありません");
+    }
+    ensure_no_pending_journal(paths)?;
+    let raw = fs::read_to_string(&path)
+        .with_context(|| format!("やり直しログを読めませんでした: {}", path.display()))?;
+    let log = serde_json::from_str::<UndoLog>(&raw)
+        .with_context(|| format!("やり直しログが壊れています: {}", path.display()))?;
+    let operations = validate_redo_operations(&log)?;
+    let _lock = acquire_apply_lock(paths, &undo_log_roots(&log)?)?;
+
+    let planned: Vec<StagedRename> = operations
+        .iter()
+        .enumerate()
+        .map(|(index, operation)| StagedRename {
+            original_path: operation.from.clone(),
+            target_path: operation.to.clone(),
+            temp_path: temp_path_for(&operation.from, index),
+        })
+        .collect();
+
+    let mut journal = ApplyJournal {
+        phase: JournalPhase::Staging,
+        entries: planned.clone(),
+        undo: log.clone(),
+    };
+    journal.undo.operations.clear();
+    write_journal(&journal, paths)?;
+
+    let mut staged = Vec::<StagedRename>::with_capacity(planned.len());
+    for entry in planned {
+        if let Err(err) = move_file(&entry.original_path, &entry.temp_path) {
+            let stage_err = anyhow::Error::from(err).context(format!(
+                "一時リネームに失敗しました: {} -> {}{}",
+                entry.original_path.display(),
+                entry.temp_path.display(),
+                readonly_hint(&entry.original_path)
+            ));
+            if let Err(rollback_err) = rollback_staged_to_original_paths(&staged) {
+                return Err(stage_err.context(format!(
+                    "一時リネーム失敗後のロールバックにも失敗しました: {rollback_err}"
+                )));
+            }
+            return Err(discard_journal_after_rollback(stage_err, paths));
+        }
+        staged.push(entry);
+    }
+
+    let mut created_dirs = Vec::<PathBuf>::new();
+    let prepared = create_target_dirs(&staged, &mut created_dirs).and_then(|()| {
+        journal.phase = JournalPhase::Finalizing;
+        journal.entries = staged.clone();
+        journal.undo.created_dirs = created_dirs.clone();
+        write_journal(&journal, paths)
+    });
+    if let Err(err) = prepared {
+        let rollback_result = rollback_staged_to_original_paths(&staged)
+            .and_then(|()| remove_created_dirs(&created_dirs));
+        if let Err(rollback_err) = rollback_result {
+            return Err(err.context(format!(
+                "やり直し準備失敗後のロールバックにも失敗しました: {rollback_err}"
+            )));
+        }
+        return Err(discard_journal_after_rollback(err, paths));
+    }
+
+    for (finalized, entry) in staged.iter().enumerate() {
+        if let Err(err) = move_file(&entry.temp_path, &entry.target_path) {
+            let redo_err = anyhow::Error::from(err).context(format!(
+                "最終リネームに失敗しました: {} -> {}",
+                entry.temp_path.display(),
+                entry.target_path.display()
+            ));
+            let rollback_result = rollback_after_final_rename_failure(&staged, finalized)
+                .and_then(|()| remove_created_dirs(&created_dirs));
+            if let Err(rollback_err) = rollback_result {
+                return Err(redo_err.context(format!(
+                    "最終リネーム失敗後のロールバックにも失敗しました: {rollback_err}"
+                )));
+            }
+            return Err(discard_journal_after_rollback(redo_err, paths));
+        }
+    }
+
+    let mut undo = journal.undo.clone();
+    undo.operations = operations
+        .into_iter()
+        .map(|operation| RenameOperation::recorded(operation.from, operation.to))
+        .collect();
+    if let Err(persist_err) = write_undo_log(&undo, paths) {
+        let rollback_result = rollback_after_undo_persist_failure(&undo.operations)
+            .and_then(|()| remove_created_dirs(&created_dirs));
+        let err = compose_persist_failure_error(persist_err, rollback_result, Ok(()));
+        return Err(discard_journal_after_rollback(err, paths));
+    }
+    remove_journal(paths)?;
+
+    Ok(RedoResult {
+        reapplied: undo.operations.len(),
+    })
+}
+
+// 取り消し後に別のファイルが置かれていたり、元ファイルが消えていたりすれば再適用しない
+fn validate_redo_operations(log: &UndoLog) -> Result<Vec<RenameOperation>> {
+    if log.output_dir.is_some() {
+        bail!("コピー/ハードリンク出力の適用はやり直せません");
+    }
+    let roots = undo_log_roots(log)?;
+
+    let mut operations = Vec::<RenameOperation>::with_capacity(log.operations.len());
+    let mut seen_to = HashSet::<PathBuf>::new();
+    for operation in &log.operations {
+        let from = normalize_path_within_roots(&operation.from, &roots, "やり直し元パス")?;
+        if !from.is_file() {
+            bail!("やり直し元のファイルがありません: {}", from.display());
+        }
+        let parent = operation.to.parent().with_context(|| {
+            format!(
+                "やり直し先パスに親ディレクトリがありません: {}",
+                operation.to.display()
+            )
+        })?;
+        let file_name = operation.to.file_name().with_context(|| {
+            format!(
+                "やり直し先パスのファイル名が不正です: {}",
+                operation.to.display()
+            )
+        })?;
+        let canonical_parent = canonicalize_with_missing_dirs(parent).with_context(|| {
+            format!(
+                "やり直し先パスを解決できませんでした: {}",
+                operation.to.display()
+            )
+        })?;
+        if !path_within_any_root(&canonical_parent, &roots) {
+            bail!("やり直し先パスが許可範囲外です: {}", operation.to.display());
+        }
+        let to = canonical_parent.join(file_name);
+        if !seen_to.insert(to.clone()) {
+            bail!(
+                "やり直しログに重複したやり直し先パスがあります: {}",
+                to.display()
+            );
+        }
+        operations.push(RenameOperation::new(from, to));
+    }
+
+    // 入れ替えや連鎖リネームでは、他の操作の元ファイルが先に置かれていてもよい
+    let sources: HashSet<&Path> = operations.iter().map(|op| op.from.as_path()).collect();
+    for operation in &operations {
+        if operation.to.exists() && !sources.contains(operation.to.as_path()) {
+            bail!(
+                "やり直し先に既にファイルがあります: {}",
+                operation.to.display()
+            );
+        }
+    }
+    Ok(operations)
+}
+
+fn undo_log_roots(log: &UndoLog) -> Result<Vec<PathBuf>> {
+    let mut raw_jpg_roots = if !log.jpg_roots.is_empty() {
+        log.jpg_roots.clone()
+    } else if let Some(jpg_root) = log.jpg_root.as_ref() {
+        vec![jpg_root.clone()]
+    } else {
+        bail!("取り消しログにJPGルートが記録されていません");
+    };
+    raw_jpg_roots.extend(log.companion_roots.iter().cloned());
+    let mut jpg_roots = canonicalize_jpg_roots(&raw_jpg_roots)?;
+    if log.follow_symlinks {
+        extend_roots_with_symlinked_parents(
+            &raw_jpg_roots,
+            &mut jpg_roots,
+            log.operations
+                .iter()
+                .flat_map(|operation| [operation.from.as_path(), operation.to.as_path()]),
+        );
+    }
+    Ok(jpg_roots)
+}
+
+fn validate_undo_log(log: &UndoLog) -> Result<ValidatedUndoLog> {
+    let jpg_roots = undo_log_roots(log)?;
+
+    // コピー適用の記録では、取り消し先は出力フォルダ側にある
+    let target_roots = match log.output_dir.as_ref() {
+        Some(output_dir) => vec![fs::canonicalize(output_dir).with_context(|| {
+            format!(
+                "出力フォルダを解決できませんでした: {}",
+                output_dir.display()
+            )
+        })?],
+        None => jpg_roots.clone(),
+    };
+
+    let mut seen_from = HashSet::<PathBuf>::new();
+    let mut seen_to = HashSet::<PathBuf>::new();
+    let mut operations = Vec::<RenameOperation>::with_capacity(log.operations.len());
+    for operation in &log.operations {
+        let normalized_from =
+            normalize_path_within_roots(&operation.from, &jpg_roots, "取り消し元パス")?;
+        let normalized_to =
+            normalize_path_within_roots(&operation.to, &target_roots, "取り消し先パス")?;
+
+        if !seen_from.insert(normalized_from.clone()) {
+            bail!(
+                "取り消しログに重複した取り消し元パスがあります: {}",
+                normalized_from.display()
+            );
+        }
+        if !seen_to.insert(normalized_to.clone()) {
+            bail!(
+                "取り消しログに重複した取り消し先パスがあります: {}",
+                normalized_to.display()
+            );
+        }
+
+        operations.push(RenameOperation {
+            from: normalized_from,
+            to: normalized_to,
+            size: operation.size,
+            hash: operation.hash.clone(),
+            original_modified: operation.original_modified,
+            original_created: operation.original_created,
+        });
+    }
+
+    let mut created_dirs = Vec::<PathBuf>::new();
+    for created_dir in &log.created_dirs {
+        if !created_dir.is_dir() {
+            continue;
+        }
+        created_dirs.push(normalize_path_within_roots(
+            created_dir,
+            &target_roots,
+            "作成フォルダ",
+        )?);
+    }
+
+    if !log.backup_originals {
+        return Ok(ValidatedUndoLog {
+            operations,
+            jpg_roots,
+            backup_roots: Vec::new(),
+            backup_paths: Vec::new(),
+            created_dirs,
+            remove_targets: log.output_dir.is_some(),
+        });
+    }
+
+    let backup_roots = backup_roots_for(&jpg_roots, log.backup_dir.as_deref())?;
+
+    let mut backup_paths = Vec::<PathBuf>::new();
+    for backup_path in &log.backup_paths {
+        if !backup_path.exists() {
+            continue;
+        }
+        if backup_path.is_dir() {
+            bail!(
+                "取り消しログのバックアップパスがディレクトリです: {}",
+                backup_path.display()
+            );
+        }
+        backup_paths.push(normalize_path_within_roots(
+            backup_path,
+            &backup_roots,
+            "バックアップパス",
+        )?);
+    }
+
+    Ok(ValidatedUndoLog {
+        operations,
+        jpg_roots,
+        backup_roots,
+        backup_paths,
+        created_dirs,
+        remove_targets: log.output_dir.is_some(),
+    })
+}
+
+fn normalize_path_within_roots(path: &Path, roots: &[PathBuf], label: &str) -> Result<PathBuf> {
+    let parent = path
+        .parent()
+        .with_context(|| format!("{label}に親ディレクトリがありません: {}", path.display()))?;
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{label}のファイル名が不正です: {}", path.display()))?;
+    let canonical_parent = fs::canonicalize(parent).with_context(|| {
         format!(
-            "{label}の一時ファイル書き込みに失敗しました: {}",
-            temp_path.display()
+            "{label}の親ディレクトリを解決できませんでした: {}",
+            parent.display()
         )
     })?;
+    let Some(root) = pick_most_specific_root(&canonical_parent, roots) else {
+        bail!("{label}が許可範囲外です: {}", path.display());
+    };
+    if !canonical_parent.starts_with(root) {
+        bail!("{label}が許可範囲外です: {}", path.display());
+    };
+    Ok(canonical_parent.join(file_name))
+}
 
-    match fs::rename(&temp_path, target_path) {
-        Ok(()) => Ok(()),
-        Err(primary_rename_err) => {
-            if target_path.exists() {
-                fs::remove_file(target_path).with_context(|| {
-                    format!(
-                        "{label}の既存ファイル削除に失敗しました: {}",
-                        target_path.display()
-                    )
-                })?;
-                fs::rename(&temp_path, target_path).with_context(|| {
-                    format!(
-                        "{label}の置き換えに失敗しました: {} -> {}",
-                        temp_path.display(),
-                        target_path.display()
-                    )
-                })?;
-                return Ok(());
-            }
-
-            let _ = fs::remove_file(&temp_path);
-            Err(anyhow::Error::from(primary_rename_err).context(format!(
-                "{label}の置き換えに失敗しました: {} -> {}",
-                temp_path.display(),
-                target_path.display()
-            )))
+fn restore_operations(operations: &[RenameOperation]) -> Result<usize> {
+    let mut restored = 0usize;
+    for op in operations.iter().rev() {
+        if !op.to.exists() {
+            continue;
+        }
+        move_file(&op.to, &op.from).with_context(|| {
+            format!(
+                "取り消しに失敗しました: {} -> {}",
+                op.to.display(),
+                op.from.display()
+            )
+        })?;
+        if let Some(modified) = op.original_modified {
+            set_file_times(&op.from, modified, op.original_created).with_context(|| {
+                format!("ファイル日時を戻せませんでした: {}", op.from.display())
+            })?;
         }
+        restored += 1;
     }
+    Ok(restored)
 }
 
-fn cleanup_backup_if_needed(log: &ValidatedUndoLog) -> Result<()> {
-    if log.backup_paths.is_empty() {
-        return Ok(());
+fn set_capture_times(
+    operations: &[RenameOperation],
+    capture_dates: &HashMap<&Path, DateTime<Local>>,
+) -> Result<()> {
+    for operation in operations {
+        let Some(date) = capture_dates.get(operation.from.as_path()) else {
+            continue;
+        };
+        set_file_times(&operation.to, *date, Some(*date)).with_context(|| {
+            format!(
+                "ファイル日時を設定できませんでした: {}",
+                operation.to.display()
+            )
+        })?;
     }
+    Ok(())
+}
 
-    let backup_roots: Vec<PathBuf> = log
-        .jpg_roots
-        .iter()
-        .map(|root| root.join("backup"))
+// 作成日時を設定できるのはWindowsのみ。ほかのOSでは更新日時だけを揃える
+fn set_file_times(
+    path: &Path,
+    modified: DateTime<Local>,
+    created: Option<DateTime<Local>>,
+) -> io::Result<()> {
+    let times = fs::FileTimes::new().set_modified(SystemTime::from(modified));
+    #[cfg(windows)]
+    let times = match created {
+        Some(created) => {
+            use std::os::windows::fs::FileTimesExt;
+            times.set_created(SystemTime::from(created))
+        }
+        None => times,
+    };
+    #[cfg(not(windows))]
+    let _ = created;
+    // 読み取り専用のファイルでも日時だけは変えられるよう、書き込み権限では開かない
+    let mut open_options = fs::OpenOptions::new();
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_WRITE_ATTRIBUTES
+        open_options.access_mode(0x100);
+    }
+    #[cfg(not(windows))]
+    open_options.read(true);
+    open_options.open(path)?.set_times(times)
+}
+
+fn persist_undo(
+    operations: &[RenameOperation],
+    plan: &RenamePlan,
+    options: &ApplyOptions,
+    backup_paths: &[PathBuf],
+    created_dirs: &[PathBuf],
+    output_dir: Option<&Path>,
+    paths: &AppPaths,
+) -> Result<()> {
+    let log = build_undo_log(
+        operations,
+        plan,
+        options,
+        backup_paths,
+        created_dirs,
+        output_dir,
+    );
+    write_undo_log(&log, paths)
+}
+
+fn build_undo_log(
+    operations: &[RenameOperation],
+    plan: &RenamePlan,
+    options: &ApplyOptions,
+    backup_paths: &[PathBuf],
+    created_dirs: &[PathBuf],
+    output_dir: Option<&Path>,
+) -> UndoLog {
+    UndoLog {
+        operations: operations.to_vec(),
+        backup_originals: options.backup_originals && output_dir.is_none(),
+        jpg_root: Some(plan.jpg_root.clone()),
+        jpg_roots: plan_jpg_roots(plan),
+        backup_paths: backup_paths.to_vec(),
+        follow_symlinks: plan.follow_symlinks,
+        companion_roots: plan.companion_roots.clone(),
+        created_dirs: created_dirs.to_vec(),
+        output_dir: output_dir.map(Path::to_path_buf),
+        created_at: None,
+        backup_dir: options
+            .backup_dir
+            .clone()
+            .filter(|_| options.backup_originals && output_dir.is_none()),
+    }
+}
+
+// 適用ごとに履歴へ1ファイル追加し、古いものから上限を超えた分を削除する
+fn write_undo_log(log: &UndoLog, paths: &AppPaths) -> Result<()> {
+    let history_dir = undo_history_dir(paths);
+    fs::create_dir_all(&history_dir).with_context(|| {
+        format!(
+            "設定ディレクトリ作成に失敗しました: {}",
+            history_dir.display()
+        )
+    })?;
+
+    let now = Local::now();
+    let mut log = log.clone();
+    log.created_at = Some(now.to_rfc3339());
+    let stamp = now.format("%Y%m%d-%H%M%S-%3f").to_string();
+    let mut session_path = history_dir.join(format!("{stamp}.json"));
+    let mut n = 1usize;
+    while session_path.exists() {
+        session_path = history_dir.join(format!("{stamp}-{n:03}.json"));
+        n += 1;
+    }
+
+    let body =
+        serde_json::to_string_pretty(&log).context("取り消しログのシリアライズに失敗しました")?;
+    write_file_atomically(&session_path, &body, "取り消しログ")?;
+    prune_undo_history(paths)?;
+    // 新しい適用を記録したら、それより前に取り消した分はやり直せない
+    remove_redo_log(paths)
+}
+
+fn prune_undo_history(paths: &AppPaths) -> Result<()> {
+    let sessions = undo_session_paths(paths)?;
+    let excess = sessions.len().saturating_sub(MAX_UNDO_SESSIONS);
+    for (_, path) in sessions.into_iter().take(excess) {
+        fs::remove_file(&path)
+            .with_context(|| format!("古い取り消し履歴の削除に失敗しました: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn backup_index_path(paths: &AppPaths) -> PathBuf {
+    paths.config_dir.join("backup-sessions.json")
+}
+
+fn load_backup_index(paths: &AppPaths) -> Result<Vec<BackupSessionRecord>> {
+    let path = backup_index_path(paths);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let raw = fs::read_to_string(&path)
+        .with_context(|| format!("バックアップ履歴を読めませんでした: {}", path.display()))?;
+    serde_json::from_str(&raw)
+        .with_context(|| format!("バックアップ履歴が壊れています: {}", path.display()))
+}
+
+fn write_backup_index(records: &[BackupSessionRecord], paths: &AppPaths) -> Result<()> {
+    let body = serde_json::to_string_pretty(records)
+        .context("バックアップ履歴のシリアライズに失敗しました")?;
+    write_file_atomically(&backup_index_path(paths), &body, "バックアップ履歴")
+}
+
+// 保持期間で消せるよう、適用ごとのバックアップを設定フォルダに記録しておく
+fn record_backup_session(
+    plan: &RenamePlan,
+    options: &ApplyOptions,
+    backup_paths: &[PathBuf],
+    paths: &AppPaths,
+) -> Result<()> {
+    let jpg_roots = canonicalize_jpg_roots(&plan_allowed_roots(plan))?;
+    let mut records = load_backup_index(paths)?;
+    records.push(BackupSessionRecord {
+        created_at: Local::now().to_rfc3339(),
+        backup_roots: backup_roots_for(&jpg_roots, options.backup_dir.as_deref())?,
+        paths: backup_paths.to_vec(),
+    });
+    write_backup_index(&records, paths)
+}
+
+pub fn cleanup_backups(retention: &BackupRetention) -> Result<BackupCleanupResult> {
+    let paths = app_paths()?;
+    cleanup_backups_with_paths(retention, &paths)
+}
+
+fn cleanup_backups_with_paths(
+    retention: &BackupRetention,
+    paths: &AppPaths,
+) -> Result<BackupCleanupResult> {
+    enforce_backup_retention_at(retention, Local::now(), paths)
+}
+
+fn enforce_backup_retention(
+    retention: &BackupRetention,
+    now: DateTime<Local>,
+    paths: &AppPaths,
+) -> Result<()> {
+    enforce_backup_retention_at(retention, now, paths).map(|_| ())
+}
+
+// 取り消し済みなどで既にファイルがない記録は、件数に数えず履歴から外す
+fn enforce_backup_retention_at(
+    retention: &BackupRetention,
+    now: DateTime<Local>,
+    paths: &AppPaths,
+) -> Result<BackupCleanupResult> {
+    let mut result = BackupCleanupResult::default();
+    if retention.is_unlimited() {
+        return Ok(result);
+    }
+    let records: Vec<BackupSessionRecord> = load_backup_index(paths)?
+        .into_iter()
+        .filter(|record| record.paths.iter().any(|path| path.exists()))
         .collect();
+    let total = records.len();
+    let cutoff = retention
+        .keep_days
+        .map(|days| now - Duration::days(i64::from(days)));
+
+    let mut kept = Vec::<BackupSessionRecord>::with_capacity(total);
+    for (index, record) in records.into_iter().enumerate() {
+        let over_count = retention
+            .keep_sessions
+            .is_some_and(|keep| index + keep < total);
+        let too_old = cutoff.is_some_and(|cutoff| {
+            DateTime::parse_from_rfc3339(&record.created_at)
+                .is_ok_and(|created_at| created_at < cutoff)
+        });
+        if !over_count && !too_old {
+            kept.push(record);
+            continue;
+        }
+        result.removed_files += remove_backup_session_files(&record)?;
+        result.removed_sessions += 1;
+    }
+    write_backup_index(&kept, paths)?;
+    Ok(result)
+}
+
+fn remove_backup_session_files(record: &BackupSessionRecord) -> Result<usize> {
+    let mut removed = 0usize;
+    for path in &record.paths {
+        let Some(root) = pick_most_specific_root(path, &record.backup_roots) else {
+            bail!("バックアップ履歴のパスが許可範囲外です: {}", path.display());
+        };
+        if !path.is_file() {
+            continue;
+        }
+        fs::remove_file(path).with_context(|| {
+            format!("バックアップファイル削除に失敗しました: {}", path.display())
+        })?;
+        removed += 1;
+        if let Some(parent) = path.parent() {
+            remove_empty_dirs_until(parent, root)?;
+        }
+    }
+    for root in &record.backup_roots {
+        if root.is_dir() && directory_is_empty(root)? {
+            fs::remove_dir(root).with_context(|| {
+                format!("バックアップフォルダ削除に失敗しました: {}", root.display())
+            })?;
+        }
+    }
+    Ok(removed)
+}
+
+fn journal_path(paths: &AppPaths) -> PathBuf {
+    paths.config_dir.join("apply-journal.json")
+}
+
+fn write_journal(journal: &ApplyJournal, paths: &AppPaths) -> Result<()> {
+    fs::create_dir_all(&paths.config_dir).with_context(|| {
+        format!(
+            "設定ディレクトリ作成に失敗しました: {}",
+            paths.config_dir.display()
+        )
+    })?;
+    let body = serde_json::to_string_pretty(journal)
+        .context("適用ジャーナルのシリアライズに失敗しました")?;
+    write_file_atomically(&journal_path(paths), &body, "適用ジャーナル")
+}
+
+fn load_journal(paths: &AppPaths) -> Result<Option<ApplyJournal>> {
+    let path = journal_path(paths);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let raw = fs::read_to_string(&path)
+        .with_context(|| format!("適用ジャーナルを読めませんでした: {}", path.display()))?;
+    let journal =
+        serde_json::from_str::<ApplyJournal>(&raw).context("適用ジャーナルが壊れています")?;
+    Ok(Some(journal))
+}
+
+fn remove_journal(paths: &AppPaths) -> Result<()> {
+    let path = journal_path(paths);
+    if !path.exists() {
+        return Ok(());
+    }
+    fs::remove_file(&path)
+        .with_context(|| format!("適用ジャーナルの削除に失敗しました: {}", path.display()))
+}
+
+// ロールバックまで済んだ失敗ではジャーナルを残す必要がない
+fn discard_journal_after_rollback(err: anyhow::Error, paths: &AppPaths) -> anyhow::Error {
+    match remove_journal(paths) {
+        Ok(()) => err,
+        Err(journal_err) => err.context(format!(
+            "ロールバック後の適用ジャーナル削除にも失敗しました: {journal_err}"
+        )),
+    }
+}
+
+const APPLY_LOCK_FILE_NAME: &str = ".fphoto_renamer.lock";
+
+// 同じフォルダや取り消しログへの同時適用を防ぐロック。ロックはOSが管理するため異常終了しても残らない
+struct ApplyLock {
+    held: Vec<(PathBuf, fs::File)>,
+}
+
+impl Drop for ApplyLock {
+    fn drop(&mut self) {
+        for (path, file) in self.held.drain(..).rev() {
+            // ロックを持ったまま消す。消す前のファイルを開いていた側は lock_file で開き直す
+            #[cfg(unix)]
+            let _ = fs::remove_file(&path);
+            #[cfg(not(unix))]
+            let _ = path;
+            drop(file);
+        }
+    }
+}
+
+fn acquire_apply_lock(paths: &AppPaths, roots: &[PathBuf]) -> Result<ApplyLock> {
+    // 設定フォルダを作れない場合は、後段の取り消しログ保存で具体的なエラーにする
+    let mut lock_paths = Vec::new();
+    if fs::create_dir_all(&paths.config_dir).is_ok() {
+        lock_paths.push(paths.config_dir.join("apply.lock"));
+    }
+    let mut roots = roots.to_vec();
+    roots.sort();
+    roots.dedup();
+    lock_paths.extend(
+        roots
+            .iter()
+            .filter(|root| root.is_dir())
+            .map(|root| root.join(APPLY_LOCK_FILE_NAME)),
+    );
+
+    let mut lock = ApplyLock { held: Vec::new() };
+    for path in lock_paths {
+        let file = lock_file(&path)?;
+        lock.held.push((path, file));
+    }
+    Ok(lock)
+}
+
+fn lock_file(path: &Path) -> Result<fs::File> {
+    loop {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("ロックファイルを作成できませんでした: {}", path.display()))?;
+        match file.try_lock() {
+            // 開いてからロックするまでに前の持ち主が消したファイルなら、新しいファイルで取り直す
+            Ok(()) if is_current_lock_file(&file, path) => return Ok(file),
+            Ok(()) => continue,
+            Err(fs::TryLockError::WouldBlock) => bail!(
+                "別の適用または取り消しが実行中です。完了してから再実行してください: {}",
+                path.display()
+            ),
+            Err(fs::TryLockError::Error(err)) => {
+                return Err(err)
+                    .with_context(|| format!("ロックを取得できませんでした: {}", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_current_lock_file(file: &fs::File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(held), Ok(current)) => held.dev() == current.dev() && held.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+// unix 以外ではロックファイルを消さないため、開いたファイルが常に現在のもの
+#[cfg(not(unix))]
+fn is_current_lock_file(_file: &fs::File, _path: &Path) -> bool {
+    true
+}
+
+fn ensure_no_pending_journal(paths: &AppPaths) -> Result<()> {
+    if journal_path(paths).exists() {
+        bail!("中断された適用があります。先に recover で完了または取り消しを行ってください");
+    }
+    Ok(())
+}
+
+pub fn pending_apply() -> Result<Option<PendingApply>> {
+    let paths = app_paths()?;
+    pending_apply_with_paths(&paths)
+}
+
+fn pending_apply_with_paths(paths: &AppPaths) -> Result<Option<PendingApply>> {
+    Ok(load_journal(paths)?.map(|journal| PendingApply {
+        phase: journal.phase,
+        entries: journal.entries.len(),
+        leftover_temp_files: journal
+            .entries
+            .iter()
+            .filter(|entry| entry.temp_path.exists())
+            .map(|entry| entry.temp_path.clone())
+            .collect(),
+    }))
+}
+
+pub fn recover_interrupted_apply(action: RecoverAction) -> Result<RecoverResult> {
+    let paths = app_paths()?;
+    recover_interrupted_apply_with_paths(action, &paths)
+}
+
+fn recover_interrupted_apply_with_paths(
+    action: RecoverAction,
+    paths: &AppPaths,
+) -> Result<RecoverResult> {
+    let Some(mut journal) = load_journal(paths)? else {
+        bail!("中断された適用はありません");
+    };
+    let validated = validate_undo_log(&journal.undo)?;
+    validate_journal_entries(&journal.entries, &validated.jpg_roots)?;
+    let _lock = acquire_apply_lock(paths, &validated.jpg_roots)?;
+
+    let recovered = match action {
+        RecoverAction::Complete => complete_journal(&mut journal, paths)?,
+        RecoverAction::Rollback => {
+            let restored = rollback_journal(&journal)?;
+            cleanup_backup_if_needed(&validated)?;
+            remove_created_dirs(&validated.created_dirs)?;
+            restored
+        }
+    };
+    remove_journal(paths)?;
+
+    Ok(RecoverResult { action, recovered })
+}
+
+fn validate_journal_entries(entries: &[StagedRename], roots: &[PathBuf]) -> Result<()> {
+    for entry in entries {
+        let is_temp_name = entry
+            .temp_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(".fphoto_tmp_"));
+        if !is_temp_name {
+            bail!(
+                "適用ジャーナルの一時ファイル名が不正です: {}",
+                entry.temp_path.display()
+            );
+        }
+        for (path, label) in [
+            (&entry.original_path, "元ファイル"),
+            (&entry.temp_path, "一時ファイル"),
+            (&entry.target_path, "リネーム先"),
+        ] {
+            let parent = path.parent().with_context(|| {
+                format!(
+                    "適用ジャーナルの{label}に親ディレクトリがありません: {}",
+                    path.display()
+                )
+            })?;
+            let canonical_parent = canonicalize_with_missing_dirs(parent).with_context(|| {
+                format!(
+                    "適用ジャーナルの{label}を解決できませんでした: {}",
+                    path.display()
+                )
+            })?;
+            if !path_within_any_root(&canonical_parent, roots) {
+                bail!(
+                    "適用ジャーナルの{label}が許可範囲外です: {}",
+                    path.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// 残りのリネームを最後まで進め、取り消しログを保存する
+fn complete_journal(journal: &mut ApplyJournal, paths: &AppPaths) -> Result<usize> {
+    if journal.phase == JournalPhase::Staging {
+        for entry in &journal.entries {
+            if entry.temp_path.exists() || !entry.original_path.exists() {
+                continue;
+            }
+            move_file(&entry.original_path, &entry.temp_path).with_context(|| {
+                format!(
+                    "一時リネームに失敗しました: {} -> {}",
+                    entry.original_path.display(),
+                    entry.temp_path.display()
+                )
+            })?;
+        }
+    }
+
+    let mut created_dirs = journal.undo.created_dirs.clone();
+    let mut operations = Vec::<RenameOperation>::with_capacity(journal.entries.len());
+    for entry in &journal.entries {
+        if entry.temp_path.exists() {
+            create_missing_parent_dirs(&entry.target_path, &mut created_dirs)?;
+            move_file(&entry.temp_path, &entry.target_path).with_context(|| {
+                format!(
+                    "最終リネームに失敗しました: {} -> {}",
+                    entry.temp_path.display(),
+                    entry.target_path.display()
+                )
+            })?;
+        }
+        if entry.target_path.exists() {
+            operations.push(RenameOperation::recorded(
+                entry.original_path.clone(),
+                entry.target_path.clone(),
+            ));
+        }
+    }
+
+    journal.undo.operations = operations;
+    journal.undo.created_dirs = created_dirs;
+    write_undo_log(&journal.undo, paths)?;
+    Ok(journal.undo.operations.len())
+}
+
+// 最終リネーム済みのものを一時名へ戻してから、すべて元の名前へ戻す
+fn rollback_journal(journal: &ApplyJournal) -> Result<usize> {
+    if journal.phase == JournalPhase::Finalizing {
+        for entry in journal.entries.iter().rev() {
+            if entry.temp_path.exists() || !entry.target_path.exists() {
+                continue;
+            }
+            move_file(&entry.target_path, &entry.temp_path).with_context(|| {
+                format!(
+                    "ロールバック(退避)に失敗しました: {} -> {}",
+                    entry.target_path.display(),
+                    entry.temp_path.display()
+                )
+            })?;
+        }
+    }
+    let restored = journal
+        .entries
+        .iter()
+        .filter(|entry| entry.temp_path.exists())
+        .count();
+    rollback_staged_to_original_paths(&journal.entries)?;
+    Ok(restored)
+}
+
+fn write_file_atomically(target_path: &Path, body: &str, label: &str) -> Result<()> {
+    let file_name = target_path
+        .file_name()
+        .and_then(|v| v.to_str())
+        .unwrap_or("state");
+    let temp_path = target_path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+    fs::write(&temp_path, body).with_context(|| {
+        format!(
+            "{label}の一時ファイル書き込みに失敗しました: {}",
+            temp_path.display()
+        )
+    })?;
+
+    match fs::rename(&temp_path, target_path) {
+        Ok(()) => Ok(()),
+        Err(primary_rename_err) => {
+            if target_path.exists() {
+                fs::remove_file(target_path).with_context(|| {
+                    format!(
+                        "{label}の既存ファイル削除に失敗しました: {}",
+                        target_path.display()
+                    )
+                })?;
+                fs::rename(&temp_path, target_path).with_context(|| {
+                    format!(
+                        "{label}の置き換えに失敗しました: {} -> {}",
+                        temp_path.display(),
+                        target_path.display()
+                    )
+                })?;
+                return Ok(());
+            }
+
+            let _ = fs::remove_file(&temp_path);
+            Err(anyhow::Error::from(primary_rename_err).context(format!(
+                "{label}の置き換えに失敗しました: {} -> {}",
+                temp_path.display(),
+                target_path.display()
+            )))
+        }
+    }
+}
+
+fn cleanup_backup_if_needed(log: &ValidatedUndoLog) -> Result<()> {
+    if log.backup_paths.is_empty() {
+        return Ok(());
+    }
+
+    let backup_roots = &log.backup_roots;
+    for backup_path in &log.backup_paths {
+        if !backup_path.exists() {
+            continue;
+        }
+        if backup_path.is_dir() {
+            bail!(
+                "取り消しログのバックアップパスがディレクトリです: {}",
+                backup_path.display()
+            );
+        }
+        fs::remove_file(backup_path).with_context(|| {
+            format!(
+                "バックアップファイル削除に失敗しました: {}",
+                backup_path.display()
+            )
+        })?;
+        if let Some(parent) = backup_path.parent() {
+            if let Some(backup_root) = pick_most_specific_root(parent, backup_roots) {
+                remove_empty_dirs_until(parent, backup_root)?;
+            }
+        }
+    }
+
+    for backup_root in backup_roots {
+        if backup_root.exists() && backup_root.is_dir() && directory_is_empty(backup_root)? {
+            fs::remove_dir(backup_root).with_context(|| {
+                format!(
+                    "バックアップフォルダ削除に失敗しました: {}",
+                    backup_root.display()
+                )
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn directory_is_empty(path: &Path) -> Result<bool> {
+    let mut entries = fs::read_dir(path)
+        .with_context(|| format!("ディレクトリを読めませんでした: {}", path.display()))?;
+    Ok(entries.next().is_none())
+}
+
+fn remove_empty_dirs_until(start: &Path, stop: &Path) -> Result<()> {
+    let mut current = Some(start.to_path_buf());
+    while let Some(dir) = current {
+        if dir == stop || !dir.starts_with(stop) {
+            break;
+        }
+        if !dir.exists() || !dir.is_dir() || !directory_is_empty(&dir)? {
+            break;
+        }
+        fs::remove_dir(&dir)
+            .with_context(|| format!("空ディレクトリ削除に失敗しました: {}", dir.display()))?;
+        current = dir.parent().map(PathBuf::from);
+    }
+    Ok(())
+}
+
+// 移動先に既にファイルがあれば失敗させる。ハードリンクを張れる場合は確認と作成を一度に行う
+fn move_file_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+        Err(_) if fs::symlink_metadata(to).is_ok() => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("移動先に既にファイルがあります: {}", to.display()),
+        )),
+        Err(_) => move_file(from, to),
+    }
+}
+
+// 別ファイルシステムへの移動はrenameできない(EXDEV)ため、コピー・同期・削除で代替する
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match retry_transient(|| fs::rename(from, to), is_transient_lock_error) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            let is_file = fs::symlink_metadata(from)
+                .map(|metadata| metadata.is_file())
+                .unwrap_or(false);
+            if !is_file {
+                return Err(err);
+            }
+            copy_then_remove(from, to)
+        }
+        result => result,
+    }
+}
+
+#[derive(Default)]
+struct ReadonlyGuard {
+    cleared: Vec<(StagedRename, fs::Permissions)>,
+}
+
+impl Drop for ReadonlyGuard {
+    fn drop(&mut self) {
+        for (entry, permissions) in &self.cleared {
+            // 失敗やロールバックで元の名前に戻ったファイルはそちら、適用済みなら変更後のファイルへ戻す
+            let current = [&entry.original_path, &entry.target_path, &entry.temp_path]
+                .into_iter()
+                .find(|path| path.exists());
+            if let Some(path) = current {
+                let _ = fs::set_permissions(path, permissions.clone());
+            }
+        }
+    }
+}
+
+fn clear_readonly_attributes(entries: &[StagedRename]) -> Result<ReadonlyGuard> {
+    let mut guard = ReadonlyGuard::default();
+    for entry in entries {
+        let permissions = fs::metadata(&entry.original_path)
+            .with_context(|| {
+                format!(
+                    "ファイル情報を取得できませんでした: {}",
+                    entry.original_path.display()
+                )
+            })?
+            .permissions();
+        if !permissions.readonly() {
+            continue;
+        }
+        fs::set_permissions(&entry.original_path, writable_permissions(&permissions))
+            .with_context(|| {
+                format!(
+                    "読み取り専用属性を解除できませんでした: {}",
+                    entry.original_path.display()
+                )
+            })?;
+        guard.cleared.push((entry.clone(), permissions));
+    }
+    Ok(guard)
+}
+
+#[cfg(unix)]
+fn writable_permissions(permissions: &fs::Permissions) -> fs::Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    fs::Permissions::from_mode(permissions.mode() | 0o200)
+}
+
+#[cfg(not(unix))]
+fn writable_permissions(permissions: &fs::Permissions) -> fs::Permissions {
+    let mut writable = permissions.clone();
+    #[allow(clippy::permissions_set_readonly_false)]
+    writable.set_readonly(false);
+    writable
+}
+
+fn readonly_hint(path: &Path) -> &'static str {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly()) {
+        " (読み取り専用のファイルです。読み取り専用属性を一時的に解除する設定で再実行してください)"
+    } else {
+        ""
+    }
+}
+
+const TRANSIENT_RETRY_DELAYS_MS: [u64; 4] = [50, 100, 200, 400];
+
+// ウイルス対策ソフトや同期クライアントによる一時的なロックは、少し待って再試行する
+fn retry_transient<T>(
+    mut op: impl FnMut() -> io::Result<T>,
+    is_transient: impl Fn(&io::Error) -> bool,
+) -> io::Result<T> {
+    for delay_ms in TRANSIENT_RETRY_DELAYS_MS {
+        match op() {
+            Err(err) if is_transient(&err) => {
+                std::thread::sleep(std::time::Duration::from_millis(delay_ms));
+            }
+            result => return result,
+        }
+    }
+    op()
+}
+
+#[cfg(windows)]
+fn is_transient_lock_error(err: &io::Error) -> bool {
+    // ERROR_ACCESS_DENIED / ERROR_SHARING_VIOLATION / ERROR_LOCK_VIOLATION
+    matches!(err.raw_os_error(), Some(5 | 32 | 33))
+}
+
+#[cfg(not(windows))]
+fn is_transient_lock_error(_err: &io::Error) -> bool {
+    false
+}
+
+// 移動先には同じフォルダの作業ファイルへ書き切ってから置くため、途中で止まっても不完全なファイルは残らない
+fn copy_then_remove(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("移動先が既に存在します: {}", to.display()),
+        ));
+    }
+    let file_name = to
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "file".to_string());
+    let staging_path =
+        to.with_file_name(format!(".fphoto_xdev_{}_{}", std::process::id(), file_name));
+    let copied = copy_synced(from, &staging_path).and_then(|()| fs::rename(&staging_path, to));
+    if let Err(err) = copied {
+        let _ = fs::remove_file(&staging_path);
+        return Err(err);
+    }
+    if let Some(parent) = to.parent() {
+        sync_dir(parent);
+    }
+    if let Err(err) = fs::remove_file(from) {
+        // 元ファイルを消せない場合は、移動前の状態へ戻す
+        let _ = fs::remove_file(to);
+        return Err(err);
+    }
+    Ok(())
+}
+
+fn copy_synced(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    let mut source = fs::File::open(from)?;
+    let mut dest = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    io::copy(&mut source, &mut dest)?;
+    dest.set_permissions(metadata.permissions())?;
+    dest.set_modified(metadata.modified()?)?;
+    dest.sync_all()
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) {
+    if let Ok(handle) = fs::File::open(dir) {
+        let _ = handle.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) {}
+
+fn temp_path_for(original_path: &Path, index: usize) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let parent = original_path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = original_path
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_else(|| "file".to_string());
+    parent.join(format!(".fphoto_tmp_{}_{}_{}", now, index, file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::backup_original_files;
+    use super::{
+        acquire_apply_lock, apply_plan_subset_with_paths, apply_plan_with_options,
+        apply_plan_with_options_with_paths, apply_plan_with_progress_with_paths, build_undo_log,
+        cleanup_backup_if_needed, copy_then_remove, enforce_backup_retention_at,
+        ensure_no_pending_journal, is_current_lock_file, journal_path,
+        list_undo_sessions_with_paths, load_backup_index, lock_file, pending_apply_with_paths,
+        preview_undo_with_paths, read_undo_log, recover_interrupted_apply_with_paths,
+        redo_last_with_paths, remove_created_dirs, remove_exported_files, resolve_backup_path,
+        resolve_backup_path_with_reserved, restore_operations, retry_transient,
+        undo_last_files_with_paths, undo_last_with_paths, undo_session_paths,
+        undo_session_with_paths, unique_backup_path, validate_plan_against_fs, validate_undo_log,
+        write_backup_index, write_journal, ApplyJournal, ApplyMode, ApplyOptions, ApplyOutcome,
+        BackupFormat, BackupSessionRecord, CollisionPolicy, JournalPhase, RecoverAction,
+        StagedRename, StaleReason, UndoLog, UndoOptions, UndoPreviewEntry, APPLY_LOCK_FILE_NAME,
+    };
+    use crate::config::{AppPaths, BackupRetention};
+    use crate::metadata::{FieldProvenance, MetadataSource, PhotoMetadata};
+    use crate::planner::{
+        FileFingerprint, PlanTimings, ProgressSink, RenameCandidate, RenamePlan, RenameStats,
+    };
+    use chrono::{Local, TimeZone};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::Read;
+    #[cfg(unix)]
+    use std::os::unix::fs as unix_fs;
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    fn sample_metadata(jpg_path: PathBuf) -> PhotoMetadata {
+        PhotoMetadata {
+            source: MetadataSource::JpgExif,
+            date: Local::now().fixed_offset(),
+            camera_make: Some("FUJIFILM".to_string()),
+            camera_model: Some("X-T5".to_string()),
+            lens_make: Some("FUJIFILM".to_string()),
+            lens_model: Some("XF16-55".to_string()),
+            film_sim: Some("CLASSIC CHROME".to_string()),
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            extra_tags: Default::default(),
+            original_name: "IMG_0001".to_string(),
+            jpg_path,
+        }
+    }
+
+    #[test]
+    fn apply_plan_returns_unchanged_when_no_candidates_changed() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("IMG_0001.JPG");
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.clone(),
+                target_path: target,
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original),
+                rendered_base: "IMG_0001".to_string(),
+                changed: false,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+
+        let result = apply_plan_with_options(&plan, &ApplyOptions::default())
+            .expect("unchanged plan should be accepted");
+        assert_eq!(result.applied, 0);
+        assert_eq!(result.unchanged, 1);
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.files[0].outcome, ApplyOutcome::Unchanged);
+    }
+
+    #[test]
+    fn apply_plan_with_multiple_jpg_roots_succeeds() {
+        let temp = tempdir().expect("tempdir");
+        let root_a = temp.path().join("a");
+        let root_b = temp.path().join("b");
+        fs::create_dir_all(&root_a).expect("create root a");
+        fs::create_dir_all(&root_b).expect("create root b");
+
+        let original_a = root_a.join("IMG_A.JPG");
+        let original_b = root_b.join("IMG_B.JPG");
+        let target_a = root_a.join("IMG_A_NEW.JPG");
+        let target_b = root_b.join("IMG_B_NEW.JPG");
+        fs::write(&original_a, b"A").expect("write A");
+        fs::write(&original_b, b"B").expect("write B");
+
+        let plan = RenamePlan {
+            jpg_root: temp.path().to_path_buf(),
+            jpg_roots: vec![root_a.clone(), root_b.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![
+                RenameCandidate {
+                    original_path: original_a.clone(),
+                    target_path: target_a.clone(),
+                    metadata_source: MetadataSource::JpgExif,
+                    source_label: "jpg".to_string(),
+                    metadata: sample_metadata(original_a.clone()),
+                    rendered_base: "IMG_A_NEW".to_string(),
+                    changed: true,
+                    warnings: Vec::new(),
+                    fingerprint: None,
+                    provenance: FieldProvenance::default(),
+                    raw_paths: Vec::new(),
+                    raw_match: None,
+                    xmp_path: None,
+                },
+                RenameCandidate {
+                    original_path: original_b.clone(),
+                    target_path: target_b.clone(),
+                    metadata_source: MetadataSource::JpgExif,
+                    source_label: "jpg".to_string(),
+                    metadata: sample_metadata(original_b.clone()),
+                    rendered_base: "IMG_B_NEW".to_string(),
+                    changed: true,
+                    warnings: Vec::new(),
+                    fingerprint: None,
+                    provenance: FieldProvenance::default(),
+                    raw_paths: Vec::new(),
+                    raw_match: None,
+                    xmp_path: None,
+                },
+            ],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let result = apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should succeed for multi roots");
+
+        assert_eq!(result.applied, 2);
+        assert!(target_a.exists());
+        assert!(target_b.exists());
+        assert_eq!(
+            result
+                .files
+                .iter()
+                .map(|file| (file.target_path.clone(), file.outcome.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (target_a.clone(), ApplyOutcome::Renamed),
+                (target_b.clone(), ApplyOutcome::Renamed),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_plan_against_fs_reports_changed_and_missing_files() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let kept = jpg_root.join("IMG_0001.JPG");
+        let resized = jpg_root.join("IMG_0002.JPG");
+        let removed = jpg_root.join("IMG_0003.JPG");
+        for path in [&kept, &resized, &removed] {
+            fs::write(path, b"orig").expect("write original");
+        }
+        let candidates = [&kept, &resized, &removed]
+            .into_iter()
+            .map(|path| RenameCandidate {
+                original_path: path.clone(),
+                target_path: path.with_file_name("NEW.JPG"),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(path.clone()),
+                rendered_base: "NEW".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: FileFingerprint::read(path),
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            })
+            .collect();
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates,
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+
+        fs::write(&resized, b"edited").expect("rewrite");
+        fs::remove_file(&removed).expect("remove");
+
+        let report = validate_plan_against_fs(&plan);
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.stale.len(), 2);
+        assert_eq!(report.stale[0].path, resized);
+        assert_eq!(
+            report.stale[0].reason,
+            StaleReason::SizeChanged {
+                expected: 4,
+                actual: 6
+            }
+        );
+        assert_eq!(report.stale[1].path, removed);
+        assert_eq!(report.stale[1].reason, StaleReason::Missing);
+    }
+
+    #[test]
+    fn apply_plan_rejects_stale_plan_when_requested() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("IMG_0001_NEW.JPG");
+        fs::write(&original, b"orig").expect("write original");
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.clone(),
+                target_path: target.clone(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.clone()),
+                rendered_base: "IMG_0001_NEW".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: FileFingerprint::read(&original),
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        fs::write(&original, b"edited").expect("rewrite original");
+
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let options = ApplyOptions {
+            backup_originals: false,
+            reject_stale_plan: true,
+            mode: ApplyMode::Rename,
+            continue_on_error: false,
+            collision_policy: CollisionPolicy::Fail,
+            backup_dir: None,
+            backup_format: BackupFormat::Files,
+            backup_retention: BackupRetention::default(),
+            clear_readonly: false,
+            set_file_times: false,
+        };
+        let err = apply_plan_with_options_with_paths(&plan, &options, &paths)
+            .expect_err("stale plan should be rejected");
+        assert!(err.to_string().contains("プラン作成後に変更"));
+        assert!(original.exists());
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn apply_plan_renames_companions_outside_jpg_root() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        fs::create_dir_all(&raw_root).expect("create raw root");
+
+        let jpg = jpg_root.join("DSCF0001.JPG");
+        let raf = raw_root.join("DSCF0001.RAF");
+        let jpg_target = jpg_root.join("NEW.JPG");
+        let raf_target = raw_root.join("NEW.RAF");
+        fs::write(&jpg, b"jpg").expect("write jpg");
+        fs::write(&raf, b"raf").expect("write raf");
+
+        let candidate = |original: &PathBuf, target: &PathBuf, label: &str| RenameCandidate {
+            original_path: original.clone(),
+            target_path: target.clone(),
+            metadata_source: MetadataSource::JpgExif,
+            source_label: label.to_string(),
+            metadata: sample_metadata(jpg.clone()),
+            rendered_base: "NEW".to_string(),
+            changed: true,
+            warnings: Vec::new(),
+            fingerprint: None,
+            provenance: FieldProvenance::default(),
+            raw_paths: Vec::new(),
+            raw_match: None,
+            xmp_path: None,
+        };
+        let mut plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![
+                candidate(&jpg, &jpg_target, "jpg"),
+                candidate(&raf, &raf_target, "raf"),
+            ],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+
+        let err = apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect_err("companion outside roots should be rejected");
+        assert!(err.to_string().contains("JPGフォルダ外"));
+
+        plan.companion_roots = vec![raw_root.clone()];
+        let result = apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should accept companion roots");
+        assert_eq!(result.applied, 2);
+        assert!(jpg_target.exists());
+        assert!(raf_target.exists());
+
+        let log = latest_undo_log(&paths);
+        let validated = validate_undo_log(&log).expect("undo log should be valid");
+        assert_eq!(
+            restore_operations(&validated.operations).expect("restore"),
+            2
+        );
+        assert!(jpg.exists());
+        assert!(raf.exists());
+    }
+
+    #[test]
+    fn apply_plan_creates_subfolders_and_undo_prunes_them() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("2026").join("02").join("IMG_0001.JPG");
+        fs::write(&original, b"A").expect("write original");
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{year}/{month}/{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.clone(),
+                target_path: target.clone(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.clone()),
+                rendered_base: "IMG_0001".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+
+        apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should create subfolders");
+        assert!(target.exists());
+
+        let log = latest_undo_log(&paths);
+        assert_eq!(log.created_dirs.len(), 2);
+        let validated = validate_undo_log(&log).expect("undo log should be valid");
+        restore_operations(&validated.operations).expect("restore");
+        remove_created_dirs(&validated.created_dirs).expect("prune");
+        assert!(original.exists());
+        assert!(!jpg_root.join("2026").exists());
+    }
+
+    #[test]
+    fn copy_mode_leaves_originals_and_undo_removes_copies() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let output_dir = temp.path().join("export");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("2026").join("IMG_0001_renamed.JPG");
+        fs::write(&original, b"A").expect("write original");
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{year}/{orig_name}_renamed".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.clone(),
+                target_path: target.clone(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.clone()),
+                rendered_base: "IMG_0001_renamed".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let options = ApplyOptions {
+            backup_originals: false,
+            reject_stale_plan: false,
+            mode: ApplyMode::CopyTo(output_dir.clone()),
+            continue_on_error: false,
+            collision_policy: CollisionPolicy::Fail,
+            backup_dir: None,
+            backup_format: BackupFormat::Files,
+            backup_retention: BackupRetention::default(),
+            clear_readonly: false,
+            set_file_times: false,
+        };
+
+        // 書き出しは元のフォルダをロックしないため、元のフォルダの適用中でも進められる
+        let source_lock = acquire_apply_lock(
+            &AppPaths {
+                config_dir: temp.path().join("other-config"),
+                config_path: temp.path().join("other-config/config.toml"),
+                undo_path: temp.path().join("other-config/undo-last.json"),
+            },
+            std::slice::from_ref(&jpg_root),
+        )
+        .expect("source lock");
+        let result = apply_plan_with_options_with_paths(&plan, &options, &paths)
+            .expect("copy should succeed");
+        drop(source_lock);
+        assert_eq!(result.applied, 1);
+        let copy = output_dir.join("2026").join("IMG_0001_renamed.JPG");
+        assert!(original.exists());
+        assert!(!target.exists());
+        assert_eq!(fs::read(&copy).expect("read copy"), b"A");
+
+        let err = apply_plan_with_options_with_paths(&plan, &options, &paths)
+            .expect_err("existing copy must not be overwritten");
+        assert!(err.to_string().contains("既に存在します"));
+
+        let log = latest_undo_log(&paths);
+        let validated = validate_undo_log(&log).expect("undo log should be valid");
+        assert!(validated.remove_targets);
+        assert_eq!(
+            remove_exported_files(&validated.operations).expect("remove copies"),
+            1
+        );
+        remove_created_dirs(&validated.created_dirs).expect("prune");
+        assert!(original.exists());
+        assert!(!copy.exists());
+        assert!(!output_dir.join("2026").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_mode_links_originals_into_output_dir() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let output_dir = temp.path().join("links");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("20260101_IMG_0001.JPG");
+        fs::write(&original, b"A").expect("write original");
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{date}_{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.clone(),
+                target_path: target.clone(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.clone()),
+                rendered_base: "20260101_IMG_0001".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let options = ApplyOptions {
+            backup_originals: false,
+            reject_stale_plan: false,
+            mode: ApplyMode::HardlinkTo(output_dir.clone()),
+            continue_on_error: false,
+            collision_policy: CollisionPolicy::Fail,
+            backup_dir: None,
+            backup_format: BackupFormat::Files,
+            backup_retention: BackupRetention::default(),
+            clear_readonly: false,
+            set_file_times: false,
+        };
+
+        apply_plan_with_options_with_paths(&plan, &options, &paths)
+            .expect("hardlink should succeed");
+        let link = output_dir.join("20260101_IMG_0001.JPG");
+        assert!(original.exists());
+        assert_eq!(
+            fs::metadata(&link).expect("link metadata").ino(),
+            fs::metadata(&original).expect("original metadata").ino()
+        );
+
+        let log = latest_undo_log(&paths);
+        let validated = validate_undo_log(&log).expect("undo log should be valid");
+        remove_exported_files(&validated.operations).expect("remove links");
+        assert!(original.exists());
+        assert!(!link.exists());
+    }
+
+    #[test]
+    fn continue_on_error_skips_failed_file_and_logs_only_successes() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let ok_original = jpg_root.join("IMG_0001.JPG");
+        let ok_target = jpg_root.join("IMG_0001_NEW.JPG");
+        let bad_original = jpg_root.join("IMG_0002.JPG");
+        let blocker = jpg_root.join("blocker");
+        let bad_target = blocker.join("IMG_0002.JPG");
+        fs::write(&ok_original, b"A").expect("write ok original");
+        fs::write(&bad_original, b"B").expect("write bad original");
+        fs::write(&blocker, b"not a dir").expect("write blocker");
+        let candidate = |original: &PathBuf, target: &PathBuf| RenameCandidate {
+            original_path: original.clone(),
+            target_path: target.clone(),
+            metadata_source: MetadataSource::JpgExif,
+            source_label: "jpg".to_string(),
+            metadata: sample_metadata(original.clone()),
+            rendered_base: "IMG".to_string(),
+            changed: true,
+            warnings: Vec::new(),
+            fingerprint: None,
+            provenance: FieldProvenance::default(),
+            raw_paths: Vec::new(),
+            raw_match: None,
+            xmp_path: None,
+        };
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![
+                candidate(&ok_original, &ok_target),
+                candidate(&bad_original, &bad_target),
+            ],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let options = ApplyOptions {
+            backup_originals: false,
+            reject_stale_plan: false,
+            mode: ApplyMode::Rename,
+            continue_on_error: true,
+            collision_policy: CollisionPolicy::Fail,
+            backup_dir: None,
+            backup_format: BackupFormat::Files,
+            backup_retention: BackupRetention::default(),
+            clear_readonly: false,
+            set_file_times: false,
+        };
+
+        let result = apply_plan_with_options_with_paths(&plan, &options, &paths)
+            .expect("apply should continue past the failing file");
+        assert_eq!(result.applied, 1);
+        assert_eq!(result.files[0].outcome, ApplyOutcome::Renamed);
+        assert!(matches!(
+            result.files[1].outcome,
+            ApplyOutcome::Failed { .. }
+        ));
+        assert!(ok_target.exists());
+        assert!(bad_original.exists());
+
+        let log = latest_undo_log(&paths);
+        assert_eq!(log.operations.len(), 1);
+        assert_eq!(log.operations[0].to, ok_target);
+    }
+
+    #[test]
+    fn continue_on_error_restores_rename_chain_when_its_last_entry_fails() {
+        // A -> B -> C -> (作れないフォルダ)。C が失敗したら、C の元の名前を使う B、B の元の名前を使う A も戻す
+        for reversed in [false, true] {
+            let temp = tempdir().expect("tempdir");
+            let jpg_root = temp.path().join("jpg");
+            fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+            let a = jpg_root.join("A.JPG");
+            let b = jpg_root.join("B.JPG");
+            let c = jpg_root.join("C.JPG");
+            let blocker = jpg_root.join("blocker");
+            fs::write(&a, b"A").expect("write a");
+            fs::write(&b, b"B").expect("write b");
+            fs::write(&c, b"C").expect("write c");
+            fs::write(&blocker, b"not a dir").expect("write blocker");
+            let candidate = |original: &PathBuf, target: &PathBuf| RenameCandidate {
+                original_path: original.clone(),
+                target_path: target.clone(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.clone()),
+                rendered_base: "IMG".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            };
+            let mut candidates = vec![
+                candidate(&a, &b),
+                candidate(&b, &c),
+                candidate(&c, &blocker.join("C.JPG")),
+            ];
+            if reversed {
+                candidates.reverse();
+            }
+            let plan = RenamePlan {
+                jpg_root: jpg_root.clone(),
+                jpg_roots: vec![jpg_root.clone()],
+                template: "{orig_name}".to_string(),
+                exclusions: Vec::new(),
+                candidates,
+                stats: RenameStats::default(),
+                follow_symlinks: false,
+                warnings: Vec::new(),
+                timings: PlanTimings::default(),
+                companion_roots: Vec::new(),
+                exiftool_missing: false,
+                orphaned_companions: Vec::new(),
+            };
+            let paths = AppPaths {
+                config_dir: temp.path().join("config"),
+                config_path: temp.path().join("config/config.toml"),
+                undo_path: temp.path().join("config/undo-last.json"),
+            };
+            let options = ApplyOptions {
+                backup_originals: false,
+                reject_stale_plan: false,
+                mode: ApplyMode::Rename,
+                continue_on_error: true,
+                collision_policy: CollisionPolicy::Fail,
+                backup_dir: None,
+                backup_format: BackupFormat::Files,
+                backup_retention: BackupRetention::default(),
+                clear_readonly: false,
+                set_file_times: false,
+            };
+
+            let result = apply_plan_with_options_with_paths(&plan, &options, &paths)
+                .expect("apply should continue past the failing file");
+            assert_eq!(result.applied, 0);
+            assert!(result
+                .files
+                .iter()
+                .all(|file| matches!(file.outcome, ApplyOutcome::Failed { .. })));
+            assert_eq!(fs::read(&a).expect("read a"), b"A");
+            assert_eq!(fs::read(&b).expect("read b"), b"B");
+            assert_eq!(fs::read(&c).expect("read c"), b"C");
+            assert_eq!(
+                fs::read_dir(&jpg_root).expect("list jpg root").count(),
+                4,
+                "一時ファイルが残っていないこと"
+            );
+        }
+    }
+
+    #[test]
+    fn apply_plan_subset_applies_only_selected_rows() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let first = jpg_root.join("IMG_0001.JPG");
+        let first_target = jpg_root.join("A.JPG");
+        let second = jpg_root.join("IMG_0002.JPG");
+        let second_target = jpg_root.join("B.JPG");
+        fs::write(&first, b"1").expect("write first");
+        fs::write(&second, b"2").expect("write second");
+        let candidate = |original: &PathBuf, target: &PathBuf| RenameCandidate {
+            original_path: original.clone(),
+            target_path: target.clone(),
+            metadata_source: MetadataSource::JpgExif,
+            source_label: "jpg".to_string(),
+            metadata: sample_metadata(original.clone()),
+            rendered_base: "IMG".to_string(),
+            changed: true,
+            warnings: Vec::new(),
+            fingerprint: None,
+            provenance: FieldProvenance::default(),
+            raw_paths: Vec::new(),
+            raw_match: None,
+            xmp_path: None,
+        };
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![
+                candidate(&first, &first_target),
+                candidate(&second, &second_target),
+            ],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+
+        let err = apply_plan_subset_with_paths(&plan, &[2], &ApplyOptions::default(), &paths)
+            .expect_err("out of range index must be rejected");
+        assert!(err.to_string().contains("範囲外"));
+
+        let result = apply_plan_subset_with_paths(&plan, &[1], &ApplyOptions::default(), &paths)
+            .expect("subset apply should succeed");
+        assert_eq!(result.applied, 1);
+        assert_eq!(result.files.len(), 2);
+        assert_eq!(result.files[0].outcome, ApplyOutcome::Skipped);
+        assert_eq!(result.files[1].outcome, ApplyOutcome::Renamed);
+        assert!(first.exists());
+        assert!(!first_target.exists());
+        assert!(second_target.exists());
+
+        let log = latest_undo_log(&paths);
+        assert_eq!(log.operations.len(), 1);
+    }
+
+    #[test]
+    fn collision_policy_controls_targets_that_appeared_after_planning() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("A.JPG");
+        fs::write(&original, b"new").expect("write original");
+        fs::write(&target, b"intruder").expect("write intruder");
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.clone(),
+                target_path: target.clone(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.clone()),
+                rendered_base: "A".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let options = |collision_policy| ApplyOptions {
+            backup_originals: false,
+            reject_stale_plan: false,
+            mode: ApplyMode::Rename,
+            continue_on_error: false,
+            collision_policy,
+            backup_dir: None,
+            backup_format: BackupFormat::Files,
+            backup_retention: BackupRetention::default(),
+            clear_readonly: false,
+            set_file_times: false,
+        };
+
+        let err =
+            apply_plan_with_options_with_paths(&plan, &options(CollisionPolicy::Fail), &paths)
+                .expect_err("fail policy must reject the collision");
+        assert!(err.to_string().contains("既にファイルが存在します"));
+
+        let result =
+            apply_plan_with_options_with_paths(&plan, &options(CollisionPolicy::Skip), &paths)
+                .expect("skip policy should succeed");
+        assert_eq!(result.applied, 0);
+        assert_eq!(result.files[0].outcome, ApplyOutcome::Skipped);
+        assert!(original.exists());
+        assert!(undo_session_paths(&paths).expect("list").is_empty());
+
+        let result =
+            apply_plan_with_options_with_paths(&plan, &options(CollisionPolicy::Suffix), &paths)
+                .expect("suffix policy should succeed");
+        let suffixed = jpg_root.join("A_001.JPG");
+        assert_eq!(result.applied, 1);
+        assert_eq!(result.files[0].target_path, suffixed);
+        assert_eq!(fs::read(&suffixed).expect("read suffixed"), b"new");
+        assert_eq!(fs::read(&target).expect("read intruder"), b"intruder");
+    }
+
+    #[test]
+    fn skip_policy_keeps_files_whose_name_a_skipped_file_still_holds() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+
+        // A -> B, B -> C。C はプラン作成後に現れたため B はスキップされ、B の名前は空かない
+        let a = jpg_root.join("A.JPG");
+        let b = jpg_root.join("B.JPG");
+        let c = jpg_root.join("C.JPG");
+        fs::write(&a, b"A").expect("write a");
+        fs::write(&b, b"B").expect("write b");
+        fs::write(&c, b"intruder").expect("write intruder");
+        let candidate = |original: &PathBuf, target: &PathBuf| RenameCandidate {
+            original_path: original.clone(),
+            target_path: target.clone(),
+            metadata_source: MetadataSource::JpgExif,
+            source_label: "jpg".to_string(),
+            metadata: sample_metadata(original.clone()),
+            rendered_base: "IMG".to_string(),
+            changed: true,
+            warnings: Vec::new(),
+            fingerprint: None,
+            provenance: FieldProvenance::default(),
+            raw_paths: Vec::new(),
+            raw_match: None,
+            xmp_path: None,
+        };
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![candidate(&a, &b), candidate(&b, &c)],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let options = ApplyOptions {
+            backup_originals: false,
+            reject_stale_plan: false,
+            mode: ApplyMode::Rename,
+            continue_on_error: false,
+            collision_policy: CollisionPolicy::Skip,
+            backup_dir: None,
+            backup_format: BackupFormat::Files,
+            backup_retention: BackupRetention::default(),
+            clear_readonly: false,
+            set_file_times: false,
+        };
+
+        let result = apply_plan_with_options_with_paths(&plan, &options, &paths)
+            .expect("skip policy should succeed");
+        assert_eq!(result.applied, 0);
+        assert_eq!(result.files[0].outcome, ApplyOutcome::Skipped);
+        assert_eq!(result.files[1].outcome, ApplyOutcome::Skipped);
+        assert_eq!(fs::read(&a).expect("read a"), b"A");
+        assert_eq!(fs::read(&b).expect("read b"), b"B");
+        assert_eq!(fs::read(&c).expect("read c"), b"intruder");
+    }
+
+    fn latest_undo_log(paths: &AppPaths) -> UndoLog {
+        let (_, path) = undo_session_paths(paths)
+            .expect("list undo sessions")
+            .pop()
+            .expect("undo session should exist");
+        read_undo_log(&path).expect("read undo log")
+    }
+
+    fn interrupted_apply_fixture(root: &Path) -> (RenamePlan, Vec<StagedRename>) {
+        let jpg_root = root.join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let entries: Vec<StagedRename> = ["A", "B"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| StagedRename {
+                original_path: jpg_root.join(format!("IMG_{name}.JPG")),
+                target_path: jpg_root.join(format!("NEW_{name}.JPG")),
+                temp_path: jpg_root.join(format!(".fphoto_tmp_0_{index}_IMG_{name}.JPG")),
+            })
+            .collect();
+        // A は一時名のまま、B は最終リネーム済みの状態で中断したとみなす
+        fs::write(&entries[0].temp_path, b"A").expect("write staged A");
+        fs::write(&entries[1].target_path, b"B").expect("write finalized B");
+        let plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: Vec::new(),
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        (plan, entries)
+    }
+
+    #[test]
+    fn recover_rolls_back_or_completes_interrupted_apply() {
+        for action in [RecoverAction::Rollback, RecoverAction::Complete] {
+            let temp = tempdir().expect("tempdir");
+            let (plan, entries) = interrupted_apply_fixture(temp.path());
+            let paths = AppPaths {
+                config_dir: temp.path().join("config"),
+                config_path: temp.path().join("config/config.toml"),
+                undo_path: temp.path().join("config/undo-last.json"),
+            };
+            let journal = ApplyJournal {
+                phase: JournalPhase::Finalizing,
+                entries: entries.clone(),
+                undo: build_undo_log(&[], &plan, &ApplyOptions::default(), &[], &[], None),
+            };
+            write_journal(&journal, &paths).expect("write journal");
+
+            let pending = pending_apply_with_paths(&paths)
+                .expect("read journal")
+                .expect("journal should be pending");
+            assert_eq!(pending.entries, 2);
+            assert_eq!(
+                pending.leftover_temp_files,
+                vec![entries[0].temp_path.clone()]
+            );
+            let err = ensure_no_pending_journal(&paths).expect_err("apply must be blocked");
+            assert!(err.to_string().contains("recover"));
+
+            let result =
+                recover_interrupted_apply_with_paths(action, &paths).expect("recover should work");
+            assert!(pending_apply_with_paths(&paths).expect("read").is_none());
+            match action {
+                RecoverAction::Rollback => {
+                    assert_eq!(result.recovered, 2);
+                    assert!(entries.iter().all(|entry| entry.original_path.exists()));
+                    assert!(undo_session_paths(&paths).expect("list").is_empty());
+                }
+                RecoverAction::Complete => {
+                    assert_eq!(result.recovered, 2);
+                    assert!(entries.iter().all(|entry| entry.target_path.exists()));
+                    let log = latest_undo_log(&paths);
+                    assert_eq!(log.operations.len(), 2);
+                }
+            }
+            assert!(entries.iter().all(|entry| !entry.temp_path.exists()));
+        }
+    }
+
+    fn single_rename_plan(jpg_root: &Path, original: &Path, target: &Path) -> RenamePlan {
+        RenamePlan {
+            jpg_root: jpg_root.to_path_buf(),
+            jpg_roots: vec![jpg_root.to_path_buf()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.to_path_buf(),
+                target_path: target.to_path_buf(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.to_path_buf()),
+                rendered_base: "NEW".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn apply_fails_fast_while_another_apply_holds_the_lock() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("NEW.JPG");
+        fs::write(&original, b"1").expect("write original");
+        let plan = single_rename_plan(&jpg_root, &original, &target);
+
+        let lock = acquire_apply_lock(&paths, std::slice::from_ref(&jpg_root)).expect("first lock");
+        let err = apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect_err("concurrent apply must be rejected");
+        assert!(err
+            .to_string()
+            .contains("別の適用または取り消しが実行中です"));
+        assert!(original.exists());
+        assert!(!target.exists());
+        drop(lock);
+
+        #[cfg(unix)]
+        assert!(!jpg_root.join(APPLY_LOCK_FILE_NAME).exists());
+        apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should succeed after the lock is released");
+        assert!(target.exists());
+        #[cfg(unix)]
+        assert!(!jpg_root.join(APPLY_LOCK_FILE_NAME).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lock_file_detects_a_lock_file_removed_by_its_previous_owner() {
+        let temp = tempdir().expect("tempdir");
+        let path = temp.path().join(APPLY_LOCK_FILE_NAME);
+        let stale = lock_file(&path).expect("first lock");
+        assert!(is_current_lock_file(&stale, &path));
+
+        // 前の持ち主が消した後に同じパスへ作られたファイルとは別物として扱う
+        fs::remove_file(&path).expect("remove lock file");
+        let current = lock_file(&path).expect("lock new file");
+        assert!(!is_current_lock_file(&stale, &path));
+        assert!(is_current_lock_file(&current, &path));
+    }
+
+    #[test]
+    fn apply_with_clear_readonly_restores_attribute_on_renamed_file() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("NEW.JPG");
+        fs::write(&original, b"1").expect("write original");
+        let mut permissions = fs::metadata(&original).expect("metadata").permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&original, permissions).expect("set readonly");
+        let plan = single_rename_plan(&jpg_root, &original, &target);
+
+        apply_plan_with_options_with_paths(
+            &plan,
+            &ApplyOptions {
+                clear_readonly: true,
+                ..ApplyOptions::default()
+            },
+            &paths,
+        )
+        .expect("apply should succeed");
+
+        assert!(!original.exists());
+        assert!(fs::metadata(&target)
+            .expect("target metadata")
+            .permissions()
+            .readonly());
+    }
+
+    #[test]
+    fn apply_with_set_file_times_uses_capture_date_and_undo_restores_it() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("NEW.JPG");
+        fs::write(&original, b"1").expect("write original");
+        let original_modified = fs::metadata(&original)
+            .and_then(|metadata| metadata.modified())
+            .expect("original mtime");
+        let capture_date = Local
+            .with_ymd_and_hms(2024, 5, 1, 9, 30, 0)
+            .single()
+            .expect("valid capture date");
+        let mut plan = single_rename_plan(&jpg_root, &original, &target);
+        plan.candidates[0].metadata.date = capture_date.fixed_offset();
+
+        apply_plan_with_options_with_paths(
+            &plan,
+            &ApplyOptions {
+                set_file_times: true,
+                ..ApplyOptions::default()
+            },
+            &paths,
+        )
+        .expect("apply should succeed");
+        let modified = fs::metadata(&target)
+            .and_then(|metadata| metadata.modified())
+            .expect("target mtime");
+        assert_eq!(modified, std::time::SystemTime::from(capture_date));
+
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo should succeed");
+        let restored = fs::metadata(&original)
+            .and_then(|metadata| metadata.modified())
+            .expect("restored mtime");
+        assert_eq!(restored, original_modified);
+    }
+
+    #[test]
+    fn apply_reports_applied_progress() {
+        struct RecordingProgress(std::sync::Mutex<Vec<(usize, usize)>>);
+        impl ProgressSink for RecordingProgress {
+            fn files_scanned(&self, _scanned_files: usize) {}
+            fn metadata_read(&self, _done: usize, _total: usize) {}
+            fn files_applied(&self, done: usize, total: usize) {
+                self.0.lock().expect("lock").push((done, total));
+            }
+        }
+
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("NEW.JPG");
+        fs::write(&original, b"1").expect("write original");
+        let plan = single_rename_plan(&jpg_root, &original, &target);
+        let progress = RecordingProgress(std::sync::Mutex::new(Vec::new()));
+
+        apply_plan_with_progress_with_paths(
+            &plan,
+            &ApplyOptions::default(),
+            Some(&progress),
+            &paths,
+        )
+        .expect("apply should succeed");
+
+        assert_eq!(*progress.0.lock().expect("lock"), vec![(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn preview_undo_lists_restorations_without_touching_files() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("NEW.JPG");
+        fs::write(&original, b"1").expect("write original");
+        let plan = single_rename_plan(&jpg_root, &original, &target);
+        apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should succeed");
+
+        let preview = preview_undo_with_paths(&paths).expect("preview should succeed");
+        let canonical_root = fs::canonicalize(&jpg_root).expect("canonicalize root");
+        assert_eq!(
+            preview.entries,
+            vec![UndoPreviewEntry {
+                current_path: canonical_root.join("NEW.JPG"),
+                restore_path: canonical_root.join("IMG_0001.JPG"),
+                missing: false,
+                modified: false,
+            }]
+        );
+        assert!(target.exists());
+        assert!(!original.exists());
+
+        fs::remove_file(&target).expect("remove renamed file");
+        let preview = preview_undo_with_paths(&paths).expect("preview should succeed");
+        assert!(preview.entries[0].missing);
+        assert_eq!(
+            list_undo_sessions_with_paths(&paths)
+                .expect("list sessions")
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn undo_refuses_files_modified_after_apply_unless_allowed() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("NEW.JPG");
+        fs::write(&original, b"original").expect("write original");
+        let plan = single_rename_plan(&jpg_root, &original, &target);
+        apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should succeed");
+
+        let log = latest_undo_log(&paths);
+        assert_eq!(log.operations[0].size, Some(8));
+        assert!(log.operations[0].hash.is_some());
+
+        // サイズが同じでも内容が違えば検出する
+        fs::write(&target, b"replaced").expect("replace renamed file");
+        let preview = preview_undo_with_paths(&paths).expect("preview should succeed");
+        assert!(preview.entries[0].modified);
+
+        let err = undo_last_with_paths(&UndoOptions::default(), &paths)
+            .expect_err("undo should refuse modified file");
+        assert!(err.to_string().contains("内容が変更されたファイル"));
+        assert!(target.exists());
+        assert!(!original.exists());
+
+        let result = undo_last_with_paths(
+            &UndoOptions {
+                allow_modified: true,
+            },
+            &paths,
+        )
+        .expect("forced undo should succeed");
+        assert_eq!(result.restored, 1);
+        assert_eq!(result.modified.len(), 1);
+        assert_eq!(fs::read(&original).expect("read original"), b"replaced");
+    }
+
+    #[test]
+    fn undo_last_files_restores_selection_and_keeps_the_rest() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let first = jpg_root.join("IMG_0001.JPG");
+        let second = jpg_root.join("IMG_0002.JPG");
+        fs::write(&first, b"1").expect("write first");
+        fs::write(&second, b"2").expect("write second");
+        let mut plan = single_rename_plan(&jpg_root, &first, &jpg_root.join("FIRST.JPG"));
+        let mut second_candidate = plan.candidates[0].clone();
+        second_candidate.original_path = second.clone();
+        second_candidate.target_path = jpg_root.join("SECOND.JPG");
+        plan.candidates.push(second_candidate);
+        apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should succeed");
+
+        let result = undo_last_files_with_paths(
+            &[jpg_root.join("SECOND.JPG")],
+            &UndoOptions::default(),
+            &paths,
+        )
+        .expect("partial undo should succeed");
+        assert_eq!(result.restored, 1);
+        assert!(second.exists());
+        assert!(jpg_root.join("FIRST.JPG").exists());
+
+        let log = latest_undo_log(&paths);
+        assert_eq!(log.operations.len(), 1);
+        assert!(log.operations[0].to.ends_with("FIRST.JPG"));
+
+        let err = undo_last_files_with_paths(
+            &[jpg_root.join("OTHER.JPG")],
+            &UndoOptions::default(),
+            &paths,
+        )
+        .expect_err("unknown file should be rejected");
+        assert!(err.to_string().contains("含まれないファイル"));
+
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo the rest");
+        assert!(first.exists());
+        assert!(list_undo_sessions_with_paths(&paths)
+            .expect("list sessions")
+            .is_empty());
+    }
+
+    #[test]
+    fn backup_dir_outside_jpg_root_is_used_and_cleaned_on_undo() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let backup_dir = temp.path().join("nas").join("backups");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        fs::write(&original, b"original").expect("write original");
+        let plan = single_rename_plan(&jpg_root, &original, &jpg_root.join("NEW.JPG"));
+        let options = ApplyOptions {
+            backup_originals: true,
+            backup_dir: Some(backup_dir.clone()),
+            ..ApplyOptions::default()
+        };
+
+        apply_plan_with_options_with_paths(&plan, &options, &paths).expect("apply should succeed");
+        let backup_path = backup_dir.join("jpg").join("IMG_0001.JPG");
+        assert_eq!(fs::read(&backup_path).expect("read backup"), b"original");
+        assert!(!jpg_root.join("backup").exists());
+
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo should succeed");
+        assert!(original.exists());
+        assert!(!backup_path.exists());
+        assert!(!backup_dir.join("jpg").exists());
+        assert!(backup_dir.is_dir());
+    }
+
+    #[test]
+    fn zip_backup_stores_originals_in_one_archive_and_undo_removes_it() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(jpg_root.join("day1")).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let first = jpg_root.join("IMG_0001.JPG");
+        let second = jpg_root.join("day1").join("IMG_0002.JPG");
+        fs::write(&first, b"first").expect("write first");
+        fs::write(&second, b"second").expect("write second");
+        let mut plan = single_rename_plan(&jpg_root, &first, &jpg_root.join("FIRST.JPG"));
+        let mut second_candidate = plan.candidates[0].clone();
+        second_candidate.original_path = second.clone();
+        second_candidate.target_path = jpg_root.join("day1").join("SECOND.JPG");
+        plan.candidates.push(second_candidate);
+        let options = ApplyOptions {
+            backup_originals: true,
+            backup_format: BackupFormat::Zip,
+            ..ApplyOptions::default()
+        };
+
+        apply_plan_with_options_with_paths(&plan, &options, &paths).expect("apply should succeed");
+        let log = latest_undo_log(&paths);
+        assert_eq!(log.backup_paths.len(), 1);
+        let archive_path = log.backup_paths[0].clone();
+        assert_eq!(
+            archive_path.extension().and_then(|ext| ext.to_str()),
+            Some("zip")
+        );
+
+        let mut archive =
+            zip::ZipArchive::new(fs::File::open(&archive_path).expect("open archive"))
+                .expect("read archive");
+        let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(names, vec!["jpg/IMG_0001.JPG", "jpg/day1/IMG_0002.JPG"]);
+        let mut body = String::new();
+        archive
+            .by_name("jpg/day1/IMG_0002.JPG")
+            .expect("entry")
+            .read_to_string(&mut body)
+            .expect("read entry");
+        assert_eq!(body, "second");
+
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo should succeed");
+        assert!(first.exists());
+        assert!(second.exists());
+        assert!(!archive_path.exists());
+        assert!(!jpg_root.join("backup").exists());
+    }
+
+    #[test]
+    fn backup_retention_keeps_only_recent_sessions_after_apply() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let options = ApplyOptions {
+            backup_originals: true,
+            backup_retention: BackupRetention {
+                keep_sessions: Some(2),
+                keep_days: None,
+            },
+            ..ApplyOptions::default()
+        };
 
-    for backup_path in &log.backup_paths {
-        if !backup_path.exists() {
-            continue;
-        }
-        if backup_path.is_dir() {
-            bail!(
-                "取り消しログのバックアップパスがディレクトリです: {}",
-                backup_path.display()
+        for index in 1..=3 {
+            let original = jpg_root.join(format!("IMG_000{index}.JPG"));
+            fs::write(&original, format!("{index}")).expect("write original");
+            let plan = single_rename_plan(
+                &jpg_root,
+                &original,
+                &jpg_root.join(format!("NEW_{index}.JPG")),
             );
+            apply_plan_with_options_with_paths(&plan, &options, &paths)
+                .expect("apply should succeed");
         }
-        fs::remove_file(backup_path).with_context(|| {
-            format!(
-                "バックアップファイル削除に失敗しました: {}",
-                backup_path.display()
-            )
-        })?;
-        if let Some(parent) = backup_path.parent() {
-            if let Some(backup_root) = pick_most_specific_root(parent, &backup_roots) {
-                remove_empty_dirs_until(parent, backup_root)?;
-            }
-        }
+
+        let backup_root = jpg_root.join("backup");
+        assert!(!backup_root.join("IMG_0001.JPG").exists());
+        assert!(backup_root.join("IMG_0002.JPG").exists());
+        assert!(backup_root.join("IMG_0003.JPG").exists());
+        assert_eq!(load_backup_index(&paths).expect("load index").len(), 2);
     }
 
-    for backup_root in backup_roots {
-        if backup_root.exists() && backup_root.is_dir() && directory_is_empty(&backup_root)? {
-            fs::remove_dir(&backup_root).with_context(|| {
-                format!(
-                    "バックアップフォルダ削除に失敗しました: {}",
-                    backup_root.display()
-                )
-            })?;
-        }
+    #[test]
+    fn cleanup_backups_removes_sessions_older_than_keep_days() {
+        let temp = tempdir().expect("tempdir");
+        let backup_root = temp.path().join("jpg").join("backup");
+        fs::create_dir_all(backup_root.join("day1")).expect("create backup root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        fs::create_dir_all(&paths.config_dir).expect("create config dir");
+        let old_backup = backup_root.join("day1").join("OLD.JPG");
+        let new_backup = backup_root.join("NEW.JPG");
+        fs::write(&old_backup, b"old").expect("write old backup");
+        fs::write(&new_backup, b"new").expect("write new backup");
+        let now = Local::now();
+        let record = |days_ago: i64, path: &Path| BackupSessionRecord {
+            created_at: (now - chrono::Duration::days(days_ago)).to_rfc3339(),
+            backup_roots: vec![backup_root.clone()],
+            paths: vec![path.to_path_buf()],
+        };
+        write_backup_index(&[record(30, &old_backup), record(1, &new_backup)], &paths)
+            .expect("write index");
+
+        let result = enforce_backup_retention_at(
+            &BackupRetention {
+                keep_sessions: None,
+                keep_days: Some(7),
+            },
+            now,
+            &paths,
+        )
+        .expect("cleanup should succeed");
+        assert_eq!(result.removed_sessions, 1);
+        assert_eq!(result.removed_files, 1);
+        assert!(!old_backup.exists());
+        assert!(!backup_root.join("day1").exists());
+        assert!(new_backup.exists());
+        assert_eq!(load_backup_index(&paths).expect("load index").len(), 1);
     }
 
-    Ok(())
-}
+    #[test]
+    fn redo_reapplies_last_undone_session() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("sub").join("NEW.JPG");
+        fs::write(&original, b"1").expect("write original");
+        let plan = single_rename_plan(&jpg_root, &original, &target);
+
+        apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should succeed");
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo should succeed");
+        assert!(original.exists());
+        assert!(!jpg_root.join("sub").exists());
+
+        let result = redo_last_with_paths(&paths).expect("redo should succeed");
+        assert_eq!(result.reapplied, 1);
+        assert!(!original.exists());
+        assert!(target.exists());
+        assert!(!journal_path(&paths).exists());
+        assert_eq!(
+            list_undo_sessions_with_paths(&paths)
+                .expect("list sessions")
+                .len(),
+            1
+        );
 
-fn directory_is_empty(path: &Path) -> Result<bool> {
-    let mut entries = fs::read_dir(path)
-        .with_context(|| format!("ディレクトリを読めませんでした: {}", path.display()))?;
-    Ok(entries.next().is_none())
-}
+        let err = redo_last_with_paths(&paths).expect_err("redo history should be consumed");
+        assert!(err.to_string().contains("やり直し可能な履歴がありません"));
 
-fn remove_empty_dirs_until(start: &Path, stop: &Path) -> Result<()> {
-    let mut current = Some(start.to_path_buf());
-    while let Some(dir) = current {
-        if dir == stop || !dir.starts_with(stop) {
-            break;
-        }
-        if !dir.exists() || !dir.is_dir() || !directory_is_empty(&dir)? {
-            break;
-        }
-        fs::remove_dir(&dir)
-            .with_context(|| format!("空ディレクトリ削除に失敗しました: {}", dir.display()))?;
-        current = dir.parent().map(PathBuf::from);
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo redone session");
+        assert!(original.exists());
+        assert!(!target.exists());
     }
-    Ok(())
-}
 
-fn temp_path_for(original_path: &Path, index: usize) -> PathBuf {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_millis())
-        .unwrap_or(0);
-    let parent = original_path.parent().unwrap_or_else(|| Path::new("."));
-    let file_name = original_path
-        .file_name()
-        .map(|v| v.to_string_lossy().to_string())
-        .unwrap_or_else(|| "file".to_string());
-    parent.join(format!(".fphoto_tmp_{}_{}_{}", now, index, file_name))
-}
+    #[test]
+    fn redo_refuses_when_target_was_taken_after_undo() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+        let original = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("NEW.JPG");
+        fs::write(&original, b"1").expect("write original");
+        let plan = single_rename_plan(&jpg_root, &original, &target);
 
-#[cfg(test)]
-mod tests {
-    #[cfg(unix)]
-    use super::backup_original_files;
-    use super::{
-        apply_plan_with_options, apply_plan_with_options_with_paths, cleanup_backup_if_needed,
-        resolve_backup_path, resolve_backup_path_with_reserved, restore_operations,
-        unique_backup_path, validate_undo_log, ApplyOptions, UndoLog,
-    };
-    use crate::config::AppPaths;
-    use crate::metadata::{MetadataSource, PhotoMetadata};
-    use crate::planner::{RenameCandidate, RenamePlan, RenameStats};
-    use chrono::Local;
-    use std::collections::HashSet;
-    use std::fs;
-    #[cfg(unix)]
-    use std::os::unix::fs as unix_fs;
-    use std::path::PathBuf;
-    use tempfile::tempdir;
+        apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect("apply should succeed");
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo should succeed");
+        fs::write(&target, b"other").expect("write conflicting file");
 
-    fn sample_metadata(jpg_path: PathBuf) -> PhotoMetadata {
-        PhotoMetadata {
-            source: MetadataSource::JpgExif,
-            date: Local::now(),
-            camera_make: Some("FUJIFILM".to_string()),
-            camera_model: Some("X-T5".to_string()),
-            lens_make: Some("FUJIFILM".to_string()),
-            lens_model: Some("XF16-55".to_string()),
-            film_sim: Some("CLASSIC CHROME".to_string()),
-            original_name: "IMG_0001".to_string(),
-            jpg_path,
+        let err = redo_last_with_paths(&paths).expect_err("redo should be refused");
+        assert!(err
+            .to_string()
+            .contains("やり直し先に既にファイルがあります"));
+        assert!(original.exists());
+        assert_eq!(fs::read(&target).expect("read target"), b"other");
+    }
+
+    #[test]
+    fn undo_history_keeps_sessions_and_undoes_specific_one() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+
+        let first = jpg_root.join("IMG_0001.JPG");
+        let first_target = jpg_root.join("FIRST.JPG");
+        let second = jpg_root.join("IMG_0002.JPG");
+        let second_target = jpg_root.join("SECOND.JPG");
+        fs::write(&first, b"1").expect("write first");
+        fs::write(&second, b"2").expect("write second");
+        for plan in [
+            single_rename_plan(&jpg_root, &first, &first_target),
+            single_rename_plan(&jpg_root, &second, &second_target),
+        ] {
+            apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+                .expect("apply should succeed");
         }
+
+        let sessions = list_undo_sessions_with_paths(&paths).expect("list sessions");
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().all(|session| session.file_count == 1));
+        assert!(sessions.iter().all(|session| session.created_at.is_some()));
+        assert_eq!(sessions[0].roots, vec![jpg_root.clone()]);
+
+        undo_session_with_paths(&sessions[1].id, &UndoOptions::default(), &paths)
+            .expect("undo older session");
+        assert!(first.exists());
+        assert!(second_target.exists());
+
+        undo_last_with_paths(&UndoOptions::default(), &paths).expect("undo newest session");
+        assert!(second.exists());
+        assert!(list_undo_sessions_with_paths(&paths)
+            .expect("list sessions")
+            .is_empty());
     }
 
     #[test]
-    fn apply_plan_returns_unchanged_when_no_candidates_changed() {
+    fn apply_plan_rejects_new_subfolder_outside_jpg_root() {
         let temp = tempdir().expect("tempdir");
         let jpg_root = temp.path().join("jpg");
         fs::create_dir_all(&jpg_root).expect("create jpg root");
 
         let original = jpg_root.join("IMG_0001.JPG");
-        let target = jpg_root.join("IMG_0001.JPG");
+        let target = jpg_root.join("..").join("outside").join("IMG_0001.JPG");
+        fs::write(&original, b"A").expect("write original");
         let plan = RenamePlan {
             jpg_root: jpg_root.clone(),
             jpg_roots: vec![jpg_root.clone()],
@@ -832,73 +5067,35 @@ mod tests {
                 target_path: target,
                 metadata_source: MetadataSource::JpgExif,
                 source_label: "jpg".to_string(),
-                metadata: sample_metadata(original),
+                metadata: sample_metadata(original.clone()),
                 rendered_base: "IMG_0001".to_string(),
-                changed: false,
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
             }],
             stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
         };
-
-        let result = apply_plan_with_options(&plan, &ApplyOptions::default())
-            .expect("unchanged plan should be accepted");
-        assert_eq!(result.applied, 0);
-        assert_eq!(result.unchanged, 1);
-    }
-
-    #[test]
-    fn apply_plan_with_multiple_jpg_roots_succeeds() {
-        let temp = tempdir().expect("tempdir");
-        let root_a = temp.path().join("a");
-        let root_b = temp.path().join("b");
-        fs::create_dir_all(&root_a).expect("create root a");
-        fs::create_dir_all(&root_b).expect("create root b");
-
-        let original_a = root_a.join("IMG_A.JPG");
-        let original_b = root_b.join("IMG_B.JPG");
-        let target_a = root_a.join("IMG_A_NEW.JPG");
-        let target_b = root_b.join("IMG_B_NEW.JPG");
-        fs::write(&original_a, b"A").expect("write A");
-        fs::write(&original_b, b"B").expect("write B");
-
-        let plan = RenamePlan {
-            jpg_root: temp.path().to_path_buf(),
-            jpg_roots: vec![root_a.clone(), root_b.clone()],
-            template: "{orig_name}".to_string(),
-            exclusions: Vec::new(),
-            candidates: vec![
-                RenameCandidate {
-                    original_path: original_a.clone(),
-                    target_path: target_a.clone(),
-                    metadata_source: MetadataSource::JpgExif,
-                    source_label: "jpg".to_string(),
-                    metadata: sample_metadata(original_a.clone()),
-                    rendered_base: "IMG_A_NEW".to_string(),
-                    changed: true,
-                },
-                RenameCandidate {
-                    original_path: original_b.clone(),
-                    target_path: target_b.clone(),
-                    metadata_source: MetadataSource::JpgExif,
-                    source_label: "jpg".to_string(),
-                    metadata: sample_metadata(original_b.clone()),
-                    rendered_base: "IMG_B_NEW".to_string(),
-                    changed: true,
-                },
-            ],
-            stats: RenameStats::default(),
-        };
-
         let paths = AppPaths {
             config_dir: temp.path().join("config"),
             config_path: temp.path().join("config/config.toml"),
             undo_path: temp.path().join("config/undo-last.json"),
         };
-        let result = apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
-            .expect("apply should succeed for multi roots");
 
-        assert_eq!(result.applied, 2);
-        assert!(target_a.exists());
-        assert!(target_b.exists());
+        let err = apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect_err("target outside root should be rejected");
+        assert!(err.to_string().contains("JPGフォルダ外"));
+        assert!(original.exists());
+        assert!(!temp.path().join("outside").exists());
     }
 
     #[test]
@@ -952,6 +5149,12 @@ mod tests {
             jpg_root: Some(jpg_root.clone()),
             jpg_roots: Vec::new(),
             backup_paths: vec![backup_file],
+            follow_symlinks: false,
+            companion_roots: Vec::new(),
+            created_dirs: Vec::new(),
+            output_dir: None,
+            created_at: None,
+            backup_dir: None,
         };
         let validated = validate_undo_log(&log).expect("undo log should be valid");
         cleanup_backup_if_needed(&validated).expect("cleanup should succeed");
@@ -971,6 +5174,12 @@ mod tests {
             jpg_root: Some(jpg_root),
             jpg_roots: Vec::new(),
             backup_paths: Vec::new(),
+            follow_symlinks: false,
+            companion_roots: Vec::new(),
+            created_dirs: Vec::new(),
+            output_dir: None,
+            created_at: None,
+            backup_dir: None,
         };
         let validated = validate_undo_log(&log).expect("undo log should be valid");
         cleanup_backup_if_needed(&validated).expect("cleanup should succeed");
@@ -994,6 +5203,12 @@ mod tests {
             jpg_root: Some(jpg_root),
             jpg_roots: Vec::new(),
             backup_paths: vec![tracked.clone()],
+            follow_symlinks: false,
+            companion_roots: Vec::new(),
+            created_dirs: Vec::new(),
+            output_dir: None,
+            created_at: None,
+            backup_dir: None,
         };
         let validated = validate_undo_log(&log).expect("undo log should be valid");
         cleanup_backup_if_needed(&validated).expect("cleanup should succeed");
@@ -1018,6 +5233,12 @@ mod tests {
             jpg_root: Some(jpg_root),
             jpg_roots: Vec::new(),
             backup_paths: Vec::new(),
+            follow_symlinks: false,
+            companion_roots: Vec::new(),
+            created_dirs: Vec::new(),
+            output_dir: None,
+            created_at: None,
+            backup_dir: None,
         };
         let validated = validate_undo_log(&log).expect("undo log should be valid");
         cleanup_backup_if_needed(&validated).expect("cleanup should succeed");
@@ -1068,6 +5289,12 @@ mod tests {
             metadata: sample_metadata(original),
             rendered_base: "IMG_0001_NEW".to_string(),
             changed: true,
+            warnings: Vec::new(),
+            fingerprint: None,
+            provenance: FieldProvenance::default(),
+            raw_paths: Vec::new(),
+            raw_match: None,
+            xmp_path: None,
         };
         let plan = RenamePlan {
             jpg_root: jpg_root.clone(),
@@ -1076,14 +5303,101 @@ mod tests {
             exclusions: Vec::new(),
             candidates: vec![candidate.clone()],
             stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
         };
 
-        let err = backup_original_files(&plan, &[&candidate]).expect_err("symlink root must fail");
+        let err =
+            backup_original_files(&plan, &[&candidate], None).expect_err("symlink root must fail");
         assert!(err
             .to_string()
             .contains("バックアップフォルダがJPGフォルダ外を指しています"));
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn apply_plan_accepts_symlinked_subfolder_when_follow_symlinks_enabled() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let outside_root = temp.path().join("library");
+        fs::create_dir_all(&jpg_root).expect("create jpg root");
+        fs::create_dir_all(&outside_root).expect("create outside root");
+        unix_fs::symlink(&outside_root, jpg_root.join("2025")).expect("create year symlink");
+
+        let original = jpg_root.join("2025").join("IMG_0001.JPG");
+        let renamed = jpg_root.join("2025").join("RENAMED_0001.JPG");
+        fs::write(&original, b"x").expect("write original");
+
+        let mut plan = RenamePlan {
+            jpg_root: jpg_root.clone(),
+            jpg_roots: vec![jpg_root.clone()],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.clone(),
+                target_path: renamed.clone(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: sample_metadata(original.clone()),
+                rendered_base: "RENAMED_0001".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        };
+        let paths = AppPaths {
+            config_dir: temp.path().join("config"),
+            config_path: temp.path().join("config/config.toml"),
+            undo_path: temp.path().join("config/undo-last.json"),
+        };
+
+        let err = apply_plan_with_options_with_paths(&plan, &ApplyOptions::default(), &paths)
+            .expect_err("symlinked folder must be rejected without follow_symlinks");
+        assert!(err
+            .to_string()
+            .contains("JPGフォルダ外の元ファイルは適用できません"));
+
+        plan.follow_symlinks = true;
+        apply_plan_with_options_with_paths(
+            &plan,
+            &ApplyOptions {
+                backup_originals: true,
+                reject_stale_plan: false,
+                mode: ApplyMode::Rename,
+                continue_on_error: false,
+                collision_policy: CollisionPolicy::Fail,
+                backup_dir: None,
+                backup_format: BackupFormat::Files,
+                backup_retention: BackupRetention::default(),
+                clear_readonly: false,
+                set_file_times: false,
+            },
+            &paths,
+        )
+        .expect("symlinked folder should be accepted");
+        assert!(outside_root.join("RENAMED_0001.JPG").exists());
+        assert!(jpg_root
+            .join("backup")
+            .join("2025")
+            .join("IMG_0001.JPG")
+            .exists());
+    }
+
     #[test]
     fn apply_plan_rolls_back_when_final_rename_fails_midway() {
         let temp = tempdir().expect("tempdir");
@@ -1114,6 +5428,12 @@ mod tests {
                     metadata: sample_metadata(original_a.clone()),
                     rendered_base: "RENAMED_A".to_string(),
                     changed: true,
+                    warnings: Vec::new(),
+                    fingerprint: None,
+                    provenance: FieldProvenance::default(),
+                    raw_paths: Vec::new(),
+                    raw_match: None,
+                    xmp_path: None,
                 },
                 RenameCandidate {
                     original_path: original_b.clone(),
@@ -1123,9 +5443,21 @@ mod tests {
                     metadata: sample_metadata(original_b.clone()),
                     rendered_base: "blocked".to_string(),
                     changed: true,
+                    warnings: Vec::new(),
+                    fingerprint: None,
+                    provenance: FieldProvenance::default(),
+                    raw_paths: Vec::new(),
+                    raw_match: None,
+                    xmp_path: None,
                 },
             ],
             stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
         };
 
         let err = apply_plan_with_options(&plan, &ApplyOptions::default())
@@ -1171,8 +5503,20 @@ mod tests {
                 metadata: sample_metadata(original.clone()),
                 rendered_base: "RENAMED_0001".to_string(),
                 changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
             }],
             stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
         };
 
         let blocked_config_dir = temp.path().join("blocked-config");
@@ -1187,6 +5531,15 @@ mod tests {
             &plan,
             &ApplyOptions {
                 backup_originals: true,
+                reject_stale_plan: false,
+                mode: ApplyMode::Rename,
+                continue_on_error: false,
+                collision_policy: CollisionPolicy::Fail,
+                backup_dir: None,
+                backup_format: BackupFormat::Files,
+                backup_retention: BackupRetention::default(),
+                clear_readonly: false,
+                set_file_times: false,
             },
             &blocked_paths,
         )
@@ -1228,8 +5581,20 @@ mod tests {
                 metadata: sample_metadata(original.clone()),
                 rendered_base: "RENAMED".to_string(),
                 changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
             }],
             stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
         };
 
         let err = apply_plan_with_options(&plan, &ApplyOptions::default())
@@ -1266,6 +5631,12 @@ mod tests {
                     metadata: sample_metadata(original_a.clone()),
                     rendered_base: "SAME".to_string(),
                     changed: true,
+                    warnings: Vec::new(),
+                    fingerprint: None,
+                    provenance: FieldProvenance::default(),
+                    raw_paths: Vec::new(),
+                    raw_match: None,
+                    xmp_path: None,
                 },
                 RenameCandidate {
                     original_path: original_b.clone(),
@@ -1275,9 +5646,21 @@ mod tests {
                     metadata: sample_metadata(original_b.clone()),
                     rendered_base: "SAME".to_string(),
                     changed: true,
+                    warnings: Vec::new(),
+                    fingerprint: None,
+                    provenance: FieldProvenance::default(),
+                    raw_paths: Vec::new(),
+                    raw_match: None,
+                    xmp_path: None,
                 },
             ],
             stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
         };
 
         let err = apply_plan_with_options(&plan, &ApplyOptions::default())
@@ -1300,19 +5683,19 @@ mod tests {
 
         let log = UndoLog {
             operations: vec![
-                super::RenameOperation {
-                    from: from_a.clone(),
-                    to: to_a.clone(),
-                },
-                super::RenameOperation {
-                    from: from_b.clone(),
-                    to: to_b,
-                },
+                super::RenameOperation::new(from_a.clone(), to_a.clone()),
+                super::RenameOperation::new(from_b.clone(), to_b),
             ],
             backup_originals: false,
             jpg_root: None,
             jpg_roots: Vec::new(),
             backup_paths: Vec::new(),
+            follow_symlinks: false,
+            companion_roots: Vec::new(),
+            created_dirs: Vec::new(),
+            output_dir: None,
+            created_at: None,
+            backup_dir: None,
         };
 
         let restored = restore_operations(&log.operations).expect("restore should succeed");
@@ -1334,17 +5717,91 @@ mod tests {
         let outside_to = outside_root.join("RENAMED_0001.JPG");
 
         let log = UndoLog {
-            operations: vec![super::RenameOperation {
-                from: inside_from,
-                to: outside_to,
-            }],
+            operations: vec![super::RenameOperation::new(inside_from, outside_to)],
             backup_originals: false,
             jpg_root: Some(jpg_root),
             jpg_roots: Vec::new(),
             backup_paths: Vec::new(),
+            follow_symlinks: false,
+            companion_roots: Vec::new(),
+            created_dirs: Vec::new(),
+            output_dir: None,
+            created_at: None,
+            backup_dir: None,
         };
 
         let err = validate_undo_log(&log).expect_err("outside path must be rejected");
         assert!(err.to_string().contains("許可範囲外"));
     }
+
+    #[test]
+    fn copy_then_remove_moves_file_and_keeps_modified_time() {
+        let temp = tempdir().expect("tempdir");
+        let from = temp.path().join("IMG_0001.JPG");
+        let to_dir = temp.path().join("other");
+        fs::create_dir_all(&to_dir).expect("create target dir");
+        let to = to_dir.join("RENAMED_0001.JPG");
+        fs::write(&from, b"jpg").expect("write source");
+        let modified = fs::metadata(&from)
+            .and_then(|metadata| metadata.modified())
+            .expect("source mtime");
+
+        copy_then_remove(&from, &to).expect("fallback move should succeed");
+
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).expect("read moved"), b"jpg");
+        assert_eq!(
+            fs::metadata(&to)
+                .and_then(|metadata| metadata.modified())
+                .expect("moved mtime"),
+            modified
+        );
+        let leftovers = fs::read_dir(&to_dir).expect("read target dir").count();
+        assert_eq!(leftovers, 1);
+    }
+
+    #[test]
+    fn retry_transient_retries_until_success() {
+        let mut attempts = 0;
+        let result = retry_transient(
+            || {
+                attempts += 1;
+                if attempts < 3 {
+                    Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+                } else {
+                    Ok(attempts)
+                }
+            },
+            |err| err.kind() == std::io::ErrorKind::PermissionDenied,
+        );
+        assert_eq!(result.expect("third attempt should succeed"), 3);
+    }
+
+    #[test]
+    fn retry_transient_returns_other_errors_immediately() {
+        let mut attempts = 0;
+        let result: std::io::Result<()> = retry_transient(
+            || {
+                attempts += 1;
+                Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            },
+            |err| err.kind() == std::io::ErrorKind::PermissionDenied,
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn copy_then_remove_refuses_existing_target() {
+        let temp = tempdir().expect("tempdir");
+        let from = temp.path().join("IMG_0001.JPG");
+        let to = temp.path().join("RENAMED_0001.JPG");
+        fs::write(&from, b"jpg").expect("write source");
+        fs::write(&to, b"existing").expect("write target");
+
+        copy_then_remove(&from, &to).expect_err("existing target must not be replaced");
+
+        assert_eq!(fs::read(&from).expect("read source"), b"jpg");
+        assert_eq!(fs::read(&to).expect("read target"), b"existing");
+    }
 }
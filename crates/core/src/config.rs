@@ -1,7 +1,14 @@
+use crate::constants::{
+    default_primary_extensions, default_raw_extensions, DEFAULT_MAX_FILENAME_LEN,
+    MAX_FILENAME_LEN_RANGE,
+};
+use crate::exif_reader::is_valid_exif_tag_name;
+use crate::template::validate_template;
 use crate::DEFAULT_TEMPLATE;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -12,27 +19,230 @@ pub struct AppConfig {
     #[serde(default = "default_true")]
     pub dedupe_same_maker: bool,
     #[serde(default)]
+    pub dedupe_model_maker: bool,
+    #[serde(default)]
     pub backup_originals: bool,
     #[serde(default)]
     pub raw_parent_if_missing: bool,
+    #[serde(default)]
+    pub recursive_default: bool,
+    #[serde(default)]
+    pub include_hidden_default: bool,
+    #[serde(default = "default_primary_extensions")]
+    pub primary_extensions: Vec<String>,
+    // JPG と対応付ける RAW の拡張子 (優先順)
+    #[serde(default = "default_raw_extensions")]
+    pub raw_extensions: Vec<String>,
+    #[serde(default = "default_max_filename_len")]
+    pub max_filename_len: usize,
+    #[serde(default)]
+    pub backup_retention: BackupRetention,
+    // {camera_maker} / {lens_maker} のメーカー名の置き換え (EXIFの表記 -> 表示名)
+    #[serde(default)]
+    pub maker_aliases: BTreeMap<String, String>,
+    // exiftool で追加で読み、プランの各候補に添えるタグ (ISO / GPSLatitude など)
+    #[serde(default)]
+    pub extra_exif_tags: Vec<String>,
+}
+
+// 未指定の条件は無制限。両方指定した場合はどちらかを超えた時点で削除する
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackupRetention {
+    #[serde(default)]
+    pub keep_sessions: Option<usize>,
+    #[serde(default)]
+    pub keep_days: Option<u32>,
+}
+
+impl BackupRetention {
+    pub fn is_unlimited(&self) -> bool {
+        self.keep_sessions.is_none() && self.keep_days.is_none()
+    }
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_max_filename_len() -> usize {
+    DEFAULT_MAX_FILENAME_LEN
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             template: DEFAULT_TEMPLATE.to_string(),
             exclude_strings: Vec::new(),
             dedupe_same_maker: true,
+            dedupe_model_maker: false,
             backup_originals: false,
             raw_parent_if_missing: false,
+            recursive_default: false,
+            include_hidden_default: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            max_filename_len: DEFAULT_MAX_FILENAME_LEN,
+            backup_retention: BackupRetention::default(),
+            maker_aliases: BTreeMap::new(),
+            extra_exif_tags: Vec::new(),
+        }
+    }
+}
+
+pub const CONFIG_FIELD_KEYS: &[&str] = &[
+    "template",
+    "exclude_strings",
+    "backup_originals",
+    "dedupe_same_maker",
+    "dedupe_model_maker",
+    "recursive_default",
+    "include_hidden_default",
+    "max_filename_len",
+    "maker_aliases",
+    "extra_exif_tags",
+    "raw_extensions",
+];
+
+impl AppConfig {
+    // exclude_strings は1行1件で返す
+    pub fn get_field(&self, key: &str) -> Result<String> {
+        Ok(match key {
+            "template" => self.template.clone(),
+            "exclude_strings" => self.exclude_strings.join("\n"),
+            "backup_originals" => self.backup_originals.to_string(),
+            "dedupe_same_maker" => self.dedupe_same_maker.to_string(),
+            "dedupe_model_maker" => self.dedupe_model_maker.to_string(),
+            "recursive_default" => self.recursive_default.to_string(),
+            "include_hidden_default" => self.include_hidden_default.to_string(),
+            "max_filename_len" => self.max_filename_len.to_string(),
+            "maker_aliases" => self
+                .maker_aliases
+                .iter()
+                .map(|(from, to)| format!("{from}={to}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "extra_exif_tags" => self.extra_exif_tags.join("\n"),
+            "raw_extensions" => self.raw_extensions.join("\n"),
+            _ => return Err(unknown_field(key)),
+        })
+    }
+
+    pub fn set_field(&mut self, key: &str, values: &[String]) -> Result<()> {
+        match key {
+            "template" => {
+                let template = single_value(key, values)?;
+                validate_template(template)
+                    .with_context(|| format!("テンプレートが不正です: {template}"))?;
+                self.template = template.to_string();
+            }
+            "exclude_strings" => {
+                if values.iter().any(|value| value.is_empty()) {
+                    bail!("exclude_strings に空文字は指定できません");
+                }
+                self.exclude_strings = values.to_vec();
+            }
+            "backup_originals" => self.backup_originals = parse_bool(key, values)?,
+            "dedupe_same_maker" => self.dedupe_same_maker = parse_bool(key, values)?,
+            "dedupe_model_maker" => self.dedupe_model_maker = parse_bool(key, values)?,
+            "recursive_default" => self.recursive_default = parse_bool(key, values)?,
+            "include_hidden_default" => self.include_hidden_default = parse_bool(key, values)?,
+            "max_filename_len" => {
+                let value = single_value(key, values)?;
+                let len = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|len| MAX_FILENAME_LEN_RANGE.contains(len))
+                    .with_context(|| {
+                        format!(
+                            "{key} には {}〜{} の数値を指定してください: {value}",
+                            MAX_FILENAME_LEN_RANGE.start(),
+                            MAX_FILENAME_LEN_RANGE.end()
+                        )
+                    })?;
+                self.max_filename_len = len;
+            }
+            "maker_aliases" => {
+                let mut aliases = BTreeMap::new();
+                for value in values {
+                    let (from, to) = value
+                        .split_once('=')
+                        .map(|(from, to)| (from.trim(), to.trim()))
+                        .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+                        .with_context(|| {
+                            format!("{key} は 元の名前=表示名 の形式で指定してください: {value}")
+                        })?;
+                    aliases.insert(from.to_string(), to.to_string());
+                }
+                self.maker_aliases = aliases;
+            }
+            "extra_exif_tags" => {
+                if let Some(tag) = values.iter().find(|tag| !is_valid_exif_tag_name(tag)) {
+                    bail!("{key} には ISO や EXIF:GPSLatitude のようなタグ名を指定してください: {tag}");
+                }
+                self.extra_exif_tags = values.to_vec();
+            }
+            "raw_extensions" => {
+                if values.is_empty() {
+                    bail!("{key} を空にはできません");
+                }
+                if let Some(ext) = values.iter().find(|ext| !is_valid_extension(ext)) {
+                    bail!("{key} には ARW や .nef のような拡張子を指定してください: {ext}");
+                }
+                self.raw_extensions = values.to_vec();
+            }
+            _ => return Err(unknown_field(key)),
+        }
+        Ok(())
+    }
+
+    pub fn unset_field(&mut self, key: &str) -> Result<()> {
+        let defaults = AppConfig::default();
+        match key {
+            "template" => self.template = defaults.template,
+            "exclude_strings" => self.exclude_strings = defaults.exclude_strings,
+            "backup_originals" => self.backup_originals = defaults.backup_originals,
+            "dedupe_same_maker" => self.dedupe_same_maker = defaults.dedupe_same_maker,
+            "dedupe_model_maker" => self.dedupe_model_maker = defaults.dedupe_model_maker,
+            "recursive_default" => self.recursive_default = defaults.recursive_default,
+            "include_hidden_default" => {
+                self.include_hidden_default = defaults.include_hidden_default
+            }
+            "max_filename_len" => self.max_filename_len = defaults.max_filename_len,
+            "maker_aliases" => self.maker_aliases = defaults.maker_aliases,
+            "extra_exif_tags" => self.extra_exif_tags = defaults.extra_exif_tags,
+            "raw_extensions" => self.raw_extensions = defaults.raw_extensions,
+            _ => return Err(unknown_field(key)),
         }
+        Ok(())
     }
 }
 
+fn is_valid_extension(ext: &str) -> bool {
+    let ext = ext.trim().trim_start_matches('.');
+    !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn unknown_field(key: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "不明な設定項目です: {key} (指定可能: {})",
+        CONFIG_FIELD_KEYS.join(", ")
+    )
+}
+
+fn single_value<'a>(key: &str, values: &'a [String]) -> Result<&'a str> {
+    match values {
+        [value] => Ok(value),
+        _ => bail!("{key} には値を1つだけ指定してください"),
+    }
+}
+
+fn parse_bool(key: &str, values: &[String]) -> Result<bool> {
+    let value = single_value(key, values)?;
+    value
+        .parse::<bool>()
+        .with_context(|| format!("{key} には true または false を指定してください: {value}"))
+}
+
 #[derive(Debug, Clone)]
 pub struct AppPaths {
     pub config_dir: PathBuf,
@@ -81,7 +291,62 @@ pub fn save_config(config: &AppConfig) -> Result<()> {
     Ok(())
 }
 
-fn write_file_atomically(target_path: &Path, body: &str, label: &str) -> Result<()> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigImportMode {
+    Merge,
+    Overwrite,
+}
+
+pub fn export_config(path: &Path) -> Result<()> {
+    let config = load_config()?;
+    let body = toml::to_string_pretty(&config).context("設定のシリアライズに失敗しました")?;
+    fs::write(path, body).with_context(|| format!("設定を書き出せませんでした: {}", path.display()))
+}
+
+pub fn import_config(path: &Path, mode: ConfigImportMode) -> Result<AppConfig> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("設定ファイルを読めませんでした: {}", path.display()))?;
+    let config = merge_imported_config(&load_config()?, &raw, mode)
+        .with_context(|| format!("取り込む設定が不正です: {}", path.display()))?;
+    save_config(&config)?;
+    Ok(config)
+}
+
+// Merge はファイルにある項目だけを現在の設定へ上書きし、Overwrite は無い項目を既定値にする
+fn merge_imported_config(
+    current: &AppConfig,
+    raw: &str,
+    mode: ConfigImportMode,
+) -> Result<AppConfig> {
+    let imported = toml::from_str::<toml::Table>(raw).context("TOMLとして読み込めませんでした")?;
+    let base = match mode {
+        ConfigImportMode::Merge => current.clone(),
+        ConfigImportMode::Overwrite => AppConfig::default(),
+    };
+    let mut table = toml::Table::try_from(&base).context("設定のシリアライズに失敗しました")?;
+    for (key, value) in imported {
+        if !table.contains_key(&key) {
+            bail!("不明な設定項目です: {key}");
+        }
+        table.insert(key, value);
+    }
+    let config = AppConfig::deserialize(table).context("設定の形式が不正です")?;
+    validate_template(&config.template)
+        .with_context(|| format!("テンプレートが不正です: {}", config.template))?;
+    if config.primary_extensions.is_empty() {
+        bail!("primary_extensions が空です");
+    }
+    if config.raw_extensions.is_empty() {
+        bail!("raw_extensions が空です");
+    }
+    if !MAX_FILENAME_LEN_RANGE.contains(&config.max_filename_len) {
+        bail!("max_filename_len が範囲外です: {}", config.max_filename_len);
+    }
+    Ok(config)
+}
+
+pub(crate) fn write_file_atomically(target_path: &Path, body: &str, label: &str) -> Result<()> {
     let file_name = target_path
         .file_name()
         .and_then(|v| v.to_str())
@@ -127,8 +392,8 @@ fn write_file_atomically(target_path: &Path, body: &str, label: &str) -> Result<
 
 #[cfg(test)]
 mod tests {
-    use super::AppConfig;
-    use crate::DEFAULT_TEMPLATE;
+    use super::{merge_imported_config, AppConfig, ConfigImportMode};
+    use crate::{default_raw_extensions, DEFAULT_PRIMARY_EXTENSIONS, DEFAULT_TEMPLATE};
 
     #[test]
     fn default_config_has_expected_values() {
@@ -138,6 +403,153 @@ mod tests {
         assert!(cfg.dedupe_same_maker);
         assert!(!cfg.backup_originals);
         assert!(!cfg.raw_parent_if_missing);
+        assert!(!cfg.recursive_default);
+        assert!(!cfg.include_hidden_default);
+        assert_eq!(cfg.primary_extensions, DEFAULT_PRIMARY_EXTENSIONS);
+        assert_eq!(cfg.max_filename_len, 240);
+        assert!(cfg.backup_retention.is_unlimited());
+    }
+
+    #[test]
+    fn config_fields_can_be_set_read_and_reset() {
+        let mut cfg = AppConfig::default();
+        cfg.set_field("template", &["{orig_name}".to_string()])
+            .expect("set template");
+        cfg.set_field("exclude_strings", &["-NR".to_string(), "-DxO".to_string()])
+            .expect("set exclusions");
+        cfg.set_field("backup_originals", &["true".to_string()])
+            .expect("set backup flag");
+
+        assert_eq!(cfg.get_field("template").expect("get"), "{orig_name}");
+        assert_eq!(cfg.get_field("exclude_strings").expect("get"), "-NR\n-DxO");
+        assert_eq!(cfg.get_field("backup_originals").expect("get"), "true");
+
+        cfg.unset_field("template").expect("unset");
+        assert_eq!(cfg.template, DEFAULT_TEMPLATE);
+    }
+
+    #[test]
+    fn raw_extensions_accept_extensions_only() {
+        let mut cfg = AppConfig::default();
+        assert!(cfg.raw_extensions.iter().any(|ext| ext == "arw"));
+        cfg.set_field("raw_extensions", &["NEF".to_string(), ".cr3".to_string()])
+            .expect("set raw extensions");
+        assert_eq!(cfg.get_field("raw_extensions").expect("get"), "NEF\n.cr3");
+        assert!(cfg.set_field("raw_extensions", &[]).is_err());
+        assert!(cfg
+            .set_field("raw_extensions", &["*.nef".to_string()])
+            .is_err());
+        cfg.unset_field("raw_extensions").expect("unset");
+        assert_eq!(cfg.raw_extensions, default_raw_extensions());
+    }
+
+    #[test]
+    fn extra_exif_tags_accept_tag_names_only() {
+        let mut cfg = AppConfig::default();
+        cfg.set_field(
+            "extra_exif_tags",
+            &["ISO".to_string(), "EXIF:GPSLatitude".to_string()],
+        )
+        .expect("set extra tags");
+        assert_eq!(
+            cfg.get_field("extra_exif_tags").expect("get"),
+            "ISO\nEXIF:GPSLatitude"
+        );
+
+        for invalid in ["-o", "iso", "ISO x", "TagsFromFile", "EXIF:"] {
+            assert!(
+                cfg.set_field("extra_exif_tags", &[invalid.to_string()])
+                    .is_err(),
+                "{invalid} should be rejected"
+            );
+        }
+        cfg.unset_field("extra_exif_tags").expect("unset");
+        assert!(cfg.extra_exif_tags.is_empty());
+    }
+
+    #[test]
+    fn config_field_updates_are_validated() {
+        let mut cfg = AppConfig::default();
+        assert!(cfg
+            .set_field("template", &["{unknown".to_string()])
+            .is_err());
+        assert!(cfg
+            .set_field("dedupe_same_maker", &["yes".to_string()])
+            .is_err());
+        assert!(cfg
+            .set_field("max_filename_len", &["1000".to_string()])
+            .is_err());
+        assert!(cfg.get_field("language").is_err());
+        assert!(cfg
+            .set_field("maker_aliases", &["NIKON CORPORATION".to_string()])
+            .is_err());
+        assert_eq!(cfg.template, DEFAULT_TEMPLATE);
+        assert!(cfg.dedupe_same_maker);
+
+        cfg.set_field(
+            "maker_aliases",
+            &[
+                "NIKON CORPORATION = Nikon".to_string(),
+                "SONY=SONY".to_string(),
+            ],
+        )
+        .expect("set aliases");
+        assert_eq!(
+            cfg.get_field("maker_aliases").expect("get"),
+            "NIKON CORPORATION=Nikon\nSONY=SONY"
+        );
+    }
+
+    #[test]
+    fn merge_import_keeps_fields_missing_from_file() {
+        let current = AppConfig {
+            backup_originals: true,
+            exclude_strings: vec!["-NR".to_string()],
+            ..AppConfig::default()
+        };
+
+        let raw = r#"template = "{orig_name}""#;
+        let merged = merge_imported_config(&current, raw, ConfigImportMode::Merge).expect("merge");
+        assert_eq!(merged.template, "{orig_name}");
+        assert!(merged.backup_originals);
+        assert_eq!(merged.exclude_strings, vec!["-NR"]);
+
+        let replaced =
+            merge_imported_config(&current, raw, ConfigImportMode::Overwrite).expect("overwrite");
+        assert_eq!(replaced.template, "{orig_name}");
+        assert!(!replaced.backup_originals);
+        assert!(replaced.exclude_strings.is_empty());
+    }
+
+    #[test]
+    fn import_rejects_unknown_keys_and_invalid_values() {
+        let current = AppConfig::default();
+        let err = merge_imported_config(&current, r#"tempalte = "x""#, ConfigImportMode::Merge)
+            .expect_err("typo must be rejected");
+        assert!(err.to_string().contains("不明な設定項目"));
+
+        assert!(merge_imported_config(
+            &current,
+            r#"backup_originals = "yes""#,
+            ConfigImportMode::Merge
+        )
+        .is_err());
+        assert!(merge_imported_config(
+            &current,
+            r#"template = "{unknown""#,
+            ConfigImportMode::Merge
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn backup_retention_round_trips_through_toml() {
+        let mut cfg = AppConfig::default();
+        cfg.backup_retention.keep_sessions = Some(5);
+        let raw = toml::to_string_pretty(&cfg).expect("serialize");
+        let parsed: AppConfig = toml::from_str(&raw).expect("deserialize");
+        assert_eq!(parsed.backup_retention.keep_sessions, Some(5));
+        assert_eq!(parsed.backup_retention.keep_days, None);
     }
 
     #[test]
@@ -156,5 +568,8 @@ exclude_strings = ["-NR"]
         assert!(cfg.dedupe_same_maker);
         assert!(!cfg.backup_originals);
         assert!(!cfg.raw_parent_if_missing);
+        assert!(cfg.recursive_default);
+        assert!(!cfg.include_hidden_default);
+        assert_eq!(cfg.primary_extensions, DEFAULT_PRIMARY_EXTENSIONS);
     }
 }
@@ -1,2 +1,34 @@
 pub const DEFAULT_TEMPLATE: &str =
     "{year}{month}{day}_{hour}{minute}{second}_{camera_maker}_{camera_model}_{lens_maker}_{lens_model}_{film_sim}_{orig_name}";
+
+pub const MAX_UNDO_SESSIONS: usize = 20;
+
+pub const PLAN_FILE_VERSION: u32 = 1;
+
+// EXIFから読む項目や解釈を変えた時は上げて、古いキャッシュを読み直させる
+pub const METADATA_CACHE_VERSION: u32 = 22;
+
+// ext4 などは UTF-8 のバイト数、NTFS は UTF-16 の単位数で 255 までなので、連番の余地を残す
+pub const DEFAULT_MAX_FILENAME_LEN: usize = 240;
+pub const MAX_FILENAME_LEN_RANGE: std::ops::RangeInclusive<usize> = 16..=255;
+
+pub const DEFAULT_PRIMARY_EXTENSIONS: &[&str] = &["jpg", "jpeg", "tif", "tiff", "png"];
+
+pub fn default_primary_extensions() -> Vec<String> {
+    DEFAULT_PRIMARY_EXTENSIONS
+        .iter()
+        .map(|ext| ext.to_string())
+        .collect()
+}
+
+// JPG と対応付ける RAW の拡張子。同名の RAW が複数ある場合は先のものを使う
+pub const DEFAULT_RAW_EXTENSIONS: &[&str] = &[
+    "dng", "raf", "arw", "nef", "nrw", "cr2", "cr3", "orf", "rw2", "pef", "srw",
+];
+
+pub fn default_raw_extensions() -> Vec<String> {
+    DEFAULT_RAW_EXTENSIONS
+        .iter()
+        .map(|ext| ext.to_string())
+        .collect()
+}
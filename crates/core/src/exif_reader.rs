@@ -1,21 +1,66 @@
-use crate::metadata::PartialMetadata;
-use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
-use exif::{Field, Reader as KamadakReader, Value as ExifValue};
-use exiftool::ExifTool;
+use crate::exiftool_process::{can_pass_in_argfile, json_once, ExiftoolProcess};
+use crate::metadata::{
+    local_date, parse_rating, parse_utc_offset, MetadataBackend, PartialMetadata,
+};
+use crate::metadata_cache::MetadataCache;
+use crate::planner::FileFingerprint;
+use crate::xmp_reader::parse_xmp_metadata;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use exif::{Context as ExifContext, Field, In, Reader as KamadakReader, Tag, Value as ExifValue};
+use rayon::prelude::*;
 use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 const EXIFTOOL_PATH_ENV: &str = "FPHOTO_EXIFTOOL_PATH";
+const EXIFTOOL_WORKERS_ENV: &str = "FPHOTO_EXIFTOOL_WORKERS";
+const EXIFTOOL_TIMEOUT_ENV: &str = "FPHOTO_EXIFTOOL_TIMEOUT";
+const DEFAULT_EXIFTOOL_TIMEOUT: Duration = Duration::from_secs(60);
 const FUJIFILM_MAKER_NOTE_PREFIX: &[u8] = b"FUJIFILM";
 const FUJIFILM_TAG_FILM_MODE: u16 = 0x1401;
+const FUJIFILM_TAG_WB_FINE_TUNE: u16 = 0x100A;
+const FUJIFILM_TAG_GRAIN_ROUGHNESS: u16 = 0x1047;
+const FUJIFILM_TAG_COLOR_CHROME: u16 = 0x1048;
+const FUJIFILM_TAG_GRAIN_SIZE: u16 = 0x104C;
+const FUJIFILM_TAG_DEVELOPMENT_DR: u16 = 0x1403;
+const FUJIFILM_TAG_AUTO_DR: u16 = 0x140B;
+const NIKON_MAKER_NOTE_PREFIX: &[u8] = b"Nikon\0";
+const NIKON_MAKER_NOTE_TIFF_OFFSET: usize = 10;
+const NIKON_TAG_PICTURE_CONTROL_DATA: u16 = 0x0023;
+const PANASONIC_MAKER_NOTE_PREFIX: &[u8] = b"Panasonic\0\0\0";
+const PANASONIC_TAG_PHOTO_STYLE: u16 = 0x0089;
+// RAF ヘッダ: 0x00 マジック, 0x1C カメラ名(32バイト), 0x54 プレビューJPEGの位置, 0x58 その長さ (ビッグエンディアン)
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+const RAF_CAMERA_NAME_RANGE: std::ops::Range<usize> = 0x1C..0x3C;
+const RAF_JPEG_OFFSET_POS: usize = 0x54;
+const RAF_HEADER_LEN: usize = 0x5C;
+// kamadak-exif が名前を持たない DNG 固有タグ
+const DNG_TAG_VERSION: u16 = 0xC612;
+const DNG_TAG_UNIQUE_CAMERA_MODEL: u16 = 0xC614;
+const DNG_TAG_LOCALIZED_CAMERA_MODEL: u16 = 0xC615;
+const JPEG_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+// 動画の QuickTime の日時は UTC で記録されるため、動画を読む時だけローカル時刻へ変換させる
+const EXIFTOOL_VIDEO_ARGS: &[&str] = &["-api", "QuickTimeUTC=1"];
 const EXIFTOOL_ARGS: &[&str] = &[
     "-DateTimeOriginal",
     "-DateTimeDigitized",
     "-DateTime",
+    "-OffsetTimeOriginal",
+    "-OffsetTimeDigitized",
+    "-OffsetTime",
+    "-SubSecTimeOriginal",
+    "-SubSecTimeDigitized",
+    "-SubSecTime",
+    "-GPSDateStamp",
+    "-GPSTimeStamp",
+    "-CreateDate",
+    "-MediaCreateDate",
     "-Make",
     "-Model",
     "-Saturation",
@@ -33,22 +78,377 @@ const EXIFTOOL_ARGS: &[&str] = &[
     "-FilmSimulation",
     "-FilmSimulationName",
     "-PictureMode",
+    "-CreativeStyle",
+    "-CreativeStyleSetting",
+    "-PictureControlName",
+    "-PictureStyle",
+    "-ArtFilter",
+    "-PhotoStyle",
+    "-DevelopmentDynamicRange",
+    "-AutoDynamicRange",
+    "-GrainEffect",
+    "-GrainEffectRoughness",
+    "-GrainEffectSize",
+    "-ColorChromeEffect",
+    "-WhiteBalanceFineTune",
+    "-FocalLengthIn35mmFormat",
+    "-ExposureCompensation",
+    "-Flash",
+    "-ImageUniqueID",
+    "-DocumentID",
+    "-Rating",
+    "-Label",
+    "-Subject",
+    "-Error",
+    "-Warning",
 ];
 
-static EXIFTOOL_INSTANCE: OnceLock<Option<Mutex<ExifTool>>> = OnceLock::new();
+// 追加タグとして受け付けない名前。exiftool のオプションと解釈されるもの
+const EXIFTOOL_RESERVED_NAMES: &[&str] = &[
+    "tagsfromfile",
+    "execute",
+    "stay_open",
+    "common_args",
+    "config",
+    "srcfile",
+];
+
+// 1回の exiftool 呼び出しに渡すファイル数。引数が長くなりすぎない範囲でまとめる
+const EXIFTOOL_BATCH_SIZE: usize = 100;
+
+static EXIFTOOL_POOL: OnceLock<Option<ExifToolPool>> = OnceLock::new();
+
+// rayon の並列読み込みが1プロセスで直列にならないよう、exiftool を必要になった分だけ起動して使い回す
+struct ExifToolPool {
+    executable: Option<PathBuf>,
+    timeout: Duration,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<ExiftoolProcess>,
+    spawned: usize,
+    max_workers: usize,
+}
+
+impl ExifToolPool {
+    fn new(
+        first: ExiftoolProcess,
+        executable: Option<PathBuf>,
+        max_workers: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            executable,
+            timeout,
+            state: Mutex::new(PoolState {
+                idle: vec![first],
+                spawned: 1,
+                max_workers: max_workers.max(1),
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    fn max_workers(&self) -> usize {
+        self.state.lock().map_or(1, |state| state.max_workers)
+    }
+
+    // 壊れたファイルやネットワーク越しの読み込みで応答が止まったプロセスは終了させて手放し、
+    // 次の呼び出しで新しく起動する
+    fn with_worker<T>(
+        &self,
+        f: impl FnOnce(&mut ExiftoolProcess, Duration) -> Result<T>,
+    ) -> Result<T> {
+        let mut exiftool = self.checkout()?;
+        let out = f(&mut exiftool, self.timeout);
+        if let Ok(mut state) = self.state.lock() {
+            if exiftool.is_usable() {
+                state.idle.push(exiftool);
+            } else {
+                state.spawned -= 1;
+            }
+        }
+        self.returned.notify_one();
+        out
+    }
+
+    fn checkout(&self) -> Result<ExiftoolProcess> {
+        let lock_error = || anyhow!("ExifTool のロック取得に失敗しました");
+        let mut state = self.state.lock().map_err(|_| lock_error())?;
+        loop {
+            if let Some(exiftool) = state.idle.pop() {
+                return Ok(exiftool);
+            }
+            if state.spawned < state.max_workers {
+                state.spawned += 1;
+                drop(state);
+                match ExiftoolProcess::spawn(self.executable.as_deref()) {
+                    Ok(exiftool) => return Ok(exiftool),
+                    Err(err) => {
+                        state = self.state.lock().map_err(|_| lock_error())?;
+                        state.spawned -= 1;
+                        // 動いているプロセスが無ければ待っても空かないため、この呼び出しは失敗させる
+                        if state.spawned == 0 {
+                            return Err(err);
+                        }
+                        log::warn!("追加のexiftoolを起動できませんでした ({err})");
+                        // 起動できた数を上限にして、以降は空きを待つ
+                        state.max_workers = state.spawned;
+                        continue;
+                    }
+                }
+            }
+            state = self.returned.wait(state).map_err(|_| lock_error())?;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ExifTimings {
+    exiftool_nanos: AtomicU64,
+    kamadak_nanos: AtomicU64,
+}
+
+impl ExifTimings {
+    pub(crate) fn exiftool_ms(&self) -> u64 {
+        self.exiftool_nanos.load(Ordering::Relaxed) / 1_000_000
+    }
+
+    pub(crate) fn kamadak_ms(&self) -> u64 {
+        self.kamadak_nanos.load(Ordering::Relaxed) / 1_000_000
+    }
+
+    fn measure<T>(counter: &AtomicU64, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let out = f();
+        counter.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        out
+    }
+}
+
+// フォルダ全体を exiftool にまとめて渡して先読みした結果。読めなかったファイルは含まない。
+// キャッシュが有効な場合、キャッシュにあるファイルは先読みせず、読んだ結果はキャッシュへ書き戻す
+#[derive(Debug, Default)]
+pub(crate) struct ExifPrefetch<'a> {
+    entries: HashMap<PathBuf, PartialMetadata>,
+    cache: Option<&'a MetadataCache>,
+    extra_tags: &'a [String],
+}
+
+impl<'a> ExifPrefetch<'a> {
+    pub(crate) fn load(
+        paths: &[PathBuf],
+        cache: Option<&'a MetadataCache>,
+        extra_tags: &'a [String],
+        timings: &ExifTimings,
+    ) -> Self {
+        let Some(pool) = exiftool_pool() else {
+            return Self {
+                entries: HashMap::new(),
+                cache,
+                extra_tags,
+            };
+        };
+        let paths: Vec<PathBuf> = match cache {
+            Some(cache) => paths
+                .iter()
+                .filter(|path| {
+                    FileFingerprint::read(path)
+                        .is_none_or(|fingerprint| cache.get(path, &fingerprint).is_none())
+                })
+                .cloned()
+                .collect(),
+            None => paths.to_vec(),
+        };
+        // 全プロセスに仕事が行き渡るよう、件数が少ない時は1回あたりのファイル数を減らす
+        let chunk_size = paths
+            .len()
+            .div_ceil(pool.max_workers())
+            .clamp(1, EXIFTOOL_BATCH_SIZE);
+        // 一括では渡せない名前は、後から1件ずつ読む
+        let (videos, stills): (Vec<_>, Vec<_>) = paths
+            .into_iter()
+            .filter(|path| can_pass_in_argfile(path))
+            .partition(|path| is_video(path));
+        let still_args = exiftool_args(extra_tags, false);
+        let video_args = exiftool_args(extra_tags, true);
+        let entries = stills
+            .par_chunks(chunk_size)
+            .map(|chunk| (chunk, &still_args))
+            .chain(
+                videos
+                    .par_chunks(chunk_size)
+                    .map(|chunk| (chunk, &video_args)),
+            )
+            .flat_map_iter(|(chunk, args)| {
+                let values = ExifTimings::measure(&timings.exiftool_nanos, || {
+                    read_exiftool_batch(pool, chunk, args)
+                });
+                match_source_files(chunk, values)
+                    .into_iter()
+                    .filter(|(_, json)| json.get("Error").is_none())
+                    .map(|(path, json)| {
+                        let mut meta = partial_metadata_from_json(&json);
+                        meta.extra_tags = extra_tags_from_json(&json, extra_tags);
+                        meta.exiftool_messages = exiftool_messages_from_json(&json);
+                        (path, meta)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Self {
+            entries,
+            cache,
+            extra_tags,
+        }
+    }
+
+    fn get(&self, path: &Path) -> Option<&PartialMetadata> {
+        self.entries.get(path)
+    }
+}
+
+pub(crate) fn exiftool_available() -> bool {
+    exiftool_pool().is_some()
+}
+
+// 失敗した場合は空を返し、各ファイルは1件ずつの読み込みに任せる
+fn read_exiftool_batch(pool: &ExifToolPool, chunk: &[PathBuf], args: &[String]) -> Vec<JsonValue> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match pool.with_worker(|exiftool, timeout| exiftool.json_batch(chunk, &args, timeout)) {
+        Ok(values) => values,
+        Err(err) => {
+            log::warn!("exiftoolの一括読み込みに失敗しました ({err})");
+            Vec::new()
+        }
+    }
+}
+
+// 読めなかったファイルは結果から抜けるため、順番ではなく SourceFile で元のパスに対応付ける。
+// 対応しない結果は捨て、そのファイルは1件ずつの読み込みに任せる
+fn match_source_files(chunk: &[PathBuf], values: Vec<JsonValue>) -> Vec<(PathBuf, JsonValue)> {
+    let by_source: HashMap<String, &PathBuf> = chunk
+        .iter()
+        .filter_map(|path| Some((source_file_key(path.to_str()?), path)))
+        .collect();
+    values
+        .into_iter()
+        .filter_map(|json| {
+            let source = json.get("SourceFile")?.as_str()?;
+            let path = by_source.get(&source_file_key(source))?;
+            Some(((*path).clone(), json))
+        })
+        .collect()
+}
+
+// Windows の exiftool は SourceFile の区切りを / に置き換えて返す
+fn source_file_key(path: &str) -> String {
+    if cfg!(windows) {
+        path.replace('\\', "/")
+    } else {
+        path.to_string()
+    }
+}
+
+pub fn read_exif_metadata(
+    path: &Path,
+    prefetch: &ExifPrefetch<'_>,
+    timings: &ExifTimings,
+) -> Result<PartialMetadata> {
+    let (Some(cache), Some(fingerprint)) = (prefetch.cache, FileFingerprint::read(path)) else {
+        return read_exif_metadata_uncached(path, prefetch, timings);
+    };
+    if let Some(meta) = cache.get(path, &fingerprint) {
+        return Ok(meta);
+    }
+    let meta = read_exif_metadata_uncached(path, prefetch, timings)?;
+    cache.insert(path, fingerprint, &meta);
+    Ok(meta)
+}
+
+fn read_exif_metadata_uncached(
+    path: &Path,
+    prefetch: &ExifPrefetch<'_>,
+    timings: &ExifTimings,
+) -> Result<PartialMetadata> {
+    let result = read_exif_metadata_from_readers(path, prefetch, timings);
+    if result
+        .as_ref()
+        .is_ok_and(|meta| meta.rating.is_some() && !metadata_has_missing_fields(meta))
+    {
+        return result;
+    }
 
-pub fn read_exif_metadata(path: &Path) -> Result<PartialMetadata> {
-    match read_exif_metadata_with_exiftool(path) {
+    // 編集ソフトがJPG内に書き込んだXMPで、EXIFに無い項目（レーティング等）を補う
+    match read_jpeg_xmp_packet(path) {
+        Ok(Some(xml)) => {
+            let mut xmp_meta = parse_xmp_metadata(&xml);
+            xmp_meta.backend = Some(MetadataBackend::EmbeddedXmp);
+            match result {
+                Ok(mut meta) => {
+                    meta.merge_missing_from(&xmp_meta);
+                    Ok(meta)
+                }
+                Err(err) => {
+                    log::debug!(
+                        "EXIFが読めないため埋め込みXMPのみを使用: {} ({err})",
+                        path.display()
+                    );
+                    Ok(xmp_meta)
+                }
+            }
+        }
+        Ok(None) => result,
+        Err(err) => {
+            log::debug!("埋め込みXMPを読めませんでした: {} ({err})", path.display());
+            result
+        }
+    }
+}
+
+fn read_exif_metadata_from_readers(
+    path: &Path,
+    prefetch: &ExifPrefetch<'_>,
+    timings: &ExifTimings,
+) -> Result<PartialMetadata> {
+    let read_kamadak = || {
+        ExifTimings::measure(&timings.kamadak_nanos, || {
+            read_exif_metadata_with_kamadak(path)
+        })
+    };
+    let exiftool_result = match prefetch.get(path) {
+        Some(meta) => Ok(meta.clone()),
+        None => ExifTimings::measure(&timings.exiftool_nanos, || {
+            read_exif_metadata_with_exiftool(path, prefetch.extra_tags)
+        }),
+    };
+    match exiftool_result {
         Ok(mut exiftool_meta) => {
+            exiftool_meta.backend = Some(MetadataBackend::Exiftool);
             if metadata_has_missing_fields(&exiftool_meta) {
-                if let Ok(kamadak_meta) = read_exif_metadata_with_kamadak(path) {
+                if let Ok(kamadak_meta) = read_kamadak() {
                     exiftool_meta.merge_missing_from(&kamadak_meta);
                 }
             }
             Ok(exiftool_meta)
         }
-        Err(exiftool_err) => match read_exif_metadata_with_kamadak(path) {
-            Ok(kamadak_meta) => Ok(kamadak_meta),
+        Err(exiftool_err) => match read_kamadak() {
+            Ok(mut kamadak_meta) => {
+                log::debug!(
+                    "exiftoolで読めなかったためkamadak-exifを使用: {} ({exiftool_err})",
+                    path.display()
+                );
+                kamadak_meta.backend = Some(MetadataBackend::Kamadak);
+                // exiftool 自体が無い場合は計画全体の警告で伝える
+                if exiftool_available() {
+                    kamadak_meta
+                        .exiftool_messages
+                        .push(exiftool_err.to_string());
+                }
+                Ok(kamadak_meta)
+            }
             Err(kamadak_err) => Err(anyhow!(
                 "EXIFを解析できませんでした: {} (exiftool: {}; kamadak-exif: {})",
                 path.display(),
@@ -68,22 +468,73 @@ fn metadata_has_missing_fields(meta: &PartialMetadata) -> bool {
         || meta.film_sim.is_none()
 }
 
-fn exiftool_instance() -> Option<&'static Mutex<ExifTool>> {
-    EXIFTOOL_INSTANCE.get_or_init(init_exiftool).as_ref()
+fn exiftool_pool() -> Option<&'static ExifToolPool> {
+    EXIFTOOL_POOL.get_or_init(init_exiftool).as_ref()
 }
 
-fn init_exiftool() -> Option<Mutex<ExifTool>> {
+fn init_exiftool() -> Option<ExifToolPool> {
+    let max_workers = configured_exiftool_workers();
     if let Some(path) = configured_exiftool_path() {
-        if let Ok(exiftool) = ExifTool::with_executable(&path) {
-            return Some(Mutex::new(exiftool));
+        match ExiftoolProcess::spawn(Some(&path)) {
+            Ok(exiftool) => {
+                log::info!(
+                    "exiftoolを使用: {} (最大{max_workers}プロセス)",
+                    path.display()
+                );
+                return Some(ExifToolPool::new(
+                    exiftool,
+                    Some(path),
+                    max_workers,
+                    configured_exiftool_timeout(),
+                ));
+            }
+            Err(err) => log::warn!("exiftoolを起動できませんでした: {} ({err})", path.display()),
         }
     }
 
-    if let Ok(exiftool) = ExifTool::new() {
-        return Some(Mutex::new(exiftool));
+    match ExiftoolProcess::spawn(None) {
+        Ok(exiftool) => {
+            log::info!("exiftoolを使用: PATH上のexiftool (最大{max_workers}プロセス)");
+            Some(ExifToolPool::new(
+                exiftool,
+                None,
+                max_workers,
+                configured_exiftool_timeout(),
+            ))
+        }
+        Err(err) => {
+            log::info!("exiftoolが見つからないためkamadak-exifのみで読み込みます ({err})");
+            None
+        }
     }
+}
 
-    None
+// 既定は rayon のスレッド数。FPHOTO_EXIFTOOL_WORKERS で上書きできる
+fn configured_exiftool_workers() -> usize {
+    std::env::var(EXIFTOOL_WORKERS_ENV)
+        .ok()
+        .and_then(|raw| parse_worker_count(&raw))
+        .unwrap_or_else(rayon::current_num_threads)
+}
+
+// 1回の呼び出しを待つ秒数。FPHOTO_EXIFTOOL_TIMEOUT で変更できる
+fn configured_exiftool_timeout() -> Duration {
+    std::env::var(EXIFTOOL_TIMEOUT_ENV)
+        .ok()
+        .and_then(|raw| parse_timeout_secs(&raw))
+        .unwrap_or(DEFAULT_EXIFTOOL_TIMEOUT)
+}
+
+fn parse_timeout_secs(raw: &str) -> Option<Duration> {
+    raw.trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+}
+
+fn parse_worker_count(raw: &str) -> Option<usize> {
+    raw.trim().parse::<usize>().ok().filter(|count| *count > 0)
 }
 
 fn configured_exiftool_path() -> Option<PathBuf> {
@@ -94,27 +545,121 @@ fn configured_exiftool_path() -> Option<PathBuf> {
     Some(PathBuf::from(raw))
 }
 
-fn read_exif_metadata_with_exiftool(path: &Path) -> Result<PartialMetadata> {
-    let exiftool_mutex = exiftool_instance().ok_or_else(|| anyhow!("ExifTool が利用できません"))?;
-    let json = {
-        let exiftool = exiftool_mutex
-            .lock()
-            .map_err(|_| anyhow!("ExifTool のロック取得に失敗しました"))?;
-        exiftool
-            .json(path, EXIFTOOL_ARGS)
-            .map_err(|err| anyhow!("ExifTool 取得失敗: {err}"))?
-    };
+fn read_exif_metadata_with_exiftool(path: &Path, extra_tags: &[String]) -> Result<PartialMetadata> {
+    let pool = exiftool_pool().ok_or_else(|| anyhow!("ExifTool が利用できません"))?;
+    let args = exiftool_args(extra_tags, is_video(path));
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let json = if can_pass_in_argfile(path) {
+        pool.with_worker(|exiftool, timeout| exiftool.json(path, &args, timeout))
+    } else {
+        json_once(pool.executable.as_deref(), path, &args, pool.timeout)
+    }
+    .map_err(|err| anyhow!("ExifTool 取得失敗: {err}"))?;
+    if let Some(error) = pick_json_string(&json, &["Error"]) {
+        bail!("ExifTool 取得失敗: {error}");
+    }
+
+    let mut meta = partial_metadata_from_json(&json);
+    meta.extra_tags = extra_tags_from_json(&json, extra_tags);
+    meta.exiftool_messages = exiftool_messages_from_json(&json);
+    Ok(meta)
+}
+
+// exiftool が結果に含める警告。"[minor]" 付きは多くのファイルで出る軽微なものなので拾わない
+fn exiftool_messages_from_json(json: &JsonValue) -> Vec<String> {
+    pick_json_string(json, &["Warning"])
+        .filter(|warning| !warning.starts_with("[minor]"))
+        .into_iter()
+        .collect()
+}
+
+// 設定の extra_exif_tags ("ISO" / "EXIF:GPSLatitude" など)。グループ指定はJSONのキーに含まれない
+pub(crate) fn is_valid_exif_tag_name(tag: &str) -> bool {
+    let name = exif_tag_key(tag);
+    tag.split(':').all(|part| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }) && name.len() >= 2
+        && name.starts_with(|c: char| c.is_ascii_uppercase())
+        && !EXIFTOOL_RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(name))
+}
+
+pub(crate) fn is_video(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy();
+            ext.eq_ignore_ascii_case("mov") || ext.eq_ignore_ascii_case("mp4")
+        })
+        .unwrap_or(false)
+}
+
+fn exif_tag_key(tag: &str) -> &str {
+    tag.rsplit(':').next().unwrap_or(tag)
+}
+
+fn exiftool_args(extra_tags: &[String], video: bool) -> Vec<String> {
+    let video_args = if video { EXIFTOOL_VIDEO_ARGS } else { &[] };
+    video_args
+        .iter()
+        .chain(EXIFTOOL_ARGS)
+        .map(|arg| arg.to_string())
+        .chain(
+            extra_tags
+                .iter()
+                .filter(|tag| is_valid_exif_tag_name(tag))
+                .map(|tag| format!("-{tag}")),
+        )
+        .collect()
+}
+
+// 追加タグの値は文字列にそろえる。読めなかったタグは含めない
+fn extra_tags_from_json(json: &JsonValue, extra_tags: &[String]) -> BTreeMap<String, String> {
+    extra_tags
+        .iter()
+        .filter(|tag| is_valid_exif_tag_name(tag))
+        .filter_map(|tag| {
+            let key = exif_tag_key(tag);
+            pick_json_string(json, &[key]).map(|value| (key.to_string(), value))
+        })
+        .collect()
+}
 
+fn partial_metadata_from_json(json: &JsonValue) -> PartialMetadata {
     let date = pick_json_string(
-        &json,
-        &["DateTimeOriginal", "DateTimeDigitized", "DateTime"],
+        json,
+        &[
+            "DateTimeOriginal",
+            "DateTimeDigitized",
+            "DateTime",
+            "CreateDate",
+            "MediaCreateDate",
+        ],
     )
-    .and_then(|raw| parse_date(&raw));
-    let camera_make = pick_json_string(&json, &["Make"]);
-    let camera_model = pick_json_string(&json, &["Model"]);
-    let lens_make = pick_json_string(&json, &["LensMake", "LensManufacturer"]);
+    .and_then(|raw| {
+        let offset = pick_json_string(
+            json,
+            &["OffsetTimeOriginal", "OffsetTimeDigitized", "OffsetTime"],
+        );
+        parse_date(&raw, offset.as_deref().and_then(parse_utc_offset))
+    })
+    .map(|date| {
+        match pick_json_string(
+            json,
+            &["SubSecTimeOriginal", "SubSecTimeDigitized", "SubSecTime"],
+        ) {
+            Some(sub_sec) => with_sub_sec(date, &sub_sec),
+            None => date,
+        }
+    });
+    let camera_make = pick_json_string(json, &["Make"]);
+    let camera_model = pick_json_string(json, &["Model"]);
+    let lens_make = pick_json_string(json, &["LensMake", "LensManufacturer"]);
     let lens_model = pick_json_string(
-        &json,
+        json,
         &[
             "LensModel",
             "Lens",
@@ -123,16 +668,142 @@ fn read_exif_metadata_with_exiftool(path: &Path) -> Result<PartialMetadata> {
             "LensSpecification",
         ],
     );
-    let film_sim = pick_film_simulation_from_json(&json);
+    let film_sim = pick_film_simulation_from_json(json);
+    let rating = pick_json_string(json, &["Rating"]).and_then(|raw| parse_rating(&raw));
+    let label = pick_json_string(json, &["Label"]);
+    let keywords = pick_json_list(json, "Subject");
+    let dynamic_range = pick_json_string(json, &["DevelopmentDynamicRange", "AutoDynamicRange"])
+        .and_then(|raw| format_dynamic_range(&raw));
+    let grain_effect = format_grain_effect(
+        pick_json_string(json, &["GrainEffectRoughness", "GrainEffect"]),
+        pick_json_string(json, &["GrainEffectSize"]),
+    );
+    let color_chrome_effect = pick_json_string(json, &["ColorChromeEffect"]);
+    let wb_shift =
+        pick_json_string(json, &["WhiteBalanceFineTune"]).and_then(|raw| format_wb_shift(&raw));
+    let focal_length_eq = pick_json_string(json, &["FocalLengthIn35mmFormat"])
+        .and_then(|raw| format_focal_length(&raw));
+    let exposure_compensation = pick_json_string(json, &["ExposureCompensation"])
+        .and_then(|raw| format_exposure_compensation(&raw));
+    let flash_fired = pick_json_string(json, &["Flash"]).and_then(|raw| parse_flash_fired(&raw));
+    let image_unique_id = pick_json_string(json, &["ImageUniqueID"])
+        .and_then(|raw| normalize_image_unique_id(&raw))
+        .or_else(|| {
+            pick_json_string(json, &["DocumentID"]).and_then(|raw| normalize_image_unique_id(&raw))
+        });
+    let gps_date = pick_json_string(json, &["GPSDateStamp"])
+        .zip(pick_json_string(json, &["GPSTimeStamp"]))
+        .and_then(|(date, time)| parse_gps_date(&date, &time));
 
-    Ok(PartialMetadata {
+    PartialMetadata {
         date,
         camera_make: normalize(camera_make),
         camera_model: normalize(camera_model),
         lens_make: normalize(lens_make),
         lens_model: normalize(lens_model),
         film_sim: normalize(film_sim),
-    })
+        rating,
+        dynamic_range,
+        grain_effect,
+        color_chrome_effect: normalize(color_chrome_effect),
+        wb_shift,
+        focal_length_eq,
+        exposure_compensation,
+        flash_fired,
+        image_unique_id,
+        label: normalize(label),
+        keywords,
+        gps_date,
+        ..PartialMetadata::default()
+    }
+}
+
+// "200%" や 200 を "DR200" にそろえる
+fn format_dynamic_range(raw: &str) -> Option<String> {
+    let value = raw.trim().trim_end_matches('%').trim();
+    (!value.is_empty() && value.chars().all(|c| c.is_ascii_digit())).then(|| format!("DR{value}"))
+}
+
+// 強度がオフならサイズは見ない。"Weak Small" のように強度とサイズを並べる
+fn format_grain_effect(roughness: Option<String>, size: Option<String>) -> Option<String> {
+    let roughness = normalize(roughness)?;
+    if roughness.eq_ignore_ascii_case("off") {
+        return Some("Off".to_string());
+    }
+    match normalize(size).filter(|size| !size.eq_ignore_ascii_case("off")) {
+        Some(size) => Some(format!("{roughness} {size}")),
+        None => Some(roughness),
+    }
+}
+
+// exiftool の "Red +2, Blue -1" を "R+2B-1" にする
+fn format_wb_shift(raw: &str) -> Option<String> {
+    let mut values = raw
+        .split(|c: char| !(c.is_ascii_digit() || c == '-' || c == '+'))
+        .filter(|part| !part.is_empty() && *part != "-" && *part != "+")
+        .filter_map(|part| part.parse::<i64>().ok());
+    let red = values.next()?;
+    let blue = values.next()?;
+    Some(format!("R{red:+}B{blue:+}"))
+}
+
+// exiftool の "53 mm" や kamadak-exif の 53 を "53mm" にそろえる。0 は不明を表す
+pub(crate) fn format_focal_length(raw: &str) -> Option<String> {
+    let number = raw
+        .trim()
+        .trim_end_matches("mm")
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| *value > 0.0)?;
+    Some(format!("{}mm", number.round() as u32))
+}
+
+// exiftool の "+2/3"、kamadak-exif の "-0.67 EV"、XMP の "-2/3" を "+0.7EV" の形にそろえる
+pub(crate) fn format_exposure_compensation(raw: &str) -> Option<String> {
+    let text = raw.trim().trim_end_matches("EV").trim();
+    let value = match text.split_once('/') {
+        Some((numerator, denominator)) => {
+            let denominator = denominator
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|v| *v != 0.0)?;
+            numerator.trim().parse::<f64>().ok()? / denominator
+        }
+        None => text.parse::<f64>().ok()?,
+    };
+    let rounded = (value * 10.0).round() / 10.0;
+    if rounded == 0.0 {
+        return Some("0EV".to_string());
+    }
+    Some(format!("{rounded:+}EV"))
+}
+
+// Flash は数値 (bit0 が発光) か "Fired" / "Off, Did not fire" のような文字列で返る
+pub(crate) fn parse_flash_fired(raw: &str) -> Option<bool> {
+    let text = raw.trim();
+    if let Ok(value) = text.parse::<u16>() {
+        return Some(value & 1 == 1);
+    }
+    let lower = text.to_ascii_lowercase();
+    if lower.contains("not fire")
+        || lower.contains("no flash")
+        || lower == "off"
+        || lower == "false"
+    {
+        Some(false)
+    } else if lower.contains("fired") || lower == "true" {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+// ImageUniqueID を書かない機種は 0 埋めの値を入れるため、無いものとして扱う
+pub(crate) fn normalize_image_unique_id(raw: &str) -> Option<String> {
+    let text = raw.trim().trim_matches('\0').trim();
+    (!text.is_empty() && text.chars().any(|c| c != '0')).then(|| text.to_string())
 }
 
 fn pick_json_string(json: &JsonValue, keys: &[&str]) -> Option<String> {
@@ -146,6 +817,22 @@ fn pick_json_string(json: &JsonValue, keys: &[&str]) -> Option<String> {
     None
 }
 
+// キーワードのように複数の値を持つタグ。1つだけの場合は文字列で返る
+fn pick_json_list(json: &JsonValue, key: &str) -> Vec<String> {
+    let mut values = Vec::<String>::new();
+    let items = match json.get(key) {
+        Some(JsonValue::Array(items)) => items.iter().collect(),
+        Some(value) => vec![value],
+        None => Vec::new(),
+    };
+    for text in items.into_iter().filter_map(json_value_to_string) {
+        if !values.contains(&text) {
+            values.push(text);
+        }
+    }
+    values
+}
+
 fn json_value_to_string(value: &JsonValue) -> Option<String> {
     match value {
         JsonValue::String(v) => {
@@ -170,6 +857,35 @@ fn pick_film_simulation_from_json(json: &JsonValue) -> Option<String> {
         }
     }
 
+    // 他社の仕上がり設定は ColorMode (B&W など) より具体的なので先に見る
+    if let Some(raw) = pick_json_string(json, &["CreativeStyle", "CreativeStyleSetting"]) {
+        if let Some(mapped) = normalize_sony_creative_style(&raw) {
+            return Some(mapped);
+        }
+    }
+
+    if let Some(raw) = pick_json_string(json, &["PictureControlName"]) {
+        if let Some(mapped) = normalize_nikon_picture_control(&raw) {
+            return Some(mapped);
+        }
+    }
+
+    if let Some(raw) = pick_json_string(json, &["PictureStyle"]) {
+        if let Some(mapped) = normalize_canon_picture_style(&raw) {
+            return Some(mapped);
+        }
+    }
+
+    if let Some(mapped) = pick_olympus_picture_mode_from_json(json) {
+        return Some(mapped);
+    }
+
+    if let Some(raw) = pick_json_string(json, &["PhotoStyle"]) {
+        if let Some(mapped) = normalize_panasonic_photo_style(&raw) {
+            return Some(mapped);
+        }
+    }
+
     if let Some(raw) = pick_json_string(json, &["ColorMode"]) {
         if let Some(mapped) = normalize_film_simulation_name(&raw, false) {
             return Some(mapped);
@@ -199,6 +915,214 @@ fn pick_film_simulation_from_json(json: &JsonValue) -> Option<String> {
     None
 }
 
+// Sony の Creative Look は略号 (ST, VV2 ...)、それ以前の Creative Style は名前で記録される
+const SONY_CREATIVE_LOOKS: &[&str] = &["ST", "PT", "NT", "VV", "VV2", "FL", "IN", "SH", "BW", "SE"];
+const SONY_CREATIVE_STYLES: &[(&str, &str)] = &[
+    ("STANDARD", "Standard"),
+    ("VIVID", "Vivid"),
+    ("NEUTRAL", "Neutral"),
+    ("CLEAR", "Clear"),
+    ("DEEP", "Deep"),
+    ("LIGHT", "Light"),
+    ("PORTRAIT", "Portrait"),
+    ("LANDSCAPE", "Landscape"),
+    ("SUNSET", "Sunset"),
+    ("NIGHT VIEW", "Night View"),
+    ("NIGHT VIEW/PORTRAIT", "Night View"),
+    ("NIGHTSCAPE", "Night View"),
+    ("AUTUMN LEAVES", "Autumn Leaves"),
+    ("B&W", "B&W"),
+    ("BLACK & WHITE", "B&W"),
+    ("SEPIA", "Sepia"),
+];
+
+fn normalize_sony_creative_style(raw: &str) -> Option<String> {
+    let upper = raw.trim().trim_matches('"').to_ascii_uppercase();
+    if let Some(look) = SONY_CREATIVE_LOOKS.iter().find(|look| **look == upper) {
+        return Some(look.to_string());
+    }
+    SONY_CREATIVE_STYLES
+        .iter()
+        .find(|(key, _)| *key == upper)
+        .map(|(_, name)| name.to_string())
+}
+
+// カスタムピクチャーコントロールは利用者が付けた名前をそのまま使う
+const NIKON_PICTURE_CONTROLS: &[&str] = &[
+    "Auto",
+    "Standard",
+    "Neutral",
+    "Vivid",
+    "Monochrome",
+    "Flat Monochrome",
+    "Deep Tone Monochrome",
+    "Portrait",
+    "Rich Tone Portrait",
+    "Landscape",
+    "Flat",
+    "Dream",
+    "Morning",
+    "Pop",
+    "Sunday",
+    "Somber",
+    "Dramatic",
+    "Silence",
+    "Bleached",
+    "Melancholic",
+    "Pure",
+    "Denim",
+    "Toy",
+    "Sepia",
+    "Blue",
+    "Red",
+    "Pink",
+    "Charcoal",
+    "Graphite",
+    "Binary",
+    "Carbon",
+];
+
+const CANON_PICTURE_STYLES: &[&str] = &[
+    "Auto",
+    "Standard",
+    "Portrait",
+    "Landscape",
+    "Fine Detail",
+    "Neutral",
+    "Faithful",
+    "Monochrome",
+    "High Saturation",
+    "Low Saturation",
+    "Adobe RGB",
+];
+
+// ユーザー設定 ("User Def. 1") とPC登録 ("PC 1") の枠は番号付きの短い名前にする
+fn normalize_canon_picture_style(raw: &str) -> Option<String> {
+    let text = raw.trim().trim_matches('"').trim();
+    if text.is_empty() || text.eq_ignore_ascii_case("n/a") || text.eq_ignore_ascii_case("none") {
+        return None;
+    }
+    if let Some(name) = CANON_PICTURE_STYLES
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(text))
+    {
+        return Some(name.to_string());
+    }
+    for (prefix, short) in [("User Def.", "UserDef"), ("PC", "PC"), ("CM Set", "CMSet")] {
+        let Some(slot) = text
+            .get(..prefix.len())
+            .filter(|head| head.eq_ignore_ascii_case(prefix))
+            .map(|_| text[prefix.len()..].trim())
+        else {
+            continue;
+        };
+        if !slot.is_empty() && slot.chars().all(|c| c.is_ascii_digit()) {
+            return Some(format!("{short}{slot}"));
+        }
+    }
+    Some(text.to_string())
+}
+
+// Olympus/OM System はアートフィルター適用時にそちらを優先する。PictureMode は富士フイルムでは
+// 撮影モードを指すため、メーカーが Olympus/OM のときだけ使う
+fn pick_olympus_picture_mode_from_json(json: &JsonValue) -> Option<String> {
+    let make = pick_json_string(json, &["Make"])?.to_ascii_uppercase();
+    if !make.contains("OLYMPUS") && !make.starts_with("OM ") {
+        return None;
+    }
+    if let Some(filter) =
+        pick_json_string(json, &["ArtFilter"]).and_then(|raw| olympus_first_value(&raw))
+    {
+        return Some(filter);
+    }
+    pick_json_string(json, &["PictureMode"]).and_then(|raw| olympus_first_value(&raw))
+}
+
+// exiftool は "Grainy Film; 0; 0; 0" のように設定値を続けて出力する
+fn olympus_first_value(raw: &str) -> Option<String> {
+    let head = raw.split(';').next()?.trim();
+    if head.is_empty()
+        || head.eq_ignore_ascii_case("off")
+        || head.eq_ignore_ascii_case("n/a")
+        || head.eq_ignore_ascii_case("art")
+    {
+        return None;
+    }
+    Some(head.to_string())
+}
+
+const PANASONIC_PHOTO_STYLES: &[&str] = &[
+    "Standard",
+    "Vivid",
+    "Natural",
+    "Monochrome",
+    "L.Monochrome",
+    "L.Monochrome D",
+    "L.Monochrome S",
+    "Scenery",
+    "Portrait",
+    "Cinelike D",
+    "Cinelike D2",
+    "Cinelike V",
+    "Cinelike V2",
+    "Like709",
+    "Flat",
+    "V-Log",
+];
+
+// リアルタイムLUTは "Real Time LUT: <名前>" のように名前が付く場合はその名前を使う
+fn normalize_panasonic_photo_style(raw: &str) -> Option<String> {
+    let text = raw.trim().trim_matches('"').trim();
+    if text.is_empty() || text.eq_ignore_ascii_case("n/a") || text.eq_ignore_ascii_case("auto") {
+        return None;
+    }
+    if let Some((head, name)) = text.split_once(':') {
+        if head.to_ascii_uppercase().contains("LUT") && !name.trim().is_empty() {
+            return Some(name.trim().to_string());
+        }
+    }
+    // "Standard or Custom" や "L. Monochrome D" の表記ゆれをそろえる
+    let compact = text
+        .trim_end_matches(" or Custom")
+        .replace("L. ", "L.")
+        .replace("  ", " ");
+    let name = PANASONIC_PHOTO_STYLES
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(&compact))
+        .map_or(compact.as_str(), |name| *name);
+    Some(name.to_string())
+}
+
+fn map_panasonic_photo_style(code: u16) -> Option<&'static str> {
+    match code {
+        1 => Some("Standard"),
+        2 => Some("Vivid"),
+        3 => Some("Natural"),
+        4 => Some("Monochrome"),
+        5 => Some("Scenery"),
+        6 => Some("Portrait"),
+        8 => Some("Cinelike D"),
+        9 => Some("Cinelike V"),
+        11 => Some("L.Monochrome"),
+        12 => Some("Like709"),
+        15 => Some("L.Monochrome D"),
+        17 => Some("V-Log"),
+        _ => None,
+    }
+}
+
+fn normalize_nikon_picture_control(raw: &str) -> Option<String> {
+    let text = raw.trim().trim_matches('"').trim();
+    if text.is_empty() || text.eq_ignore_ascii_case("n/a") || text.eq_ignore_ascii_case("none") {
+        return None;
+    }
+    let name = NIKON_PICTURE_CONTROLS
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(text))
+        .map_or(text, |name| *name);
+    Some(name.to_string())
+}
+
 pub(crate) fn normalize_film_simulation_from_camera_profile(raw: &str) -> Option<String> {
     let text = raw.trim().trim_matches('"');
     if text.is_empty() {
@@ -335,24 +1259,45 @@ fn normalize_film_simulation_name(raw: &str, allow_unmapped: bool) -> Option<Str
 }
 
 fn read_exif_metadata_with_kamadak(path: &Path) -> Result<PartialMetadata> {
-    let file = File::open(path)
+    let mut file = File::open(path)
         .with_context(|| format!("EXIF読み込み対象を開けませんでした: {}", path.display()))?;
-    let mut buf = BufReader::new(file);
     let mut reader = KamadakReader::new();
     reader.continue_on_error(true);
-    let exif = reader
-        .read_from_container(&mut buf)
-        .or_else(|err| err.distill_partial_result(|_| {}))
-        .with_context(|| format!("EXIFを解析できませんでした: {}", path.display()))?;
+    let raf = read_raf_preview(&mut file)
+        .with_context(|| format!("RAFのヘッダを読めませんでした: {}", path.display()))?;
+    let exif = match &raf {
+        // kamadak-exif は RAF を扱えないため、埋め込みのプレビューJPEGから読む
+        Some(raf) => reader.read_from_container(&mut Cursor::new(&raf.jpeg)),
+        None => reader.read_from_container(&mut BufReader::new(file)),
+    }
+    .or_else(|err| err.distill_partial_result(|_| {}))
+    .with_context(|| format!("EXIFを解析できませんでした: {}", path.display()))?;
 
     let date = find_field_value(
         &exif,
         &["DateTimeOriginal", "DateTimeDigitized", "DateTime"],
     )
-    .and_then(|raw| parse_date(&raw));
+    .and_then(|raw| {
+        let offset = find_field_value(
+            &exif,
+            &["OffsetTimeOriginal", "OffsetTimeDigitized", "OffsetTime"],
+        );
+        parse_date(&raw, offset.as_deref().and_then(parse_utc_offset))
+    })
+    .map(|date| {
+        match find_field_value(
+            &exif,
+            &["SubSecTimeOriginal", "SubSecTimeDigitized", "SubSecTime"],
+        ) {
+            Some(sub_sec) => with_sub_sec(date, &sub_sec),
+            None => date,
+        }
+    });
 
     let camera_make = find_field_value(&exif, &["Make", "CameraMake"]);
-    let camera_model = find_field_value(&exif, &["Model", "CameraModel", "UniqueCameraModel"]);
+    let camera_model = find_field_value(&exif, &["Model", "CameraModel", "UniqueCameraModel"])
+        .or_else(|| find_dng_camera_model(&exif))
+        .or_else(|| raf.and_then(|raf| raf.camera_name));
     let lens_make = find_field_value(&exif, &["LensMake", "LensManufacturer"]);
     let lens_model = find_field_value(
         &exif,
@@ -373,16 +1318,109 @@ fn read_exif_metadata_with_kamadak(path: &Path) -> Result<PartialMetadata> {
             "PictureMode",
         ],
     )
-    .or_else(|| find_fujifilm_film_simulation(&exif));
+    .or_else(|| find_fujifilm_film_simulation(&exif))
+    .or_else(|| find_nikon_picture_control(&exif))
+    .or_else(|| find_panasonic_photo_style(&exif));
 
-    Ok(PartialMetadata {
+    let mut meta = PartialMetadata {
         date,
         camera_make: normalize(camera_make),
         camera_model: normalize(camera_model),
         lens_make: normalize(lens_make),
         lens_model: normalize(lens_model),
         film_sim: normalize(film_sim),
-    })
+        focal_length_eq: find_field_value(&exif, &["FocalLengthIn35mmFilm"])
+            .and_then(|raw| format_focal_length(&raw)),
+        exposure_compensation: find_field_value(&exif, &["ExposureBiasValue"])
+            .and_then(|raw| format_exposure_compensation(&raw)),
+        flash_fired: find_field_value(&exif, &["Flash"]).and_then(|raw| parse_flash_fired(&raw)),
+        image_unique_id: find_field_value(&exif, &["ImageUniqueID"])
+            .and_then(|raw| normalize_image_unique_id(&raw)),
+        gps_date: find_field_value(&exif, &["GPSDateStamp"])
+            .zip(find_field_value(&exif, &["GPSTimeStamp"]))
+            .and_then(|(date, time)| parse_gps_date(&date, &time)),
+        ..PartialMetadata::default()
+    };
+    if let Some(maker_note) = find_maker_note(&exif) {
+        meta.merge_missing_from(&read_fujifilm_recipe(maker_note));
+    }
+    Ok(meta)
+}
+
+// JPEGのAPPnセグメントを画像データの手前まで順に見て、XMPパケットを取り出す
+fn read_jpeg_xmp_packet(path: &Path) -> Result<Option<String>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut soi = [0u8; 2];
+    if file.read_exact(&mut soi).is_err() || soi != [0xFF, 0xD8] {
+        return Ok(None);
+    }
+
+    loop {
+        let mut marker = [0u8; 2];
+        file.read_exact(&mut marker)?;
+        if marker[0] != 0xFF {
+            anyhow::bail!("JPEGのセグメントが不正です");
+        }
+        // SOS/EOI 以降にメタデータは無い
+        if marker[1] == 0xDA || marker[1] == 0xD9 {
+            return Ok(None);
+        }
+        let mut len = [0u8; 2];
+        file.read_exact(&mut len)?;
+        let len = usize::from(u16::from_be_bytes(len));
+        if len < 2 {
+            anyhow::bail!("JPEGのセグメント長が不正です");
+        }
+        let mut body = vec![0u8; len - 2];
+        file.read_exact(&mut body)?;
+        if marker[1] == 0xE1 {
+            if let Some(packet) = body.strip_prefix(JPEG_XMP_SIGNATURE) {
+                return Ok(Some(String::from_utf8_lossy(packet).into_owned()));
+            }
+        }
+    }
+}
+
+struct RafPreview {
+    camera_name: Option<String>,
+    jpeg: Vec<u8>,
+}
+
+// RAF でなければ None を返し、ファイル位置を先頭に戻す
+fn read_raf_preview(file: &mut File) -> Result<Option<RafPreview>> {
+    let mut header = [0u8; RAF_HEADER_LEN];
+    let is_raf = match file.read_exact(&mut header) {
+        Ok(()) => header.starts_with(RAF_MAGIC),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => false,
+        Err(err) => return Err(err.into()),
+    };
+    if !is_raf {
+        file.seek(SeekFrom::Start(0))?;
+        return Ok(None);
+    }
+
+    let jpeg_offset = read_be_u32(&header, RAF_JPEG_OFFSET_POS).context("RAFヘッダが短すぎます")?;
+    let jpeg_len =
+        read_be_u32(&header, RAF_JPEG_OFFSET_POS + 4).context("RAFヘッダが短すぎます")?;
+    let file_len = file.metadata()?.len();
+    if jpeg_len == 0 || u64::from(jpeg_offset) + u64::from(jpeg_len) > file_len {
+        anyhow::bail!("RAFのプレビューJPEGの位置が不正です");
+    }
+    file.seek(SeekFrom::Start(u64::from(jpeg_offset)))?;
+    let mut jpeg = vec![0u8; jpeg_len as usize];
+    file.read_exact(&mut jpeg)?;
+
+    let camera_name = header[RAF_CAMERA_NAME_RANGE]
+        .split(|byte| *byte == 0)
+        .next()
+        .map(|raw| String::from_utf8_lossy(raw).trim().to_string())
+        .filter(|name| !name.is_empty());
+    Ok(Some(RafPreview { camera_name, jpeg }))
+}
+
+fn read_be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
 }
 
 fn normalize(value: Option<String>) -> Option<String> {
@@ -437,8 +1475,19 @@ fn display_value_string(field: &Field, exif: &exif::Exif) -> Option<String> {
     }
 }
 
-fn find_fujifilm_film_simulation(exif: &exif::Exif) -> Option<String> {
-    let maker_note = exif.fields().find_map(|field| {
+// DNG は TIFF として読めるが、Model が無い変換ファイルでは UniqueCameraModel にしか機種名が入らない
+fn find_dng_camera_model(exif: &exif::Exif) -> Option<String> {
+    exif.get_field(Tag(ExifContext::Tiff, DNG_TAG_VERSION), In::PRIMARY)?;
+    [DNG_TAG_UNIQUE_CAMERA_MODEL, DNG_TAG_LOCALIZED_CAMERA_MODEL]
+        .into_iter()
+        .find_map(|number| {
+            let field = exif.get_field(Tag(ExifContext::Tiff, number), In::PRIMARY)?;
+            field_value_to_string(field, exif)
+        })
+}
+
+fn find_maker_note(exif: &exif::Exif) -> Option<&[u8]> {
+    exif.fields().find_map(|field| {
         if !field.tag.to_string().eq_ignore_ascii_case("MakerNote") {
             return None;
         }
@@ -446,14 +1495,101 @@ fn find_fujifilm_film_simulation(exif: &exif::Exif) -> Option<String> {
             ExifValue::Undefined(bytes, _) | ExifValue::Byte(bytes) => Some(bytes.as_slice()),
             _ => None,
         }
-    })?;
+    })
+}
 
-    let code = parse_fujifilm_film_mode_code(maker_note)?;
+fn find_fujifilm_film_simulation(exif: &exif::Exif) -> Option<String> {
+    let code = parse_fujifilm_film_mode_code(find_maker_note(exif)?)?;
     let name = map_fujifilm_film_mode(code)?;
     Some(name.to_string())
 }
 
+fn find_nikon_picture_control(exif: &exif::Exif) -> Option<String> {
+    let raw = parse_nikon_picture_control_name(find_maker_note(exif)?)?;
+    normalize_nikon_picture_control(&raw)
+}
+
+// Nikon の MakerNote は "Nikon\0" + バージョンの後に独自の TIFF ヘッダを持ち、オフセットはそこからの相対位置
+fn parse_nikon_picture_control_name(maker_note: &[u8]) -> Option<String> {
+    if !maker_note.starts_with(NIKON_MAKER_NOTE_PREFIX) {
+        return None;
+    }
+    let tiff = maker_note.get(NIKON_MAKER_NOTE_TIFF_OFFSET..)?;
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| read_u16_with_order(tiff, offset, big_endian);
+    let read_u32 = |offset: usize| read_u32_with_order(tiff, offset, big_endian);
+
+    let ifd_offset = read_u32(4)? as usize;
+    let entry_count = read_u16(ifd_offset)? as usize;
+    let entry_offset = (0..entry_count)
+        .map(|index| ifd_offset + 2 + index * 12)
+        .find(|offset| read_u16(*offset) == Some(NIKON_TAG_PICTURE_CONTROL_DATA))?;
+    let count = read_u32(entry_offset + 4)? as usize;
+    let value_offset = read_u32(entry_offset + 8)? as usize;
+    let data = tiff.get(value_offset..value_offset.checked_add(count)?)?;
+
+    // PictureControlData の先頭4バイトはバージョン ("0100"/"0200"/"0300")。0300 以降は名前の位置がずれる
+    let name_offset = if data.starts_with(b"03") { 8 } else { 4 };
+    let name = data.get(name_offset..name_offset + 20)?;
+    let name = name.split(|byte| *byte == 0).next()?;
+    let text = String::from_utf8_lossy(name).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
 fn parse_fujifilm_film_mode_code(maker_note: &[u8]) -> Option<u16> {
+    let values = find_fujifilm_tag_values(maker_note, FUJIFILM_TAG_FILM_MODE)?;
+    values.first().map(|value| *value as u16)
+}
+
+fn read_fujifilm_recipe(maker_note: &[u8]) -> PartialMetadata {
+    let first = |tag| find_fujifilm_tag_values(maker_note, tag)?.first().copied();
+    let level = |value: i64| match value {
+        0 => Some("Off"),
+        32 => Some("Weak"),
+        64 => Some("Strong"),
+        _ => None,
+    };
+    let size = |value: i64| match value {
+        0 => Some("Off"),
+        16 => Some("Small"),
+        32 => Some("Large"),
+        _ => None,
+    };
+
+    let dynamic_range = first(FUJIFILM_TAG_DEVELOPMENT_DR)
+        .filter(|value| *value > 0)
+        .or_else(|| first(FUJIFILM_TAG_AUTO_DR))
+        .and_then(|value| format_dynamic_range(&value.to_string()));
+    let grain_effect = format_grain_effect(
+        first(FUJIFILM_TAG_GRAIN_ROUGHNESS)
+            .and_then(level)
+            .map(str::to_string),
+        first(FUJIFILM_TAG_GRAIN_SIZE)
+            .and_then(size)
+            .map(str::to_string),
+    );
+    let color_chrome_effect = first(FUJIFILM_TAG_COLOR_CHROME)
+        .and_then(level)
+        .map(str::to_string);
+    // MakerNote には1段=20で記録されている (exiftool の表示と同じ段数にそろえる)
+    let wb_shift = find_fujifilm_tag_values(maker_note, FUJIFILM_TAG_WB_FINE_TUNE)
+        .filter(|values| values.len() >= 2)
+        .map(|values| format!("R{:+}B{:+}", values[0] / 20, values[1] / 20));
+
+    PartialMetadata {
+        dynamic_range,
+        grain_effect,
+        color_chrome_effect,
+        wb_shift,
+        ..PartialMetadata::default()
+    }
+}
+
+fn find_fujifilm_tag_values(maker_note: &[u8], target_tag: u16) -> Option<Vec<i64>> {
     if maker_note.len() < 16 || !maker_note.starts_with(FUJIFILM_MAKER_NOTE_PREFIX) {
         return None;
     }
@@ -466,17 +1602,13 @@ fn parse_fujifilm_film_mode_code(maker_note: &[u8]) -> Option<u16> {
         offsets.push(offset as usize);
     }
 
-    for offset in offsets {
-        if let Some(code) = parse_fujifilm_ifd_short_tag(maker_note, offset, FUJIFILM_TAG_FILM_MODE)
-        {
-            return Some(code);
-        }
-    }
-
-    None
+    offsets
+        .into_iter()
+        .find_map(|offset| parse_fujifilm_ifd_tag(maker_note, offset, target_tag))
 }
 
-fn parse_fujifilm_ifd_short_tag(data: &[u8], ifd_offset: usize, target_tag: u16) -> Option<u16> {
+// SHORT/LONG/SSHORT/SLONG の値を読む。4バイトに収まらない場合は値の位置を指すオフセットを辿る
+fn parse_fujifilm_ifd_tag(data: &[u8], ifd_offset: usize, target_tag: u16) -> Option<Vec<i64>> {
     let entry_count = read_le_u16(data, ifd_offset)? as usize;
     let entries_start = ifd_offset.checked_add(2)?;
 
@@ -493,28 +1625,72 @@ fn parse_fujifilm_ifd_short_tag(data: &[u8], ifd_offset: usize, target_tag: u16)
 
         let field_type = read_le_u16(data, entry_offset + 2)?;
         let count = read_le_u32(data, entry_offset + 4)? as usize;
+        let width = match field_type {
+            3 | 8 => 2,
+            4 | 9 => 4,
+            _ => return None,
+        };
         if count == 0 {
             return None;
         }
+        let value_start = if count.checked_mul(width)? <= 4 {
+            entry_offset + 8
+        } else {
+            read_le_u32(data, entry_offset + 8)? as usize
+        };
+
+        return (0..count)
+            .map(|index| {
+                let offset = value_start.checked_add(index * width)?;
+                match field_type {
+                    3 => read_le_u16(data, offset).map(i64::from),
+                    8 => read_le_u16(data, offset).map(|v| i64::from(v as i16)),
+                    4 => read_le_u32(data, offset).map(i64::from),
+                    _ => read_le_u32(data, offset).map(|v| i64::from(v as i32)),
+                }
+            })
+            .collect();
+    }
 
-        if field_type == 3 {
-            if count <= 2 {
-                return read_le_u16(data, entry_offset + 8);
-            }
-
-            let value_offset = read_le_u32(data, entry_offset + 8)? as usize;
-            return read_le_u16(data, value_offset);
-        }
+    None
+}
 
-        if field_type == 4 && count == 1 {
-            let value = read_le_u32(data, entry_offset + 8)?;
-            return Some((value & 0xffff) as u16);
-        }
+fn find_panasonic_photo_style(exif: &exif::Exif) -> Option<String> {
+    let code = parse_panasonic_photo_style_code(find_maker_note(exif)?, !exif.little_endian())?;
+    map_panasonic_photo_style(code).map(str::to_string)
+}
 
+// Panasonic の MakerNote はヘッダの直後が IFD で、バイトオーダーは EXIF 本体と同じ
+fn parse_panasonic_photo_style_code(maker_note: &[u8], big_endian: bool) -> Option<u16> {
+    if !maker_note.starts_with(PANASONIC_MAKER_NOTE_PREFIX) {
         return None;
     }
+    let ifd_offset = PANASONIC_MAKER_NOTE_PREFIX.len();
+    let entry_count = read_u16_with_order(maker_note, ifd_offset, big_endian)? as usize;
+    (0..entry_count)
+        .map(|index| ifd_offset + 2 + index * 12)
+        .find(|offset| {
+            read_u16_with_order(maker_note, *offset, big_endian) == Some(PANASONIC_TAG_PHOTO_STYLE)
+        })
+        .and_then(|offset| read_u16_with_order(maker_note, offset + 8, big_endian))
+}
 
-    None
+fn read_u16_with_order(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes: [u8; 2] = data.get(offset..offset.checked_add(2)?)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn read_u32_with_order(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
 }
 
 fn read_le_u16(data: &[u8], offset: usize) -> Option<u16> {
@@ -549,7 +1725,33 @@ fn map_fujifilm_film_mode(code: u16) -> Option<&'static str> {
     }
 }
 
-fn parse_date(input: &str) -> Option<DateTime<Local>> {
+// GPSの日付と時刻はUTCで記録される。秒は小数のこともある
+fn parse_gps_date(date_stamp: &str, time_stamp: &str) -> Option<DateTime<FixedOffset>> {
+    let date = NaiveDate::parse_from_str(date_stamp.trim(), "%Y:%m:%d").ok()?;
+    let time = NaiveTime::parse_from_str(time_stamp.trim(), "%H:%M:%S%.f").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_time(time)).fixed_offset())
+}
+
+// SubSecTimeOriginal は "123" のような秒未満の桁だけが入るため、9桁に右詰めしてナノ秒にする
+fn with_sub_sec(date: DateTime<FixedOffset>, raw: &str) -> DateTime<FixedOffset> {
+    let digits: String = raw
+        .trim()
+        .chars()
+        .take_while(|ch| ch.is_ascii_digit())
+        .take(9)
+        .collect();
+    if digits.is_empty() || date.nanosecond() != 0 {
+        return date;
+    }
+    format!("{digits:0<9}")
+        .parse::<u32>()
+        .ok()
+        .and_then(|nanos| date.with_nanosecond(nanos))
+        .unwrap_or(date)
+}
+
+// オフセットの無い日時は OffsetTimeOriginal などの値、それも無ければPCのタイムゾーンで読む
+fn parse_date(input: &str, offset: Option<FixedOffset>) -> Option<DateTime<FixedOffset>> {
     let normalized = input.trim();
 
     let candidates = [
@@ -565,11 +1767,15 @@ fn parse_date(input: &str) -> Option<DateTime<Local>> {
 
     for fmt in candidates {
         if let Ok(dt) = DateTime::parse_from_str(normalized, fmt) {
-            return Some(dt.with_timezone(&Local));
+            return Some(dt);
         }
         if let Ok(naive) = NaiveDateTime::parse_from_str(normalized, fmt) {
-            if let Some(local) = Local.from_local_datetime(&naive).single() {
-                return Some(local);
+            let date = match offset {
+                Some(offset) => offset.from_local_datetime(&naive).single(),
+                None => local_date(&naive),
+            };
+            if date.is_some() {
+                return date;
             }
         }
     }
@@ -580,11 +1786,24 @@ fn parse_date(input: &str) -> Option<DateTime<Local>> {
 #[cfg(test)]
 mod tests {
     use super::{
-        map_fujifilm_film_mode, normalize_film_simulation_from_saturation,
-        normalize_film_simulation_name, parse_fujifilm_film_mode_code,
-        pick_film_simulation_from_json,
+        exiftool_args, exiftool_messages_from_json, extra_tags_from_json,
+        format_exposure_compensation, format_focal_length, map_fujifilm_film_mode,
+        match_source_files, normalize_canon_picture_style,
+        normalize_film_simulation_from_saturation, normalize_film_simulation_name,
+        normalize_image_unique_id, normalize_nikon_picture_control,
+        normalize_panasonic_photo_style, normalize_sony_creative_style, parse_flash_fired,
+        parse_fujifilm_film_mode_code, parse_gps_date, parse_nikon_picture_control_name,
+        parse_panasonic_photo_style_code, parse_timeout_secs, parse_worker_count,
+        partial_metadata_from_json, pick_film_simulation_from_json,
+        read_exif_metadata_with_kamadak, read_fujifilm_recipe, read_jpeg_xmp_packet, ExifToolPool,
     };
+    use crate::exiftool_process::ExiftoolProcess;
+    use crate::xmp_reader::parse_xmp_metadata;
     use serde_json::json;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tempfile::tempdir;
 
     #[test]
     fn parse_fujifilm_film_mode_from_maker_note() {
@@ -607,6 +1826,232 @@ mod tests {
         assert_eq!(code, Some(0x0700));
     }
 
+    #[test]
+    fn read_fujifilm_recipe_from_maker_note() {
+        // IFD[4]: DevelopmentDynamicRange=400, GrainEffectRoughness=Weak, ColorChromeEffect=Strong,
+        // WhiteBalanceFineTune=(+40, -60) (SLONG x2 はIFDの後ろに置く)
+        let entries = 4usize;
+        let ifd_offset = 12usize;
+        let values_offset = ifd_offset + 2 + entries * 12 + 4;
+        let mut note = b"FUJIFILM".to_vec();
+        note.extend_from_slice(&(ifd_offset as u32).to_le_bytes());
+        note.extend_from_slice(&(entries as u16).to_le_bytes());
+        let mut entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            note.extend_from_slice(&tag.to_le_bytes());
+            note.extend_from_slice(&kind.to_le_bytes());
+            note.extend_from_slice(&count.to_le_bytes());
+            note.extend_from_slice(&value.to_le_bytes());
+        };
+        entry(0x100A, 9, 2, values_offset as u32);
+        entry(0x1047, 9, 1, 32);
+        entry(0x1048, 9, 1, 64);
+        entry(0x1403, 3, 1, 400);
+        note.extend_from_slice(&0u32.to_le_bytes());
+        note.extend_from_slice(&40i32.to_le_bytes());
+        note.extend_from_slice(&(-60i32).to_le_bytes());
+
+        let recipe = read_fujifilm_recipe(&note);
+        assert_eq!(recipe.dynamic_range.as_deref(), Some("DR400"));
+        assert_eq!(recipe.grain_effect.as_deref(), Some("Weak"));
+        assert_eq!(recipe.color_chrome_effect.as_deref(), Some("Strong"));
+        assert_eq!(recipe.wb_shift.as_deref(), Some("R+2B-3"));
+    }
+
+    #[test]
+    fn partial_metadata_reads_fujifilm_recipe_from_json() {
+        let json = json!({
+            "Make": "FUJIFILM",
+            "DevelopmentDynamicRange": 200,
+            "GrainEffectRoughness": "Strong",
+            "GrainEffectSize": "Large",
+            "ColorChromeEffect": "Off",
+            "WhiteBalanceFineTune": "Red +1, Blue -4"
+        });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(meta.dynamic_range.as_deref(), Some("DR200"));
+        assert_eq!(meta.grain_effect.as_deref(), Some("Strong Large"));
+        assert_eq!(meta.color_chrome_effect.as_deref(), Some("Off"));
+        assert_eq!(meta.wb_shift.as_deref(), Some("R+1B-4"));
+    }
+
+    #[test]
+    fn partial_metadata_reads_focal_length_in_35mm_format() {
+        let json = json!({ "FocalLengthIn35mmFormat": "53 mm" });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(meta.focal_length_eq.as_deref(), Some("53mm"));
+        assert_eq!(format_focal_length("35"), Some("35mm".to_string()));
+        assert_eq!(format_focal_length("52.5 mm"), Some("53mm".to_string()));
+        assert_eq!(format_focal_length("0"), None);
+        assert_eq!(format_focal_length("unknown"), None);
+    }
+
+    #[test]
+    fn exposure_compensation_and_flash_are_normalized() {
+        let json = json!({ "ExposureCompensation": "+2/3", "Flash": "On, Fired" });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(meta.exposure_compensation.as_deref(), Some("+0.7EV"));
+        assert_eq!(meta.flash_fired, Some(true));
+
+        assert_eq!(
+            format_exposure_compensation("-0.67 EV"),
+            Some("-0.7EV".to_string())
+        );
+        assert_eq!(format_exposure_compensation("-1"), Some("-1EV".to_string()));
+        assert_eq!(format_exposure_compensation("0"), Some("0EV".to_string()));
+        assert_eq!(format_exposure_compensation("1/0"), None);
+        assert_eq!(parse_flash_fired("Off, Did not fire"), Some(false));
+        assert_eq!(parse_flash_fired("No Flash"), Some(false));
+        assert_eq!(parse_flash_fired("16"), Some(false));
+        assert_eq!(parse_flash_fired("25"), Some(true));
+        assert_eq!(parse_flash_fired("unknown"), None);
+    }
+
+    #[test]
+    fn image_unique_id_skips_zero_filled_values() {
+        let json = json!({ "ImageUniqueID": "4A5B6C7D8E9F00112233445566778899" });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(
+            meta.image_unique_id.as_deref(),
+            Some("4A5B6C7D8E9F00112233445566778899")
+        );
+
+        let json = json!({
+            "ImageUniqueID": "00000000000000000000000000000000",
+            "DocumentID": "xmp.did:1234abcd"
+        });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(meta.image_unique_id.as_deref(), Some("xmp.did:1234abcd"));
+        assert_eq!(normalize_image_unique_id("  "), None);
+    }
+
+    #[test]
+    fn read_exif_metadata_with_kamadak_reads_png_exif_chunk() {
+        // Minimal big-endian TIFF: IFD0[1]: tag=0x010F(Make), type=ASCII, count=5, offset=26
+        let mut tiff = vec![0u8; 26];
+        tiff[0..4].copy_from_slice(b"MM\x00\x2a");
+        tiff[4..8].copy_from_slice(&8u32.to_be_bytes());
+        tiff[8..10].copy_from_slice(&1u16.to_be_bytes());
+        tiff[10..12].copy_from_slice(&0x010Fu16.to_be_bytes());
+        tiff[12..14].copy_from_slice(&2u16.to_be_bytes());
+        tiff[14..18].copy_from_slice(&5u32.to_be_bytes());
+        tiff[18..22].copy_from_slice(&26u32.to_be_bytes());
+        tiff.extend_from_slice(b"SONY\0");
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&(tiff.len() as u32).to_be_bytes());
+        png.extend_from_slice(b"eXIf");
+        png.extend_from_slice(&tiff);
+        png.extend_from_slice(&[0u8; 4]);
+
+        let temp = tempdir().expect("tempdir");
+        let path = temp.path().join("Screenshot.png");
+        fs::write(&path, png).expect("png file");
+
+        let meta = read_exif_metadata_with_kamadak(&path).expect("read png exif");
+        assert_eq!(meta.camera_make.as_deref(), Some("SONY"));
+    }
+
+    #[test]
+    fn read_jpeg_xmp_packet_extracts_app1_xmp() {
+        let xmp = br#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating="5" crs:LookName="Camera ACROS" /></rdf:RDF></x:xmpmeta>"#;
+        let mut jpeg = b"\xFF\xD8".to_vec();
+        // XMP より前の別セグメントは読み飛ばす
+        jpeg.extend_from_slice(b"\xFF\xE0\x00\x04\x00\x00");
+        let mut body = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+        body.extend_from_slice(xmp);
+        jpeg.extend_from_slice(b"\xFF\xE1");
+        jpeg.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+        jpeg.extend_from_slice(&body);
+        jpeg.extend_from_slice(b"\xFF\xDA\x00\x02\xFF\xD9");
+
+        let temp = tempdir().expect("tempdir");
+        let path = temp.path().join("DSCF0001.JPG");
+        fs::write(&path, jpeg).expect("jpg file");
+
+        let packet = read_jpeg_xmp_packet(&path)
+            .expect("read jpg")
+            .expect("xmp packet");
+        let meta = parse_xmp_metadata(&packet);
+        assert_eq!(meta.rating, Some(5));
+        assert_eq!(meta.film_sim.as_deref(), Some("ACROS"));
+
+        let plain = temp.path().join("plain.JPG");
+        fs::write(&plain, b"\xFF\xD8\xFF\xDA\x00\x02\xFF\xD9").expect("jpg file");
+        assert!(read_jpeg_xmp_packet(&plain).expect("read jpg").is_none());
+    }
+
+    #[test]
+    fn read_exif_metadata_with_kamadak_reads_dng_unique_camera_model() {
+        // Little-endian TIFF: IFD0[3]: Make, DNGVersion(1.4.0.0), UniqueCameraModel
+        let make = b"Leica\0";
+        let model = b"Leica Q3\0";
+        let data_offset = 8 + 2 + 3 * 12 + 4;
+        let mut dng = b"II\x2a\x00".to_vec();
+        dng.extend_from_slice(&8u32.to_le_bytes());
+        dng.extend_from_slice(&3u16.to_le_bytes());
+        let mut entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            dng.extend_from_slice(&tag.to_le_bytes());
+            dng.extend_from_slice(&kind.to_le_bytes());
+            dng.extend_from_slice(&count.to_le_bytes());
+            dng.extend_from_slice(&value.to_le_bytes());
+        };
+        entry(0x010F, 2, make.len() as u32, data_offset as u32);
+        entry(0xC612, 1, 4, u32::from_le_bytes([1, 4, 0, 0]));
+        entry(
+            0xC614,
+            2,
+            model.len() as u32,
+            (data_offset + make.len()) as u32,
+        );
+        dng.extend_from_slice(&0u32.to_le_bytes());
+        dng.extend_from_slice(make);
+        dng.extend_from_slice(model);
+
+        let temp = tempdir().expect("tempdir");
+        let path = temp.path().join("L1000001.DNG");
+        fs::write(&path, dng).expect("dng file");
+
+        let meta = read_exif_metadata_with_kamadak(&path).expect("read dng exif");
+        assert_eq!(meta.camera_make.as_deref(), Some("Leica"));
+        assert_eq!(meta.camera_model.as_deref(), Some("Leica Q3"));
+    }
+
+    #[test]
+    fn read_exif_metadata_with_kamadak_reads_raf_embedded_jpeg() {
+        // IFD0[1]: tag=0x010F(Make), type=ASCII, count=9, offset=26
+        let mut tiff = vec![0u8; 26];
+        tiff[0..4].copy_from_slice(b"MM\x00\x2a");
+        tiff[4..8].copy_from_slice(&8u32.to_be_bytes());
+        tiff[8..10].copy_from_slice(&1u16.to_be_bytes());
+        tiff[10..12].copy_from_slice(&0x010Fu16.to_be_bytes());
+        tiff[12..14].copy_from_slice(&2u16.to_be_bytes());
+        tiff[14..18].copy_from_slice(&9u32.to_be_bytes());
+        tiff[18..22].copy_from_slice(&26u32.to_be_bytes());
+        tiff.extend_from_slice(b"FUJIFILM\0");
+
+        let mut jpeg = b"\xFF\xD8\xFF\xE1".to_vec();
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(b"\xFF\xD9");
+
+        let mut raf = vec![0u8; 0x100];
+        raf[0..16].copy_from_slice(b"FUJIFILMCCD-RAW ");
+        raf[0x1C..0x1C + 5].copy_from_slice(b"X-T5\0");
+        raf[0x54..0x58].copy_from_slice(&0x100u32.to_be_bytes());
+        raf[0x58..0x5C].copy_from_slice(&(jpeg.len() as u32).to_be_bytes());
+        raf.extend_from_slice(&jpeg);
+
+        let temp = tempdir().expect("tempdir");
+        let path = temp.path().join("DSCF0001.RAF");
+        fs::write(&path, raf).expect("raf file");
+
+        let meta = read_exif_metadata_with_kamadak(&path).expect("read raf exif");
+        assert_eq!(meta.camera_make.as_deref(), Some("FUJIFILM"));
+        // EXIFにモデル名が無い場合はRAFヘッダのカメラ名を使う
+        assert_eq!(meta.camera_model.as_deref(), Some("X-T5"));
+    }
+
     #[test]
     fn map_fujifilm_film_mode_name() {
         assert_eq!(map_fujifilm_film_mode(0x000), Some("PROVIA"));
@@ -631,6 +2076,147 @@ mod tests {
         );
     }
 
+    #[test]
+    fn normalize_sony_creative_style_values() {
+        assert_eq!(normalize_sony_creative_style("VV2").as_deref(), Some("VV2"));
+        assert_eq!(normalize_sony_creative_style("fl").as_deref(), Some("FL"));
+        assert_eq!(
+            normalize_sony_creative_style("Autumn Leaves").as_deref(),
+            Some("Autumn Leaves")
+        );
+        assert_eq!(normalize_sony_creative_style("Off"), None);
+
+        let json = json!({"Make": "SONY", "Saturation": "Normal", "CreativeStyle": "IN"});
+        assert_eq!(pick_film_simulation_from_json(&json).as_deref(), Some("IN"));
+    }
+
+    #[test]
+    fn parse_nikon_picture_control_from_maker_note() {
+        // "Nikon\0" + version + big-endian TIFF: IFD[1]: tag=0x0023, type=UNDEFINED, count=58
+        let mut data = b"0310".to_vec();
+        data.extend_from_slice(&[0u8; 4]);
+        let mut name = b"MONOCHROME".to_vec();
+        name.resize(20, 0);
+        data.extend_from_slice(&name);
+        data.resize(58, 0);
+
+        let mut note = b"Nikon\0\x02\x10\x00\x00".to_vec();
+        note.extend_from_slice(b"MM\x00\x2a");
+        note.extend_from_slice(&8u32.to_be_bytes());
+        note.extend_from_slice(&1u16.to_be_bytes());
+        note.extend_from_slice(&0x0023u16.to_be_bytes());
+        note.extend_from_slice(&7u16.to_be_bytes());
+        note.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        note.extend_from_slice(&26u32.to_be_bytes());
+        note.extend_from_slice(&0u32.to_be_bytes());
+        note.extend_from_slice(&data);
+
+        let raw = parse_nikon_picture_control_name(&note).expect("picture control");
+        assert_eq!(raw, "MONOCHROME");
+        assert_eq!(
+            normalize_nikon_picture_control(&raw).as_deref(),
+            Some("Monochrome")
+        );
+        assert_eq!(
+            normalize_nikon_picture_control("MY STREET").as_deref(),
+            Some("MY STREET")
+        );
+
+        let json = json!({"Make": "NIKON CORPORATION", "ColorMode": "B&W", "PictureControlName": "Flat Monochrome"});
+        assert_eq!(
+            pick_film_simulation_from_json(&json).as_deref(),
+            Some("Flat Monochrome")
+        );
+    }
+
+    #[test]
+    fn normalize_canon_picture_style_values() {
+        assert_eq!(
+            normalize_canon_picture_style("faithful").as_deref(),
+            Some("Faithful")
+        );
+        assert_eq!(
+            normalize_canon_picture_style("User Def. 2").as_deref(),
+            Some("UserDef2")
+        );
+        assert_eq!(
+            normalize_canon_picture_style("PC 1").as_deref(),
+            Some("PC1")
+        );
+        assert_eq!(normalize_canon_picture_style("n/a"), None);
+
+        let json = json!({"Make": "Canon", "PictureStyle": "Monochrome"});
+        assert_eq!(
+            pick_film_simulation_from_json(&json).as_deref(),
+            Some("Monochrome")
+        );
+    }
+
+    #[test]
+    fn pick_film_simulation_reads_olympus_art_filter_and_picture_mode() {
+        let json = json!({
+            "Make": "OM Digital Solutions",
+            "PictureMode": "Art; 1",
+            "ArtFilter": "Grainy Film; 0; 0; 0"
+        });
+        assert_eq!(
+            pick_film_simulation_from_json(&json).as_deref(),
+            Some("Grainy Film")
+        );
+
+        let json = json!({
+            "Make": "OLYMPUS CORPORATION",
+            "PictureMode": "Natural",
+            "ArtFilter": "Off; 0; 0; 0"
+        });
+        assert_eq!(
+            pick_film_simulation_from_json(&json).as_deref(),
+            Some("Natural")
+        );
+
+        // 富士フイルムの PictureMode は撮影モードなので使わない
+        let json = json!({"Make": "FUJIFILM", "PictureMode": "Aperture-priority AE"});
+        assert_eq!(pick_film_simulation_from_json(&json), None);
+    }
+
+    #[test]
+    fn normalize_panasonic_photo_style_values() {
+        assert_eq!(
+            normalize_panasonic_photo_style("Standard or Custom").as_deref(),
+            Some("Standard")
+        );
+        assert_eq!(
+            normalize_panasonic_photo_style("L. Monochrome D").as_deref(),
+            Some("L.Monochrome D")
+        );
+        assert_eq!(
+            normalize_panasonic_photo_style("Real Time LUT: Teal Film").as_deref(),
+            Some("Teal Film")
+        );
+        assert_eq!(normalize_panasonic_photo_style("Auto"), None);
+
+        let json = json!({"Make": "Panasonic", "FilmMode": "n/a", "PhotoStyle": "Cinelike D"});
+        assert_eq!(
+            pick_film_simulation_from_json(&json).as_deref(),
+            Some("Cinelike D")
+        );
+    }
+
+    #[test]
+    fn parse_panasonic_photo_style_from_maker_note() {
+        // "Panasonic\0\0\0" + IFD[1]: tag=0x0089(PhotoStyle), type=SHORT, count=1, value=4
+        let mut note = b"Panasonic\0\0\0".to_vec();
+        note.extend_from_slice(&1u16.to_le_bytes());
+        note.extend_from_slice(&0x0089u16.to_le_bytes());
+        note.extend_from_slice(&3u16.to_le_bytes());
+        note.extend_from_slice(&1u32.to_le_bytes());
+        note.extend_from_slice(&4u32.to_le_bytes());
+        note.extend_from_slice(&0u32.to_le_bytes());
+
+        assert_eq!(parse_panasonic_photo_style_code(&note, false), Some(4));
+        assert_eq!(parse_panasonic_photo_style_code(b"FUJIFILM", false), None);
+    }
+
     #[test]
     fn normalize_film_simulation_from_saturation_values() {
         assert_eq!(
@@ -660,6 +2246,163 @@ mod tests {
         assert_eq!(normalize_film_simulation_from_saturation("+2 (high)"), None);
     }
 
+    #[test]
+    fn parse_worker_count_rejects_zero_and_garbage() {
+        assert_eq!(parse_worker_count(" 4 "), Some(4));
+        assert_eq!(parse_worker_count("0"), None);
+        assert_eq!(parse_worker_count("many"), None);
+    }
+
+    #[test]
+    fn parse_timeout_secs_rejects_zero_and_garbage() {
+        assert_eq!(parse_timeout_secs(" 30 "), Some(Duration::from_secs(30)));
+        assert_eq!(parse_timeout_secs("0"), None);
+        assert_eq!(parse_timeout_secs("-5"), None);
+    }
+
+    #[test]
+    fn partial_metadata_from_batch_json_entry() {
+        let json = json!({
+            "SourceFile": "/photos/DSCF0001.JPG",
+            "DateTimeOriginal": "2026:02:08 10:20:30",
+            "Make": "FUJIFILM",
+            "Model": " X-T5 ",
+            "LensModel": "XF16-55mmF2.8 R LM WR",
+            "FilmMode": "F0/Standard (Provia)",
+            "Rating": 4
+        });
+        let meta = partial_metadata_from_json(&json);
+        assert!(meta.date.is_some());
+        assert_eq!(meta.camera_make.as_deref(), Some("FUJIFILM"));
+        assert_eq!(meta.camera_model.as_deref(), Some("X-T5"));
+        assert_eq!(meta.lens_make, None);
+        assert_eq!(meta.lens_model.as_deref(), Some("XF16-55mmF2.8 R LM WR"));
+        assert_eq!(meta.film_sim.as_deref(), Some("PROVIA"));
+        assert_eq!(meta.rating, Some(4));
+    }
+
+    #[test]
+    fn batch_results_are_matched_by_source_file() {
+        let chunk = vec![
+            PathBuf::from("/photos/DSCF0001.JPG"),
+            PathBuf::from("/photos/DSCF0002.JPG"),
+            PathBuf::from("/photos/DSCF0003.JPG"),
+        ];
+        // 2件目が読めず、残りが順不同で返った場合
+        let values = vec![
+            json!({ "SourceFile": "/photos/DSCF0003.JPG", "Model": "X-T5" }),
+            json!({ "SourceFile": "/photos/other.JPG", "Model": "X-H2" }),
+            json!({ "SourceFile": "/photos/DSCF0001.JPG", "Model": "X100VI" }),
+        ];
+        let matched = match_source_files(&chunk, values);
+        let models: Vec<(&Path, &str)> = matched
+            .iter()
+            .map(|(path, json)| (path.as_path(), json["Model"].as_str().unwrap_or_default()))
+            .collect();
+        assert_eq!(
+            models,
+            vec![
+                (Path::new("/photos/DSCF0003.JPG"), "X-T5"),
+                (Path::new("/photos/DSCF0001.JPG"), "X100VI"),
+            ]
+        );
+    }
+
+    #[test]
+    fn quicktime_utc_is_requested_only_for_videos() {
+        let still = exiftool_args(&[], false);
+        assert!(!still.contains(&"QuickTimeUTC=1".to_string()));
+        assert!(!still.contains(&"-api".to_string()));
+
+        let video = exiftool_args(&[], true);
+        assert_eq!(
+            video[..2],
+            ["-api".to_string(), "QuickTimeUTC=1".to_string()]
+        );
+        assert_eq!(video.len(), still.len() + 2);
+    }
+
+    #[test]
+    fn extra_tags_are_requested_and_read_by_key() {
+        let tags = [
+            "ISO".to_string(),
+            "EXIF:GPSLatitude".to_string(),
+            "-o".to_string(),
+        ];
+        let args = exiftool_args(&tags, false);
+        assert!(args.contains(&"-ISO".to_string()));
+        assert!(args.contains(&"-EXIF:GPSLatitude".to_string()));
+        assert!(!args.contains(&"--o".to_string()));
+
+        let json = json!({ "ISO": 400, "GPSLatitude": "35 deg 39' 29.00\" N" });
+        let extra = extra_tags_from_json(&json, &tags);
+        assert_eq!(extra.get("ISO").map(String::as_str), Some("400"));
+        assert_eq!(
+            extra.get("GPSLatitude").map(String::as_str),
+            Some("35 deg 39' 29.00\" N")
+        );
+        assert_eq!(extra.len(), 2);
+    }
+
+    #[test]
+    fn exiftool_messages_skip_minor_warnings() {
+        let json = json!({ "Make": "FUJIFILM", "Warning": "Truncated JPEG image" });
+        assert_eq!(
+            exiftool_messages_from_json(&json),
+            vec!["Truncated JPEG image".to_string()]
+        );
+        let minor = json!({ "Warning": "[minor] Possibly incorrect maker notes offsets" });
+        assert!(exiftool_messages_from_json(&minor).is_empty());
+        assert!(exiftool_messages_from_json(&json!({ "Make": "FUJIFILM" })).is_empty());
+    }
+
+    #[test]
+    fn partial_metadata_from_json_applies_offset_time_original() {
+        let json = json!({
+            "DateTimeOriginal": "2026:02:08 10:20:30",
+            "OffsetTimeOriginal": "+09:00",
+            "OffsetTime": "+01:00"
+        });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(
+            meta.date.map(|date| date.to_rfc3339()).as_deref(),
+            Some("2026-02-08T10:20:30+09:00")
+        );
+    }
+
+    #[test]
+    fn partial_metadata_from_json_applies_sub_sec_time_original() {
+        let json = json!({
+            "DateTimeOriginal": "2026:02:08 10:20:30",
+            "OffsetTimeOriginal": "+09:00",
+            "SubSecTimeOriginal": "45"
+        });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(
+            meta.date.map(|date| date.to_rfc3339()).as_deref(),
+            Some("2026-02-08T10:20:30.450+09:00")
+        );
+    }
+
+    #[test]
+    fn partial_metadata_from_json_reads_gps_date_as_utc() {
+        let json = json!({
+            "GPSDateStamp": "2026:02:08",
+            "GPSTimeStamp": "01:20:30.5"
+        });
+        let meta = partial_metadata_from_json(&json);
+        assert_eq!(meta.date, None);
+        assert_eq!(
+            meta.gps_date.map(|date| date.to_rfc3339()).as_deref(),
+            Some("2026-02-08T01:20:30.500+00:00")
+        );
+        assert_eq!(
+            parse_gps_date("2026:02:08", "01:20:30").map(|d| d.timestamp()),
+            Some(1_770_513_630)
+        );
+        assert_eq!(parse_gps_date("2026:02:08", "n/a"), None);
+    }
+
     #[test]
     fn pick_film_simulation_prefers_saturation_over_film_mode() {
         let json = json!({
@@ -706,4 +2449,29 @@ mod tests {
             Some("ACROS+ R FILTER")
         );
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn exiftool_pool_fails_instead_of_waiting_when_no_worker_can_be_spawned() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempdir().expect("tempdir");
+        let script = temp.path().join("exiftool");
+        fs::write(&script, "#!/bin/sh\nexec sleep 30\n").expect("write script");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("chmod script");
+        let first = ExiftoolProcess::spawn(Some(&script)).expect("spawn");
+        let pool = ExifToolPool::new(first, Some(script.clone()), 1, Duration::from_millis(200));
+
+        let err = pool
+            .with_worker(|exiftool, timeout| exiftool.json(&script, &[], timeout))
+            .expect_err("timeout");
+        assert!(err.to_string().contains("打ち切りました"));
+
+        fs::remove_file(&script).expect("remove script");
+        let err = pool
+            .with_worker(|exiftool, timeout| exiftool.json(&script, &[], timeout))
+            .expect_err("spawn failure");
+        assert!(err.to_string().contains("起動できません"));
+        assert_eq!(pool.max_workers(), 1);
+    }
 }
@@ -0,0 +1,305 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value as JsonValue;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+// exiftool を -stay_open で起動したままにし、-execute ごとに結果を受け取る。
+// 応答の読み取りはプロセスごとに1本のスレッドが担い、応答しない場合はプロセスを終了させる
+pub(crate) struct ExiftoolProcess {
+    child: Child,
+    stdin: ChildStdin,
+    responses: Receiver<Vec<u8>>,
+    next_id: u64,
+    killed: bool,
+}
+
+impl ExiftoolProcess {
+    pub(crate) fn spawn(executable: Option<&Path>) -> Result<Self> {
+        let (program, mut command) = exiftool_command(executable);
+        command
+            .args(["-stay_open", "True", "-@", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("exiftoolを起動できません: {}", program.display()))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("exiftoolの入出力を取得できません");
+        };
+        let (sender, responses) = mpsc::channel();
+        std::thread::spawn(move || read_responses(stdout, sender));
+        Ok(Self {
+            child,
+            stdin,
+            responses,
+            next_id: 0,
+            killed: false,
+        })
+    }
+
+    // 打ち切りや異常終了で使えなくなったプロセスはプールへ戻さない
+    pub(crate) fn is_usable(&self) -> bool {
+        !self.killed
+    }
+
+    pub(crate) fn json(
+        &mut self,
+        path: &Path,
+        args: &[&str],
+        timeout: Duration,
+    ) -> Result<JsonValue> {
+        let mut values = self.json_batch(&[path.to_path_buf()], args, timeout)?;
+        if values.len() != 1 {
+            bail!("exiftoolの結果がありません");
+        }
+        Ok(values.remove(0))
+    }
+
+    // 読めなかったファイルは結果に含まれないため、呼び出し側で SourceFile から対応付ける
+    pub(crate) fn json_batch(
+        &mut self,
+        paths: &[PathBuf],
+        args: &[&str],
+        timeout: Duration,
+    ) -> Result<Vec<JsonValue>> {
+        self.next_id += 1;
+        let mut command = String::from("-json\n-charset\nfilename=utf8\n");
+        for arg in args {
+            command.push_str(arg);
+            command.push('\n');
+        }
+        for path in paths {
+            command.push_str(&path.to_string_lossy());
+            command.push('\n');
+        }
+        command.push_str(&format!("-execute{}\n", self.next_id));
+        if let Err(err) = self
+            .stdin
+            .write_all(command.as_bytes())
+            .and_then(|()| self.stdin.flush())
+        {
+            self.kill();
+            bail!("exiftoolへの書き込みに失敗しました ({err})");
+        }
+
+        let output = match self.responses.recv_timeout(timeout) {
+            Ok(output) => output,
+            Err(RecvTimeoutError::Timeout) => {
+                self.kill();
+                bail!(
+                    "exiftoolが{}秒以内に応答しないため打ち切りました",
+                    timeout.as_secs()
+                );
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.kill();
+                bail!("exiftoolが異常終了しました");
+            }
+        };
+        if output.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Vec::new());
+        }
+        serde_json::from_slice(&output)
+            .map_err(|err| anyhow!("exiftoolの結果を解析できません ({err})"))
+    }
+
+    fn kill(&mut self) {
+        if self.killed {
+            return;
+        }
+        self.killed = true;
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn exiftool_command(executable: Option<&Path>) -> (PathBuf, Command) {
+    let program = executable.map_or_else(|| PathBuf::from("exiftool"), Path::to_path_buf);
+    #[cfg_attr(not(windows), allow(unused_mut))]
+    let mut command = Command::new(&program);
+    // GUI から起動した時にコンソールウィンドウを開かない
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    (program, command)
+}
+
+// -stay_open の引数は1行1件のUTF-8として読まれるため、改行を含む名前やUTF-8でない名前は渡せない
+pub(crate) fn can_pass_in_argfile(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| !path.contains(['\n', '\r']))
+}
+
+// 引数ファイルで渡せないパスは、コマンドライン引数に置いて exiftool を1回だけ起動して読む
+pub(crate) fn json_once(
+    executable: Option<&Path>,
+    path: &Path,
+    args: &[&str],
+    timeout: Duration,
+) -> Result<JsonValue> {
+    let (program, mut command) = exiftool_command(executable);
+    command
+        .arg("-json")
+        .args(args)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("exiftoolを起動できません: {}", program.display()))?;
+    let Some(mut stdout) = child.stdout.take() else {
+        let _ = child.kill();
+        let _ = child.wait();
+        bail!("exiftoolの出力を取得できません");
+    };
+    let (sender, output) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf);
+        let _ = sender.send(buf);
+    });
+    let output = match output.recv_timeout(timeout) {
+        Ok(output) => output,
+        Err(RecvTimeoutError::Timeout) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!(
+                "exiftoolが{}秒以内に応答しないため打ち切りました",
+                timeout.as_secs()
+            );
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("exiftoolが異常終了しました");
+        }
+    };
+    let _ = child.wait();
+    let mut values: Vec<JsonValue> = serde_json::from_slice(&output)
+        .map_err(|err| anyhow!("exiftoolの結果を解析できません ({err})"))?;
+    if values.len() != 1 {
+        bail!("exiftoolの結果がありません");
+    }
+    Ok(values.remove(0))
+}
+
+impl Drop for ExiftoolProcess {
+    fn drop(&mut self) {
+        if self.killed {
+            return;
+        }
+        // 終了の指示が届かない場合に備えて、最後は強制終了する
+        let _ = self.stdin.write_all(b"-stay_open\nFalse\n");
+        let _ = self.stdin.flush();
+        self.kill();
+    }
+}
+
+// {ready} の行までを1回分の応答としてまとめて送る。プロセスが終われば送信側を閉じる
+fn read_responses(stdout: ChildStdout, sender: Sender<Vec<u8>>) {
+    let mut reader = BufReader::new(stdout);
+    let mut output = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        if is_ready_line(&line) {
+            if sender.send(std::mem::take(&mut output)).is_err() {
+                return;
+            }
+        } else {
+            output.extend_from_slice(&line);
+        }
+    }
+}
+
+fn is_ready_line(line: &[u8]) -> bool {
+    let line = line.trim_ascii();
+    line.starts_with(b"{ready") && line.ends_with(b"}")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn write_script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}")).expect("write script");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("chmod script");
+        path
+    }
+
+    #[test]
+    fn json_batch_reads_each_execute_response() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let script = write_script(
+            temp.path(),
+            "exiftool",
+            "while read -r line; do\n  case \"$line\" in\n    -execute*) echo '[{\"Make\":\"FUJIFILM\"}]'; echo \"{ready${line#-execute}}\" ;;\n  esac\ndone\n",
+        );
+        let mut process = ExiftoolProcess::spawn(Some(&script)).expect("spawn");
+        for _ in 0..2 {
+            let json = process
+                .json(Path::new("a.jpg"), &["-Make"], Duration::from_secs(10))
+                .expect("json");
+            assert_eq!(json["Make"], "FUJIFILM");
+        }
+        assert!(process.is_usable());
+    }
+
+    #[test]
+    fn json_batch_kills_process_that_does_not_respond() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let script = write_script(temp.path(), "exiftool", "exec sleep 30\n");
+        let mut process = ExiftoolProcess::spawn(Some(&script)).expect("spawn");
+
+        let err = process
+            .json(Path::new("a.jpg"), &[], Duration::from_millis(200))
+            .expect_err("timeout");
+        assert!(err.to_string().contains("打ち切りました"));
+        assert!(!process.is_usable());
+        assert!(process.child.try_wait().expect("try_wait").is_some());
+    }
+
+    #[test]
+    fn argfile_rejects_names_with_newlines_or_invalid_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        assert!(can_pass_in_argfile(Path::new("/photos/DSCF0001.JPG")));
+        assert!(!can_pass_in_argfile(Path::new("/photos/a\nb.JPG")));
+        assert!(!can_pass_in_argfile(Path::new(OsStr::from_bytes(
+            b"/photos/\xff.JPG"
+        ))));
+    }
+
+    #[test]
+    fn json_once_passes_path_as_command_line_argument() {
+        let temp = tempfile::tempdir().expect("tempdir");
+        let script = write_script(
+            temp.path(),
+            "exiftool",
+            "for last; do :; done\n[ -f \"$last\" ] && echo '[{\"Make\":\"FUJIFILM\"}]'\n",
+        );
+        let path = temp.path().join("a\nb.JPG");
+        std::fs::write(&path, b"").expect("write photo");
+        let json =
+            json_once(Some(&script), &path, &["-Make"], Duration::from_secs(10)).expect("json");
+        assert_eq!(json["Make"], "FUJIFILM");
+    }
+}
@@ -2,24 +2,47 @@ mod apply;
 mod config;
 mod constants;
 mod exif_reader;
+mod exiftool_process;
 mod matcher;
 mod metadata;
+mod metadata_cache;
+mod plan_file;
 mod planner;
 mod sanitize;
 mod template;
 mod xmp_reader;
 
 pub use apply::{
-    apply_plan, apply_plan_with_options, undo_last, ApplyOptions, ApplyResult, UndoResult,
+    apply_plan, apply_plan_subset, apply_plan_with_options, apply_plan_with_progress,
+    cleanup_backups, list_undo_sessions, pending_apply, preview_undo, recover_interrupted_apply,
+    redo_last, undo_last, undo_last_files, undo_last_with_options, undo_session,
+    undo_session_with_options, validate_plan_against_fs, ApplyFileResult, ApplyMode, ApplyOptions,
+    ApplyOutcome, ApplyResult, BackupCleanupResult, BackupFormat, CollisionPolicy, JournalPhase,
+    PendingApply, PlanValidationReport, RecoverAction, RecoverResult, RedoResult, StaleEntry,
+    StaleReason, UndoOptions, UndoPreview, UndoPreviewEntry, UndoResult, UndoSession,
 };
-pub use config::{app_paths, load_config, save_config, AppConfig, AppPaths};
-pub use constants::DEFAULT_TEMPLATE;
-pub use metadata::{MetadataSource, PhotoMetadata};
+pub use config::{
+    app_paths, export_config, import_config, load_config, save_config, AppConfig, AppPaths,
+    BackupRetention, ConfigImportMode, CONFIG_FIELD_KEYS,
+};
+pub use constants::{
+    default_primary_extensions, default_raw_extensions, DEFAULT_MAX_FILENAME_LEN,
+    DEFAULT_PRIMARY_EXTENSIONS, DEFAULT_RAW_EXTENSIONS, DEFAULT_TEMPLATE, MAX_FILENAME_LEN_RANGE,
+    MAX_UNDO_SESSIONS, PLAN_FILE_VERSION,
+};
+pub use metadata::{
+    parse_time_shift, parse_utc_offset, DateZone, FieldProvenance, FieldSource, MetadataBackend,
+    MetadataSource, PhotoMetadata, SourceFilter, TimeShift,
+};
+pub use plan_file::{load_plan_file, save_plan_file};
 pub use planner::{
-    generate_plan, generate_plan_for_jpg_files, render_preview_sample, PlanOptions,
-    RenameCandidate, RenamePlan, RenameStats,
+    collect_folder_stats, generate_plan, generate_plan_chunked, generate_plan_for_jpg_files,
+    generate_plan_for_jpg_files_chunked, generate_plan_for_jpg_files_with_control,
+    generate_plan_with_control, inspect_jpg_file, render_preview_sample, FileFingerprint,
+    FileInspection, FolderStats, PlanControl, PlanOptions, PlanTimings, PlanWarning,
+    PlanWarningEntry, ProgressSink, RawMatchMethod, RenameCandidate, RenamePlan, RenameStats,
 };
 pub use template::{
-    parse_template, render_template, render_template_with_options, validate_template,
-    TemplateError, TemplatePart,
+    parse_template, render_template, render_template_with_options, split_template_segments,
+    validate_template, MakerNameOptions, TemplateError, TemplatePart, TEMPLATE_TOKENS,
 };
@@ -1,30 +1,72 @@
-use std::collections::HashMap;
+use crate::xmp_reader::read_xmp_raw_file_name;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use walkdir::WalkDir;
 
-const RAW_EXT_PRIORITY: &[&str] = &["dng", "raf"];
 const XMP_EXT_PRIORITY: &[&str] = &["xmp"];
 
+// JPG -> (相対フォルダ, RAW の stem, 撮影日時の差)
+type CaptureTimeMatches = HashMap<PathBuf, (PathBuf, String, TimeDelta)>;
+// 相対フォルダ -> ImageUniqueID -> RAW の stem
+type UniqueIdIndex = HashMap<PathBuf, HashMap<String, String>>;
+
 #[derive(Debug, Clone)]
 pub struct RawMatchIndex {
     recursive: bool,
     jpg_root: PathBuf,
+    // 設定の RAW 拡張子 (小文字・ドット無し)。並び順が優先順位
+    raw_extensions: Vec<String>,
     files_by_rel_dir: HashMap<PathBuf, HashMap<String, Vec<PathBuf>>>,
+    // この索引で RAW を探す JPG。撮影日時での対応付けで、JPG 同士の取り合いを決めるのに使う
+    jpg_paths: Vec<PathBuf>,
+    // 登録した JPG の (相対フォルダ, stem の小文字)。複製の XMP が別の写真のものか見分けるのに使う
+    jpg_stems: HashSet<(PathBuf, String)>,
+    // crs:RawFileName の stem (小文字) -> XMP。名前で見つからないファイルが出た時に初めて作る
+    xmp_by_raw_file_name: OnceLock<HashMap<PathBuf, HashMap<String, PathBuf>>>,
+    // 撮影日時で対応付けた JPG -> RAW。撮影日時での対応付けを使う時に初めて作る
+    raw_by_capture_time: OnceLock<CaptureTimeMatches>,
+    // ImageUniqueID -> RAW の stem。ImageUniqueID での対応付けを使う時に初めて作る
+    raw_by_unique_id: OnceLock<UniqueIdIndex>,
+}
+
+// 名前以外 (ImageUniqueID・撮影日時) で対応付けた RAW (同名の別形式を含む) と、その RAW と同名の XMP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedRaw {
+    pub raw_paths: Vec<PathBuf>,
+    pub xmp_path: Option<PathBuf>,
 }
 
-pub fn build_raw_match_index(jpg_root: &Path, raw_root: &Path, recursive: bool) -> RawMatchIndex {
+pub fn build_raw_match_index(
+    jpg_root: &Path,
+    raw_root: &Path,
+    recursive: bool,
+    follow_symlinks: bool,
+    raw_extensions: &[String],
+) -> RawMatchIndex {
+    let raw_extensions = normalize_extensions(raw_extensions);
     let mut files_by_rel_dir = HashMap::<PathBuf, HashMap<String, Vec<PathBuf>>>::new();
 
     if recursive {
-        for entry in WalkDir::new(raw_root).sort_by_file_name() {
+        for entry in WalkDir::new(raw_root)
+            .follow_links(follow_symlinks)
+            .sort_by_file_name()
+        {
             let Ok(entry) = entry else {
                 continue;
             };
             if !entry.file_type().is_file() {
                 continue;
             }
-            insert_index_path(&mut files_by_rel_dir, raw_root, entry.path(), true);
+            insert_index_path(
+                &mut files_by_rel_dir,
+                raw_root,
+                entry.path(),
+                true,
+                &raw_extensions,
+            );
         }
     } else if let Ok(entries) = fs::read_dir(raw_root) {
         for entry in entries.flatten() {
@@ -32,7 +74,13 @@ pub fn build_raw_match_index(jpg_root: &Path, raw_root: &Path, recursive: bool)
             if !path.is_file() {
                 continue;
             }
-            insert_index_path(&mut files_by_rel_dir, raw_root, &path, false);
+            insert_index_path(
+                &mut files_by_rel_dir,
+                raw_root,
+                &path,
+                false,
+                &raw_extensions,
+            );
         }
     }
 
@@ -45,27 +93,296 @@ pub fn build_raw_match_index(jpg_root: &Path, raw_root: &Path, recursive: bool)
     RawMatchIndex {
         recursive,
         jpg_root: jpg_root.to_path_buf(),
+        raw_extensions,
         files_by_rel_dir,
+        jpg_paths: Vec::new(),
+        jpg_stems: HashSet::new(),
+        xmp_by_raw_file_name: OnceLock::new(),
+        raw_by_capture_time: OnceLock::new(),
+        raw_by_unique_id: OnceLock::new(),
     }
 }
 
 impl RawMatchIndex {
+    pub fn register_jpg(&mut self, jpg_path: PathBuf) {
+        if let Some(stem) = jpg_path.file_stem() {
+            let rel_dir = self.resolve_search_rel_dir(&jpg_path);
+            self.jpg_stems
+                .insert((rel_dir, stem.to_string_lossy().to_ascii_lowercase()));
+        }
+        self.jpg_paths.push(jpg_path);
+    }
+
     pub fn find_raw(&self, jpg_path: &Path) -> Option<PathBuf> {
-        self.find_matching_by_priority(jpg_path, RAW_EXT_PRIORITY)
+        self.find_raws(jpg_path).into_iter().next()
     }
 
-    pub fn find_xmp(&self, jpg_path: &Path) -> Option<PathBuf> {
-        self.find_matching_by_priority(jpg_path, XMP_EXT_PRIORITY)
+    // 同じ名前の RAW が複数の形式 (DNG と RAF など) で並ぶ場合は、設定の優先順ですべて返す
+    pub fn find_raws(&self, jpg_path: &Path) -> Vec<PathBuf> {
+        let rel_dir = self.resolve_search_rel_dir(jpg_path);
+        let Some(stem_original) = jpg_path
+            .file_stem()
+            .map(|v| v.to_string_lossy().to_string())
+        else {
+            return Vec::new();
+        };
+        let found = self.find_all_in_rel_dir(&rel_dir, &stem_original, &self.raw_extensions);
+        if !found.is_empty() {
+            return found;
+        }
+
+        // RAW と XMP だけが改名済みの場合は、XMP に残る元のファイル名で見つけた XMP と同名の RAW を使う
+        self.find_xmp_by_raw_file_name(jpg_path)
+            .and_then(|xmp| xmp.file_stem().map(|v| v.to_string_lossy().to_string()))
+            .map(|xmp_stem| self.find_all_in_rel_dir(&rel_dir, &xmp_stem, &self.raw_extensions))
+            .unwrap_or_default()
     }
 
-    fn find_matching_by_priority(&self, jpg_path: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    pub fn find_xmp(&self, jpg_path: &Path) -> Option<PathBuf> {
         let rel_dir = self.resolve_search_rel_dir(jpg_path);
         let stem_original = jpg_path.file_stem()?.to_string_lossy().to_string();
+        let candidates = self
+            .files_by_rel_dir
+            .get(&rel_dir)
+            .and_then(|stem_map| stem_map.get(&stem_original.to_ascii_lowercase()))
+            .map(|candidates| {
+                candidates
+                    .iter()
+                    .filter(|path| !self.belongs_to_other_photo(&rel_dir, &stem_original, path))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        XMP_EXT_PRIORITY
+            .iter()
+            .find_map(|ext| pick_candidate_with_case_variants(&candidates, &stem_original, ext))
+            .or_else(|| self.find_xmp_by_raw_file_name(jpg_path))
+    }
+
+    // 複製の XMP (NAME_01.xmp) と同じ名前の JPG や RAW があれば、その写真の XMP として扱う
+    fn belongs_to_other_photo(&self, rel_dir: &Path, stem_original: &str, xmp_path: &Path) -> bool {
+        let Some(xmp_stem) = xmp_path.file_stem().and_then(|v| v.to_str()) else {
+            return false;
+        };
+        let own_stem = double_extension_base_stem(xmp_stem).unwrap_or(xmp_stem);
+        if own_stem.eq_ignore_ascii_case(stem_original) {
+            return false;
+        }
+        let own_key = own_stem.to_ascii_lowercase();
+        let has_raw = self
+            .files_by_rel_dir
+            .get(rel_dir)
+            .and_then(|stem_map| stem_map.get(&own_key))
+            .is_some_and(|candidates| candidates.iter().any(|path| !has_extension(path, "xmp")));
+        has_raw || self.jpg_stems.contains(&(rel_dir.to_path_buf(), own_key))
+    }
+
+    fn find_xmp_by_raw_file_name(&self, jpg_path: &Path) -> Option<PathBuf> {
+        let rel_dir = self.resolve_search_rel_dir(jpg_path);
+        let stem_key = jpg_path.file_stem()?.to_string_lossy().to_ascii_lowercase();
+        let found = self
+            .xmp_by_raw_file_name
+            .get_or_init(|| index_xmp_by_raw_file_name(&self.files_by_rel_dir))
+            .get(&rel_dir)?
+            .get(&stem_key)
+            .cloned();
+        if let Some(xmp) = &found {
+            log::debug!(
+                "crs:RawFileName で対応付けました: {} -> {}",
+                jpg_path.display(),
+                xmp.display()
+            );
+        }
+        found
+    }
+
+    // 名前で RAW が見つからない JPG を、ImageUniqueID が同じ RAW と組み合わせる
+    pub fn find_by_unique_id(
+        &self,
+        jpg_path: &Path,
+        unique_id: &str,
+        read_unique_id: impl Fn(&Path) -> Option<String>,
+    ) -> Option<MatchedRaw> {
+        let rel_dir = self.resolve_search_rel_dir(jpg_path);
+        let raw_stem = self
+            .raw_by_unique_id
+            .get_or_init(|| self.index_raw_by_unique_id(read_unique_id))
+            .get(&rel_dir)?
+            .get(unique_id)?;
+        let found = self.matched_raw(&rel_dir, raw_stem)?;
+        log::debug!(
+            "ImageUniqueID で対応付けました: {} -> {}",
+            jpg_path.display(),
+            found.raw_paths[0].display()
+        );
+        Some(found)
+    }
+
+    // 名前で RAW が見つからない JPG を、撮影日時の差が tolerance 以内で最も近い RAW と組み合わせる。
+    // 対応付けは登録した JPG 全体で一度に決めるため、tolerance は最初の呼び出しの値を使う
+    pub fn find_by_capture_time(
+        &self,
+        jpg_path: &Path,
+        tolerance: TimeDelta,
+        read_capture_time: impl Fn(&Path) -> Option<DateTime<FixedOffset>>,
+    ) -> Option<(MatchedRaw, TimeDelta)> {
+        let (rel_dir, raw_stem, difference) = self
+            .raw_by_capture_time
+            .get_or_init(|| self.match_by_capture_time(tolerance, read_capture_time))
+            .get(jpg_path)?;
+        let difference = *difference;
+        let found = self.matched_raw(rel_dir, raw_stem)?;
+        log::debug!(
+            "撮影日時で対応付けました: {} -> {} ({}ms)",
+            jpg_path.display(),
+            found.raw_paths[0].display(),
+            difference.num_milliseconds()
+        );
+        Some((found, difference))
+    }
+
+    // 対応付いたファイルと同じ名前のまとまりに1つも入らなかった RAW/XMP を、パス順で返す
+    pub fn unmatched_files(&self, matched: &HashSet<PathBuf>) -> Vec<PathBuf> {
+        let mut all = BTreeSet::<&PathBuf>::new();
+        let mut claimed = HashSet::<&PathBuf>::new();
+        for candidates in self.files_by_rel_dir.values().flat_map(HashMap::values) {
+            all.extend(candidates);
+            if candidates.iter().any(|path| matched.contains(path)) {
+                claimed.extend(candidates);
+            }
+        }
+        all.into_iter()
+            .filter(|path| !claimed.contains(path))
+            .cloned()
+            .collect()
+    }
+
+    fn matched_raw(&self, rel_dir: &Path, raw_stem: &str) -> Option<MatchedRaw> {
+        let raw_paths = self.find_all_in_rel_dir(rel_dir, raw_stem, &self.raw_extensions);
+        (!raw_paths.is_empty()).then(|| MatchedRaw {
+            raw_paths,
+            xmp_path: self.find_in_rel_dir(rel_dir, raw_stem, XMP_EXT_PRIORITY),
+        })
+    }
+
+    // 名前で対応の付く JPG がある RAW は候補にせず、1つの RAW は最も近い1つの JPG にだけ対応付ける。
+    // 差が同じ候補が並ぶ場合はどちらとも決められないため、対応付けない
+    fn match_by_capture_time(
+        &self,
+        tolerance: TimeDelta,
+        read_capture_time: impl Fn(&Path) -> Option<DateTime<FixedOffset>>,
+    ) -> CaptureTimeMatches {
+        let mut name_matched = HashSet::<(PathBuf, String)>::new();
+        let mut unmatched_jpgs = Vec::new();
+        for jpg_path in &self.jpg_paths {
+            let rel_dir = self.resolve_search_rel_dir(jpg_path);
+            let raws = self.find_raws(jpg_path);
+            if raws.is_empty() && self.find_xmp(jpg_path).is_none() {
+                unmatched_jpgs.push((rel_dir, jpg_path));
+                continue;
+            }
+            for raw in raws {
+                if let Some(stem) = raw.file_stem() {
+                    name_matched.insert((rel_dir.clone(), stem.to_string_lossy().to_lowercase()));
+                }
+            }
+        }
+
+        let mut raw_times = HashMap::<&PathBuf, Vec<(DateTime<FixedOffset>, String)>>::new();
+        for (rel_dir, raw, stem) in self.primary_raws() {
+            if name_matched.contains(&(rel_dir.clone(), stem.to_lowercase())) {
+                continue;
+            }
+            if let Some(time) = read_capture_time(raw) {
+                raw_times.entry(rel_dir).or_default().push((time, stem));
+            }
+        }
+
+        // RAW ごとに、最も近いとした JPG を集める
+        let mut proposals = HashMap::<(PathBuf, String), Vec<(TimeDelta, &PathBuf)>>::new();
+        for (rel_dir, jpg_path) in unmatched_jpgs {
+            let (Some(raws), Some(capture_time)) =
+                (raw_times.get(&rel_dir), read_capture_time(jpg_path))
+            else {
+                continue;
+            };
+            let Some(nearest) = unique_min_by_key(
+                raws.iter()
+                    .map(|(time, stem)| ((*time - capture_time).abs(), stem))
+                    .filter(|(difference, _)| *difference <= tolerance),
+                |(difference, _)| *difference,
+            ) else {
+                continue;
+            };
+            let (difference, stem) = nearest;
+            proposals
+                .entry((rel_dir, stem.clone()))
+                .or_default()
+                .push((difference, jpg_path));
+        }
+
+        let mut matches = CaptureTimeMatches::new();
+        for ((rel_dir, stem), jpgs) in proposals {
+            if let Some((difference, jpg_path)) =
+                unique_min_by_key(jpgs.into_iter(), |(difference, _)| *difference)
+            {
+                matches.insert(jpg_path.clone(), (rel_dir, stem, difference));
+            }
+        }
+        matches
+    }
+
+    // 同じ ImageUniqueID の RAW が複数ある場合は、名前の並びで先のものを使う
+    fn index_raw_by_unique_id(
+        &self,
+        read_unique_id: impl Fn(&Path) -> Option<String>,
+    ) -> UniqueIdIndex {
+        let mut index = UniqueIdIndex::new();
+        for (rel_dir, raw, stem) in self.primary_raws() {
+            let Some(unique_id) = read_unique_id(raw) else {
+                continue;
+            };
+            let entry = index
+                .entry(rel_dir.clone())
+                .or_default()
+                .entry(unique_id)
+                .or_insert_with(|| stem.clone());
+            if stem < *entry {
+                *entry = stem;
+            }
+        }
+        index
+    }
+
+    // 同名の RAW が複数の形式で並ぶ場合は、優先順で先の形式だけを読む
+    fn primary_raws(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf, String)> {
+        self.files_by_rel_dir
+            .iter()
+            .flat_map(move |(rel_dir, stem_map)| {
+                stem_map.values().filter_map(move |candidates| {
+                    let raw = self
+                        .raw_extensions
+                        .iter()
+                        .find_map(|ext| candidates.iter().find(|path| has_extension(path, ext)))?;
+                    let stem = raw.file_stem()?.to_string_lossy().to_string();
+                    Some((rel_dir, raw, stem))
+                })
+            })
+    }
+
+    fn find_in_rel_dir(
+        &self,
+        rel_dir: &Path,
+        stem_original: &str,
+        extensions: &[impl AsRef<str>],
+    ) -> Option<PathBuf> {
         let stem_key = stem_original.to_ascii_lowercase();
-        let candidates = self.files_by_rel_dir.get(&rel_dir)?.get(&stem_key)?;
+        let candidates = self.files_by_rel_dir.get(rel_dir)?.get(&stem_key)?;
 
         for ext in extensions {
-            if let Some(path) = pick_candidate_with_case_variants(candidates, &stem_original, ext) {
+            if let Some(path) =
+                pick_candidate_with_case_variants(candidates, stem_original, ext.as_ref())
+            {
                 return Some(path);
             }
         }
@@ -73,6 +390,25 @@ impl RawMatchIndex {
         None
     }
 
+    fn find_all_in_rel_dir(
+        &self,
+        rel_dir: &Path,
+        stem_original: &str,
+        extensions: &[String],
+    ) -> Vec<PathBuf> {
+        let Some(candidates) = self
+            .files_by_rel_dir
+            .get(rel_dir)
+            .and_then(|stem_map| stem_map.get(&stem_original.to_ascii_lowercase()))
+        else {
+            return Vec::new();
+        };
+        extensions
+            .iter()
+            .filter_map(|ext| pick_candidate_with_case_variants(candidates, stem_original, ext))
+            .collect()
+    }
+
     fn resolve_search_rel_dir(&self, jpg_path: &Path) -> PathBuf {
         if !self.recursive {
             return PathBuf::new();
@@ -86,15 +422,85 @@ impl RawMatchIndex {
     }
 }
 
+// 最小のキーを持つ要素が1つだけの場合にそれを返す
+fn unique_min_by_key<T, K: Ord>(
+    items: impl Iterator<Item = T>,
+    key: impl Fn(&T) -> K,
+) -> Option<T> {
+    let mut best: Option<(K, T)> = None;
+    let mut tied = false;
+    for item in items {
+        let item_key = key(&item);
+        match &best {
+            Some((best_key, _)) if item_key > *best_key => {}
+            Some((best_key, _)) if item_key == *best_key => tied = true,
+            _ => {
+                best = Some((item_key, item));
+                tied = false;
+            }
+        }
+    }
+    best.filter(|_| !tied).map(|(_, item)| item)
+}
+
+// 同じ元ファイル名を持つ XMP が複数ある場合は、パスの並びで先のものを使う
+fn index_xmp_by_raw_file_name(
+    files_by_rel_dir: &HashMap<PathBuf, HashMap<String, Vec<PathBuf>>>,
+) -> HashMap<PathBuf, HashMap<String, PathBuf>> {
+    let mut index = HashMap::<PathBuf, HashMap<String, PathBuf>>::new();
+    for (rel_dir, stem_map) in files_by_rel_dir {
+        for path in stem_map.values().flatten() {
+            if !has_extension(path, "xmp") {
+                continue;
+            }
+            let Some(stem_key) = raw_file_name_stem_key(path) else {
+                continue;
+            };
+            let entry = index
+                .entry(rel_dir.clone())
+                .or_default()
+                .entry(stem_key)
+                .or_insert_with(|| path.clone());
+            if *path < *entry {
+                *entry = path.clone();
+            }
+        }
+    }
+    index
+}
+
+fn raw_file_name_stem_key(xmp_path: &Path) -> Option<String> {
+    let raw_file_name = read_xmp_raw_file_name(xmp_path)?;
+    Path::new(&raw_file_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_ascii_lowercase())
+        .filter(|stem| !stem.is_empty())
+}
+
 pub fn find_matching_raw(
     jpg_root: &Path,
     raw_root: &Path,
     jpg_path: &Path,
     recursive: bool,
+    raw_extensions: &[String],
 ) -> Option<PathBuf> {
-    find_matching_by_priority(jpg_root, raw_root, jpg_path, recursive, RAW_EXT_PRIORITY)
+    let raw_extensions = normalize_extensions(raw_extensions);
+    find_matching_by_priority(jpg_root, raw_root, jpg_path, recursive, &raw_extensions)
+}
+
+// 設定の拡張子 (".ARW" など) を比較用の "arw" にそろえる。重複は先のものを残す
+pub(crate) fn normalize_extensions(extensions: &[String]) -> Vec<String> {
+    let mut normalized = Vec::<String>::new();
+    for ext in extensions {
+        let ext = ext.trim().trim_start_matches('.').to_ascii_lowercase();
+        if !ext.is_empty() && !normalized.contains(&ext) {
+            normalized.push(ext);
+        }
+    }
+    normalized
 }
 
+// crs:RawFileName での対応付けはフォルダ内の XMP をすべて読むため、一度だけ索引を作る RawMatchIndex でのみ行う
 pub fn find_matching_xmp(
     jpg_root: &Path,
     raw_root: &Path,
@@ -102,6 +508,117 @@ pub fn find_matching_xmp(
     recursive: bool,
 ) -> Option<PathBuf> {
     find_matching_by_priority(jpg_root, raw_root, jpg_path, recursive, XMP_EXT_PRIORITY)
+        .or_else(|| {
+            find_xmp_variant(
+                jpg_root,
+                raw_root,
+                jpg_path,
+                recursive,
+                double_extension_base_stem,
+            )
+        })
+        .or_else(|| find_xmp_variant(jpg_root, raw_root, jpg_path, recursive, duplicate_base_stem))
+        .or_else(|| {
+            find_xmp_variant(
+                jpg_root,
+                raw_root,
+                jpg_path,
+                recursive,
+                duplicate_with_extension_base_stem,
+            )
+        })
+}
+
+// RAW フォルダ未指定時に JPG と同じフォルダのサイドカー (NAME.xmp / NAME.JPG.xmp) を探す。
+// JPG ごとに呼ばれるため、フォルダの一覧は読まずに名前を決め打ちで確認する
+pub fn find_sidecar_xmp(jpg_path: &Path) -> Option<PathBuf> {
+    let dir = jpg_path.parent()?;
+    let stem = jpg_path.file_stem()?.to_string_lossy().to_string();
+    let file_name = jpg_path.file_name()?.to_string_lossy().to_string();
+    [stem, file_name]
+        .iter()
+        .flat_map(|base| [format!("{base}.xmp"), format!("{base}.XMP")])
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+// 同名の XMP が無い時に、NAME.EXT.xmp や darktable の複製 NAME_01.xmp をパスの並び順で探す
+fn find_xmp_variant(
+    jpg_root: &Path,
+    raw_root: &Path,
+    jpg_path: &Path,
+    recursive: bool,
+    base_stem: fn(&str) -> Option<&str>,
+) -> Option<PathBuf> {
+    let search_dir = resolve_search_dir(jpg_root, raw_root, jpg_path, recursive);
+    let stem = jpg_path.file_stem()?.to_string_lossy().to_string();
+    let files: Vec<PathBuf> = fs::read_dir(&search_dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    let mut variants: Vec<&PathBuf> = files
+        .iter()
+        .filter(|path| has_extension(path, "xmp"))
+        .filter(|path| {
+            path.file_stem()
+                .and_then(|v| v.to_str())
+                .and_then(base_stem)
+                .is_some_and(|base| base.eq_ignore_ascii_case(&stem))
+        })
+        .collect();
+    variants.sort();
+    variants
+        .into_iter()
+        .find(|path| !belongs_to_other_file(path, &stem, &files, jpg_path))
+        .cloned()
+}
+
+// 複製の XMP (NAME_01.xmp) と同じ名前の RAW や JPG があれば、その写真の XMP として扱う
+fn belongs_to_other_file(
+    xmp_path: &Path,
+    stem_original: &str,
+    raw_dir_files: &[PathBuf],
+    jpg_path: &Path,
+) -> bool {
+    let Some(xmp_stem) = xmp_path.file_stem().and_then(|v| v.to_str()) else {
+        return false;
+    };
+    let own_stem = double_extension_base_stem(xmp_stem).unwrap_or(xmp_stem);
+    if own_stem.eq_ignore_ascii_case(stem_original) {
+        return false;
+    }
+    let same_stem = |path: &Path| {
+        path.file_stem()
+            .is_some_and(|stem| stem.to_string_lossy().eq_ignore_ascii_case(own_stem))
+    };
+    raw_dir_files
+        .iter()
+        .any(|path| !has_extension(path, "xmp") && same_stem(path))
+        || jpg_path
+            .parent()
+            .and_then(|dir| fs::read_dir(dir).ok())
+            .is_some_and(|entries| entries.flatten().any(|entry| same_stem(&entry.path())))
+}
+
+// "DSC0001.RAF" -> "DSC0001"。拡張子付きで書かれた XMP (DSC0001.RAF.xmp) の stem から元の名前を取り出す
+pub(crate) fn double_extension_base_stem(stem: &str) -> Option<&str> {
+    let (base, ext) = stem.rsplit_once('.')?;
+    (!base.is_empty() && !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+        .then_some(base)
+}
+
+// "DSC0001_01" -> "DSC0001"。darktable の複製番号は2桁で、このツールの連番 (_001) とは区別する
+pub(crate) fn duplicate_base_stem(stem: &str) -> Option<&str> {
+    let (base, number) = stem.rsplit_once('_')?;
+    (!base.is_empty() && number.len() == 2 && number.chars().all(|c| c.is_ascii_digit()))
+        .then_some(base)
+}
+
+// "DSC0001_01.RAF" -> "DSC0001"。darktable は複製の XMP を NAME_NN.EXT.xmp で書く
+pub(crate) fn duplicate_with_extension_base_stem(stem: &str) -> Option<&str> {
+    double_extension_base_stem(stem).and_then(duplicate_base_stem)
 }
 
 fn find_matching_by_priority(
@@ -109,13 +626,13 @@ fn find_matching_by_priority(
     raw_root: &Path,
     jpg_path: &Path,
     recursive: bool,
-    extensions: &[&str],
+    extensions: &[impl AsRef<str>],
 ) -> Option<PathBuf> {
     let search_dir = resolve_search_dir(jpg_root, raw_root, jpg_path, recursive);
     let stem = jpg_path.file_stem()?.to_string_lossy().to_string();
 
     for ext in extensions {
-        if let Some(path) = find_candidate_with_case_variants(&search_dir, &stem, ext) {
+        if let Some(path) = find_candidate_with_case_variants(&search_dir, &stem, ext.as_ref()) {
             return Some(path);
         }
     }
@@ -178,12 +695,13 @@ fn insert_index_path(
     raw_root: &Path,
     path: &Path,
     recursive: bool,
+    raw_extensions: &[String],
 ) {
     let ext = path
         .extension()
         .and_then(|v| v.to_str())
         .unwrap_or_default();
-    if !is_index_target_extension(ext) {
+    if !is_index_target_extension(ext, raw_extensions) {
         return;
     }
 
@@ -206,6 +724,21 @@ fn insert_index_path(
 
     let stem_key = stem.to_ascii_lowercase();
     let stem_map = files_by_rel_dir.entry(rel_dir).or_default();
+    // NAME.EXT.xmp や複製の XMP は元の名前でも引けるようにする。同名の XMP があればそちらが先に選ばれ、
+    // 無ければパスの並び順で NAME.EXT.xmp、NAME_01.xmp の順になる
+    if ext.eq_ignore_ascii_case("xmp") {
+        let bases = [
+            double_extension_base_stem(stem),
+            duplicate_base_stem(stem),
+            duplicate_with_extension_base_stem(stem),
+        ];
+        for base in bases.into_iter().flatten() {
+            stem_map
+                .entry(base.to_ascii_lowercase())
+                .or_default()
+                .push(path.to_path_buf());
+        }
+    }
     stem_map
         .entry(stem_key)
         .or_default()
@@ -263,15 +796,30 @@ fn file_name_equals(path: &Path, expected: &str) -> bool {
         .unwrap_or(false)
 }
 
-fn is_index_target_extension(ext: &str) -> bool {
-    ext.eq_ignore_ascii_case("dng")
-        || ext.eq_ignore_ascii_case("raf")
-        || ext.eq_ignore_ascii_case("xmp")
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .and_then(|v| v.to_str())
+        .is_some_and(|v| v.eq_ignore_ascii_case(ext))
+}
+
+fn is_index_target_extension(ext: &str, raw_extensions: &[String]) -> bool {
+    ext.eq_ignore_ascii_case("xmp")
+        || raw_extensions
+            .iter()
+            .any(|raw_ext| raw_ext.eq_ignore_ascii_case(ext))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{build_raw_match_index, find_matching_raw, find_matching_xmp};
+    use super::{
+        build_raw_match_index, double_extension_base_stem, duplicate_base_stem,
+        duplicate_with_extension_base_stem, find_matching_raw, find_matching_xmp, find_sidecar_xmp,
+        normalize_extensions,
+    };
+    use super::{MatchedRaw, RawMatchIndex};
+    use crate::constants::default_raw_extensions;
+    use chrono::{DateTime, FixedOffset, TimeDelta};
+    use std::collections::HashSet;
     use std::fs::{self, File};
     use std::path::Path;
     use tempfile::tempdir;
@@ -296,11 +844,18 @@ mod tests {
         touch(&xmp);
 
         let found_xmp = find_matching_xmp(&jpg_root, &raw_root, &jpg, false);
-        let found_raw = find_matching_raw(&jpg_root, &raw_root, &jpg, false);
+        let found_raw =
+            find_matching_raw(&jpg_root, &raw_root, &jpg, false, &default_raw_extensions());
         assert_eq!(found_xmp.as_deref(), Some(xmp.as_path()));
         assert!(found_raw.is_none());
 
-        let index = build_raw_match_index(&jpg_root, &raw_root, false);
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
         assert_eq!(index.find_xmp(&jpg).as_deref(), Some(xmp.as_path()));
         assert!(index.find_raw(&jpg).is_none());
     }
@@ -319,11 +874,18 @@ mod tests {
         touch(&dng);
         touch(&raf);
 
-        let found = find_matching_raw(&jpg_root, &raw_root, &jpg, false);
+        let found = find_matching_raw(&jpg_root, &raw_root, &jpg, false, &default_raw_extensions());
         assert_eq!(found.as_deref(), Some(dng.as_path()));
 
-        let index = build_raw_match_index(&jpg_root, &raw_root, false);
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
         assert_eq!(index.find_raw(&jpg).as_deref(), Some(dng.as_path()));
+        assert_eq!(index.find_raws(&jpg), vec![dng.clone(), raf.clone()]);
     }
 
     #[test]
@@ -339,7 +901,8 @@ mod tests {
         touch(&raf);
 
         let found_xmp = find_matching_xmp(&jpg_root, &raw_root, &jpg, true);
-        let found_raw = find_matching_raw(&jpg_root, &raw_root, &jpg, true);
+        let found_raw =
+            find_matching_raw(&jpg_root, &raw_root, &jpg, true, &default_raw_extensions());
 
         let found_xmp = found_xmp.expect("xmp should be found");
         let found_raw = found_raw.expect("raw should be found");
@@ -356,8 +919,482 @@ mod tests {
             .map(|v| v.eq_ignore_ascii_case("raf"))
             .unwrap_or(false));
 
-        let index = build_raw_match_index(&jpg_root, &raw_root, true);
+        let index =
+            build_raw_match_index(&jpg_root, &raw_root, true, false, &default_raw_extensions());
+        assert_eq!(index.find_xmp(&jpg).as_deref(), Some(xmp.as_path()));
+        assert_eq!(index.find_raw(&jpg).as_deref(), Some(raf.as_path()));
+    }
+
+    #[test]
+    fn matches_configured_raw_extensions_in_order() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        let jpg = jpg_root.join("DSC0009.JPG");
+        let arw = raw_root.join("DSC0009.ARW");
+        let nef = raw_root.join("DSC0009.nef");
+        touch(&arw);
+        touch(&nef);
+
+        let nef_first = vec![".NEF".to_string(), "arw".to_string()];
+        assert_eq!(normalize_extensions(&nef_first), vec!["nef", "arw"]);
+        assert_eq!(
+            find_matching_raw(&jpg_root, &raw_root, &jpg, false, &nef_first).as_deref(),
+            Some(nef.as_path())
+        );
+        let index = build_raw_match_index(&jpg_root, &raw_root, false, false, &nef_first);
+        assert_eq!(index.find_raw(&jpg).as_deref(), Some(nef.as_path()));
+
+        // 既定の一覧では ARW が NEF より先
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        assert_eq!(index.find_raw(&jpg).as_deref(), Some(arw.as_path()));
+
+        // 一覧に無い拡張子は対象外
+        let index = build_raw_match_index(&jpg_root, &raw_root, false, false, &["dng".to_string()]);
+        assert!(index.find_raw(&jpg).is_none());
+    }
+
+    #[test]
+    fn falls_back_to_crs_raw_file_name_after_rename() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        // 前回の実行で RAW と XMP だけ改名された状態
+        let jpg = jpg_root.join("DSCF0004.JPG");
+        let raf = raw_root.join("20260208_X-T5.RAF");
+        let xmp = raw_root.join("20260208_X-T5.xmp");
+        touch(&raf);
+        fs::write(
+            &xmp,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description crs:RawFileName="DSCF0004.RAF" /></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("write xmp");
+        touch(&raw_root.join("OTHER.xmp"));
+
+        // 索引を使わない検索では JPG ごとに XMP を読み直さない
+        assert!(find_matching_xmp(&jpg_root, &raw_root, &jpg, false).is_none());
+        assert!(
+            find_matching_raw(&jpg_root, &raw_root, &jpg, false, &default_raw_extensions())
+                .is_none()
+        );
+
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
         assert_eq!(index.find_xmp(&jpg).as_deref(), Some(xmp.as_path()));
         assert_eq!(index.find_raw(&jpg).as_deref(), Some(raf.as_path()));
+        assert!(index.find_xmp(&jpg_root.join("DSCF0005.JPG")).is_none());
+    }
+
+    #[test]
+    fn finds_darktable_duplicate_xmp_after_base_sidecar() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        let jpg = jpg_root.join("DSC0006.JPG");
+        let first = raw_root.join("DSC0006_01.xmp");
+        let second = raw_root.join("DSC0006_02.xmp");
+        touch(&second);
+        touch(&first);
+
+        assert_eq!(
+            find_matching_xmp(&jpg_root, &raw_root, &jpg, false).as_deref(),
+            Some(first.as_path())
+        );
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        assert_eq!(index.find_xmp(&jpg).as_deref(), Some(first.as_path()));
+
+        let base = raw_root.join("DSC0006.xmp");
+        touch(&base);
+        assert_eq!(
+            find_matching_xmp(&jpg_root, &raw_root, &jpg, false).as_deref(),
+            Some(base.as_path())
+        );
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        assert_eq!(index.find_xmp(&jpg).as_deref(), Some(base.as_path()));
+        // 複製の JPG は自分の名前の XMP を使う
+        assert_eq!(
+            index.find_xmp(&jpg_root.join("DSC0006_02.JPG")).as_deref(),
+            Some(second.as_path())
+        );
+        assert_eq!(duplicate_base_stem("DSC0006_1"), None);
+        assert_eq!(duplicate_base_stem("_01"), None);
+        assert_eq!(duplicate_base_stem("DSC0006_001"), None);
+        assert_eq!(
+            duplicate_with_extension_base_stem("DSC0006_01.RAF"),
+            Some("DSC0006")
+        );
+    }
+
+    #[test]
+    fn duplicate_xmp_of_another_photo_is_not_used() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        // shot_001 は連番で付けた別の写真の名前
+        let jpg = jpg_root.join("shot.JPG");
+        touch(&jpg);
+        touch(&raw_root.join("shot_001.xmp"));
+        assert!(find_matching_xmp(&jpg_root, &raw_root, &jpg, false).is_none());
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        assert!(index.find_xmp(&jpg).is_none());
+
+        // 2桁でも、同じ名前の JPG や RAW があればその写真の XMP
+        let other_jpg = jpg_root.join("shot_01.JPG");
+        let other_xmp = raw_root.join("shot_01.xmp");
+        touch(&other_jpg);
+        touch(&other_xmp);
+        assert!(find_matching_xmp(&jpg_root, &raw_root, &jpg, false).is_none());
+        let mut index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        index.register_jpg(jpg.clone());
+        index.register_jpg(other_jpg.clone());
+        assert!(index.find_xmp(&jpg).is_none());
+        assert_eq!(
+            index.find_xmp(&other_jpg).as_deref(),
+            Some(other_xmp.as_path())
+        );
+
+        fs::remove_file(&other_jpg).expect("remove other jpg");
+        touch(&raw_root.join("shot_01.RAF"));
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        assert!(index.find_xmp(&jpg).is_none());
+
+        // 対応する写真が無い darktable の複製 (拡張子付き) は元の写真のもの
+        fs::remove_file(raw_root.join("shot_01.RAF")).expect("remove raf");
+        fs::remove_file(&other_xmp).expect("remove other xmp");
+        let duplicate = raw_root.join("shot_02.RAF.xmp");
+        touch(&duplicate);
+        assert_eq!(
+            find_matching_xmp(&jpg_root, &raw_root, &jpg, false).as_deref(),
+            Some(duplicate.as_path())
+        );
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        assert_eq!(index.find_xmp(&jpg).as_deref(), Some(duplicate.as_path()));
+    }
+
+    #[test]
+    fn finds_xmp_written_with_original_extension() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        let jpg = jpg_root.join("DSC0007.JPG");
+        let with_ext = raw_root.join("DSC0007.RAF.xmp");
+        touch(&raw_root.join("DSC0007_01.xmp"));
+        touch(&with_ext);
+
+        assert_eq!(
+            find_matching_xmp(&jpg_root, &raw_root, &jpg, false).as_deref(),
+            Some(with_ext.as_path())
+        );
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        assert_eq!(index.find_xmp(&jpg).as_deref(), Some(with_ext.as_path()));
+        assert_eq!(double_extension_base_stem("DSC0007.RAF"), Some("DSC0007"));
+        assert_eq!(double_extension_base_stem("DSC0007"), None);
+    }
+
+    #[test]
+    fn finds_sidecar_xmp_next_to_jpg() {
+        let temp = tempdir().expect("tempdir");
+        let jpg = temp.path().join("DSC0008.JPG");
+        touch(&jpg);
+        assert!(find_sidecar_xmp(&jpg).is_none());
+
+        let with_ext = temp.path().join("DSC0008.JPG.xmp");
+        touch(&with_ext);
+        assert_eq!(find_sidecar_xmp(&jpg).as_deref(), Some(with_ext.as_path()));
+
+        let base = temp.path().join("DSC0008.xmp");
+        touch(&base);
+        assert_eq!(find_sidecar_xmp(&jpg).as_deref(), Some(base.as_path()));
+    }
+
+    fn capture_time_index(raw_root: &Path, jpg_root: &Path, jpgs: &[&Path]) -> RawMatchIndex {
+        let mut index =
+            build_raw_match_index(jpg_root, raw_root, false, false, &default_raw_extensions());
+        for jpg in jpgs {
+            index.register_jpg(jpg.to_path_buf());
+        }
+        index
+    }
+
+    fn capture_times<'a>(
+        times: &'a [(&str, &str)],
+    ) -> impl Fn(&Path) -> Option<DateTime<FixedOffset>> + 'a {
+        move |path: &Path| {
+            let stem = path.file_stem()?.to_str()?;
+            times
+                .iter()
+                .find(|(name, _)| *name == stem)
+                .map(|(_, time)| DateTime::parse_from_rfc3339(time).expect("date"))
+        }
+    }
+
+    #[test]
+    fn finds_raw_by_nearest_capture_time_within_tolerance() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        let sunset = jpg_root.join("2026-02-08_sunset.JPG");
+        let dawn = jpg_root.join("2026-02-08_dawn.JPG");
+        let late = jpg_root.join("2026-02-08_late.JPG");
+        for jpg in [&sunset, &dawn, &late] {
+            touch(jpg);
+        }
+        touch(&raw_root.join("DSCF0001.RAF"));
+        touch(&raw_root.join("DSCF0001.xmp"));
+        touch(&raw_root.join("DSCF0002.RAF"));
+        touch(&raw_root.join("DSCF0002.dng"));
+
+        let index = capture_time_index(&raw_root, &jpg_root, &[&sunset, &dawn, &late]);
+        let times = [
+            ("DSCF0001", "2026-02-08T10:20:30.100+09:00"),
+            ("DSCF0002", "2026-02-08T10:20:30.400+09:00"),
+            ("2026-02-08_sunset", "2026-02-08T10:20:30.350+09:00"),
+            ("2026-02-08_dawn", "2026-02-08T10:20:30.000+09:00"),
+            ("2026-02-08_late", "2026-02-08T10:20:32+09:00"),
+        ];
+        let tolerance = TimeDelta::milliseconds(500);
+
+        let (found, difference) = index
+            .find_by_capture_time(&sunset, tolerance, capture_times(&times))
+            .expect("match by capture time");
+        assert_eq!(
+            found.raw_paths,
+            vec![raw_root.join("DSCF0002.dng"), raw_root.join("DSCF0002.RAF")]
+        );
+        assert_eq!(found.xmp_path, None);
+        assert_eq!(difference, TimeDelta::milliseconds(50));
+
+        let (found, _) = index
+            .find_by_capture_time(&dawn, tolerance, capture_times(&times))
+            .expect("match by capture time");
+        assert_eq!(found.raw_paths, vec![raw_root.join("DSCF0001.RAF")]);
+        assert_eq!(found.xmp_path, Some(raw_root.join("DSCF0001.xmp")));
+
+        assert!(index
+            .find_by_capture_time(&late, tolerance, capture_times(&times))
+            .is_none());
+    }
+
+    #[test]
+    fn capture_time_skips_raw_matched_by_name_to_another_jpg() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        let renamed = jpg_root.join("A.JPG");
+        let named = jpg_root.join("IMG_0001.JPG");
+        touch(&renamed);
+        touch(&named);
+        touch(&raw_root.join("IMG_0001.DNG"));
+
+        let index = capture_time_index(&raw_root, &jpg_root, &[&renamed, &named]);
+        let times = [
+            ("A", "2026-02-08T10:20:30+09:00"),
+            ("IMG_0001", "2026-02-08T10:20:30+09:00"),
+        ];
+
+        assert!(index
+            .find_by_capture_time(
+                &renamed,
+                TimeDelta::milliseconds(1000),
+                capture_times(&times)
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn capture_time_gives_each_raw_to_the_nearest_jpg_only() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        let near = jpg_root.join("near.JPG");
+        let far = jpg_root.join("far.JPG");
+        touch(&near);
+        touch(&far);
+        touch(&raw_root.join("DSCF0001.RAF"));
+
+        let index = capture_time_index(&raw_root, &jpg_root, &[&far, &near]);
+        let times = [
+            ("DSCF0001", "2026-02-08T10:20:30.000+09:00"),
+            ("near", "2026-02-08T10:20:30.100+09:00"),
+            ("far", "2026-02-08T10:20:30.200+09:00"),
+        ];
+        let tolerance = TimeDelta::milliseconds(1000);
+
+        assert!(index
+            .find_by_capture_time(&far, tolerance, capture_times(&times))
+            .is_none());
+        let (found, _) = index
+            .find_by_capture_time(&near, tolerance, capture_times(&times))
+            .expect("match by capture time");
+        assert_eq!(found.raw_paths, vec![raw_root.join("DSCF0001.RAF")]);
+    }
+
+    #[test]
+    fn capture_time_refuses_ties() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        let between = jpg_root.join("between.JPG");
+        let first = jpg_root.join("first.JPG");
+        let second = jpg_root.join("second.JPG");
+        for jpg in [&between, &first, &second] {
+            touch(jpg);
+        }
+        touch(&raw_root.join("DSCF0001.RAF"));
+        touch(&raw_root.join("DSCF0002.RAF"));
+        touch(&raw_root.join("DSCF0009.RAF"));
+
+        let index = capture_time_index(&raw_root, &jpg_root, &[&between, &first, &second]);
+        let times = [
+            // 2つの RAW のちょうど中間
+            ("DSCF0001", "2026-02-08T10:20:30.000+09:00"),
+            ("DSCF0002", "2026-02-08T10:20:30.400+09:00"),
+            ("between", "2026-02-08T10:20:30.200+09:00"),
+            // 1つの RAW から同じ差の2枚
+            ("DSCF0009", "2026-02-08T11:00:00.000+09:00"),
+            ("first", "2026-02-08T10:59:59.900+09:00"),
+            ("second", "2026-02-08T11:00:00.100+09:00"),
+        ];
+        let tolerance = TimeDelta::milliseconds(1000);
+
+        for jpg in [&between, &first, &second] {
+            assert!(index
+                .find_by_capture_time(jpg, tolerance, capture_times(&times))
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn finds_raw_by_image_unique_id() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        let jpg = jpg_root.join("2026-02-08_sunset.JPG");
+        touch(&jpg);
+        touch(&raw_root.join("IMG_0001.CR3"));
+        touch(&raw_root.join("IMG_0002.CR3"));
+        touch(&raw_root.join("IMG_0002.xmp"));
+
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        let read_unique_id = |path: &Path| match path.file_stem()?.to_str()? {
+            "IMG_0001" => Some("4A5B6C7D8E9F0011".to_string()),
+            "IMG_0002" => Some("00112233445566FF".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            index.find_by_unique_id(&jpg, "00112233445566FF", read_unique_id),
+            Some(MatchedRaw {
+                raw_paths: vec![raw_root.join("IMG_0002.CR3")],
+                xmp_path: Some(raw_root.join("IMG_0002.xmp")),
+            })
+        );
+        assert!(index
+            .find_by_unique_id(&jpg, "FFEEDDCCBBAA9988", read_unique_id)
+            .is_none());
+    }
+
+    #[test]
+    fn lists_unmatched_raw_and_xmp_files() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        for name in [
+            "DSCF0001.RAF",
+            "DSCF0001.xmp",
+            "DSCF0001_01.xmp",
+            "DSCF0002.RAF",
+            "DSCF0003.xmp",
+        ] {
+            touch(&raw_root.join(name));
+        }
+
+        let index = build_raw_match_index(
+            &jpg_root,
+            &raw_root,
+            false,
+            false,
+            &default_raw_extensions(),
+        );
+        let matched = HashSet::from([raw_root.join("DSCF0001.RAF")]);
+        // 複製の XMP は元の名前のまとまりにも入るため、対応付いた扱いになる
+        assert_eq!(
+            index.unmatched_files(&matched),
+            vec![raw_root.join("DSCF0002.RAF"), raw_root.join("DSCF0003.xmp")]
+        );
     }
 }
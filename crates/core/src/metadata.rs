@@ -1,8 +1,11 @@
-use chrono::{DateTime, Local};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MetadataSource {
     JpgExif,
     Xmp,
@@ -11,43 +14,400 @@ pub enum MetadataSource {
     FallbackFileModified,
 }
 
+// 集計表示用のラベル
+impl fmt::Display for MetadataSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MetadataSource::JpgExif => "jpg_exif",
+            MetadataSource::Xmp => "xmp",
+            MetadataSource::RawExif => "raw_exif",
+            MetadataSource::XmpAndRawExif => "xmp+raw_exif",
+            MetadataSource::FallbackFileModified => "file_modified",
+        })
+    }
+}
+
+// --only-source 用。XMPとRAWの両方から読んだ写真はどちらの指定でも対象にする
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SourceFilter {
+    Xmp,
+    Raw,
+    Jpg,
+}
+
+impl SourceFilter {
+    pub fn matches(self, source: MetadataSource) -> bool {
+        match self {
+            SourceFilter::Xmp => {
+                matches!(source, MetadataSource::Xmp | MetadataSource::XmpAndRawExif)
+            }
+            SourceFilter::Raw => {
+                matches!(
+                    source,
+                    MetadataSource::RawExif | MetadataSource::XmpAndRawExif
+                )
+            }
+            SourceFilter::Jpg => source == MetadataSource::JpgExif,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PhotoMetadata {
     pub source: MetadataSource,
-    pub date: DateTime<Local>,
+    pub date: DateTime<FixedOffset>,
     pub camera_make: Option<String>,
     pub camera_model: Option<String>,
     pub lens_make: Option<String>,
     pub lens_model: Option<String>,
     pub film_sim: Option<String>,
+    #[serde(default)]
+    pub rating: Option<i8>,
+    #[serde(default)]
+    pub dynamic_range: Option<String>,
+    #[serde(default)]
+    pub grain_effect: Option<String>,
+    #[serde(default)]
+    pub color_chrome_effect: Option<String>,
+    #[serde(default)]
+    pub wb_shift: Option<String>,
+    // 35mm判換算の焦点距離 ("53mm" など)
+    #[serde(default)]
+    pub focal_length_eq: Option<String>,
+    // 露出補正 ("+0.7EV" など) とフラッシュ発光の有無
+    #[serde(default)]
+    pub exposure_compensation: Option<String>,
+    #[serde(default)]
+    pub flash_fired: Option<bool>,
+    // EXIF の ImageUniqueID (無ければ DocumentID)。ファイル名が変わった JPG と RAW の対応付けに使える
+    #[serde(default)]
+    pub image_unique_id: Option<String>,
+    // XMP の dc:title / dc:description (xml:lang -> 本文)。言語指定が無いものは x-default
+    #[serde(default)]
+    pub title: BTreeMap<String, String>,
+    #[serde(default)]
+    pub description: BTreeMap<String, String>,
+    // XMP の xmp:Label (カラーラベル名) と dc:subject (キーワード)
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    // 設定の extra_exif_tags で指定した exiftool のタグ (タグ名 -> 値)
+    #[serde(default)]
+    pub extra_tags: BTreeMap<String, String>,
     pub original_name: String,
     pub jpg_path: PathBuf,
 }
 
+// 指定の言語 ("ja" は "ja-JP" にも一致)、x-default、最初の項目の順に選ぶ
+pub(crate) fn pick_localized<'a>(
+    values: &'a BTreeMap<String, String>,
+    language: Option<&str>,
+) -> Option<&'a str> {
+    let find = |wanted: &str| {
+        values.iter().find_map(|(lang, text)| {
+            let primary = lang.split('-').next().unwrap_or(lang);
+            (lang.eq_ignore_ascii_case(wanted) || primary.eq_ignore_ascii_case(wanted))
+                .then_some(text.as_str())
+        })
+    };
+    language
+        .map(str::trim)
+        .filter(|lang| !lang.is_empty())
+        .and_then(find)
+        .or_else(|| find("x-default"))
+        .or_else(|| values.values().next().map(String::as_str))
+}
+
+// EXIFのメーカー名はそのままだと長かったり大文字だけだったりするため、短い表記にそろえる
+const BUILTIN_MAKER_NAMES: &[(&str, &str)] = &[
+    ("NIKON CORPORATION", "Nikon"),
+    ("NIKON", "Nikon"),
+    ("SONY", "Sony"),
+    ("Canon", "Canon"),
+    ("OM Digital Solutions", "OM-System"),
+    ("OLYMPUS CORPORATION", "Olympus"),
+    ("OLYMPUS IMAGING CORP.", "Olympus"),
+    ("OLYMPUS OPTICAL CO.,LTD", "Olympus"),
+    ("Panasonic", "Panasonic"),
+    ("LEICA CAMERA AG", "Leica"),
+    ("RICOH IMAGING COMPANY, LTD.", "Ricoh"),
+    ("PENTAX Corporation", "Pentax"),
+    ("PENTAX", "Pentax"),
+    ("Hasselblad", "Hasselblad"),
+    ("EASTMAN KODAK COMPANY", "Kodak"),
+];
+
+// 利用者の設定 (maker_aliases) を組み込みの表より優先する。どちらも大文字小文字を区別しない
+pub fn normalize_maker_name<'a>(raw: &'a str, aliases: &'a BTreeMap<String, String>) -> &'a str {
+    let name = raw.trim();
+    if let Some((_, alias)) = aliases
+        .iter()
+        .find(|(from, _)| from.trim().eq_ignore_ascii_case(name))
+    {
+        return alias.trim();
+    }
+    BUILTIN_MAKER_NAMES
+        .iter()
+        .find(|(from, _)| from.eq_ignore_ascii_case(name))
+        .map_or(name, |(_, to)| *to)
+}
+
 impl PhotoMetadata {
-    pub fn normalized_camera_make(&self) -> Option<&str> {
+    pub fn normalized_camera_make<'a>(
+        &'a self,
+        aliases: &'a BTreeMap<String, String>,
+    ) -> Option<&'a str> {
         self.camera_make
+            .as_deref()
+            .map(|make| normalize_maker_name(make, aliases))
+            .filter(|s| !s.is_empty())
+    }
+
+    // "NIKON D750" (メーカー NIKON CORPORATION) や "Canon EOS R5" の先頭のメーカー名を外す。
+    // "OM-1" のように区切りが空白でない場合や、外すと空になる場合はそのまま
+    pub fn camera_model_without_maker<'a>(
+        &'a self,
+        aliases: &'a BTreeMap<String, String>,
+    ) -> Option<&'a str> {
+        let model = self.camera_model.as_deref()?.trim();
+        let Some(make) = self
+            .camera_make
             .as_deref()
             .map(str::trim)
             .filter(|s| !s.is_empty())
+        else {
+            return Some(model);
+        };
+        let prefixes = [
+            make,
+            normalize_maker_name(make, aliases),
+            make.split_whitespace().next().unwrap_or(make),
+        ];
+        let stripped = prefixes.iter().find_map(|prefix| {
+            let head = model.get(..prefix.len())?;
+            let rest = model[prefix.len()..].strip_prefix(char::is_whitespace)?;
+            head.eq_ignore_ascii_case(prefix)
+                .then(|| rest.trim())
+                .filter(|rest| !rest.is_empty())
+        });
+        Some(stripped.unwrap_or(model))
     }
 
-    pub fn normalized_lens_make(&self) -> Option<&str> {
+    pub fn title(&self, language: Option<&str>) -> Option<&str> {
+        pick_localized(&self.title, language)
+    }
+
+    pub fn description(&self, language: Option<&str>) -> Option<&str> {
+        pick_localized(&self.description, language)
+    }
+
+    pub fn normalized_lens_make<'a>(
+        &'a self,
+        aliases: &'a BTreeMap<String, String>,
+    ) -> Option<&'a str> {
         self.lens_make
             .as_deref()
-            .map(str::trim)
+            .map(|make| normalize_maker_name(make, aliases))
             .filter(|s| !s.is_empty())
     }
+
+    // 撮影日時はカメラの時計の値 (壁時計) のまま指定のオフセットで読み直す。
+    // ファイル更新日時やGPSの日時 (camera_clock = false) は時刻そのものが正しいため、表示するオフセットだけを変える
+    pub fn apply_date_zone(&mut self, zone: DateZone, camera_clock: bool) {
+        if let Some(timezone) = zone.timezone {
+            self.date = if !camera_clock {
+                self.date.with_timezone(&timezone)
+            } else {
+                timezone
+                    .from_local_datetime(&self.date.naive_local())
+                    .single()
+                    .unwrap_or(self.date)
+            };
+        }
+        if zone.utc {
+            self.date = self.date.with_timezone(&Utc).fixed_offset();
+        }
+    }
+
+    // 機種指定のずれを全機種向けより優先する。カメラの時計以外から得た日付はずらさない。
+    // ずらすと表せる日付の範囲を外れる場合は、日付を変えずに false を返す
+    pub fn apply_time_shifts(&mut self, shifts: &[TimeShift], camera_clock: bool) -> bool {
+        if !camera_clock {
+            return true;
+        }
+        let model = self.camera_model.as_deref().map(str::trim);
+        let shift = shifts
+            .iter()
+            .find(|shift| {
+                shift
+                    .camera_model
+                    .as_deref()
+                    .is_some_and(|target| model.is_some_and(|m| m.eq_ignore_ascii_case(target)))
+            })
+            .or_else(|| shifts.iter().find(|shift| shift.camera_model.is_none()));
+        let Some(shift) = shift else {
+            return true;
+        };
+        match self.date.checked_add_signed(shift.offset) {
+            Some(date) => {
+                self.date = date;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// カメラの時計のずれを補正する (--shift-time)。camera_model が None なら全機種に適用
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeShift {
+    pub camera_model: Option<String>,
+    pub offset: Duration,
 }
 
-#[derive(Debug, Clone, Default)]
+// 時計の設定ミスとして考えられる範囲。これを超える値は入力の誤りとして扱う
+const MAX_TIME_SHIFT_DAYS: i64 = 36_500;
+
+// "+1h3m" / "-30s" / "X-T5=-1h3m" の形式。単位は d / h / m / s
+pub fn parse_time_shift(raw: &str) -> Result<TimeShift> {
+    let (camera_model, amount) = match raw.rsplit_once('=') {
+        Some((model, amount)) => {
+            let model = model.trim();
+            if model.is_empty() {
+                bail!("時刻のずれの機種名が空です: {raw}");
+            }
+            (Some(model.to_string()), amount.trim())
+        }
+        None => (None, raw.trim()),
+    };
+    let (sign, mut rest) = match amount.as_bytes().first() {
+        Some(b'-') => (-1, &amount[1..]),
+        Some(b'+') => (1, &amount[1..]),
+        _ => (1, amount),
+    };
+    if rest.is_empty() {
+        bail!("時刻のずれを +1h3m のように指定してください: {raw}");
+    }
+    let mut seconds: i64 = 0;
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let value: i64 = rest[..digits]
+            .parse()
+            .with_context(|| format!("時刻のずれを +1h3m のように指定してください: {raw}"))?;
+        let unit = match rest[digits..].chars().next() {
+            Some('d') => 86_400,
+            Some('h') => 3_600,
+            Some('m') => 60,
+            Some('s') => 1,
+            _ => bail!("時刻のずれの単位は d / h / m / s で指定してください: {raw}"),
+        };
+        seconds = value
+            .checked_mul(unit)
+            .and_then(|value| seconds.checked_add(value))
+            .with_context(|| format!("時刻のずれが大きすぎます: {raw}"))?;
+        rest = &rest[digits + 1..];
+    }
+    let offset = Duration::try_seconds(sign * seconds)
+        .filter(|offset| offset.abs() <= Duration::days(MAX_TIME_SHIFT_DAYS))
+        .with_context(|| {
+            format!("時刻のずれは {MAX_TIME_SHIFT_DAYS}日 以内で指定してください: {raw}")
+        })?;
+    Ok(TimeShift {
+        camera_model,
+        offset,
+    })
+}
+
+// timezone はカメラの時計のタイムゾーンを上書きする (旅行先で設定を変え忘れた場合など)。
+// utc を指定すると日付トークンをUTCで展開する
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateZone {
+    pub timezone: Option<FixedOffset>,
+    pub utc: bool,
+}
+
+// "+09:00" / "+0900" / "-05" / "Z" / "UTC" を受け付ける
+pub fn parse_utc_offset(raw: &str) -> Option<FixedOffset> {
+    let raw = raw.trim();
+    if raw.eq_ignore_ascii_case("z") || raw.eq_ignore_ascii_case("utc") {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, rest) = match raw.as_bytes().first()? {
+        b'+' => (1, &raw[1..]),
+        b'-' => (-1, &raw[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    if hours.is_empty() || !(hours.len() <= 2 && minutes.len() <= 2) {
+        return None;
+    }
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+// PC のタイムゾーンとして読む。オフセットの無いEXIF日時の既定の扱い
+pub(crate) fn local_date(naive: &NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    Local
+        .from_local_datetime(naive)
+        .single()
+        .map(|date| date.fixed_offset())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PartialMetadata {
-    pub date: Option<DateTime<Local>>,
+    // EXIFにオフセットが無い場合はPCのタイムゾーンの時刻として読む
+    pub date: Option<DateTime<FixedOffset>>,
     pub camera_make: Option<String>,
     pub camera_model: Option<String>,
     pub lens_make: Option<String>,
     pub lens_model: Option<String>,
     pub film_sim: Option<String>,
+    pub rating: Option<i8>,
+    // 富士フイルムのレシピ項目 (ダイナミックレンジ・グレイン・カラークローム・WBシフト)
+    #[serde(default)]
+    pub dynamic_range: Option<String>,
+    #[serde(default)]
+    pub grain_effect: Option<String>,
+    #[serde(default)]
+    pub color_chrome_effect: Option<String>,
+    #[serde(default)]
+    pub wb_shift: Option<String>,
+    #[serde(default)]
+    pub focal_length_eq: Option<String>,
+    #[serde(default)]
+    pub exposure_compensation: Option<String>,
+    #[serde(default)]
+    pub flash_fired: Option<bool>,
+    #[serde(default)]
+    pub image_unique_id: Option<String>,
+    #[serde(default)]
+    pub title: BTreeMap<String, String>,
+    #[serde(default)]
+    pub description: BTreeMap<String, String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    // GPSDateStamp / GPSTimeStamp (UTC)
+    #[serde(default)]
+    pub gps_date: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub extra_tags: BTreeMap<String, String>,
+    // 最初に値を読めたリーダー。XMPサイドカーから読んだ場合は None
+    #[serde(default)]
+    pub backend: Option<MetadataBackend>,
+    // 読み込めたが exiftool が警告・エラーを返した場合の内容
+    #[serde(default)]
+    pub exiftool_messages: Vec<String>,
 }
 
 impl PartialMetadata {
@@ -70,40 +430,468 @@ impl PartialMetadata {
         if self.film_sim.is_none() {
             self.film_sim = fallback.film_sim.clone();
         }
+        if self.rating.is_none() {
+            self.rating = fallback.rating;
+        }
+        if self.dynamic_range.is_none() {
+            self.dynamic_range = fallback.dynamic_range.clone();
+        }
+        if self.grain_effect.is_none() {
+            self.grain_effect = fallback.grain_effect.clone();
+        }
+        if self.color_chrome_effect.is_none() {
+            self.color_chrome_effect = fallback.color_chrome_effect.clone();
+        }
+        if self.wb_shift.is_none() {
+            self.wb_shift = fallback.wb_shift.clone();
+        }
+        if self.focal_length_eq.is_none() {
+            self.focal_length_eq = fallback.focal_length_eq.clone();
+        }
+        if self.exposure_compensation.is_none() {
+            self.exposure_compensation = fallback.exposure_compensation.clone();
+        }
+        if self.flash_fired.is_none() {
+            self.flash_fired = fallback.flash_fired;
+        }
+        if self.image_unique_id.is_none() {
+            self.image_unique_id = fallback.image_unique_id.clone();
+        }
+        if self.title.is_empty() {
+            self.title = fallback.title.clone();
+        }
+        if self.description.is_empty() {
+            self.description = fallback.description.clone();
+        }
+        if self.label.is_none() {
+            self.label = fallback.label.clone();
+        }
+        if self.keywords.is_empty() {
+            self.keywords = fallback.keywords.clone();
+        }
+        if self.gps_date.is_none() {
+            self.gps_date = fallback.gps_date;
+        }
+        for (tag, value) in &fallback.extra_tags {
+            self.extra_tags
+                .entry(tag.clone())
+                .or_insert_with(|| value.clone());
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldSource {
+    Xmp,
+    RawExif,
+    JpgExif,
+    Gps,
+    FileModified,
+}
+
+// EXIFを読んだ手段。kamadak-exif だけではメーカーノートのフィルムシミュレーション等を読めない
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataBackend {
+    Exiftool,
+    Kamadak,
+    EmbeddedXmp,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FieldProvenance {
+    #[serde(default)]
+    pub backend: Option<MetadataBackend>,
+    pub date: Option<FieldSource>,
+    pub camera_make: Option<FieldSource>,
+    pub camera_model: Option<FieldSource>,
+    pub lens_make: Option<FieldSource>,
+    pub lens_model: Option<FieldSource>,
+    pub film_sim: Option<FieldSource>,
+    pub rating: Option<FieldSource>,
+    #[serde(default)]
+    pub dynamic_range: Option<FieldSource>,
+    #[serde(default)]
+    pub grain_effect: Option<FieldSource>,
+    #[serde(default)]
+    pub color_chrome_effect: Option<FieldSource>,
+    #[serde(default)]
+    pub wb_shift: Option<FieldSource>,
+    #[serde(default)]
+    pub focal_length_eq: Option<FieldSource>,
+    #[serde(default)]
+    pub exposure_compensation: Option<FieldSource>,
+    #[serde(default)]
+    pub flash_fired: Option<FieldSource>,
+    #[serde(default)]
+    pub image_unique_id: Option<FieldSource>,
+    #[serde(default)]
+    pub label: Option<FieldSource>,
+    #[serde(default)]
+    pub title: Option<FieldSource>,
+    #[serde(default)]
+    pub description: Option<FieldSource>,
+    #[serde(default)]
+    pub keywords: Option<FieldSource>,
+    // 追加タグはタグごとに埋まるため、タグ名ごとに持つ
+    #[serde(default)]
+    pub extra_tags: BTreeMap<String, FieldSource>,
+}
+
+impl FieldProvenance {
+    // merge_missing_from と同じ順序で呼ぶと、各項目を最初に埋めたソースが残る
+    pub fn record(&mut self, meta: &PartialMetadata, source: FieldSource) {
+        fn fill<T>(slot: &mut Option<FieldSource>, value: &Option<T>, source: FieldSource) {
+            if slot.is_none() && value.is_some() {
+                *slot = Some(source);
+            }
+        }
+        if self.backend.is_none() {
+            self.backend = meta.backend;
+        }
+        fill(&mut self.date, &meta.date, source);
+        fill(&mut self.camera_make, &meta.camera_make, source);
+        fill(&mut self.camera_model, &meta.camera_model, source);
+        fill(&mut self.lens_make, &meta.lens_make, source);
+        fill(&mut self.lens_model, &meta.lens_model, source);
+        fill(&mut self.film_sim, &meta.film_sim, source);
+        fill(&mut self.rating, &meta.rating, source);
+        fill(&mut self.dynamic_range, &meta.dynamic_range, source);
+        fill(&mut self.grain_effect, &meta.grain_effect, source);
+        fill(
+            &mut self.color_chrome_effect,
+            &meta.color_chrome_effect,
+            source,
+        );
+        fill(&mut self.wb_shift, &meta.wb_shift, source);
+        fill(&mut self.focal_length_eq, &meta.focal_length_eq, source);
+        fill(
+            &mut self.exposure_compensation,
+            &meta.exposure_compensation,
+            source,
+        );
+        fill(&mut self.flash_fired, &meta.flash_fired, source);
+        fill(&mut self.image_unique_id, &meta.image_unique_id, source);
+        fill(&mut self.label, &meta.label, source);
+        if self.title.is_none() && !meta.title.is_empty() {
+            self.title = Some(source);
+        }
+        if self.description.is_none() && !meta.description.is_empty() {
+            self.description = Some(source);
+        }
+        if self.keywords.is_none() && !meta.keywords.is_empty() {
+            self.keywords = Some(source);
+        }
+        for tag in meta.extra_tags.keys() {
+            self.extra_tags.entry(tag.clone()).or_insert(source);
+        }
+    }
+
+    pub(crate) fn with_date_fallback(mut self) -> Self {
+        self.date.get_or_insert(FieldSource::FileModified);
+        self
+    }
+}
+
+pub(crate) fn parse_rating(raw: &str) -> Option<i8> {
+    let value = raw.trim().parse::<f64>().ok()?;
+    if !value.is_finite() {
+        return None;
+    }
+    Some(value.round().clamp(-1.0, 5.0) as i8)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{PartialMetadata, PhotoMetadata};
+    use super::{
+        normalize_maker_name, parse_rating, parse_time_shift, parse_utc_offset, pick_localized,
+        DateZone, FieldProvenance, FieldSource, MetadataBackend, PartialMetadata, PhotoMetadata,
+        SourceFilter, TimeShift,
+    };
     use crate::metadata::MetadataSource;
-    use chrono::Local;
+    use chrono::{DateTime, Duration, FixedOffset, Local, TimeZone};
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
 
+    #[test]
+    fn source_filter_matches_combined_sources() {
+        assert!(SourceFilter::Xmp.matches(MetadataSource::Xmp));
+        assert!(SourceFilter::Xmp.matches(MetadataSource::XmpAndRawExif));
+        assert!(!SourceFilter::Xmp.matches(MetadataSource::JpgExif));
+        assert!(SourceFilter::Raw.matches(MetadataSource::XmpAndRawExif));
+        assert!(!SourceFilter::Raw.matches(MetadataSource::Xmp));
+        assert!(SourceFilter::Jpg.matches(MetadataSource::JpgExif));
+        assert!(!SourceFilter::Jpg.matches(MetadataSource::FallbackFileModified));
+    }
+
+    #[test]
+    fn metadata_source_displays_short_label() {
+        assert_eq!(MetadataSource::XmpAndRawExif.to_string(), "xmp+raw_exif");
+        assert_eq!(
+            MetadataSource::FallbackFileModified.to_string(),
+            "file_modified"
+        );
+    }
+
     #[test]
     fn normalized_make_trims_and_drops_empty() {
         let mut meta = PhotoMetadata {
             source: MetadataSource::JpgExif,
-            date: Local::now(),
+            date: Local::now().fixed_offset(),
             camera_make: Some("  FUJIFILM  ".to_string()),
             camera_model: None,
             lens_make: Some("   ".to_string()),
             lens_model: None,
             film_sim: None,
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            extra_tags: Default::default(),
             original_name: "IMG_0001".to_string(),
             jpg_path: PathBuf::from("/tmp/IMG_0001.JPG"),
         };
 
-        assert_eq!(meta.normalized_camera_make(), Some("FUJIFILM"));
-        assert_eq!(meta.normalized_lens_make(), None);
+        assert_eq!(
+            meta.normalized_camera_make(&BTreeMap::new()),
+            Some("FUJIFILM")
+        );
+        assert_eq!(meta.normalized_lens_make(&BTreeMap::new()), None);
 
         meta.camera_make = Some(" ".to_string());
-        assert_eq!(meta.normalized_camera_make(), None);
+        assert_eq!(meta.normalized_camera_make(&BTreeMap::new()), None);
+    }
+
+    #[test]
+    fn camera_model_without_maker_strips_leading_make() {
+        let meta = |make: &str, model: &str| PhotoMetadata {
+            source: MetadataSource::JpgExif,
+            date: Local::now().fixed_offset(),
+            camera_make: Some(make.to_string()),
+            camera_model: Some(model.to_string()),
+            lens_make: None,
+            lens_model: None,
+            film_sim: None,
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            extra_tags: Default::default(),
+            original_name: "IMG_0001".to_string(),
+            jpg_path: PathBuf::from("/tmp/IMG_0001.JPG"),
+        };
+        let none = BTreeMap::new();
+        let nikon = meta("NIKON CORPORATION", "NIKON D750");
+        assert_eq!(nikon.camera_model_without_maker(&none), Some("D750"));
+        let canon = meta("Canon", "Canon EOS R5");
+        assert_eq!(canon.camera_model_without_maker(&none), Some("EOS R5"));
+        let om = meta("OM Digital Solutions", "OM-1");
+        assert_eq!(om.camera_model_without_maker(&none), Some("OM-1"));
+        let fuji = meta("FUJIFILM", "X-T5");
+        assert_eq!(fuji.camera_model_without_maker(&none), Some("X-T5"));
+    }
+
+    #[test]
+    fn parse_utc_offset_accepts_common_forms() {
+        let hours = |h: i32| FixedOffset::east_opt(h * 3600);
+        assert_eq!(parse_utc_offset("+09:00"), hours(9));
+        assert_eq!(parse_utc_offset("+0900"), hours(9));
+        assert_eq!(parse_utc_offset("-05"), hours(-5));
+        assert_eq!(
+            parse_utc_offset("+05:30"),
+            FixedOffset::east_opt(5 * 3600 + 30 * 60)
+        );
+        assert_eq!(parse_utc_offset("Z"), hours(0));
+        assert_eq!(parse_utc_offset("utc"), hours(0));
+        assert_eq!(parse_utc_offset("09:00"), None);
+        assert_eq!(parse_utc_offset("+25:00"), None);
+        assert_eq!(parse_utc_offset("+abc"), None);
+    }
+
+    #[test]
+    fn apply_date_zone_reinterprets_camera_clock_and_renders_utc() {
+        let tokyo = FixedOffset::east_opt(9 * 3600).expect("offset");
+        let paris = FixedOffset::east_opt(3600).expect("offset");
+        let mut meta = PhotoMetadata {
+            source: MetadataSource::JpgExif,
+            date: paris
+                .with_ymd_and_hms(2026, 2, 8, 10, 20, 30)
+                .single()
+                .expect("date"),
+            camera_make: None,
+            camera_model: None,
+            lens_make: None,
+            lens_model: None,
+            film_sim: None,
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            extra_tags: Default::default(),
+            original_name: "IMG_0001".to_string(),
+            jpg_path: PathBuf::from("/tmp/IMG_0001.JPG"),
+        };
+
+        // カメラの時計は東京のまま: 壁時計 10:20:30 を +09:00 として読み直す
+        meta.apply_date_zone(
+            DateZone {
+                timezone: Some(tokyo),
+                utc: false,
+            },
+            true,
+        );
+        assert_eq!(meta.date.to_rfc3339(), "2026-02-08T10:20:30+09:00");
+
+        meta.apply_date_zone(
+            DateZone {
+                timezone: None,
+                utc: true,
+            },
+            true,
+        );
+        assert_eq!(meta.date.to_rfc3339(), "2026-02-08T01:20:30+00:00");
+
+        // ファイル更新日時は時刻を保ったまま表示だけ変える
+        meta.apply_date_zone(
+            DateZone {
+                timezone: Some(tokyo),
+                utc: false,
+            },
+            false,
+        );
+        assert_eq!(meta.date.to_rfc3339(), "2026-02-08T10:20:30+09:00");
+    }
+
+    #[test]
+    fn parse_time_shift_reads_units_and_camera_model() {
+        let shift = parse_time_shift("+1h3m").expect("global shift");
+        assert_eq!(shift.camera_model, None);
+        assert_eq!(shift.offset, Duration::seconds(3780));
+
+        let shift = parse_time_shift("X-T5=-1d30s").expect("model shift");
+        assert_eq!(shift.camera_model.as_deref(), Some("X-T5"));
+        assert_eq!(shift.offset, Duration::seconds(-86_430));
+
+        assert!(parse_time_shift("1x").is_err());
+        assert!(parse_time_shift("+").is_err());
+        assert!(parse_time_shift("h").is_err());
+        assert!(parse_time_shift("=+1h").is_err());
+        assert!(parse_time_shift("+36500d").is_ok());
+        assert!(parse_time_shift("+36501d").is_err());
+        assert!(parse_time_shift("-99999999999d").is_err());
+    }
+
+    #[test]
+    fn apply_time_shifts_prefers_matching_camera_model() {
+        let base = Local
+            .with_ymd_and_hms(2026, 2, 8, 10, 0, 0)
+            .single()
+            .expect("date")
+            .fixed_offset();
+        let mut meta = PhotoMetadata {
+            source: MetadataSource::RawExif,
+            date: base,
+            camera_make: None,
+            camera_model: Some("X-T5".to_string()),
+            lens_make: None,
+            lens_model: None,
+            film_sim: None,
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            extra_tags: Default::default(),
+            original_name: "IMG_0001".to_string(),
+            jpg_path: PathBuf::from("/tmp/IMG_0001.JPG"),
+        };
+        let shifts = [
+            TimeShift {
+                camera_model: None,
+                offset: Duration::minutes(5),
+            },
+            TimeShift {
+                camera_model: Some("x-t5".to_string()),
+                offset: Duration::minutes(-63),
+            },
+        ];
+        assert!(meta.apply_time_shifts(&shifts, true));
+        assert_eq!(meta.date, base - Duration::minutes(63));
+
+        meta.date = base;
+        meta.camera_model = Some("X-H2".to_string());
+        assert!(meta.apply_time_shifts(&shifts, true));
+        assert_eq!(meta.date, base + Duration::minutes(5));
+
+        meta.date = base;
+        assert!(meta.apply_time_shifts(&shifts, false));
+        assert_eq!(meta.date, base);
+
+        // 表せる範囲を外れる場合は日付を変えない
+        let latest = DateTime::<FixedOffset>::MAX_UTC.fixed_offset();
+        meta.date = latest;
+        assert!(!meta.apply_time_shifts(&shifts, true));
+        assert_eq!(meta.date, latest);
+    }
+
+    #[test]
+    fn normalize_maker_name_uses_builtin_table_and_user_aliases() {
+        let none = BTreeMap::new();
+        assert_eq!(normalize_maker_name("NIKON CORPORATION", &none), "Nikon");
+        assert_eq!(normalize_maker_name(" sony ", &none), "Sony");
+        assert_eq!(
+            normalize_maker_name("OM Digital Solutions", &none),
+            "OM-System"
+        );
+        assert_eq!(normalize_maker_name("FUJIFILM", &none), "FUJIFILM");
+
+        let aliases = BTreeMap::from([
+            ("fujifilm".to_string(), "Fuji".to_string()),
+            ("SONY".to_string(), "SONY".to_string()),
+        ]);
+        assert_eq!(normalize_maker_name("FUJIFILM", &aliases), "Fuji");
+        assert_eq!(normalize_maker_name("SONY", &aliases), "SONY");
     }
 
     #[test]
     fn merge_missing_from_only_fills_missing_fields() {
-        let now = Local::now();
+        let now = Local::now().fixed_offset();
         let mut base = PartialMetadata {
             date: Some(now),
             camera_make: Some("SONY".to_string()),
@@ -111,6 +899,23 @@ mod tests {
             lens_make: None,
             lens_model: Some("35mm F2".to_string()),
             film_sim: None,
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            gps_date: None,
+            extra_tags: BTreeMap::new(),
+            backend: None,
+            exiftool_messages: Vec::new(),
         };
         let fallback = PartialMetadata {
             date: None,
@@ -119,6 +924,23 @@ mod tests {
             lens_make: Some("FUJIFILM".to_string()),
             lens_model: Some("XF16-55".to_string()),
             film_sim: Some("CLASSIC CHROME".to_string()),
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            gps_date: None,
+            extra_tags: BTreeMap::new(),
+            backend: None,
+            exiftool_messages: Vec::new(),
         };
 
         base.merge_missing_from(&fallback);
@@ -129,4 +951,129 @@ mod tests {
         assert_eq!(base.lens_model.as_deref(), Some("35mm F2"));
         assert_eq!(base.film_sim.as_deref(), Some("CLASSIC CHROME"));
     }
+
+    #[test]
+    fn parse_rating_accepts_integer_and_decimal_values() {
+        assert_eq!(parse_rating("3"), Some(3));
+        assert_eq!(parse_rating(" 4.0 "), Some(4));
+        assert_eq!(parse_rating("-1"), Some(-1));
+        assert_eq!(parse_rating("9"), Some(5));
+        assert_eq!(parse_rating("five"), None);
+    }
+
+    #[test]
+    fn pick_localized_prefers_language_then_default() {
+        let values = BTreeMap::from([
+            ("en-US".to_string(), "Harbor".to_string()),
+            ("ja-JP".to_string(), "港".to_string()),
+            ("x-default".to_string(), "Default".to_string()),
+        ]);
+        assert_eq!(pick_localized(&values, Some("ja")), Some("港"));
+        assert_eq!(pick_localized(&values, Some("EN-us")), Some("Harbor"));
+        assert_eq!(pick_localized(&values, Some("fr")), Some("Default"));
+        assert_eq!(pick_localized(&values, None), Some("Default"));
+
+        let without_default = BTreeMap::from([("de".to_string(), "Hafen".to_string())]);
+        assert_eq!(pick_localized(&without_default, None), Some("Hafen"));
+        assert_eq!(pick_localized(&BTreeMap::new(), Some("ja")), None);
+    }
+
+    #[test]
+    fn field_provenance_keeps_first_source_per_field() {
+        let xmp = PartialMetadata {
+            date: Some(Local::now().fixed_offset()),
+            camera_make: None,
+            camera_model: None,
+            lens_make: None,
+            lens_model: None,
+            film_sim: Some("ACROS".to_string()),
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            gps_date: None,
+            extra_tags: BTreeMap::new(),
+            backend: None,
+            exiftool_messages: Vec::new(),
+        };
+        let raw = PartialMetadata {
+            date: Some(Local::now().fixed_offset()),
+            camera_make: Some("FUJIFILM".to_string()),
+            camera_model: Some("X-T5".to_string()),
+            lens_make: None,
+            lens_model: None,
+            film_sim: Some("PROVIA".to_string()),
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            gps_date: None,
+            extra_tags: BTreeMap::new(),
+            backend: Some(MetadataBackend::Kamadak),
+            exiftool_messages: Vec::new(),
+        };
+
+        let mut provenance = FieldProvenance::default();
+        provenance.record(&xmp, FieldSource::Xmp);
+        provenance.record(&raw, FieldSource::RawExif);
+
+        assert_eq!(provenance.date, Some(FieldSource::Xmp));
+        assert_eq!(provenance.film_sim, Some(FieldSource::Xmp));
+        assert_eq!(provenance.camera_make, Some(FieldSource::RawExif));
+        assert_eq!(provenance.camera_model, Some(FieldSource::RawExif));
+        assert_eq!(provenance.lens_model, None);
+        assert_eq!(provenance.rating, None);
+        // XMPサイドカーはEXIFリーダーを通らないため、RAWを読んだリーダーが残る
+        assert_eq!(provenance.backend, Some(MetadataBackend::Kamadak));
+    }
+
+    #[test]
+    fn field_provenance_records_text_fields_and_extra_tags() {
+        let xmp = PartialMetadata {
+            title: BTreeMap::from([("x-default".to_string(), "夕景".to_string())]),
+            keywords: vec!["海".to_string()],
+            extra_tags: BTreeMap::from([("ISO".to_string(), "200".to_string())]),
+            ..PartialMetadata::default()
+        };
+        let jpg = PartialMetadata {
+            description: BTreeMap::from([("x-default".to_string(), "港にて".to_string())]),
+            keywords: vec!["港".to_string()],
+            extra_tags: BTreeMap::from([
+                ("ISO".to_string(), "400".to_string()),
+                ("LensSerialNumber".to_string(), "12345".to_string()),
+            ]),
+            ..PartialMetadata::default()
+        };
+
+        let mut provenance = FieldProvenance::default();
+        provenance.record(&xmp, FieldSource::Xmp);
+        provenance.record(&jpg, FieldSource::JpgExif);
+
+        assert_eq!(provenance.title, Some(FieldSource::Xmp));
+        assert_eq!(provenance.description, Some(FieldSource::JpgExif));
+        assert_eq!(provenance.keywords, Some(FieldSource::Xmp));
+        assert_eq!(provenance.extra_tags.get("ISO"), Some(&FieldSource::Xmp));
+        assert_eq!(
+            provenance.extra_tags.get("LensSerialNumber"),
+            Some(&FieldSource::JpgExif)
+        );
+    }
 }
@@ -0,0 +1,213 @@
+use crate::config::{app_paths, write_file_atomically};
+use crate::constants::METADATA_CACHE_VERSION;
+use crate::metadata::PartialMetadata;
+use crate::planner::FileFingerprint;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    fingerprint: FileFingerprint,
+    exiftool: bool,
+    #[serde(default)]
+    extra_tags: Vec<String>,
+    metadata: PartialMetadata,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+// EXIFの読み込み結果をサイズ・更新日時と一緒に保存し、ファイルが変わっていなければ再利用する
+#[derive(Debug)]
+pub(crate) struct MetadataCache {
+    path: PathBuf,
+    exiftool: bool,
+    extra_tags: Vec<String>,
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+    dirty: AtomicBool,
+}
+
+impl MetadataCache {
+    pub(crate) fn open_default(exiftool: bool, extra_tags: &[String]) -> Option<Self> {
+        match app_paths() {
+            Ok(paths) => Some(Self::open(
+                paths.config_dir.join("metadata-cache.json"),
+                exiftool,
+                extra_tags,
+            )),
+            Err(err) => {
+                log::warn!("メタデータキャッシュを使用できません ({err})");
+                None
+            }
+        }
+    }
+
+    // exiftool の有無や追加タグで読める項目が変わるため、読み込んだ時と同じ条件の結果だけを使う
+    pub(crate) fn open(path: PathBuf, exiftool: bool, extra_tags: &[String]) -> Self {
+        let entries = match fs::read_to_string(&path) {
+            Ok(raw) => match serde_json::from_str::<CacheFile>(&raw) {
+                Ok(file) if file.version == METADATA_CACHE_VERSION => file.entries,
+                Ok(_) => {
+                    log::debug!("メタデータキャッシュの形式が古いため作り直します");
+                    HashMap::new()
+                }
+                Err(err) => {
+                    log::warn!(
+                        "メタデータキャッシュを読めないため作り直します: {} ({err})",
+                        path.display()
+                    );
+                    HashMap::new()
+                }
+            },
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path,
+            exiftool,
+            extra_tags: extra_tags.to_vec(),
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    // 読み込み前に取った fingerprint を get と insert で共有し、1件あたりの stat を1回にする
+    pub(crate) fn get(
+        &self,
+        path: &Path,
+        fingerprint: &FileFingerprint,
+    ) -> Option<PartialMetadata> {
+        let entries = self.entries.lock().ok()?;
+        let entry = entries.get(path)?;
+        (entry.fingerprint == *fingerprint
+            && entry.exiftool == self.exiftool
+            && entry.extra_tags == self.extra_tags)
+            .then(|| entry.metadata.clone())
+    }
+
+    pub(crate) fn insert(
+        &self,
+        path: &Path,
+        fingerprint: FileFingerprint,
+        metadata: &PartialMetadata,
+    ) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(
+                path.to_path_buf(),
+                CacheEntry {
+                    fingerprint,
+                    exiftool: self.exiftool,
+                    extra_tags: self.extra_tags.clone(),
+                    metadata: metadata.clone(),
+                },
+            );
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // 消えたファイルの分は保存時に落とす
+    pub(crate) fn save(&self) -> Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let entries = self
+            .entries
+            .lock()
+            .map_err(|_| anyhow::anyhow!("メタデータキャッシュのロック取得に失敗しました"))?
+            .iter()
+            .filter(|(path, _)| path.exists())
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect();
+        let body = serde_json::to_string(&CacheFile {
+            version: METADATA_CACHE_VERSION,
+            entries,
+        })
+        .context("メタデータキャッシュのシリアライズに失敗しました")?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!(
+                    "メタデータキャッシュの保存先を作成できませんでした: {}",
+                    parent.display()
+                )
+            })?;
+        }
+        write_file_atomically(&self.path, &body, "メタデータキャッシュ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataCache;
+    use crate::metadata::PartialMetadata;
+    use crate::planner::FileFingerprint;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn sample_metadata() -> PartialMetadata {
+        PartialMetadata {
+            camera_make: Some("FUJIFILM".to_string()),
+            camera_model: Some("X-T5".to_string()),
+            rating: Some(3),
+            ..PartialMetadata::default()
+        }
+    }
+
+    #[test]
+    fn metadata_cache_round_trips_until_file_changes() {
+        let temp = tempdir().expect("tempdir");
+        let cache_path = temp.path().join("cache").join("metadata-cache.json");
+        let photo = temp.path().join("DSCF0001.JPG");
+        fs::write(&photo, b"not-a-real-jpg").expect("jpg file");
+        let fingerprint = FileFingerprint::read(&photo).expect("fingerprint");
+
+        let cache = MetadataCache::open(cache_path.clone(), true, &[]);
+        assert!(cache.get(&photo, &fingerprint).is_none());
+        cache.insert(&photo, fingerprint, &sample_metadata());
+        cache.save().expect("save cache");
+        // 一時ファイルを経由して書き込み、保存先には残さない
+        let saved = fs::read_dir(cache_path.parent().expect("cache dir"))
+            .expect("read cache dir")
+            .map(|entry| entry.expect("dir entry").file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(saved, vec!["metadata-cache.json"]);
+
+        let reopened = MetadataCache::open(cache_path.clone(), true, &[]);
+        let cached = reopened.get(&photo, &fingerprint).expect("cached metadata");
+        assert_eq!(cached.camera_model.as_deref(), Some("X-T5"));
+        assert_eq!(cached.rating, Some(3));
+
+        // exiftool の有無や追加タグが変わった場合は読み直す
+        assert!(MetadataCache::open(cache_path.clone(), false, &[])
+            .get(&photo, &fingerprint)
+            .is_none());
+        assert!(
+            MetadataCache::open(cache_path.clone(), true, &["ISO".to_string()])
+                .get(&photo, &fingerprint)
+                .is_none()
+        );
+
+        fs::write(&photo, b"edited-jpg-with-new-size").expect("rewrite jpg");
+        let edited = FileFingerprint::read(&photo).expect("fingerprint");
+        assert!(reopened.get(&photo, &edited).is_none());
+    }
+
+    #[test]
+    fn metadata_cache_ignores_corrupt_file() {
+        let temp = tempdir().expect("tempdir");
+        let cache_path = temp.path().join("metadata-cache.json");
+        fs::write(&cache_path, b"{not json").expect("corrupt cache");
+        let photo = temp.path().join("DSCF0001.JPG");
+        fs::write(&photo, b"not-a-real-jpg").expect("jpg file");
+
+        let fingerprint = FileFingerprint::read(&photo).expect("fingerprint");
+        let cache = MetadataCache::open(cache_path, true, &[]);
+        assert!(cache.get(&photo, &fingerprint).is_none());
+    }
+}
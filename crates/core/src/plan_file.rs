@@ -0,0 +1,169 @@
+use crate::constants::PLAN_FILE_VERSION;
+use crate::planner::RenamePlan;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+struct PlanFileRef<'a> {
+    version: u32,
+    plan: &'a RenamePlan,
+}
+
+#[derive(Deserialize)]
+struct PlanFile {
+    plan: RenamePlan,
+}
+
+pub fn save_plan_file(plan: &RenamePlan, path: &Path) -> Result<()> {
+    let body = serde_json::to_string_pretty(&PlanFileRef {
+        version: PLAN_FILE_VERSION,
+        plan,
+    })
+    .context("プランのシリアライズに失敗しました")?;
+    fs::write(path, body)
+        .with_context(|| format!("プランファイルを書き込めませんでした: {}", path.display()))
+}
+
+// 手で編集されたプランも受け付けるため、変更有無は読み込み時に計算し直す
+pub fn load_plan_file(path: &Path) -> Result<RenamePlan> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("プランファイルを読めませんでした: {}", path.display()))?;
+    parse_plan_file(&raw).with_context(|| format!("プランファイルが不正です: {}", path.display()))
+}
+
+fn parse_plan_file(raw: &str) -> Result<RenamePlan> {
+    let value: serde_json::Value =
+        serde_json::from_str(raw).context("JSONとして読み込めませんでした")?;
+    let Some(version) = value.get("version").and_then(serde_json::Value::as_u64) else {
+        bail!("version がありません");
+    };
+    if version == 0 || version > u64::from(PLAN_FILE_VERSION) {
+        bail!("対応していないバージョンです: {version} (対応: {PLAN_FILE_VERSION}まで)");
+    }
+    let mut file: PlanFile = serde_json::from_value(value).context("プランの形式が不正です")?;
+
+    let mut seen_targets = HashSet::<PathBuf>::new();
+    for candidate in &mut file.plan.candidates {
+        if candidate.target_path.file_name().is_none() {
+            bail!(
+                "変更後のファイル名がありません: {}",
+                candidate.target_path.display()
+            );
+        }
+        if !seen_targets.insert(candidate.target_path.clone()) {
+            bail!(
+                "変更後のパスが重複しています: {}",
+                candidate.target_path.display()
+            );
+        }
+        candidate.changed = candidate.original_path != candidate.target_path;
+    }
+    Ok(file.plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_plan_file, parse_plan_file, save_plan_file};
+    use crate::metadata::{FieldProvenance, MetadataSource, PhotoMetadata};
+    use crate::planner::{PlanTimings, RenameCandidate, RenamePlan, RenameStats};
+    use chrono::Local;
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    fn sample_plan(original: &Path, target: &Path) -> RenamePlan {
+        RenamePlan {
+            jpg_root: PathBuf::from("/photos"),
+            jpg_roots: vec![PathBuf::from("/photos")],
+            template: "{orig_name}".to_string(),
+            exclusions: Vec::new(),
+            candidates: vec![RenameCandidate {
+                original_path: original.to_path_buf(),
+                target_path: target.to_path_buf(),
+                metadata_source: MetadataSource::JpgExif,
+                source_label: "jpg".to_string(),
+                metadata: PhotoMetadata {
+                    source: MetadataSource::JpgExif,
+                    date: Local::now().fixed_offset(),
+                    camera_make: None,
+                    camera_model: None,
+                    lens_make: None,
+                    lens_model: None,
+                    film_sim: None,
+                    rating: None,
+                    dynamic_range: None,
+                    grain_effect: None,
+                    color_chrome_effect: None,
+                    wb_shift: None,
+                    focal_length_eq: None,
+                    exposure_compensation: None,
+                    flash_fired: None,
+                    image_unique_id: None,
+                    title: Default::default(),
+                    description: Default::default(),
+                    label: None,
+                    keywords: Vec::new(),
+                    extra_tags: Default::default(),
+                    original_name: "IMG_0001".to_string(),
+                    jpg_path: original.to_path_buf(),
+                },
+                rendered_base: "NEW".to_string(),
+                changed: true,
+                warnings: Vec::new(),
+                fingerprint: None,
+                provenance: FieldProvenance::default(),
+                raw_paths: Vec::new(),
+                raw_match: None,
+                xmp_path: None,
+            }],
+            stats: RenameStats::default(),
+            follow_symlinks: false,
+            warnings: Vec::new(),
+            timings: PlanTimings::default(),
+            companion_roots: Vec::new(),
+            exiftool_missing: false,
+            orphaned_companions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn plan_file_round_trips_and_recomputes_changed() {
+        let temp = tempdir().expect("tempdir");
+        let path = temp.path().join("plan.json");
+        let original = Path::new("/photos/IMG_0001.JPG");
+        let mut plan = sample_plan(original, original);
+        plan.candidates[0].changed = true;
+
+        save_plan_file(&plan, &path).expect("save plan");
+        let loaded = load_plan_file(&path).expect("load plan");
+
+        assert_eq!(loaded.candidates.len(), 1);
+        assert_eq!(loaded.candidates[0].original_path, original);
+        assert!(!loaded.candidates[0].changed);
+    }
+
+    #[test]
+    fn parse_plan_file_rejects_unknown_version() {
+        let err = parse_plan_file(r#"{"version": 99, "plan": {}}"#)
+            .expect_err("future version must be rejected");
+        assert!(err.to_string().contains("対応していないバージョン"));
+
+        let err = parse_plan_file(r#"{"plan": {}}"#).expect_err("missing version");
+        assert!(err.to_string().contains("version がありません"));
+    }
+
+    #[test]
+    fn parse_plan_file_rejects_duplicate_targets() {
+        let target = Path::new("/photos/NEW.JPG");
+        let mut plan = sample_plan(Path::new("/photos/IMG_0001.JPG"), target);
+        let mut second = plan.candidates[0].clone();
+        second.original_path = PathBuf::from("/photos/IMG_0002.JPG");
+        plan.candidates.push(second);
+        let raw = serde_json::json!({ "version": 1, "plan": plan }).to_string();
+
+        let err = parse_plan_file(&raw).expect_err("duplicate targets must be rejected");
+        assert!(err.to_string().contains("重複"));
+    }
+}
@@ -1,20 +1,39 @@
-use crate::exif_reader::read_exif_metadata;
-use crate::matcher::{build_raw_match_index, find_matching_raw, find_matching_xmp, RawMatchIndex};
-use crate::metadata::{MetadataSource, PartialMetadata, PhotoMetadata};
+use crate::constants::{
+    default_primary_extensions, default_raw_extensions, DEFAULT_MAX_FILENAME_LEN,
+};
+use crate::exif_reader::{
+    exiftool_available, is_video, read_exif_metadata, ExifPrefetch, ExifTimings,
+};
+use crate::matcher::{
+    build_raw_match_index, double_extension_base_stem, duplicate_base_stem,
+    duplicate_with_extension_base_stem, find_matching_raw, find_matching_xmp, find_sidecar_xmp,
+    MatchedRaw, RawMatchIndex,
+};
+use crate::metadata::{
+    DateZone, FieldProvenance, FieldSource, MetadataSource, PartialMetadata, PhotoMetadata,
+    SourceFilter, TimeShift,
+};
+use crate::metadata_cache::MetadataCache;
 use crate::sanitize::{
     apply_exclusions, cleanup_filename, normalize_spaces_to_underscore, sanitize_filename,
     truncate_filename_if_needed,
 };
-use crate::template::{parse_template, render_template_with_options, TemplatePart};
+use crate::template::{
+    parse_template, render_template_with_options, split_template_segments, MakerNameOptions,
+    TemplatePart, Token,
+};
 use crate::xmp_reader::read_xmp_metadata;
 use crate::DEFAULT_TEMPLATE;
-use anyhow::{Context, Result};
-use chrono::{DateTime, Local};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, Local, TimeDelta};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use walkdir::WalkDir;
 
 #[derive(Debug, Clone)]
@@ -24,10 +43,40 @@ pub struct PlanOptions {
     pub raw_from_jpg_parent_when_missing: bool,
     pub recursive: bool,
     pub include_hidden: bool,
+    pub follow_symlinks: bool,
     pub template: String,
     pub dedupe_same_maker: bool,
     pub exclusions: Vec<String>,
     pub max_filename_len: usize,
+    pub min_rating: Option<i8>,
+    pub min_file_size: u64,
+    pub include_videos: bool,
+    pub raw_primary: bool,
+    pub primary_extensions: Vec<String>,
+    // JPG と対応付ける RAW の拡張子 (優先順)。raw_primary では処理対象の拡張子になる
+    pub raw_extensions: Vec<String>,
+    pub strict_metadata: bool,
+    pub changed_only: bool,
+    pub rename_companions: bool,
+    pub only_source: Option<SourceFilter>,
+    pub metadata_cache: bool,
+    pub maker_aliases: BTreeMap<String, String>,
+    pub dedupe_model_maker: bool,
+    pub date_zone: DateZone,
+    pub time_shifts: Vec<TimeShift>,
+    pub prefer_gps_time: bool,
+    pub extra_exif_tags: Vec<String>,
+    // 名前で RAW が見つからない時に、撮影日時の差がこの範囲の RAW と組み合わせる (None で無効)
+    pub raw_time_tolerance: Option<Duration>,
+    // 名前で RAW が見つからない時に、ImageUniqueID が同じ RAW と組み合わせる (撮影日時より先に試す)
+    pub match_raw_by_unique_id: bool,
+}
+
+impl PlanOptions {
+    // 監視モードなどで、1ファイルがプランの対象になる拡張子かを判定する
+    pub fn is_scan_target(&self, path: &Path) -> bool {
+        ScanTargets::from_options(self).matches(path)
+    }
 }
 
 impl Default for PlanOptions {
@@ -38,10 +87,30 @@ impl Default for PlanOptions {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: DEFAULT_TEMPLATE.to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
-            max_filename_len: 240,
+            max_filename_len: DEFAULT_MAX_FILENAME_LEN,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: BTreeMap::new(),
+            dedupe_model_maker: false,
+            date_zone: DateZone::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         }
     }
 }
@@ -56,20 +125,201 @@ pub struct RenameCandidate {
     pub metadata: PhotoMetadata,
     pub rendered_base: String,
     pub changed: bool,
+    #[serde(default)]
+    pub warnings: Vec<PlanWarning>,
+    #[serde(default)]
+    pub fingerprint: Option<FileFingerprint>,
+    #[serde(default)]
+    pub provenance: FieldProvenance,
+    // 対応付けた RAW (設定の優先順)。DNG と RAF のように同名で複数ある場合はすべて入る
+    #[serde(default)]
+    pub raw_paths: Vec<PathBuf>,
+    // RAW をどう対応付けたか。撮影日時で組み合わせた場合は取り違えがないか確認できるよう残す
+    #[serde(default)]
+    pub raw_match: Option<RawMatchMethod>,
+    // 対応付けた XMP
+    #[serde(default)]
+    pub xmp_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RawMatchMethod {
+    Name,
+    ImageUniqueId,
+    CaptureTime,
 }
 
 fn default_source_label() -> String {
     "jpg".to_string()
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileInspection {
+    pub xmp_path: Option<PathBuf>,
+    pub raw_path: Option<PathBuf>,
+    pub candidate: RenameCandidate,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileFingerprint {
+    pub size: u64,
+    pub modified_unix_ms: Option<i64>,
+}
+
+impl FileFingerprint {
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified_unix_ms = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as i64);
+        Some(Self {
+            size: metadata.len(),
+            modified_unix_ms,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlanWarning {
+    ExifReadFailed { path: PathBuf, message: String },
+    ExiftoolMessage { path: PathBuf, message: String },
+    XmpReadFailed { path: PathBuf, message: String },
+    FilmSimMissing,
+    DateFromFileModified,
+    RawMatchedByCaptureTime { path: PathBuf, difference_ms: i64 },
+    TimeShiftOutOfRange,
+}
+
+impl fmt::Display for PlanWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanWarning::ExifReadFailed { path, message } => {
+                write!(f, "EXIFを読めませんでした: {} ({message})", path.display())
+            }
+            PlanWarning::ExiftoolMessage { path, message } => {
+                write!(f, "exiftoolの警告: {} ({message})", path.display())
+            }
+            PlanWarning::XmpReadFailed { path, message } => {
+                write!(f, "XMPを読めませんでした: {} ({message})", path.display())
+            }
+            PlanWarning::FilmSimMissing => write!(f, "フィルムシミュレーションが見つかりません"),
+            PlanWarning::DateFromFileModified => {
+                write!(f, "撮影日時がないためファイル更新日時を使用しました")
+            }
+            PlanWarning::RawMatchedByCaptureTime {
+                path,
+                difference_ms,
+            } => write!(
+                f,
+                "RAWを撮影日時で対応付けました: {} (差 {difference_ms}ms)",
+                path.display()
+            ),
+            PlanWarning::TimeShiftOutOfRange => {
+                write!(
+                    f,
+                    "時刻のずれを適用すると日付が範囲外になるため補正しませんでした"
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanWarningEntry {
+    pub original_path: PathBuf,
+    pub warning: PlanWarning,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct RenameStats {
     pub scanned_files: usize,
     pub jpg_files: usize,
     pub skipped_non_jpg: usize,
     pub skipped_hidden: usize,
+    #[serde(default)]
+    pub skipped_below_rating: usize,
+    #[serde(default)]
+    pub skipped_small_files: usize,
+    #[serde(default)]
+    pub skipped_missing_date: usize,
+    #[serde(default)]
+    pub skipped_other_source: usize,
     pub planned: usize,
     pub unchanged: usize,
+    #[serde(default)]
+    pub by_camera: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub by_film_sim: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub by_source: BTreeMap<MetadataSource, usize>,
+}
+
+const UNKNOWN_STATS_KEY: &str = "(不明)";
+
+fn stats_key(value: Option<&str>) -> String {
+    value
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .unwrap_or(UNKNOWN_STATS_KEY)
+        .to_string()
+}
+
+impl RenameStats {
+    fn record_metadata(&mut self, metadata: &PhotoMetadata) {
+        *self
+            .by_camera
+            .entry(stats_key(metadata.camera_model.as_deref()))
+            .or_default() += 1;
+        *self
+            .by_film_sim
+            .entry(stats_key(metadata.film_sim.as_deref()))
+            .or_default() += 1;
+        *self.by_source.entry(metadata.source).or_default() += 1;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FolderStats {
+    pub scanned_files: usize,
+    pub photos: usize,
+    pub skipped_below_rating: usize,
+    pub by_camera: BTreeMap<String, usize>,
+    pub by_lens: BTreeMap<String, usize>,
+    pub by_film_sim: BTreeMap<String, usize>,
+    pub by_source: BTreeMap<MetadataSource, usize>,
+    pub earliest: Option<DateTime<FixedOffset>>,
+    pub latest: Option<DateTime<FixedOffset>>,
+}
+
+impl FolderStats {
+    fn record_metadata(&mut self, metadata: &PhotoMetadata) {
+        self.photos += 1;
+        *self
+            .by_camera
+            .entry(stats_key(metadata.camera_model.as_deref()))
+            .or_default() += 1;
+        *self
+            .by_lens
+            .entry(stats_key(metadata.lens_model.as_deref()))
+            .or_default() += 1;
+        *self
+            .by_film_sim
+            .entry(stats_key(metadata.film_sim.as_deref()))
+            .or_default() += 1;
+        *self.by_source.entry(metadata.source).or_default() += 1;
+        // ファイル更新日時で補った日付は撮影期間に含めない
+        if metadata.source != MetadataSource::FallbackFileModified {
+            self.earliest = Some(
+                self.earliest
+                    .map_or(metadata.date, |v| v.min(metadata.date)),
+            );
+            self.latest = Some(self.latest.map_or(metadata.date, |v| v.max(metadata.date)));
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,6 +331,77 @@ pub struct RenamePlan {
     pub exclusions: Vec<String>,
     pub candidates: Vec<RenameCandidate>,
     pub stats: RenameStats,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub warnings: Vec<PlanWarningEntry>,
+    #[serde(default)]
+    pub timings: PlanTimings,
+    #[serde(default)]
+    pub companion_roots: Vec<PathBuf>,
+    // exiftool が見つからず kamadak-exif のみで読んだ場合。フィルムシミュレーション等が欠けやすい
+    #[serde(default)]
+    pub exiftool_missing: bool,
+    // RAW フォルダにあって、どの JPG とも対応しなかった RAW/XMP (書き出し漏れや名前の打ち間違いの確認用)
+    #[serde(default)]
+    pub orphaned_companions: Vec<PathBuf>,
+}
+
+impl RenamePlan {
+    // 変更のある先頭N枚だけを残す。RAW/XMPなどの同伴ファイルは親の写真と一緒に数える
+    pub fn limit_changed(&self, limit: usize) -> RenamePlan {
+        let mut kept = HashSet::<&Path>::new();
+        let mut candidates = Vec::new();
+        for candidate in self.candidates.iter().filter(|c| c.changed) {
+            let photo = candidate.metadata.jpg_path.as_path();
+            if !kept.contains(photo) {
+                if kept.len() >= limit {
+                    continue;
+                }
+                kept.insert(photo);
+            }
+            candidates.push(candidate.clone());
+        }
+        RenamePlan {
+            candidates,
+            ..self.clone()
+        }
+    }
+}
+
+// metadata_ms/exiftool_ms/kamadak_ms/render_ms は並列ワーカーの合計時間
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct PlanTimings {
+    pub scan_ms: u64,
+    pub metadata_ms: u64,
+    pub exiftool_ms: u64,
+    pub kamadak_ms: u64,
+    pub render_ms: u64,
+    pub total_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct PlanTimers {
+    exif: ExifTimings,
+    metadata_nanos: AtomicU64,
+    render_nanos: AtomicU64,
+}
+
+impl PlanTimers {
+    fn add(counter: &AtomicU64, elapsed: Duration) {
+        counter.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn to_timings(&self, scan_elapsed: Duration, total_elapsed: Duration) -> PlanTimings {
+        PlanTimings {
+            scan_ms: scan_elapsed.as_millis() as u64,
+            metadata_ms: self.metadata_nanos.load(Ordering::Relaxed) / 1_000_000,
+            exiftool_ms: self.exif.exiftool_ms(),
+            kamadak_ms: self.exif.kamadak_ms(),
+            render_ms: self.render_nanos.load(Ordering::Relaxed) / 1_000_000,
+            total_ms: total_elapsed.as_millis() as u64,
+        }
+    }
 }
 
 fn default_jpg_roots() -> Vec<PathBuf> {
@@ -94,21 +415,105 @@ struct PreparedCandidate {
     source_label: String,
     rendered_base: String,
     extension: String,
+    target_dir: PathBuf,
+    sidecar_paths: Vec<PathBuf>,
+    raw_paths: Vec<PathBuf>,
+    raw_match: Option<RawMatchMethod>,
+    xmp_path: Option<PathBuf>,
+    warnings: Vec<PlanWarning>,
+    fingerprint: Option<FileFingerprint>,
+    provenance: FieldProvenance,
 }
 
 #[derive(Debug)]
 struct ResolvedMetadata {
     metadata: PhotoMetadata,
     source_label: String,
+    warnings: Vec<PlanWarning>,
+    provenance: FieldProvenance,
+    fallback_raw: Option<(MatchedRaw, RawMatchMethod)>,
+    // RAW フォルダ未指定時に JPG の隣で見つけた XMP
+    sidecar_xmp: Option<PathBuf>,
 }
 
-struct PrepareContext<'a> {
+impl ResolvedMetadata {
+    // 時計のずれはカメラの時刻に対する補正なので、タイムゾーンの変換より先に適用する
+    fn with_date_adjustments(mut self, shifts: &[TimeShift], zone: DateZone) -> Self {
+        let camera_clock = !matches!(
+            self.provenance.date,
+            Some(FieldSource::Gps | FieldSource::FileModified)
+        );
+        if !self.metadata.apply_time_shifts(shifts, camera_clock) {
+            self.warnings.push(PlanWarning::TimeShiftOutOfRange);
+        }
+        self.metadata.apply_date_zone(zone, camera_clock);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ResolveOptions<'a> {
     recursive: bool,
+    prefer_gps_time: bool,
+    raw_extensions: &'a [String],
+    raw_time_tolerance: Option<TimeDelta>,
+    match_raw_by_unique_id: bool,
+}
+
+impl<'a> ResolveOptions<'a> {
+    fn from_options(options: &'a PlanOptions) -> Self {
+        Self {
+            recursive: options.recursive,
+            prefer_gps_time: options.prefer_gps_time,
+            raw_extensions: &options.raw_extensions,
+            raw_time_tolerance: options
+                .raw_time_tolerance
+                .and_then(|tolerance| TimeDelta::from_std(tolerance).ok()),
+            match_raw_by_unique_id: options.match_raw_by_unique_id,
+        }
+    }
+}
+
+struct PrepareContext<'a> {
+    resolve: ResolveOptions<'a>,
     parts: &'a [TemplatePart],
-    dedupe_same_maker: bool,
+    maker: MakerNameOptions<'a>,
+    date_zone: DateZone,
+    time_shifts: &'a [TimeShift],
     exclusions: &'a [String],
     max_filename_len: usize,
+    raw_primary: bool,
+    rename_companions: bool,
+    uses_film_sim: bool,
     raw_match_indexes: HashMap<MatchIndexKey, RawMatchIndex>,
+    timers: PlanTimers,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScanTargets<'a> {
+    primary_extensions: &'a [String],
+    raw_extensions: &'a [String],
+    include_videos: bool,
+    raw_primary: bool,
+}
+
+impl<'a> ScanTargets<'a> {
+    fn from_options(options: &'a PlanOptions) -> Self {
+        Self {
+            primary_extensions: &options.primary_extensions,
+            raw_extensions: &options.raw_extensions,
+            include_videos: options.include_videos,
+            raw_primary: options.raw_primary,
+        }
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        if self.raw_primary {
+            return has_primary_extension(path, self.raw_extensions);
+        }
+        has_primary_extension(path, self.primary_extensions)
+            || (self.include_videos && is_video(path))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -125,15 +530,70 @@ struct PreparedInput {
     raw_match_key: Option<MatchIndexKey>,
 }
 
+pub trait ProgressSink: Sync {
+    fn files_scanned(&self, scanned_files: usize);
+    // 走査の最後に1回だけ呼ばれる。間引いた途中経過では最終件数が出ないため
+    fn scan_finished(&self, _scanned_files: usize) {}
+    fn metadata_read(&self, done: usize, total: usize);
+    fn files_applied(&self, _done: usize, _total: usize) {}
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PlanControl<'a> {
+    pub progress: Option<&'a dyn ProgressSink>,
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl PlanControl<'_> {
+    fn check_cancelled(&self) -> Result<()> {
+        if self
+            .cancel
+            .map(|cancel| cancel.load(Ordering::Relaxed))
+            .unwrap_or(false)
+        {
+            anyhow::bail!("プラン生成がキャンセルされました");
+        }
+        Ok(())
+    }
+
+    fn report_scanned(&self, scanned_files: usize) {
+        if let Some(progress) = self.progress {
+            progress.files_scanned(scanned_files);
+        }
+    }
+
+    fn report_scan_finished(&self, scanned_files: usize) {
+        if let Some(progress) = self.progress {
+            progress.scan_finished(scanned_files);
+        }
+    }
+
+    fn report_metadata_read(&self, done: usize, total: usize) {
+        if let Some(progress) = self.progress {
+            progress.metadata_read(done, total);
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ResolvedJpgInput {
     jpg_root: PathBuf,
     jpg_roots: Vec<PathBuf>,
     jpg_files: Vec<PathBuf>,
     jpg_root_by_file: HashMap<PathBuf, PathBuf>,
+    scan_elapsed: Duration,
+    // フォルダ全体を対象にした場合だけ、対応の無い RAW/XMP を数える
+    whole_folder: bool,
 }
 
 pub fn generate_plan(options: &PlanOptions) -> Result<RenamePlan> {
+    generate_plan_with_control(options, PlanControl::default())
+}
+
+pub fn generate_plan_with_control(
+    options: &PlanOptions,
+    control: PlanControl<'_>,
+) -> Result<RenamePlan> {
     validate_raw_input(options.raw_input.as_ref())?;
 
     let mut stats = RenameStats::default();
@@ -141,22 +601,111 @@ pub fn generate_plan(options: &PlanOptions) -> Result<RenamePlan> {
         &options.jpg_input,
         options.recursive,
         options.include_hidden,
+        options.follow_symlinks,
+        ScanTargets::from_options(options),
+        control,
         &mut stats,
     )?;
 
-    generate_plan_with_resolved_jpg_input(options, resolved_jpg_input, stats)
+    generate_plan_with_resolved_jpg_input(options, resolved_jpg_input, stats, control)
 }
 
 pub fn generate_plan_for_jpg_files(
     options: &PlanOptions,
     jpg_files: &[PathBuf],
+) -> Result<RenamePlan> {
+    generate_plan_for_jpg_files_with_control(options, jpg_files, PlanControl::default())
+}
+
+// 1ファイル分のプランと、対応付けたXMP/RAWのパスを返す (名前が想定と違う時の調査用)
+pub fn inspect_jpg_file(options: &PlanOptions, jpg_path: &Path) -> Result<FileInspection> {
+    let options = PlanOptions {
+        changed_only: false,
+        only_source: None,
+        ..options.clone()
+    };
+    let plan = generate_plan_for_jpg_files(&options, &[jpg_path.to_path_buf()])?;
+    let Some(candidate) = plan.candidates.into_iter().next() else {
+        bail!(
+            "対象外のファイルです (レーティングやサイズの条件で除外されました): {}",
+            jpg_path.display()
+        );
+    };
+
+    // 撮影日時や ImageUniqueID で対応付けた場合も含め、プランが実際に使ったファイルを返す
+    Ok(FileInspection {
+        xmp_path: candidate.xmp_path.clone(),
+        raw_path: candidate.raw_paths.first().cloned(),
+        candidate,
+    })
+}
+
+pub fn generate_plan_for_jpg_files_with_control(
+    options: &PlanOptions,
+    jpg_files: &[PathBuf],
+    control: PlanControl<'_>,
+) -> Result<RenamePlan> {
+    validate_raw_input(options.raw_input.as_ref())?;
+
+    let mut stats = RenameStats::default();
+    let resolved_jpg_input = resolve_explicit_jpg_files(jpg_files, options, control, &mut stats)?;
+    control.report_scanned(stats.scanned_files);
+
+    generate_plan_with_resolved_jpg_input(options, resolved_jpg_input, stats, control)
+}
+
+// 完成したプランの候補を batch_size 件ずつ on_batch に渡す。連番を撮影順に振るため
+// 渡し始めるのは全件を読み終えてからで、メモリ使用量は generate_plan と変わらない
+pub fn generate_plan_chunked(
+    options: &PlanOptions,
+    control: PlanControl<'_>,
+    batch_size: usize,
+    mut on_batch: impl FnMut(Vec<RenameCandidate>) -> Result<()>,
+) -> Result<RenamePlan> {
+    validate_raw_input(options.raw_input.as_ref())?;
+
+    let mut stats = RenameStats::default();
+    let resolved_jpg_input = resolve_jpg_input(
+        &options.jpg_input,
+        options.recursive,
+        options.include_hidden,
+        options.follow_symlinks,
+        ScanTargets::from_options(options),
+        control,
+        &mut stats,
+    )?;
+
+    chunk_plan_with_resolved_jpg_input(
+        options,
+        resolved_jpg_input,
+        stats,
+        control,
+        batch_size,
+        &mut on_batch,
+    )
+}
+
+pub fn generate_plan_for_jpg_files_chunked(
+    options: &PlanOptions,
+    jpg_files: &[PathBuf],
+    control: PlanControl<'_>,
+    batch_size: usize,
+    mut on_batch: impl FnMut(Vec<RenameCandidate>) -> Result<()>,
 ) -> Result<RenamePlan> {
     validate_raw_input(options.raw_input.as_ref())?;
 
     let mut stats = RenameStats::default();
-    let resolved_jpg_input = resolve_explicit_jpg_files(jpg_files, &mut stats)?;
+    let resolved_jpg_input = resolve_explicit_jpg_files(jpg_files, options, control, &mut stats)?;
+    control.report_scanned(stats.scanned_files);
 
-    generate_plan_with_resolved_jpg_input(options, resolved_jpg_input, stats)
+    chunk_plan_with_resolved_jpg_input(
+        options,
+        resolved_jpg_input,
+        stats,
+        control,
+        batch_size,
+        &mut on_batch,
+    )
 }
 
 fn validate_raw_input(raw_input: Option<&PathBuf>) -> Result<()> {
@@ -173,13 +722,257 @@ fn validate_raw_input(raw_input: Option<&PathBuf>) -> Result<()> {
 }
 
 fn generate_plan_with_resolved_jpg_input(
+    options: &PlanOptions,
+    resolved_jpg_input: ResolvedJpgInput,
+    stats: RenameStats,
+    control: PlanControl<'_>,
+) -> Result<RenamePlan> {
+    let mut candidates = Vec::new();
+    let mut plan = chunk_plan_with_resolved_jpg_input(
+        options,
+        resolved_jpg_input,
+        stats,
+        control,
+        usize::MAX,
+        &mut |batch| {
+            candidates.extend(batch);
+            Ok(())
+        },
+    )?;
+    plan.candidates = candidates;
+    Ok(plan)
+}
+
+fn chunk_plan_with_resolved_jpg_input(
     options: &PlanOptions,
     resolved_jpg_input: ResolvedJpgInput,
     mut stats: RenameStats,
+    control: PlanControl<'_>,
+    batch_size: usize,
+    on_batch: &mut dyn FnMut(Vec<RenameCandidate>) -> Result<()>,
 ) -> Result<RenamePlan> {
+    let started = Instant::now();
+    control.report_scan_finished(stats.scanned_files);
     let parts = parse_template(&options.template)?;
-    let prepared_inputs = resolved_jpg_input
-        .jpg_files
+    let jpg_files = filter_small_files(
+        &resolved_jpg_input.jpg_files,
+        options.min_file_size,
+        &mut stats,
+    )?;
+    let (prepared_inputs, raw_match_indexes) =
+        prepare_inputs(options, &resolved_jpg_input, &jpg_files);
+
+    // JPG のフォルダ以外で RAW/XMP を動かしうるフォルダ。実際に動かすものがあるものだけを計画に残す
+    let mut companion_root_candidates = Vec::<PathBuf>::new();
+    if options.rename_companions && !options.raw_primary {
+        for key in raw_match_indexes.keys() {
+            if !resolved_jpg_input.jpg_roots.contains(&key.raw_root)
+                && !companion_root_candidates.contains(&key.raw_root)
+            {
+                companion_root_candidates.push(key.raw_root.clone());
+            }
+        }
+    }
+    let mut companion_roots = BTreeSet::<PathBuf>::new();
+
+    let prepare_context = PrepareContext {
+        resolve: ResolveOptions::from_options(options),
+        parts: &parts,
+        maker: MakerNameOptions {
+            dedupe_same_maker: options.dedupe_same_maker,
+            dedupe_model_maker: options.dedupe_model_maker,
+            aliases: &options.maker_aliases,
+        },
+        date_zone: options.date_zone,
+        time_shifts: &options.time_shifts,
+        exclusions: &options.exclusions,
+        max_filename_len: options.max_filename_len,
+        raw_primary: options.raw_primary,
+        rename_companions: options.rename_companions,
+        uses_film_sim: parts
+            .iter()
+            .any(|part| matches!(part, TemplatePart::Token(Token::FilmSim))),
+        raw_match_indexes,
+        timers: PlanTimers::default(),
+    };
+    let total = prepared_inputs.len();
+    let metadata_read = AtomicUsize::new(0);
+    let mut planned_paths = HashSet::<PathBuf>::new();
+    let mut claimed_sidecars = HashSet::<PathBuf>::new();
+    let mut matched_companions = HashSet::<PathBuf>::new();
+    let mut plan_warnings = Vec::new();
+    let mut accepted = Vec::new();
+    let cache = open_metadata_cache(options);
+    for chunk in prepared_inputs.chunks(batch_size.max(1)) {
+        control.check_cancelled()?;
+        let prefetch = ExifPrefetch::load(
+            &exif_prefetch_paths(chunk, &prepare_context.raw_match_indexes),
+            cache.as_ref(),
+            &options.extra_exif_tags,
+            &prepare_context.timers.exif,
+        );
+        let prepared_results: Vec<Result<PreparedCandidate>> = chunk
+            .par_iter()
+            .map(|prepared_input| {
+                control.check_cancelled()?;
+                let result = prepare_candidate(&prepare_context, prepared_input, &prefetch);
+                let done = metadata_read.fetch_add(1, Ordering::Relaxed) + 1;
+                control.report_metadata_read(done, total);
+                result
+            })
+            .collect();
+
+        for result in prepared_results {
+            let mut prepared = result?;
+            // 条件で除外した写真の RAW/XMP も対応先はあるので、対応の無いファイルには数えない
+            matched_companions.extend(prepared.raw_paths.iter().chain(&prepared.xmp_path).cloned());
+            if !meets_min_rating(prepared.metadata.rating, options.min_rating) {
+                stats.skipped_below_rating += 1;
+                continue;
+            }
+            if options.strict_metadata
+                && prepared.metadata.source == MetadataSource::FallbackFileModified
+            {
+                stats.skipped_missing_date += 1;
+                continue;
+            }
+            if options
+                .only_source
+                .is_some_and(|filter| !filter.matches(prepared.metadata.source))
+            {
+                stats.skipped_other_source += 1;
+                continue;
+            }
+
+            // 同名のDNG/RAFが並ぶ場合、XMPは先にマッチした方だけが連れて行く
+            prepared
+                .sidecar_paths
+                .retain(|sidecar_path| claimed_sidecars.insert(sidecar_path.clone()));
+            accepted.push(prepared);
+        }
+    }
+    save_metadata_cache(cache.as_ref());
+
+    // 連番を撮影順に振るため、全件のメタデータを読んでから新しい名前を決める
+    let targets = assign_targets(&accepted, &mut planned_paths, options.max_filename_len)?;
+    let mut candidates = Vec::new();
+    for (prepared, target) in accepted.into_iter().zip(targets) {
+        let changed = target != prepared.original_path;
+        if !changed {
+            stats.unchanged += 1;
+        }
+
+        let sidecar_candidates = prepared
+            .sidecar_paths
+            .into_iter()
+            .map(|sidecar_path| {
+                let sidecar_target =
+                    sidecar_target_path(&prepared.original_path, &target, &sidecar_path);
+                let changed = sidecar_target != sidecar_path;
+                let fingerprint = FileFingerprint::read(&sidecar_path);
+                let source_label = sidecar_path
+                    .extension()
+                    .map(|v| v.to_string_lossy().to_ascii_lowercase())
+                    .unwrap_or_default();
+                RenameCandidate {
+                    original_path: sidecar_path,
+                    target_path: sidecar_target,
+                    metadata_source: prepared.metadata.source,
+                    source_label,
+                    metadata: prepared.metadata.clone(),
+                    rendered_base: prepared.rendered_base.clone(),
+                    changed,
+                    warnings: Vec::new(),
+                    fingerprint,
+                    provenance: prepared.provenance.clone(),
+                    raw_paths: Vec::new(),
+                    raw_match: None,
+                    xmp_path: None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        stats.planned += 1;
+        stats.record_metadata(&prepared.metadata);
+        plan_warnings.extend(prepared.warnings.iter().map(|warning| PlanWarningEntry {
+            original_path: prepared.original_path.clone(),
+            warning: warning.clone(),
+        }));
+        if changed || !options.changed_only {
+            candidates.push(RenameCandidate {
+                original_path: prepared.original_path,
+                target_path: target,
+                metadata_source: prepared.metadata.source,
+                source_label: prepared.source_label,
+                metadata: prepared.metadata,
+                rendered_base: prepared.rendered_base,
+                changed,
+                warnings: prepared.warnings,
+                fingerprint: prepared.fingerprint,
+                provenance: prepared.provenance,
+                raw_paths: prepared.raw_paths,
+                raw_match: prepared.raw_match,
+                xmp_path: prepared.xmp_path,
+            });
+        }
+
+        for sidecar_candidate in sidecar_candidates {
+            if !sidecar_candidate.changed {
+                stats.unchanged += 1;
+            } else if let Some(root) = companion_root_candidates
+                .iter()
+                .find(|root| sidecar_candidate.original_path.starts_with(root))
+            {
+                companion_roots.insert(root.clone());
+            }
+            stats.planned += 1;
+            if sidecar_candidate.changed || !options.changed_only {
+                candidates.push(sidecar_candidate);
+            }
+        }
+
+        if candidates.len() >= batch_size {
+            on_batch(std::mem::take(&mut candidates))?;
+        }
+    }
+    if !candidates.is_empty() {
+        on_batch(candidates)?;
+    }
+
+    let mut orphaned_companions = Vec::new();
+    if resolved_jpg_input.whole_folder && !options.raw_primary {
+        for index in prepare_context.raw_match_indexes.values() {
+            orphaned_companions.extend(index.unmatched_files(&matched_companions));
+        }
+        orphaned_companions.sort();
+        orphaned_companions.dedup();
+    }
+
+    Ok(RenamePlan {
+        jpg_root: resolved_jpg_input.jpg_root,
+        jpg_roots: resolved_jpg_input.jpg_roots,
+        template: options.template.clone(),
+        exclusions: options.exclusions.clone(),
+        candidates: Vec::new(),
+        stats,
+        follow_symlinks: options.follow_symlinks,
+        warnings: plan_warnings,
+        timings: prepare_context.timers.to_timings(
+            resolved_jpg_input.scan_elapsed,
+            resolved_jpg_input.scan_elapsed + started.elapsed(),
+        ),
+        companion_roots: companion_roots.into_iter().collect(),
+        exiftool_missing: !exiftool_available(),
+        orphaned_companions,
+    })
+}
+
+fn prepare_inputs(
+    options: &PlanOptions,
+    resolved_jpg_input: &ResolvedJpgInput,
+    jpg_files: &[PathBuf],
+) -> (Vec<PreparedInput>, HashMap<MatchIndexKey, RawMatchIndex>) {
+    let prepared_inputs = jpg_files
         .iter()
         .map(|jpg_path| {
             let jpg_root_for_file = resolved_jpg_input
@@ -187,11 +980,15 @@ fn generate_plan_with_resolved_jpg_input(
                 .get(jpg_path)
                 .cloned()
                 .unwrap_or_else(|| resolved_jpg_input.jpg_root.clone());
-            let raw_root_for_file = resolve_raw_root_for_file(
-                options.raw_input.as_ref(),
-                options.raw_from_jpg_parent_when_missing,
-                &jpg_root_for_file,
-            );
+            let raw_root_for_file = if options.raw_primary {
+                Some(jpg_root_for_file.clone())
+            } else {
+                resolve_raw_root_for_file(
+                    options.raw_input.as_ref(),
+                    options.raw_from_jpg_parent_when_missing,
+                    &jpg_root_for_file,
+                )
+            };
             PreparedInput {
                 jpg_path: jpg_path.clone(),
                 jpg_root: jpg_root_for_file,
@@ -210,92 +1007,179 @@ fn generate_plan_with_resolved_jpg_input(
                     jpg_root: prepared_input.jpg_root.clone(),
                     raw_root: raw_root_for_file.clone(),
                 };
-                raw_match_indexes.entry(key.clone()).or_insert_with(|| {
-                    build_raw_match_index(&key.jpg_root, &key.raw_root, options.recursive)
-                });
+                raw_match_indexes
+                    .entry(key.clone())
+                    .or_insert_with(|| {
+                        build_raw_match_index(
+                            &key.jpg_root,
+                            &key.raw_root,
+                            options.recursive,
+                            options.follow_symlinks,
+                            &options.raw_extensions,
+                        )
+                    })
+                    .register_jpg(prepared_input.jpg_path.clone());
                 prepared_input.raw_match_key = Some(key);
             }
             prepared_input
         })
         .collect::<Vec<_>>();
 
-    let prepare_context = PrepareContext {
-        recursive: options.recursive,
-        parts: &parts,
-        dedupe_same_maker: options.dedupe_same_maker,
-        exclusions: &options.exclusions,
-        max_filename_len: options.max_filename_len,
-        raw_match_indexes,
-    };
-    let prepared_results: Vec<Result<PreparedCandidate>> = prepared_inputs
+    (prepared_inputs, raw_match_indexes)
+}
+
+// 新ファイル名は作らず、メタデータの解決だけを行ってフォルダの内訳を集計する
+pub fn collect_folder_stats(
+    options: &PlanOptions,
+    control: PlanControl<'_>,
+) -> Result<FolderStats> {
+    validate_raw_input(options.raw_input.as_ref())?;
+
+    let mut stats = RenameStats::default();
+    let resolved_jpg_input = resolve_jpg_input(
+        &options.jpg_input,
+        options.recursive,
+        options.include_hidden,
+        options.follow_symlinks,
+        ScanTargets::from_options(options),
+        control,
+        &mut stats,
+    )?;
+    let jpg_files = filter_small_files(
+        &resolved_jpg_input.jpg_files,
+        options.min_file_size,
+        &mut stats,
+    )?;
+    let (prepared_inputs, raw_match_indexes) =
+        prepare_inputs(options, &resolved_jpg_input, &jpg_files);
+
+    let exif_timings = ExifTimings::default();
+    let cache = open_metadata_cache(options);
+    let prefetch = ExifPrefetch::load(
+        &exif_prefetch_paths(&prepared_inputs, &raw_match_indexes),
+        cache.as_ref(),
+        &options.extra_exif_tags,
+        &exif_timings,
+    );
+    let total = prepared_inputs.len();
+    let metadata_read = AtomicUsize::new(0);
+    let resolved = prepared_inputs
         .par_iter()
-        .map(|prepared_input| prepare_candidate(&prepare_context, prepared_input))
-        .collect();
+        .map(|prepared_input| {
+            control.check_cancelled()?;
+            let raw_match_index = prepared_input
+                .raw_match_key
+                .as_ref()
+                .and_then(|key| raw_match_indexes.get(key));
+            let result = resolve_metadata(
+                &prepared_input.jpg_root,
+                prepared_input.raw_root.as_deref(),
+                raw_match_index,
+                &prepared_input.jpg_path,
+                ResolveOptions::from_options(options),
+                &prefetch,
+                &exif_timings,
+            )
+            .map(|resolved| {
+                resolved.with_date_adjustments(&options.time_shifts, options.date_zone)
+            });
+            let done = metadata_read.fetch_add(1, Ordering::Relaxed) + 1;
+            control.report_metadata_read(done, total);
+            result
+        })
+        .collect::<Result<Vec<_>>>()?;
+    save_metadata_cache(cache.as_ref());
 
-    let mut prepared = Vec::with_capacity(prepared_results.len());
-    for result in prepared_results {
-        prepared.push(result?);
+    let mut folder_stats = FolderStats {
+        scanned_files: stats.scanned_files,
+        ..FolderStats::default()
+    };
+    for resolved in &resolved {
+        if !meets_min_rating(resolved.metadata.rating, options.min_rating) {
+            folder_stats.skipped_below_rating += 1;
+            continue;
+        }
+        folder_stats.record_metadata(&resolved.metadata);
     }
+    Ok(folder_stats)
+}
 
-    let mut candidates = Vec::with_capacity(prepared.len());
-    let mut planned_paths = HashSet::<PathBuf>::new();
-    for prepared in prepared {
-        let target = resolve_collision(
-            &prepared.original_path,
-            &prepared.rendered_base,
-            &prepared.extension,
-            &mut planned_paths,
-            options.max_filename_len,
-        )?;
+fn open_metadata_cache(options: &PlanOptions) -> Option<MetadataCache> {
+    if !options.metadata_cache {
+        return None;
+    }
+    MetadataCache::open_default(exiftool_available(), &options.extra_exif_tags)
+}
 
-        let changed = target != prepared.original_path;
-        if !changed {
-            stats.unchanged += 1;
-        }
-
-        stats.planned += 1;
-        candidates.push(RenameCandidate {
-            original_path: prepared.original_path,
-            target_path: target,
-            metadata_source: prepared.metadata.source,
-            source_label: prepared.source_label,
-            metadata: prepared.metadata,
-            rendered_base: prepared.rendered_base,
-            changed,
-        });
+fn save_metadata_cache(cache: Option<&MetadataCache>) {
+    if let Some(Err(err)) = cache.map(MetadataCache::save) {
+        log::warn!("メタデータキャッシュを保存できませんでした ({err:#})");
     }
+}
 
-    Ok(RenamePlan {
-        jpg_root: resolved_jpg_input.jpg_root,
-        jpg_roots: resolved_jpg_input.jpg_roots,
-        template: options.template.clone(),
-        exclusions: options.exclusions.clone(),
-        candidates,
-        stats,
-    })
+// exiftool をまとめて呼ぶため、読む可能性のあるJPGと対応するRAWのパスを先に集める
+fn exif_prefetch_paths(
+    inputs: &[PreparedInput],
+    raw_match_indexes: &HashMap<MatchIndexKey, RawMatchIndex>,
+) -> Vec<PathBuf> {
+    let mut paths = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        paths.push(input.jpg_path.clone());
+        if let Some(raw_path) = input
+            .raw_match_key
+            .as_ref()
+            .and_then(|key| raw_match_indexes.get(key))
+            .and_then(|index| index.find_raw(&input.jpg_path))
+        {
+            paths.push(raw_path);
+        }
+    }
+    paths
 }
 
 fn prepare_candidate(
     context: &PrepareContext<'_>,
     prepared_input: &PreparedInput,
+    prefetch: &ExifPrefetch<'_>,
 ) -> Result<PreparedCandidate> {
     let raw_match_index = prepared_input
         .raw_match_key
         .as_ref()
         .and_then(|key| context.raw_match_indexes.get(key));
-    let resolved = resolve_metadata(
+    let metadata_started = Instant::now();
+    let mut resolved = resolve_metadata(
         &prepared_input.jpg_root,
         prepared_input.raw_root.as_deref(),
         raw_match_index,
         &prepared_input.jpg_path,
-        context.recursive,
-    )?;
-    let rendered =
-        render_template_with_options(context.parts, &resolved.metadata, context.dedupe_same_maker);
-    let excluded = apply_exclusions(rendered, context.exclusions);
-    let normalized_spaces = normalize_spaces_to_underscore(&excluded);
-    let cleaned = cleanup_filename(&normalized_spaces);
-    let sanitized = sanitize_filename(&cleaned);
+        context.resolve,
+        prefetch,
+        &context.timers.exif,
+    )?
+    .with_date_adjustments(context.time_shifts, context.date_zone);
+    PlanTimers::add(&context.timers.metadata_nanos, metadata_started.elapsed());
+    let render_started = Instant::now();
+    let mut segments = render_name_segments(
+        context.parts,
+        &resolved.metadata,
+        context.maker,
+        context.exclusions,
+    );
+    let sanitized = segments.pop().unwrap_or_default();
+    // テンプレートに `/` があればJPGルート配下のフォルダへ移動する
+    let target_dir = if segments.is_empty() {
+        prepared_input
+            .jpg_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| prepared_input.jpg_root.clone())
+    } else {
+        segments
+            .iter()
+            .fold(prepared_input.jpg_root.clone(), |dir, segment| {
+                dir.join(segment)
+            })
+    };
 
     let extension = prepared_input
         .jpg_path
@@ -304,6 +1188,37 @@ fn prepare_candidate(
         .unwrap_or_default();
     let rendered_base =
         truncate_filename_if_needed(&sanitized, &extension, context.max_filename_len);
+    PlanTimers::add(&context.timers.render_nanos, render_started.elapsed());
+
+    let fallback_raw = resolved.fallback_raw.take();
+    let raw_paths = match (raw_match_index, &fallback_raw) {
+        (_, Some((found, _))) => found.raw_paths.clone(),
+        (Some(index), None) if !context.raw_primary => index.find_raws(&prepared_input.jpg_path),
+        _ => Vec::new(),
+    };
+    let raw_match = match &fallback_raw {
+        Some((_, method)) => Some(*method),
+        None => (!raw_paths.is_empty()).then_some(RawMatchMethod::Name),
+    };
+    let xmp_path = match (raw_match_index, fallback_raw) {
+        (_, Some((found, _))) => found.xmp_path,
+        (Some(index), None) => index.find_xmp(&prepared_input.jpg_path),
+        (None, None) => resolved.sidecar_xmp.take(),
+    };
+    // 一緒に改名する RAW/XMP。RAW 主体では対象自体が RAW なので raw_paths は空で、XMP だけになる
+    let sidecar_paths = if context.raw_primary || context.rename_companions {
+        raw_paths.iter().chain(&xmp_path).cloned().collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut warnings = resolved.warnings;
+    if context.uses_film_sim && resolved.metadata.film_sim.is_none() {
+        warnings.push(PlanWarning::FilmSimMissing);
+    }
+    if resolved.metadata.source == MetadataSource::FallbackFileModified {
+        warnings.push(PlanWarning::DateFromFileModified);
+    }
 
     Ok(PreparedCandidate {
         original_path: prepared_input.jpg_path.clone(),
@@ -311,6 +1226,14 @@ fn prepare_candidate(
         source_label: resolved.source_label,
         rendered_base,
         extension,
+        target_dir,
+        sidecar_paths,
+        raw_paths,
+        raw_match,
+        xmp_path,
+        warnings,
+        fingerprint: FileFingerprint::read(&prepared_input.jpg_path),
+        provenance: resolved.provenance,
     })
 }
 
@@ -318,14 +1241,26 @@ fn resolve_jpg_input(
     jpg_input: &Path,
     recursive: bool,
     include_hidden: bool,
+    follow_symlinks: bool,
+    targets: ScanTargets<'_>,
+    control: PlanControl<'_>,
     stats: &mut RenameStats,
 ) -> Result<ResolvedJpgInput> {
+    let started = Instant::now();
     if !jpg_input.exists() {
         anyhow::bail!("JPGフォルダが存在しません: {}", jpg_input.display());
     }
 
     if jpg_input.is_dir() {
-        let jpg_files = collect_jpg_files(jpg_input, recursive, include_hidden, stats)?;
+        let jpg_files = collect_jpg_files(
+            jpg_input,
+            recursive,
+            include_hidden,
+            follow_symlinks,
+            targets,
+            control,
+            stats,
+        )?;
         let jpg_root_by_file = jpg_files
             .iter()
             .map(|jpg_file| (jpg_file.clone(), jpg_input.to_path_buf()))
@@ -335,6 +1270,8 @@ fn resolve_jpg_input(
             jpg_roots: vec![jpg_input.to_path_buf()],
             jpg_files,
             jpg_root_by_file,
+            scan_elapsed: started.elapsed(),
+            whole_folder: true,
         });
     }
 
@@ -345,7 +1282,7 @@ fn resolve_jpg_input(
         );
     }
 
-    if !is_jpg(jpg_input) {
+    if !targets.matches(jpg_input) {
         anyhow::bail!("JPGファイルではありません: {}", jpg_input.display());
     }
 
@@ -357,6 +1294,7 @@ fn resolve_jpg_input(
     })?;
     stats.scanned_files = 1;
     stats.jpg_files = 1;
+    control.report_scanned(stats.scanned_files);
 
     let jpg_path = jpg_input.to_path_buf();
     let mut jpg_root_by_file = HashMap::<PathBuf, PathBuf>::new();
@@ -367,13 +1305,20 @@ fn resolve_jpg_input(
         jpg_roots: vec![jpg_root.to_path_buf()],
         jpg_files: vec![jpg_path],
         jpg_root_by_file,
+        scan_elapsed: started.elapsed(),
+        whole_folder: false,
     })
 }
 
+// フォルダとファイルを混在して指定できる。フォルダはそのフォルダをルートとして走査する
 fn resolve_explicit_jpg_files(
     jpg_files: &[PathBuf],
+    options: &PlanOptions,
+    control: PlanControl<'_>,
     stats: &mut RenameStats,
 ) -> Result<ResolvedJpgInput> {
+    let targets = ScanTargets::from_options(options);
+    let started = Instant::now();
     if jpg_files.is_empty() {
         anyhow::bail!("JPGファイルが指定されていません");
     }
@@ -391,14 +1336,43 @@ fn resolve_explicit_jpg_files(
     let mut resolved_jpg_roots = Vec::<PathBuf>::new();
     let mut jpg_root_by_file = HashMap::<PathBuf, PathBuf>::new();
 
+    let mut seen_files = HashSet::<PathBuf>::new();
     for jpg_file in unique_files {
         if !jpg_file.exists() {
             anyhow::bail!("JPGファイルが存在しません: {}", jpg_file.display());
         }
+        if jpg_file.is_dir() {
+            let root_canonical = fs::canonicalize(&jpg_file).with_context(|| {
+                format!("JPGフォルダを解決できませんでした: {}", jpg_file.display())
+            })?;
+            if !resolved_root_canonicals.contains(&root_canonical) {
+                resolved_root_canonicals.push(root_canonical);
+                resolved_jpg_roots.push(jpg_file.clone());
+            }
+            let folder_files = collect_jpg_files(
+                &jpg_file,
+                options.recursive,
+                options.include_hidden,
+                options.follow_symlinks,
+                targets,
+                control,
+                stats,
+            )?;
+            for folder_file in folder_files {
+                if seen_files.insert(folder_file.clone()) {
+                    jpg_root_by_file.insert(folder_file.clone(), jpg_file.clone());
+                    resolved_files.push(folder_file);
+                }
+            }
+            continue;
+        }
+        if !seen_files.insert(jpg_file.clone()) {
+            continue;
+        }
         if !jpg_file.is_file() {
             anyhow::bail!("JPGファイルではありません: {}", jpg_file.display());
         }
-        if !is_jpg(&jpg_file) {
+        if !targets.matches(&jpg_file) {
             anyhow::bail!("JPGファイルではありません: {}", jpg_file.display());
         }
 
@@ -440,6 +1414,8 @@ fn resolve_explicit_jpg_files(
         jpg_roots: resolved_jpg_roots,
         jpg_files: resolved_files,
         jpg_root_by_file,
+        scan_elapsed: started.elapsed(),
+        whole_folder: false,
     })
 }
 
@@ -486,35 +1462,64 @@ fn common_ancestor_path(paths: &[PathBuf]) -> Option<PathBuf> {
 
 pub fn render_preview_sample(
     template: &str,
-    dedupe_same_maker: bool,
+    maker: MakerNameOptions<'_>,
     exclusions: &[String],
     metadata: &PhotoMetadata,
     extension_with_dot: &str,
     max_filename_len: usize,
 ) -> Result<String> {
     let parts = parse_template(template)?;
-    let rendered = render_template_with_options(&parts, metadata, dedupe_same_maker);
-    let excluded = apply_exclusions(rendered, exclusions);
-    let normalized_spaces = normalize_spaces_to_underscore(&excluded);
-    let cleaned = cleanup_filename(&normalized_spaces);
-    let sanitized = sanitize_filename(&cleaned);
+    let mut segments = render_name_segments(&parts, metadata, maker, exclusions);
+    let sanitized = segments.pop().unwrap_or_default();
     let truncated = truncate_filename_if_needed(&sanitized, extension_with_dot, max_filename_len);
-    Ok(format!("{}{}", truncated, extension_with_dot))
+    segments.push(format!("{}{}", truncated, extension_with_dot));
+    Ok(segments.join("/"))
+}
+
+fn render_name_segments(
+    parts: &[TemplatePart],
+    metadata: &PhotoMetadata,
+    maker: MakerNameOptions<'_>,
+    exclusions: &[String],
+) -> Vec<String> {
+    split_template_segments(parts)
+        .map(|segment| {
+            let rendered = render_template_with_options(segment, metadata, maker);
+            let excluded = apply_exclusions(rendered, exclusions);
+            let normalized_spaces = normalize_spaces_to_underscore(&excluded);
+            let cleaned = cleanup_filename(&normalized_spaces);
+            sanitize_filename(&cleaned)
+        })
+        .collect()
 }
 
 fn collect_jpg_files(
     root: &Path,
     recursive: bool,
     include_hidden: bool,
+    follow_symlinks: bool,
+    targets: ScanTargets<'_>,
+    control: PlanControl<'_>,
     stats: &mut RenameStats,
 ) -> Result<Vec<PathBuf>> {
     let mut out = Vec::new();
 
     if recursive {
-        let mut walker = WalkDir::new(root).sort_by_file_name().into_iter();
+        let mut walker = WalkDir::new(root)
+            .follow_links(follow_symlinks)
+            .sort_by_file_name()
+            .into_iter();
         while let Some(entry) = walker.next() {
-            let entry =
-                entry.with_context(|| format!("フォルダ走査に失敗しました: {}", root.display()))?;
+            control.check_cancelled()?;
+            let entry = match entry {
+                Ok(entry) => entry,
+                // シンボリックリンクの循環は走査を止めずに読み飛ばす
+                Err(err) if err.loop_ancestor().is_some() => continue,
+                Err(err) => {
+                    return Err(anyhow::Error::from(err)
+                        .context(format!("フォルダ走査に失敗しました: {}", root.display())))
+                }
+            };
             let path = entry.path();
             if path.is_dir() {
                 if entry.depth() > 0 && !include_hidden && is_hidden(path) {
@@ -528,8 +1533,9 @@ fn collect_jpg_files(
                 continue;
             }
             stats.scanned_files += 1;
+            control.report_scanned(stats.scanned_files);
 
-            if is_jpg(path) {
+            if targets.matches(path) {
                 stats.jpg_files += 1;
                 out.push(path.to_path_buf());
             } else {
@@ -540,6 +1546,7 @@ fn collect_jpg_files(
         for entry in fs::read_dir(root)
             .with_context(|| format!("フォルダを読めませんでした: {}", root.display()))?
         {
+            control.check_cancelled()?;
             let entry =
                 entry.with_context(|| format!("エントリ読み取り失敗: {}", root.display()))?;
             let path = entry.path();
@@ -551,7 +1558,8 @@ fn collect_jpg_files(
                 continue;
             }
             stats.scanned_files += 1;
-            if is_jpg(&path) {
+            control.report_scanned(stats.scanned_files);
+            if targets.matches(&path) {
                 stats.jpg_files += 1;
                 out.push(path);
             } else {
@@ -570,50 +1578,92 @@ fn resolve_metadata(
     raw_root: Option<&Path>,
     raw_match_index: Option<&RawMatchIndex>,
     jpg_path: &Path,
-    recursive: bool,
+    options: ResolveOptions,
+    prefetch: &ExifPrefetch<'_>,
+    exif_timings: &ExifTimings,
 ) -> Result<ResolvedMetadata> {
     let fallback_date = file_modified_to_local(jpg_path).unwrap_or_else(Local::now);
     let original_name = jpg_path
         .file_stem()
         .map(|v| v.to_string_lossy().to_string())
         .unwrap_or_else(|| "untitled".to_string());
-    let mut jpg_exif_meta_cache: Option<PartialMetadata> = None;
-    let mut jpg_exif_loaded = false;
-
-    let mut load_jpg_exif_meta = || {
-        if !jpg_exif_loaded {
-            jpg_exif_meta_cache = read_exif_metadata(jpg_path).ok();
-            jpg_exif_loaded = true;
-        }
-    };
+    let mut warnings = Vec::new();
+    let mut provenance = FieldProvenance::default();
+    let mut jpg_exif = LazyExif::new(Some(jpg_path), prefetch, exif_timings);
 
     if let Some(raw_root) = raw_root {
-        let (xmp_path, raw_path) = if let Some(index) = raw_match_index {
+        let (mut xmp_path, mut raw_path) = if let Some(index) = raw_match_index {
             (index.find_xmp(jpg_path), index.find_raw(jpg_path))
         } else {
             (
-                find_matching_xmp(jpg_root, raw_root, jpg_path, recursive),
-                find_matching_raw(jpg_root, raw_root, jpg_path, recursive),
+                find_matching_xmp(jpg_root, raw_root, jpg_path, options.recursive),
+                find_matching_raw(
+                    jpg_root,
+                    raw_root,
+                    jpg_path,
+                    options.recursive,
+                    options.raw_extensions,
+                ),
             )
         };
-        let mut raw_exif_cache: Option<PartialMetadata> = None;
-        let mut raw_exif_loaded = false;
-        let mut load_raw_exif_meta = || -> Option<PartialMetadata> {
-            if !raw_exif_loaded {
-                raw_exif_cache = raw_path
+        // 前回の改名で JPG だけ名前が変わった場合は、ImageUniqueID、次に撮影日時 (SubSec 込み) で RAW を探す
+        let mut fallback_raw = None;
+        if let (Some(index), None, None) = (raw_match_index, &xmp_path, &raw_path) {
+            let jpg_meta = options
+                .match_raw_by_unique_id
+                .then(|| jpg_exif.get(&mut warnings))
+                .flatten();
+            let read_raw = |path: &Path| read_exif_metadata(path, prefetch, exif_timings).ok();
+            if let (true, Some(unique_id)) = (
+                options.match_raw_by_unique_id,
+                jpg_meta
                     .as_ref()
-                    .and_then(|path| read_exif_metadata(path).ok());
-                raw_exif_loaded = true;
+                    .and_then(|meta| meta.image_unique_id.as_deref()),
+            ) {
+                fallback_raw = index
+                    .find_by_unique_id(jpg_path, unique_id, |path| {
+                        read_raw(path).and_then(|meta| meta.image_unique_id)
+                    })
+                    .map(|found| (found, RawMatchMethod::ImageUniqueId));
             }
-            raw_exif_cache.clone()
-        };
+            if let (None, Some(tolerance)) = (&fallback_raw, options.raw_time_tolerance) {
+                fallback_raw = index
+                    .find_by_capture_time(jpg_path, tolerance, |path| {
+                        read_raw(path).and_then(|meta| meta.date)
+                    })
+                    .map(|(found, difference)| {
+                        warnings.push(PlanWarning::RawMatchedByCaptureTime {
+                            path: found.raw_paths[0].clone(),
+                            difference_ms: difference.num_milliseconds(),
+                        });
+                        (found, RawMatchMethod::CaptureTime)
+                    });
+            }
+            if let Some((found, _)) = &fallback_raw {
+                raw_path = found.raw_paths.first().cloned();
+                xmp_path = found.xmp_path.clone();
+            }
+        }
+        log::debug!(
+            "{}: XMP={} RAW={}",
+            jpg_path.display(),
+            xmp_path
+                .as_deref()
+                .map_or_else(|| "なし".to_string(), |path| path.display().to_string()),
+            raw_path
+                .as_deref()
+                .map_or_else(|| "なし".to_string(), |path| path.display().to_string()),
+        );
+        let mut raw_exif = LazyExif::new(raw_path.as_deref(), prefetch, exif_timings);
 
         if let Some(xmp_path) = xmp_path {
             match read_xmp_metadata(&xmp_path) {
                 Ok(mut xmp_meta) => {
                     let mut source = MetadataSource::Xmp;
+                    provenance.record(&xmp_meta, FieldSource::Xmp);
                     if metadata_has_missing_fields(&xmp_meta) {
-                        if let Some(raw) = load_raw_exif_meta().as_ref() {
+                        if let Some(raw) = raw_exif.get(&mut warnings).as_ref() {
+                            provenance.record(raw, FieldSource::RawExif);
                             let before = xmp_meta.clone();
                             xmp_meta.merge_missing_from(raw);
                             if metadata_changed(&before, &xmp_meta) {
@@ -623,23 +1673,49 @@ fn resolve_metadata(
                     }
 
                     let merged = if metadata_has_missing_fields(&xmp_meta) {
-                        load_jpg_exif_meta();
-                        merge_with_jpg_fallback(xmp_meta, jpg_exif_meta_cache.as_ref())
+                        merge_with_jpg_fallback(
+                            xmp_meta,
+                            jpg_exif.get(&mut warnings).as_ref(),
+                            &mut provenance,
+                        )
                     } else {
                         xmp_meta
                     };
-                    let metadata =
-                        to_photo_metadata(merged, source, fallback_date, original_name, jpg_path);
+                    let metadata = to_photo_metadata(
+                        merged,
+                        source,
+                        fallback_date,
+                        original_name,
+                        jpg_path,
+                        &mut provenance,
+                        options.prefer_gps_time,
+                    );
                     return Ok(ResolvedMetadata {
-                        source_label: metadata_source_label(metadata.source, raw_path.as_deref()),
+                        source_label: metadata_source_label(
+                            metadata.source,
+                            raw_path.as_deref(),
+                            jpg_path,
+                        ),
                         metadata,
+                        warnings,
+                        provenance: provenance.with_date_fallback(),
+                        fallback_raw,
+                        sidecar_xmp: None,
                     });
                 }
-                Err(_) => {
-                    if let Some(raw) = load_raw_exif_meta() {
+                Err(err) => {
+                    warnings.push(PlanWarning::XmpReadFailed {
+                        path: xmp_path.clone(),
+                        message: err.to_string(),
+                    });
+                    if let Some(raw) = raw_exif.get(&mut warnings) {
+                        provenance.record(&raw, FieldSource::RawExif);
                         let merged = if metadata_has_missing_fields(&raw) {
-                            load_jpg_exif_meta();
-                            merge_with_jpg_fallback(raw, jpg_exif_meta_cache.as_ref())
+                            merge_with_jpg_fallback(
+                                raw,
+                                jpg_exif.get(&mut warnings).as_ref(),
+                                &mut provenance,
+                            )
                         } else {
                             raw
                         };
@@ -649,23 +1725,30 @@ fn resolve_metadata(
                             fallback_date,
                             original_name,
                             jpg_path,
+                            &mut provenance,
+                            options.prefer_gps_time,
                         );
                         return Ok(ResolvedMetadata {
                             source_label: metadata_source_label(
                                 metadata.source,
                                 raw_path.as_deref(),
+                                jpg_path,
                             ),
                             metadata,
+                            warnings,
+                            provenance: provenance.with_date_fallback(),
+                            fallback_raw,
+                            sidecar_xmp: None,
                         });
                     }
                 }
             }
         }
 
-        if let Some(raw) = load_raw_exif_meta() {
+        if let Some(raw) = raw_exif.get(&mut warnings) {
+            provenance.record(&raw, FieldSource::RawExif);
             let merged = if metadata_has_missing_fields(&raw) {
-                load_jpg_exif_meta();
-                merge_with_jpg_fallback(raw, jpg_exif_meta_cache.as_ref())
+                merge_with_jpg_fallback(raw, jpg_exif.get(&mut warnings).as_ref(), &mut provenance)
             } else {
                 raw
             };
@@ -675,30 +1758,138 @@ fn resolve_metadata(
                 fallback_date,
                 original_name,
                 jpg_path,
+                &mut provenance,
+                options.prefer_gps_time,
             );
             return Ok(ResolvedMetadata {
-                source_label: metadata_source_label(metadata.source, raw_path.as_deref()),
+                source_label: metadata_source_label(metadata.source, raw_path.as_deref(), jpg_path),
                 metadata,
+                warnings,
+                provenance: provenance.with_date_fallback(),
+                fallback_raw,
+                sidecar_xmp: None,
             });
         }
     }
 
-    load_jpg_exif_meta();
-    let jpg_meta = jpg_exif_meta_cache.unwrap_or_default();
+    // RAW フォルダ未指定時は JPG の隣のサイドカーを先に見る
+    let sidecar_xmp = raw_root
+        .is_none()
+        .then(|| find_sidecar_xmp(jpg_path))
+        .flatten();
+    if let Some(xmp_path) = &sidecar_xmp {
+        match read_xmp_metadata(xmp_path) {
+            Ok(xmp_meta) => {
+                provenance.record(&xmp_meta, FieldSource::Xmp);
+                let merged = if metadata_has_missing_fields(&xmp_meta) {
+                    merge_with_jpg_fallback(
+                        xmp_meta,
+                        jpg_exif.get(&mut warnings).as_ref(),
+                        &mut provenance,
+                    )
+                } else {
+                    xmp_meta
+                };
+                let metadata = to_photo_metadata(
+                    merged,
+                    MetadataSource::Xmp,
+                    fallback_date,
+                    original_name,
+                    jpg_path,
+                    &mut provenance,
+                    options.prefer_gps_time,
+                );
+                return Ok(ResolvedMetadata {
+                    source_label: metadata_source_label(metadata.source, None, jpg_path),
+                    metadata,
+                    warnings,
+                    provenance: provenance.with_date_fallback(),
+                    fallback_raw: None,
+                    sidecar_xmp,
+                });
+            }
+            Err(err) => warnings.push(PlanWarning::XmpReadFailed {
+                path: xmp_path.clone(),
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    let jpg_meta = jpg_exif.get(&mut warnings).unwrap_or_default();
+    provenance.record(&jpg_meta, FieldSource::JpgExif);
     let metadata = to_photo_metadata(
         jpg_meta,
         MetadataSource::JpgExif,
         fallback_date,
         original_name,
         jpg_path,
+        &mut provenance,
+        options.prefer_gps_time,
     );
     Ok(ResolvedMetadata {
-        source_label: metadata_source_label(metadata.source, None),
+        source_label: metadata_source_label(metadata.source, None, jpg_path),
         metadata,
+        warnings,
+        provenance: provenance.with_date_fallback(),
+        fallback_raw: None,
+        sidecar_xmp,
     })
 }
 
-fn metadata_source_label(source: MetadataSource, raw_path: Option<&Path>) -> String {
+struct LazyExif<'a> {
+    path: Option<&'a Path>,
+    prefetch: &'a ExifPrefetch<'a>,
+    timings: &'a ExifTimings,
+    loaded: Option<Option<PartialMetadata>>,
+}
+
+impl<'a> LazyExif<'a> {
+    fn new(
+        path: Option<&'a Path>,
+        prefetch: &'a ExifPrefetch<'a>,
+        timings: &'a ExifTimings,
+    ) -> Self {
+        Self {
+            path,
+            prefetch,
+            timings,
+            loaded: None,
+        }
+    }
+
+    fn get(&mut self, warnings: &mut Vec<PlanWarning>) -> Option<PartialMetadata> {
+        if self.loaded.is_none() {
+            let meta = self.path.and_then(|path| {
+                match read_exif_metadata(path, self.prefetch, self.timings) {
+                    Ok(meta) => {
+                        warnings.extend(meta.exiftool_messages.iter().map(|message| {
+                            PlanWarning::ExiftoolMessage {
+                                path: path.to_path_buf(),
+                                message: message.clone(),
+                            }
+                        }));
+                        Some(meta)
+                    }
+                    Err(err) => {
+                        warnings.push(PlanWarning::ExifReadFailed {
+                            path: path.to_path_buf(),
+                            message: err.to_string(),
+                        });
+                        None
+                    }
+                }
+            });
+            self.loaded = Some(meta);
+        }
+        self.loaded.clone().flatten()
+    }
+}
+
+fn metadata_source_label(
+    source: MetadataSource,
+    raw_path: Option<&Path>,
+    image_path: &Path,
+) -> String {
     match source {
         MetadataSource::Xmp | MetadataSource::XmpAndRawExif => "xmp".to_string(),
         MetadataSource::RawExif => raw_path
@@ -706,7 +1897,17 @@ fn metadata_source_label(source: MetadataSource, raw_path: Option<&Path>) -> Str
             .map(|ext| ext.trim().to_ascii_lowercase())
             .filter(|ext| !ext.is_empty())
             .unwrap_or_else(|| "raw".to_string()),
-        MetadataSource::JpgExif | MetadataSource::FallbackFileModified => "jpg".to_string(),
+        MetadataSource::JpgExif | MetadataSource::FallbackFileModified => {
+            let ext = image_path
+                .extension()
+                .map(|ext| ext.to_string_lossy().trim().to_ascii_lowercase())
+                .unwrap_or_default();
+            match ext.as_str() {
+                "" | "jpg" | "jpeg" => "jpg".to_string(),
+                "tif" => "tiff".to_string(),
+                _ => ext,
+            }
+        }
     }
 }
 
@@ -719,206 +1920,2074 @@ fn metadata_has_missing_fields(meta: &PartialMetadata) -> bool {
         || meta.film_sim.is_none()
 }
 
+// GPSの時刻は撮影日時が無い場合、または prefer_gps_time 指定時に使う。ファイル更新日時よりは確か
 fn to_photo_metadata(
-    partial: PartialMetadata,
+    mut partial: PartialMetadata,
     source: MetadataSource,
     fallback_date: DateTime<Local>,
     original_name: String,
     jpg_path: &Path,
+    provenance: &mut FieldProvenance,
+    prefer_gps_time: bool,
 ) -> PhotoMetadata {
+    if let Some(gps_date) = partial
+        .gps_date
+        .filter(|_| prefer_gps_time || partial.date.is_none())
+    {
+        partial.date = Some(gps_date.with_timezone(&Local).fixed_offset());
+        provenance.date = Some(FieldSource::Gps);
+    }
     let source = if partial.date.is_none() {
+        log::debug!(
+            "{}: 撮影日時がないためファイル更新日時を使用 ({:?}から読込)",
+            jpg_path.display(),
+            source
+        );
         MetadataSource::FallbackFileModified
     } else {
         source
     };
 
-    PhotoMetadata {
-        source,
-        date: partial.date.unwrap_or(fallback_date),
-        camera_make: partial.camera_make,
-        camera_model: partial.camera_model,
-        lens_make: partial.lens_make,
-        lens_model: partial.lens_model,
-        film_sim: partial.film_sim,
-        original_name,
-        jpg_path: jpg_path.to_path_buf(),
+    PhotoMetadata {
+        source,
+        date: partial.date.unwrap_or(fallback_date.fixed_offset()),
+        camera_make: partial.camera_make,
+        camera_model: partial.camera_model,
+        lens_make: partial.lens_make,
+        lens_model: partial.lens_model,
+        film_sim: partial.film_sim,
+        rating: partial.rating,
+        dynamic_range: partial.dynamic_range,
+        grain_effect: partial.grain_effect,
+        color_chrome_effect: partial.color_chrome_effect,
+        wb_shift: partial.wb_shift,
+        focal_length_eq: partial.focal_length_eq,
+        exposure_compensation: partial.exposure_compensation,
+        flash_fired: partial.flash_fired,
+        image_unique_id: partial.image_unique_id,
+        title: partial.title,
+        description: partial.description,
+        label: partial.label,
+        keywords: partial.keywords,
+        extra_tags: partial.extra_tags,
+        original_name,
+        jpg_path: jpg_path.to_path_buf(),
+    }
+}
+
+fn metadata_changed(a: &PartialMetadata, b: &PartialMetadata) -> bool {
+    a.date != b.date
+        || a.camera_make != b.camera_make
+        || a.camera_model != b.camera_model
+        || a.lens_make != b.lens_make
+        || a.lens_model != b.lens_model
+        || a.film_sim != b.film_sim
+}
+
+fn resolve_collision(
+    original_path: &Path,
+    target_dir: &Path,
+    base: &str,
+    extension: &str,
+    sidecar_paths: &[PathBuf],
+    planned_paths: &mut HashSet<PathBuf>,
+    max_len: usize,
+) -> Result<PathBuf> {
+    let mut candidate = target_dir.join(format!("{}{}", base, extension));
+    if is_available_with_sidecar(&candidate, original_path, sidecar_paths, planned_paths) {
+        reserve_with_sidecar(&candidate, original_path, sidecar_paths, planned_paths);
+        return Ok(candidate);
+    }
+
+    let mut n = 1usize;
+    loop {
+        // 連番が切り落とされると同じ名前を試し続けるため、連番の分だけ先に元の名前を詰める
+        let suffix = format!("_{:03}", n);
+        let base = truncate_filename_if_needed(base, &format!("{suffix}{extension}"), max_len);
+        candidate = target_dir.join(format!("{base}{suffix}{extension}"));
+        if is_available_with_sidecar(&candidate, original_path, sidecar_paths, planned_paths) {
+            reserve_with_sidecar(&candidate, original_path, sidecar_paths, planned_paths);
+            return Ok(candidate);
+        }
+        n += 1;
+    }
+}
+
+fn assign_targets(
+    accepted: &[PreparedCandidate],
+    planned_paths: &mut HashSet<PathBuf>,
+    max_len: usize,
+) -> Result<Vec<PathBuf>> {
+    let mut targets = vec![None; accepted.len()];
+    for (index, prepared) in accepted.iter().enumerate() {
+        if prepared.original_path.parent() == Some(prepared.target_dir.as_path())
+            && conforms_to_rendered_base(
+                &prepared.original_path,
+                &prepared.rendered_base,
+                &prepared.extension,
+                max_len,
+            )
+            && is_available_with_sidecar(
+                &prepared.original_path,
+                &prepared.original_path,
+                &prepared.sidecar_paths,
+                planned_paths,
+            )
+        {
+            reserve_with_sidecar(
+                &prepared.original_path,
+                &prepared.original_path,
+                &prepared.sidecar_paths,
+                planned_paths,
+            );
+            targets[index] = Some(prepared.original_path.clone());
+        }
+    }
+
+    // 連番は撮影順 (SubSec 込みの撮影日時) に振り、同時刻なら元ファイル名順にする
+    let mut order = (0..accepted.len())
+        .filter(|index| targets[*index].is_none())
+        .collect::<Vec<_>>();
+    order.sort_by(|left, right| {
+        let left = &accepted[*left];
+        let right = &accepted[*right];
+        left.metadata
+            .date
+            .cmp(&right.metadata.date)
+            .then_with(|| {
+                left.original_path
+                    .file_name()
+                    .cmp(&right.original_path.file_name())
+            })
+            .then_with(|| left.original_path.cmp(&right.original_path))
+    });
+    for index in order {
+        let prepared = &accepted[index];
+        targets[index] = Some(resolve_collision(
+            &prepared.original_path,
+            &prepared.target_dir,
+            &prepared.rendered_base,
+            &prepared.extension,
+            &prepared.sidecar_paths,
+            planned_paths,
+            max_len,
+        )?);
+    }
+
+    Ok(targets.into_iter().flatten().collect())
+}
+
+// 既に `{base}` か `{base}_NNN` の名前なら、連番を振り直さずそのまま残す
+// resolve_collision が付けうる名前 (連番は 001 からの3桁以上、長さの上限に合わせて詰めた元の名前) だけを認める
+fn conforms_to_rendered_base(
+    original_path: &Path,
+    base: &str,
+    extension: &str,
+    max_len: usize,
+) -> bool {
+    let Some(file_name) = original_path.file_name().and_then(|v| v.to_str()) else {
+        return false;
+    };
+    let Some(stem) = file_name.strip_suffix(extension) else {
+        return false;
+    };
+    if stem == base {
+        return true;
+    }
+    let Some((prefix, digits)) = stem.rsplit_once('_') else {
+        return false;
+    };
+    let Some(n) = digits.parse::<usize>().ok().filter(|n| *n >= 1) else {
+        return false;
+    };
+    if format!("{n:03}") != digits {
+        return false;
+    }
+    prefix == truncate_filename_if_needed(base, &format!("_{digits}{extension}"), max_len)
+}
+
+fn is_available_with_sidecar(
+    candidate: &Path,
+    original_path: &Path,
+    sidecar_paths: &[PathBuf],
+    planned_paths: &HashSet<PathBuf>,
+) -> bool {
+    if !is_available(candidate, original_path, planned_paths) {
+        return false;
+    }
+    sidecar_paths.iter().all(|sidecar_path| {
+        is_available(
+            &sidecar_target_path(original_path, candidate, sidecar_path),
+            sidecar_path,
+            planned_paths,
+        )
+    })
+}
+
+fn reserve_with_sidecar(
+    candidate: &Path,
+    original_path: &Path,
+    sidecar_paths: &[PathBuf],
+    planned_paths: &mut HashSet<PathBuf>,
+) {
+    planned_paths.insert(candidate.to_path_buf());
+    for sidecar_path in sidecar_paths {
+        planned_paths.insert(sidecar_target_path(original_path, candidate, sidecar_path));
+    }
+}
+
+// 本体と同じフォルダのサイドカーは本体の移動先へ、別フォルダの連れ合いは同じ相対位置へ移す
+fn sidecar_target_path(
+    primary_original: &Path,
+    primary_target: &Path,
+    sidecar_path: &Path,
+) -> PathBuf {
+    let mut stem = primary_target
+        .file_stem()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // NAME.EXT.xmp の拡張子部分や darktable の複製 XMP (NAME_01.xmp) の番号は残す
+    let original_stem = primary_original
+        .file_stem()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if let Some(sidecar_stem) = sidecar_path.file_stem().and_then(|v| v.to_str()) {
+        for base_stem in [
+            double_extension_base_stem,
+            duplicate_base_stem,
+            duplicate_with_extension_base_stem,
+        ] {
+            if let Some(base) = base_stem(sidecar_stem) {
+                if base.eq_ignore_ascii_case(&original_stem) {
+                    stem.push_str(&sidecar_stem[base.len()..]);
+                    break;
+                }
+            }
+        }
+    }
+    let extension = sidecar_path
+        .extension()
+        .map(|v| format!(".{}", v.to_string_lossy()))
+        .unwrap_or_default();
+    let primary_original_dir = primary_original.parent().unwrap_or_else(|| Path::new(""));
+    let primary_target_dir = primary_target.parent().unwrap_or_else(|| Path::new(""));
+    let sidecar_dir = sidecar_path.parent().unwrap_or_else(|| Path::new(""));
+    let target_dir = if sidecar_dir == primary_original_dir {
+        primary_target_dir.to_path_buf()
+    } else {
+        match primary_target_dir.strip_prefix(primary_original_dir) {
+            Ok(relative) => sidecar_dir.join(relative),
+            Err(_) => sidecar_dir.to_path_buf(),
+        }
+    };
+    target_dir.join(format!("{}{}", stem, extension))
+}
+
+fn merge_with_jpg_fallback(
+    mut base: PartialMetadata,
+    jpg_exif_meta: Option<&PartialMetadata>,
+    provenance: &mut FieldProvenance,
+) -> PartialMetadata {
+    if let Some(jpg_meta) = jpg_exif_meta {
+        provenance.record(jpg_meta, FieldSource::JpgExif);
+        base.merge_missing_from(jpg_meta);
+    }
+    base
+}
+
+fn filter_small_files(
+    jpg_files: &[PathBuf],
+    min_file_size: u64,
+    stats: &mut RenameStats,
+) -> Result<Vec<PathBuf>> {
+    if min_file_size == 0 {
+        return Ok(jpg_files.to_vec());
+    }
+
+    let mut out = Vec::with_capacity(jpg_files.len());
+    for jpg_path in jpg_files {
+        let size = fs::metadata(jpg_path)
+            .with_context(|| format!("ファイル情報を取得できませんでした: {}", jpg_path.display()))?
+            .len();
+        if size < min_file_size {
+            stats.skipped_small_files += 1;
+            continue;
+        }
+        out.push(jpg_path.clone());
+    }
+    Ok(out)
+}
+
+fn meets_min_rating(rating: Option<i8>, min_rating: Option<i8>) -> bool {
+    match min_rating {
+        Some(min_rating) => rating.unwrap_or(0) >= min_rating,
+        None => true,
+    }
+}
+
+fn is_available(candidate: &Path, original_path: &Path, planned_paths: &HashSet<PathBuf>) -> bool {
+    if planned_paths.contains(candidate) {
+        return false;
+    }
+    if candidate == original_path {
+        return true;
+    }
+    !candidate.exists()
+}
+
+fn has_primary_extension(path: &Path, primary_extensions: &[String]) -> bool {
+    let Some(ext) = path.extension().map(|ext| ext.to_string_lossy()) else {
+        return false;
+    };
+    primary_extensions.iter().any(|candidate| {
+        candidate
+            .trim()
+            .trim_start_matches('.')
+            .eq_ignore_ascii_case(&ext)
+    })
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().starts_with('.'))
+        .unwrap_or(false)
+}
+
+fn file_modified_to_local(path: &Path) -> Option<DateTime<Local>> {
+    let time = fs::metadata(path).ok()?.modified().ok()?;
+    Some(DateTime::from(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        collect_folder_stats, conforms_to_rendered_base, generate_plan, generate_plan_chunked,
+        generate_plan_for_jpg_files, generate_plan_with_control, inspect_jpg_file,
+        merge_with_jpg_fallback, metadata_source_label, resolve_collision, PlanControl,
+        PlanOptions, PlanWarning, ProgressSink,
+    };
+    use crate::constants::{default_primary_extensions, default_raw_extensions};
+    use crate::metadata::{
+        FieldProvenance, FieldSource, MetadataSource, PartialMetadata, SourceFilter,
+    };
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tempfile::tempdir;
+
+    #[test]
+    fn merge_with_jpg_fallback_fills_missing_fields() {
+        let base = PartialMetadata {
+            camera_make: None,
+            camera_model: None,
+            lens_make: None,
+            lens_model: None,
+            ..Default::default()
+        };
+        let jpg = PartialMetadata {
+            camera_make: Some("FUJIFILM".to_string()),
+            camera_model: Some("X-H2".to_string()),
+            lens_make: Some("FUJIFILM".to_string()),
+            lens_model: Some("XF35mm F1.4 R".to_string()),
+            ..Default::default()
+        };
+
+        let merged = merge_with_jpg_fallback(base, Some(&jpg), &mut FieldProvenance::default());
+        assert_eq!(merged.camera_make.as_deref(), Some("FUJIFILM"));
+        assert_eq!(merged.camera_model.as_deref(), Some("X-H2"));
+        assert_eq!(merged.lens_make.as_deref(), Some("FUJIFILM"));
+        assert_eq!(merged.lens_model.as_deref(), Some("XF35mm F1.4 R"));
+    }
+
+    #[test]
+    fn merge_with_jpg_fallback_keeps_existing_values() {
+        let base = PartialMetadata {
+            camera_make: Some("SONY".to_string()),
+            camera_model: Some("A7C".to_string()),
+            lens_make: Some("SIGMA".to_string()),
+            lens_model: Some("35mm F2 DG DN".to_string()),
+            ..Default::default()
+        };
+        let jpg = PartialMetadata {
+            camera_make: Some("FUJIFILM".to_string()),
+            camera_model: Some("X-H2".to_string()),
+            lens_make: Some("FUJIFILM".to_string()),
+            lens_model: Some("XF35mm F1.4 R".to_string()),
+            ..Default::default()
+        };
+
+        let merged = merge_with_jpg_fallback(base, Some(&jpg), &mut FieldProvenance::default());
+        assert_eq!(merged.camera_make.as_deref(), Some("SONY"));
+        assert_eq!(merged.camera_model.as_deref(), Some("A7C"));
+        assert_eq!(merged.lens_make.as_deref(), Some("SIGMA"));
+        assert_eq!(merged.lens_model.as_deref(), Some("35mm F2 DG DN"));
+    }
+
+    #[test]
+    fn generate_plan_uses_xmp_when_only_xmp_exists_in_raw_folder() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        let jpg_path = jpg_root.join("DSC00001.JPG");
+        fs::write(&jpg_path, b"not-a-real-jpg").expect("jpg file");
+
+        let xmp = raw_root.join("DSC00001.xmp");
+        fs::write(
+            &xmp,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><exif:Make>FUJIFILM</exif:Make></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root,
+            raw_input: Some(raw_root),
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{camera_maker}_{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        let c = &plan.candidates[0];
+        assert_eq!(c.metadata_source, MetadataSource::Xmp);
+        assert_eq!(c.source_label, "xmp");
+        assert_eq!(c.metadata.camera_make.as_deref(), Some("FUJIFILM"));
+    }
+
+    #[test]
+    fn generate_plan_uses_sidecar_next_to_jpg_without_raw_folder() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        let jpg_path = jpg_root.join("DSC00001.JPG");
+        fs::write(&jpg_path, b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            jpg_root.join("DSC00001.JPG.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><exif:Make>FUJIFILM</exif:Make></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root,
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{camera_maker}_{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        let c = &plan.candidates[0];
+        assert_eq!(c.metadata_source, MetadataSource::Xmp);
+        assert_eq!(c.source_label, "xmp");
+        assert_eq!(c.metadata.camera_make.as_deref(), Some("FUJIFILM"));
+    }
+
+    #[test]
+    fn inspect_jpg_file_reports_matched_xmp_and_provenance() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        let jpg_path = jpg_root.join("DSC00001.JPG");
+        fs::write(&jpg_path, b"not-a-real-jpg").expect("jpg file");
+        let xmp = raw_root.join("DSC00001.xmp");
+        fs::write(
+            &xmp,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><exif:Make>FUJIFILM</exif:Make></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let inspection = inspect_jpg_file(
+            &PlanOptions {
+                jpg_input: jpg_path.clone(),
+                raw_input: Some(raw_root),
+                template: "{camera_maker}_{orig_name}".to_string(),
+                ..PlanOptions::default()
+            },
+            &jpg_path,
+        )
+        .expect("inspect should succeed");
+
+        assert_eq!(inspection.xmp_path, Some(xmp));
+        assert_eq!(inspection.raw_path, None);
+        assert_eq!(
+            inspection.candidate.provenance.camera_make,
+            Some(FieldSource::Xmp)
+        );
+        assert_eq!(
+            inspection.candidate.target_path,
+            jpg_root.join("FUJIFILM_DSC00001.JPG")
+        );
+    }
+
+    #[test]
+    fn inspect_jpg_file_reports_same_folder_xmp_used_by_plan() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        let jpg_path = jpg_root.join("DSC00001.JPG");
+        fs::write(&jpg_path, b"not-a-real-jpg").expect("jpg file");
+        let xmp = jpg_root.join("DSC00001.JPG.xmp");
+        fs::write(
+            &xmp,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:Make>FUJIFILM</exif:Make></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let inspection = inspect_jpg_file(
+            &PlanOptions {
+                jpg_input: jpg_path.clone(),
+                template: "{camera_maker}_{orig_name}".to_string(),
+                ..PlanOptions::default()
+            },
+            &jpg_path,
+        )
+        .expect("inspect should succeed");
+
+        // 表示する XMP は、メタデータの取得元としてプランが使ったものと一致する
+        assert_eq!(inspection.xmp_path, Some(xmp.clone()));
+        assert_eq!(inspection.candidate.xmp_path, Some(xmp));
+        assert_eq!(
+            inspection.candidate.provenance.camera_make,
+            Some(FieldSource::Xmp)
+        );
+    }
+
+    #[test]
+    fn prefer_gps_time_replaces_capture_date_with_local_gps_time() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        let jpg_path = jpg_root.join("DSC00001.JPG");
+        fs::write(&jpg_path, b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            raw_root.join("DSC00001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><exif:GPSTimeStamp>2026-02-08T01:45:00Z</exif:GPSTimeStamp></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let inspect = |prefer_gps_time| {
+            inspect_jpg_file(
+                &PlanOptions {
+                    jpg_input: jpg_path.clone(),
+                    raw_input: Some(raw_root.clone()),
+                    template: "{hour}{minute}".to_string(),
+                    prefer_gps_time,
+                    ..PlanOptions::default()
+                },
+                &jpg_path,
+            )
+            .expect("inspect should succeed")
+            .candidate
+        };
+
+        let camera = inspect(false);
+        assert_eq!(camera.provenance.date, Some(FieldSource::Xmp));
+        assert_eq!(camera.target_path, jpg_root.join("1020.JPG"));
+
+        let gps = inspect(true);
+        let expected = chrono::DateTime::parse_from_rfc3339("2026-02-08T01:45:00Z")
+            .expect("gps date")
+            .with_timezone(&chrono::Local)
+            .format("%H%M.JPG")
+            .to_string();
+        assert_eq!(gps.provenance.date, Some(FieldSource::Gps));
+        assert_eq!(gps.target_path, jpg_root.join(expected));
+    }
+
+    #[test]
+    fn collect_folder_stats_counts_metadata_without_planning_names() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC00002.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            raw_root.join("DSC00001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><exif:Model>X-T5</exif:Model><exifEX:LensModel>XF23mmF1.4 R LM WR</exifEX:LensModel></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let stats = collect_folder_stats(
+            &PlanOptions {
+                jpg_input: jpg_root,
+                raw_input: Some(raw_root),
+                ..PlanOptions::default()
+            },
+            PlanControl::default(),
+        )
+        .expect("stats should succeed");
+
+        assert_eq!(stats.photos, 2);
+        assert_eq!(stats.by_camera.get("X-T5"), Some(&1));
+        assert_eq!(stats.by_lens.get("XF23mmF1.4 R LM WR"), Some(&1));
+        assert_eq!(stats.by_lens.get("(不明)"), Some(&1));
+        assert_eq!(stats.by_source.get(&MetadataSource::Xmp), Some(&1));
+        assert_eq!(
+            stats.by_source.get(&MetadataSource::FallbackFileModified),
+            Some(&1)
+        );
+        let expected = stats.earliest.expect("earliest date");
+        assert_eq!(expected.format("%Y-%m-%d").to_string(), "2026-02-08");
+        assert_eq!(stats.latest, Some(expected));
+    }
+
+    #[test]
+    fn generate_plan_skips_candidates_below_min_rating() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        for (stem, rating) in [("DSC00001", 4), ("DSC00002", 1)] {
+            fs::write(jpg_root.join(format!("{stem}.JPG")), b"not-a-real-jpg").expect("jpg file");
+            fs::write(
+                raw_root.join(format!("{stem}.xmp")),
+                format!(
+                    r#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating="{rating}"><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#
+                ),
+            )
+            .expect("xmp file");
+        }
+        fs::write(jpg_root.join("DSC00003.JPG"), b"not-a-real-jpg").expect("jpg file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root),
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{year}_{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: Some(3),
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        assert_eq!(
+            plan.candidates[0].original_path,
+            jpg_root.join("DSC00001.JPG")
+        );
+        assert_eq!(plan.stats.skipped_below_rating, 2);
+    }
+
+    #[test]
+    fn generate_plan_skips_files_smaller_than_min_file_size() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        let large = jpg_root.join("DSC00001.JPG");
+        let small = jpg_root.join("DSC00001_thumb.JPG");
+        fs::write(&large, vec![0u8; 4096]).expect("large jpg");
+        fs::write(&small, vec![0u8; 128]).expect("small jpg");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root,
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}_x".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 1024,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        assert_eq!(plan.candidates[0].original_path, large);
+        assert_eq!(plan.stats.jpg_files, 2);
+        assert_eq!(plan.stats.skipped_small_files, 1);
+    }
+
+    #[test]
+    fn generate_plan_includes_tiff_and_png_files_and_keeps_extension() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC00002.tif"), b"not-a-real-tiff").expect("tiff file");
+        fs::write(jpg_root.join("Screenshot.PNG"), b"not-a-real-png").expect("png file");
+        fs::write(jpg_root.join("notes.txt"), b"memo").expect("text file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}_x".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 3);
+        assert_eq!(plan.stats.skipped_non_jpg, 1);
+        let tiff = &plan.candidates[1];
+        assert_eq!(tiff.target_path, jpg_root.join("DSC00002_x.tif"));
+        assert_eq!(tiff.source_label, "tiff");
+        let png = &plan.candidates[2];
+        assert_eq!(png.target_path, jpg_root.join("Screenshot_x.PNG"));
+        assert_eq!(png.source_label, "png");
+    }
+
+    #[test]
+    fn generate_plan_includes_videos_only_when_enabled() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("C0001.MP4"), b"not-a-real-mp4").expect("mp4 file");
+
+        let mut options = PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}_x".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        };
+        let plan = generate_plan(&options).expect("plan generation should succeed");
+        assert_eq!(plan.candidates.len(), 1);
+        assert_eq!(plan.stats.skipped_non_jpg, 1);
+
+        options.include_videos = true;
+        let plan = generate_plan(&options).expect("plan generation should succeed");
+        assert_eq!(plan.candidates.len(), 2);
+        let video = &plan.candidates[0];
+        assert_eq!(video.target_path, jpg_root.join("C0001_x.MP4"));
+        assert_eq!(video.source_label, "mp4");
+    }
+
+    #[test]
+    fn generate_plan_raw_primary_renames_raw_files_with_matching_xmp() {
+        let temp = tempdir().expect("tempdir");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        fs::write(raw_root.join("DSCF0001.RAF"), b"not-a-real-raf").expect("raf file");
+        fs::write(
+            raw_root.join("DSCF0001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><exif:Make>FUJIFILM</exif:Make></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+        fs::write(raw_root.join("DSCF0002.JPG"), b"not-a-real-jpg").expect("jpg file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: raw_root.clone(),
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{camera_maker}_{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: true,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 2);
+        assert_eq!(plan.stats.planned, 2);
+        assert_eq!(plan.stats.skipped_non_jpg, 2);
+        let raw = &plan.candidates[0];
+        assert_eq!(raw.original_path, raw_root.join("DSCF0001.RAF"));
+        assert_eq!(raw.target_path, raw_root.join("FUJIFILM_DSCF0001.RAF"));
+        assert_eq!(raw.source_label, "xmp");
+        let xmp = &plan.candidates[1];
+        assert_eq!(xmp.original_path, raw_root.join("DSCF0001.xmp"));
+        assert_eq!(xmp.target_path, raw_root.join("FUJIFILM_DSCF0001.xmp"));
+    }
+
+    #[test]
+    fn generate_plan_uses_configured_primary_extensions() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC00002.webp"), b"not-a-real-webp").expect("webp file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}_x".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: vec![".WEBP".to_string()],
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        assert_eq!(plan.stats.skipped_non_jpg, 1);
+        let webp = &plan.candidates[0];
+        assert_eq!(webp.target_path, jpg_root.join("DSC00002_x.webp"));
+        assert_eq!(webp.source_label, "webp");
+    }
+
+    #[derive(Default)]
+    struct CountingProgress {
+        scanned: AtomicUsize,
+        scan_finished: AtomicUsize,
+        metadata_read: AtomicUsize,
+    }
+
+    impl ProgressSink for CountingProgress {
+        fn files_scanned(&self, scanned_files: usize) {
+            self.scanned.store(scanned_files, Ordering::SeqCst);
+        }
+
+        fn scan_finished(&self, scanned_files: usize) {
+            self.scan_finished.store(scanned_files, Ordering::SeqCst);
+        }
+
+        fn metadata_read(&self, _done: usize, _total: usize) {
+            self.metadata_read.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn generate_plan_with_control_reports_progress() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC00002.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("notes.txt"), b"memo").expect("text file");
+
+        let progress = CountingProgress::default();
+        let plan = generate_plan_with_control(
+            &PlanOptions {
+                jpg_input: jpg_root,
+                raw_input: None,
+                raw_from_jpg_parent_when_missing: false,
+                recursive: false,
+                include_hidden: false,
+                follow_symlinks: false,
+                template: "{orig_name}_x".to_string(),
+                dedupe_same_maker: true,
+                exclusions: Vec::new(),
+                max_filename_len: 240,
+                min_rating: None,
+                min_file_size: 0,
+                include_videos: false,
+                raw_primary: false,
+                primary_extensions: default_primary_extensions(),
+                raw_extensions: default_raw_extensions(),
+                strict_metadata: false,
+                changed_only: false,
+                rename_companions: false,
+                only_source: None,
+                metadata_cache: false,
+                maker_aliases: Default::default(),
+                dedupe_model_maker: false,
+                date_zone: Default::default(),
+                time_shifts: Vec::new(),
+                prefer_gps_time: false,
+                extra_exif_tags: Vec::new(),
+                raw_time_tolerance: None,
+                match_raw_by_unique_id: false,
+            },
+            PlanControl {
+                progress: Some(&progress),
+                cancel: None,
+            },
+        )
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 2);
+        assert_eq!(progress.scanned.load(Ordering::SeqCst), 3);
+        assert_eq!(progress.scan_finished.load(Ordering::SeqCst), 3);
+        assert_eq!(progress.metadata_read.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn generate_plan_with_control_stops_when_cancelled() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+
+        let cancel = AtomicBool::new(true);
+        let err = generate_plan_with_control(
+            &PlanOptions {
+                jpg_input: jpg_root,
+                raw_input: None,
+                raw_from_jpg_parent_when_missing: false,
+                recursive: false,
+                include_hidden: false,
+                follow_symlinks: false,
+                template: "{orig_name}_x".to_string(),
+                dedupe_same_maker: true,
+                exclusions: Vec::new(),
+                max_filename_len: 240,
+                min_rating: None,
+                min_file_size: 0,
+                include_videos: false,
+                raw_primary: false,
+                primary_extensions: default_primary_extensions(),
+                raw_extensions: default_raw_extensions(),
+                strict_metadata: false,
+                changed_only: false,
+                rename_companions: false,
+                only_source: None,
+                metadata_cache: false,
+                maker_aliases: Default::default(),
+                dedupe_model_maker: false,
+                date_zone: Default::default(),
+                time_shifts: Vec::new(),
+                prefer_gps_time: false,
+                extra_exif_tags: Vec::new(),
+                raw_time_tolerance: None,
+                match_raw_by_unique_id: false,
+            },
+            PlanControl {
+                progress: None,
+                cancel: Some(&cancel),
+            },
+        )
+        .expect_err("cancelled plan should fail");
+        assert!(err.to_string().contains("キャンセル"));
+    }
+
+    #[test]
+    fn generate_plan_chunked_yields_batches_in_order() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        for n in 1..=5 {
+            fs::write(jpg_root.join(format!("DSC0000{n}.JPG")), b"not-a-real-jpg")
+                .expect("jpg file");
+        }
+
+        let options = PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}_x".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        };
+        let mut batch_sizes = Vec::new();
+        let mut received = Vec::new();
+        let summary = generate_plan_chunked(&options, PlanControl::default(), 2, |batch| {
+            batch_sizes.push(batch.len());
+            received.extend(batch.into_iter().map(|c| c.original_path));
+            Ok(())
+        })
+        .expect("chunked plan should succeed");
+
+        assert_eq!(batch_sizes, vec![2, 2, 1]);
+        assert!(summary.candidates.is_empty());
+        assert_eq!(summary.stats.planned, 5);
+
+        let plan = generate_plan(&options).expect("plan generation should succeed");
+        let expected = plan
+            .candidates
+            .into_iter()
+            .map(|c| c.original_path)
+            .collect::<Vec<_>>();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn generate_plan_collects_warnings_for_fallback_metadata() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        let jpg_path = jpg_root.join("DSC00001.JPG");
+        fs::write(&jpg_path, b"not-a-real-jpg").expect("jpg file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root,
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{film_sim}_{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        let warnings = &plan.candidates[0].warnings;
+        assert!(matches!(
+            warnings.first(),
+            Some(PlanWarning::ExifReadFailed { path, .. }) if path == &jpg_path
+        ));
+        assert!(warnings.contains(&PlanWarning::FilmSimMissing));
+        assert!(warnings.contains(&PlanWarning::DateFromFileModified));
+        assert_eq!(plan.warnings.len(), warnings.len());
+        assert!(plan
+            .warnings
+            .iter()
+            .all(|entry| entry.original_path == jpg_path));
+    }
+
+    #[test]
+    fn generate_plan_strict_metadata_skips_file_modified_fallback() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC00002.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            raw_root.join("DSC00001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root),
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{year}_{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: true,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        assert_eq!(
+            plan.candidates[0].original_path,
+            jpg_root.join("DSC00001.JPG")
+        );
+        assert_eq!(plan.stats.skipped_missing_date, 1);
+    }
+
+    #[test]
+    fn generate_plan_changed_only_drops_unchanged_candidates() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC 00002.JPG"), b"not-a-real-jpg").expect("jpg file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: true,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        assert_eq!(
+            plan.candidates[0].target_path,
+            jpg_root.join("DSC-00002.JPG")
+        );
+        assert_eq!(plan.stats.planned, 2);
+        assert_eq!(plan.stats.unchanged, 1);
+    }
+
+    #[test]
+    fn generate_plan_only_source_skips_other_sources() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        fs::write(jpg_root.join("DSCF0001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSCF0002.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            raw_root.join("DSCF0001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root.clone()),
+            template: "{year}_{orig_name}".to_string(),
+            only_source: Some(SourceFilter::Xmp),
+            ..PlanOptions::default()
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.candidates.len(), 1);
+        assert_eq!(
+            plan.candidates[0].original_path,
+            jpg_root.join("DSCF0001.JPG")
+        );
+        assert_eq!(plan.stats.skipped_other_source, 1);
+    }
+
+    #[test]
+    fn limit_changed_keeps_first_photos_with_companions() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        for name in ["DSCF0001", "DSCF0002", "DSCF0003"] {
+            fs::write(jpg_root.join(format!("{name}.JPG")), b"not-a-real-jpg").expect("jpg file");
+            fs::write(raw_root.join(format!("{name}.RAF")), b"not-a-real-raf").expect("raf file");
+        }
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root.clone()),
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}_x".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: true,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+        assert_eq!(plan.candidates.len(), 6);
+
+        let limited = plan.limit_changed(2);
+        let originals = limited
+            .candidates
+            .iter()
+            .map(|c| c.original_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            originals,
+            vec![
+                jpg_root.join("DSCF0001.JPG"),
+                raw_root.join("DSCF0001.RAF"),
+                jpg_root.join("DSCF0002.JPG"),
+                raw_root.join("DSCF0002.RAF"),
+            ]
+        );
+        assert_eq!(limited.stats.planned, plan.stats.planned);
+        assert!(plan.limit_changed(0).candidates.is_empty());
+    }
+
+    #[test]
+    fn generate_plan_records_field_provenance() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC00002.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            raw_root.join("DSC00001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><tiff:Make>FUJIFILM</tiff:Make></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root),
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        let with_xmp = plan
+            .candidates
+            .iter()
+            .find(|c| c.original_path == jpg_root.join("DSC00001.JPG"))
+            .expect("xmp candidate");
+        assert_eq!(with_xmp.provenance.date, Some(FieldSource::Xmp));
+        assert_eq!(with_xmp.provenance.camera_make, Some(FieldSource::Xmp));
+        assert_eq!(with_xmp.provenance.lens_model, None);
+
+        let without_xmp = plan
+            .candidates
+            .iter()
+            .find(|c| c.original_path == jpg_root.join("DSC00002.JPG"))
+            .expect("fallback candidate");
+        assert_eq!(
+            without_xmp.provenance,
+            FieldProvenance {
+                date: Some(FieldSource::FileModified),
+                ..FieldProvenance::default()
+            }
+        );
+    }
+
+    #[test]
+    fn generate_plan_breaks_down_stats_by_camera_and_source() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSC00002.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            raw_root.join("DSC00001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root,
+            raw_input: Some(raw_root),
+            template: "{orig_name}".to_string(),
+            ..PlanOptions::default()
+        })
+        .expect("plan generation should succeed");
+
+        assert_eq!(plan.stats.by_camera.get("(不明)"), Some(&2));
+        assert_eq!(plan.stats.by_source.get(&MetadataSource::Xmp), Some(&1));
+        assert_eq!(
+            plan.stats
+                .by_source
+                .get(&MetadataSource::FallbackFileModified),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn generate_plan_keeps_files_that_already_match_with_collision_suffix() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+
+        fs::write(jpg_root.join("shot_001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("shot_002.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("x.JPG"), b"not-a-real-jpg").expect("jpg file");
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "shot".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        let targets = plan
+            .candidates
+            .iter()
+            .map(|c| {
+                (
+                    c.original_path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string(),
+                    c.target_path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string(),
+                    c.changed,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![
+                (
+                    "shot_001.JPG".to_string(),
+                    "shot_001.JPG".to_string(),
+                    false
+                ),
+                (
+                    "shot_002.JPG".to_string(),
+                    "shot_002.JPG".to_string(),
+                    false
+                ),
+                ("x.JPG".to_string(), "shot.JPG".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn conforms_to_rendered_base_accepts_only_names_resolve_collision_produces() {
+        let conforms = |name: &str, max_len| {
+            conforms_to_rendered_base(Path::new(name), "shot", ".JPG", max_len)
+        };
+        assert!(conforms("shot.JPG", 240));
+        assert!(conforms("shot_001.JPG", 240));
+        assert!(conforms("shot_1234.JPG", 240));
+        assert!(!conforms("shot_000.JPG", 240));
+        assert!(!conforms("shot_0001.JPG", 240));
+        assert!(!conforms("shot_12.JPG", 240));
+        assert!(!conforms("shot_+01.JPG", 240));
+        assert!(!conforms("shot_x_001.JPG", 240));
+
+        // 上限に合わせて元の名前を詰めた連番付きの名前
+        assert!(conforms("sh_001.JPG", 10));
+        assert!(!conforms("shot_001.JPG", 10));
+    }
+
+    #[test]
+    fn resolve_collision_shortens_base_that_is_already_at_the_limit() {
+        let temp = tempdir().expect("tempdir");
+        let original = temp.path().join("x.JPG");
+        let mut planned_paths = HashSet::new();
+        planned_paths.insert(temp.path().join("abcdefgh.JPG"));
+        planned_paths.insert(temp.path().join("abcd_001.JPG"));
+
+        let target = resolve_collision(
+            &original,
+            temp.path(),
+            "abcdefgh",
+            ".JPG",
+            &[],
+            &mut planned_paths,
+            12,
+        )
+        .expect("collision resolved");
+        assert_eq!(target, temp.path().join("abcd_002.JPG"));
+    }
+
+    #[test]
+    fn generate_plan_chunked_numbers_collisions_across_batches() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        for (name, date) in [
+            ("A", "2026:02:08 10:20:32"),
+            ("B", "2026:02:08 10:20:31"),
+            ("C", "2026:02:08 10:20:30"),
+        ] {
+            fs::write(jpg_root.join(format!("{name}.JPG")), b"not-a-real-jpg").expect("jpg");
+            fs::write(
+                raw_root.join(format!("{name}.xmp")),
+                format!(
+                    r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>{date}</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#
+                ),
+            )
+            .expect("xmp");
+        }
+
+        let options = PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root),
+            template: "shot".to_string(),
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            ..PlanOptions::default()
+        };
+        let mut targets = Vec::new();
+        generate_plan_chunked(&options, PlanControl::default(), 1, |batch| {
+            targets.extend(batch.into_iter().map(|c| c.target_path));
+            Ok(())
+        })
+        .expect("chunked plan should succeed");
+
+        assert_eq!(
+            targets,
+            vec![
+                jpg_root.join("shot_002.JPG"),
+                jpg_root.join("shot_001.JPG"),
+                jpg_root.join("shot.JPG"),
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_plan_numbers_collisions_in_capture_order() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        for (name, date) in [
+            ("A", "2026:02:08 10:20:31"),
+            ("B", "2026:02:08 10:20:30"),
+            ("C", "2026:02:08 10:20:32"),
+        ] {
+            fs::write(jpg_root.join(format!("{name}.JPG")), b"not-a-real-jpg").expect("jpg");
+            fs::write(
+                raw_root.join(format!("{name}.xmp")),
+                format!(
+                    r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>{date}</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#
+                ),
+            )
+            .expect("xmp");
+        }
+
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root),
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "shot".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        let targets = plan
+            .candidates
+            .iter()
+            .map(|c| c.target_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![
+                jpg_root.join("shot_001.JPG"),
+                jpg_root.join("shot.JPG"),
+                jpg_root.join("shot_002.JPG"),
+            ]
+        );
     }
-}
 
-fn metadata_changed(a: &PartialMetadata, b: &PartialMetadata) -> bool {
-    a.date != b.date
-        || a.camera_make != b.camera_make
-        || a.camera_model != b.camera_model
-        || a.lens_make != b.lens_make
-        || a.lens_model != b.lens_model
-        || a.film_sim != b.film_sim
-}
+    #[test]
+    fn generate_plan_renames_raw_and_xmp_companions() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
 
-fn resolve_collision(
-    original_path: &Path,
-    base: &str,
-    extension: &str,
-    planned_paths: &mut HashSet<PathBuf>,
-    max_len: usize,
-) -> Result<PathBuf> {
-    let parent = original_path
-        .parent()
-        .context("親ディレクトリを取得できませんでした")?;
+        fs::write(jpg_root.join("DSCF0001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(raw_root.join("DSCF0001.RAF"), b"not-a-real-raf").expect("raf file");
+        fs::write(raw_root.join("DSCF0001.dng"), b"not-a-real-dng").expect("dng file");
+        fs::write(
+            raw_root.join("DSCF0001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
 
-    let mut candidate = parent.join(format!("{}{}", base, extension));
-    if is_available(&candidate, original_path, planned_paths) {
-        planned_paths.insert(candidate.clone());
-        return Ok(candidate);
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root.clone()),
+            raw_from_jpg_parent_when_missing: false,
+            recursive: false,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{year}_{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: true,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        })
+        .expect("plan generation should succeed");
+
+        let targets = plan
+            .candidates
+            .iter()
+            .map(|c| (c.source_label.clone(), c.target_path.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![
+                ("xmp".to_string(), jpg_root.join("2026_DSCF0001.JPG")),
+                ("dng".to_string(), raw_root.join("2026_DSCF0001.dng")),
+                ("raf".to_string(), raw_root.join("2026_DSCF0001.RAF")),
+                ("xmp".to_string(), raw_root.join("2026_DSCF0001.xmp")),
+            ]
+        );
+        // DNG と RAF の両方を JPG の候補に添える
+        assert_eq!(
+            plan.candidates[0].raw_paths,
+            vec![raw_root.join("DSCF0001.dng"), raw_root.join("DSCF0001.RAF")]
+        );
+        assert_eq!(plan.companion_roots, vec![raw_root]);
+        assert_eq!(plan.stats.planned, 4);
     }
 
-    let mut n = 1usize;
-    loop {
-        let suffix = format!("_{:03}", n);
-        let base = truncate_filename_if_needed(&(base.to_string() + &suffix), extension, max_len);
-        candidate = parent.join(format!("{}{}", base, extension));
-        if is_available(&candidate, original_path, planned_paths) {
-            planned_paths.insert(candidate.clone());
-            return Ok(candidate);
+    #[test]
+    fn generate_plan_raw_primary_with_companions_moves_each_file_once() {
+        let temp = tempdir().expect("tempdir");
+        let root = temp.path().join("raw");
+        fs::create_dir_all(&root).expect("raw root");
+        for name in ["DSCF0001.RAF", "DSCF0001.dng", "DSCF0001.xmp"] {
+            fs::write(root.join(name), b"not-a-real-raw").expect("raw file");
         }
-        n += 1;
-    }
-}
 
-fn merge_with_jpg_fallback(
-    mut base: PartialMetadata,
-    jpg_exif_meta: Option<&PartialMetadata>,
-) -> PartialMetadata {
-    if let Some(jpg_meta) = jpg_exif_meta {
-        base.merge_missing_from(jpg_meta);
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: root.clone(),
+            template: "{orig_name}_x".to_string(),
+            raw_primary: true,
+            rename_companions: true,
+            ..PlanOptions::default()
+        })
+        .expect("plan generation should succeed");
+        let mut originals = plan
+            .candidates
+            .iter()
+            .map(|c| c.original_path.clone())
+            .collect::<Vec<_>>();
+        originals.sort();
+        assert_eq!(
+            originals,
+            vec![
+                root.join("DSCF0001.RAF"),
+                root.join("DSCF0001.dng"),
+                root.join("DSCF0001.xmp"),
+            ]
+        );
+        assert_eq!(plan.stats.planned, 3);
     }
-    base
-}
 
-fn is_available(candidate: &Path, original_path: &Path, planned_paths: &HashSet<PathBuf>) -> bool {
-    if planned_paths.contains(candidate) {
-        return false;
-    }
-    if candidate == original_path {
-        return true;
-    }
-    !candidate.exists()
-}
+    #[test]
+    fn generate_plan_renames_same_folder_xmp_with_jpg() {
+        let temp = tempdir().expect("tempdir");
+        let root = temp.path().join("jpg");
+        fs::create_dir_all(&root).expect("jpg root");
+        fs::write(root.join("DSCF0001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(root.join("DSCF0001.xmp"), b"not-a-real-xmp").expect("xmp file");
 
-fn is_jpg(path: &Path) -> bool {
-    path.extension()
-        .map(|ext| {
-            let ext = ext.to_string_lossy();
-            ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg")
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: root.clone(),
+            template: "{orig_name}_x".to_string(),
+            rename_companions: true,
+            ..PlanOptions::default()
         })
-        .unwrap_or(false)
-}
+        .expect("plan generation should succeed");
+        let targets = plan
+            .candidates
+            .iter()
+            .map(|c| (c.original_path.clone(), c.target_path.clone()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            targets.get(&root.join("DSCF0001.xmp")),
+            Some(&root.join("DSCF0001_x.xmp"))
+        );
+        assert_eq!(plan.stats.planned, 2);
+    }
 
-fn is_hidden(path: &Path) -> bool {
-    path.file_name()
-        .map(|name| name.to_string_lossy().starts_with('.'))
-        .unwrap_or(false)
-}
+    #[test]
+    fn generate_plan_does_not_read_xmp_of_collision_numbered_photo() {
+        let temp = tempdir().expect("tempdir");
+        let root = temp.path().join("photos");
+        fs::create_dir_all(&root).expect("root");
+        fs::write(root.join("shot.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(
+            root.join("shot_001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2020:01:01 00:00:00</exif:DateTimeOriginal><tiff:Model>X-T5</tiff:Model><xmp:Rating>5</xmp:Rating></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("xmp file");
 
-fn file_modified_to_local(path: &Path) -> Option<DateTime<Local>> {
-    let time = fs::metadata(path).ok()?.modified().ok()?;
-    Some(DateTime::from(time))
-}
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: root.clone(),
+            raw_input: Some(root.clone()),
+            template: "{orig_name}".to_string(),
+            ..PlanOptions::default()
+        })
+        .expect("plan generation should succeed");
 
-#[cfg(test)]
-mod tests {
-    use super::{
-        generate_plan, generate_plan_for_jpg_files, merge_with_jpg_fallback, metadata_source_label,
-        PlanOptions,
-    };
-    use crate::metadata::{MetadataSource, PartialMetadata};
-    use std::fs;
-    use std::path::PathBuf;
-    use tempfile::tempdir;
+        let shot = &plan.candidates[0];
+        assert_eq!(shot.original_path, root.join("shot.JPG"));
+        assert_eq!(shot.xmp_path, None);
+        assert_eq!(shot.metadata.camera_model, None);
+        assert_ne!(shot.provenance.date, Some(FieldSource::Xmp));
+    }
 
     #[test]
-    fn merge_with_jpg_fallback_fills_missing_fields() {
-        let base = PartialMetadata {
-            camera_make: None,
-            camera_model: None,
-            lens_make: None,
-            lens_model: None,
-            ..Default::default()
-        };
-        let jpg = PartialMetadata {
-            camera_make: Some("FUJIFILM".to_string()),
-            camera_model: Some("X-H2".to_string()),
-            lens_make: Some("FUJIFILM".to_string()),
-            lens_model: Some("XF35mm F1.4 R".to_string()),
-            ..Default::default()
-        };
+    fn generate_plan_lists_only_companion_roots_with_moves() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+        fs::write(jpg_root.join("DSCF0001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(raw_root.join("DSCF0002.RAF"), b"not-a-real-raf").expect("raf file");
 
-        let merged = merge_with_jpg_fallback(base, Some(&jpg));
-        assert_eq!(merged.camera_make.as_deref(), Some("FUJIFILM"));
-        assert_eq!(merged.camera_model.as_deref(), Some("X-H2"));
-        assert_eq!(merged.lens_make.as_deref(), Some("FUJIFILM"));
-        assert_eq!(merged.lens_model.as_deref(), Some("XF35mm F1.4 R"));
+        // 別フォルダの RAW/XMP を1つも動かさない場合は、そのフォルダを計画に含めない
+        let plan = generate_plan(&PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root),
+            template: "{orig_name}_x".to_string(),
+            rename_companions: true,
+            ..PlanOptions::default()
+        })
+        .expect("plan generation should succeed");
+        assert_eq!(plan.stats.planned, 1);
+        assert!(plan.companion_roots.is_empty());
     }
 
     #[test]
-    fn merge_with_jpg_fallback_keeps_existing_values() {
-        let base = PartialMetadata {
-            camera_make: Some("SONY".to_string()),
-            camera_model: Some("A7C".to_string()),
-            lens_make: Some("SIGMA".to_string()),
-            lens_model: Some("35mm F2 DG DN".to_string()),
-            ..Default::default()
-        };
-        let jpg = PartialMetadata {
-            camera_make: Some("FUJIFILM".to_string()),
-            camera_model: Some("X-H2".to_string()),
-            lens_make: Some("FUJIFILM".to_string()),
-            lens_model: Some("XF35mm F1.4 R".to_string()),
-            ..Default::default()
+    fn generate_plan_reports_raw_and_xmp_without_jpg() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let raw_root = temp.path().join("raw");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&raw_root).expect("raw root");
+
+        fs::write(jpg_root.join("DSCF0001.JPG"), b"not-a-real-jpg").expect("jpg file");
+        fs::write(jpg_root.join("DSCF0004.JPG"), b"tiny").expect("small jpg file");
+        for name in [
+            "DSCF0001.RAF",
+            "DSCF0002.RAF",
+            "DSCF0003.xmp",
+            "DSCF0004.RAF",
+        ] {
+            fs::write(raw_root.join(name), b"not-a-real-raw").expect("raw file");
+        }
+
+        let options = PlanOptions {
+            jpg_input: jpg_root.clone(),
+            raw_input: Some(raw_root.clone()),
+            ..PlanOptions::default()
         };
+        let plan = generate_plan(&options).expect("plan generation should succeed");
+        assert_eq!(
+            plan.orphaned_companions,
+            vec![raw_root.join("DSCF0002.RAF"), raw_root.join("DSCF0003.xmp")]
+        );
 
-        let merged = merge_with_jpg_fallback(base, Some(&jpg));
-        assert_eq!(merged.camera_make.as_deref(), Some("SONY"));
-        assert_eq!(merged.camera_model.as_deref(), Some("A7C"));
-        assert_eq!(merged.lens_make.as_deref(), Some("SIGMA"));
-        assert_eq!(merged.lens_model.as_deref(), Some("35mm F2 DG DN"));
+        // 選んだファイルだけのプランでは、残りの RAW を対応漏れとして扱わない
+        let plan = generate_plan_for_jpg_files(&options, &[jpg_root.join("DSCF0001.JPG")])
+            .expect("plan generation should succeed");
+        assert!(plan.orphaned_companions.is_empty());
     }
 
     #[test]
-    fn generate_plan_uses_xmp_when_only_xmp_exists_in_raw_folder() {
+    fn generate_plan_places_files_into_template_subfolders() {
         let temp = tempdir().expect("tempdir");
         let jpg_root = temp.path().join("jpg");
         let raw_root = temp.path().join("raw");
         fs::create_dir_all(&jpg_root).expect("jpg root");
         fs::create_dir_all(&raw_root).expect("raw root");
 
-        let jpg_path = jpg_root.join("DSC00001.JPG");
-        fs::write(&jpg_path, b"not-a-real-jpg").expect("jpg file");
-
-        let xmp = raw_root.join("DSC00001.xmp");
+        fs::write(jpg_root.join("DSC00001.JPG"), b"not-a-real-jpg").expect("jpg file");
         fs::write(
-            &xmp,
-            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal><exif:Make>FUJIFILM</exif:Make></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+            raw_root.join("DSC00001.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><exif:DateTimeOriginal>2026:02:08 10:20:30</exif:DateTimeOriginal></rdf:Description></rdf:RDF></x:xmpmeta>"#,
         )
         .expect("xmp file");
 
         let plan = generate_plan(&PlanOptions {
-            jpg_input: jpg_root,
+            jpg_input: jpg_root.clone(),
             raw_input: Some(raw_root),
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
-            template: "{camera_maker}_{orig_name}".to_string(),
+            follow_symlinks: false,
+            template: "{year}/{month}/{day}_{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
         assert_eq!(plan.candidates.len(), 1);
-        let c = &plan.candidates[0];
-        assert_eq!(c.metadata_source, MetadataSource::Xmp);
-        assert_eq!(c.source_label, "xmp");
-        assert_eq!(c.metadata.camera_make.as_deref(), Some("FUJIFILM"));
+        assert_eq!(
+            plan.candidates[0].target_path,
+            jpg_root.join("2026").join("02").join("08_DSC00001.JPG")
+        );
+        assert_eq!(plan.candidates[0].rendered_base, "08_DSC00001");
     }
 
     #[test]
@@ -937,10 +4006,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         });
 
         let err = result.expect_err("plan generation should fail");
@@ -962,10 +4051,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         });
 
         let err = result.expect_err("plan generation should fail");
@@ -993,10 +4102,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         });
 
         let err = result.expect_err("plan generation should fail");
@@ -1023,10 +4152,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
@@ -1057,10 +4206,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: true,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{camera_maker}_{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
@@ -1087,10 +4256,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
@@ -1112,10 +4301,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
@@ -1148,10 +4357,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: true,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{camera_maker}_{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
@@ -1181,10 +4410,30 @@ mod tests {
                 raw_from_jpg_parent_when_missing: false,
                 recursive: false,
                 include_hidden: false,
+                follow_symlinks: false,
                 template: "{orig_name}".to_string(),
                 dedupe_same_maker: true,
                 exclusions: Vec::new(),
                 max_filename_len: 240,
+                min_rating: None,
+                min_file_size: 0,
+                include_videos: false,
+                raw_primary: false,
+                primary_extensions: default_primary_extensions(),
+                raw_extensions: default_raw_extensions(),
+                strict_metadata: false,
+                changed_only: false,
+                rename_companions: false,
+                only_source: None,
+                metadata_cache: false,
+                maker_aliases: Default::default(),
+                dedupe_model_maker: false,
+                date_zone: Default::default(),
+                time_shifts: Vec::new(),
+                prefer_gps_time: false,
+                extra_exif_tags: Vec::new(),
+                raw_time_tolerance: None,
+                match_raw_by_unique_id: false,
             },
             &[c.clone(), a.clone()],
         )
@@ -1221,10 +4470,30 @@ mod tests {
                 raw_from_jpg_parent_when_missing: false,
                 recursive: false,
                 include_hidden: false,
+                follow_symlinks: false,
                 template: "{orig_name}".to_string(),
                 dedupe_same_maker: true,
                 exclusions: Vec::new(),
                 max_filename_len: 240,
+                min_rating: None,
+                min_file_size: 0,
+                include_videos: false,
+                raw_primary: false,
+                primary_extensions: default_primary_extensions(),
+                raw_extensions: default_raw_extensions(),
+                strict_metadata: false,
+                changed_only: false,
+                rename_companions: false,
+                only_source: None,
+                metadata_cache: false,
+                maker_aliases: Default::default(),
+                dedupe_model_maker: false,
+                date_zone: Default::default(),
+                time_shifts: Vec::new(),
+                prefer_gps_time: false,
+                extra_exif_tags: Vec::new(),
+                raw_time_tolerance: None,
+                match_raw_by_unique_id: false,
             },
             &[jpg_a.clone(), jpg_b.clone()],
         )
@@ -1240,6 +4509,66 @@ mod tests {
         assert_eq!(plan.candidates[1].original_path, jpg_b);
     }
 
+    #[test]
+    fn generate_plan_for_jpg_files_accepts_folders_mixed_with_files() {
+        let temp = tempdir().expect("tempdir");
+        let folder_a = temp.path().join("session").join("a");
+        let folder_b = temp.path().join("session").join("b");
+        fs::create_dir_all(&folder_a).expect("folder a");
+        fs::create_dir_all(&folder_b).expect("folder b");
+        let jpg_a1 = folder_a.join("A1.JPG");
+        let jpg_a2 = folder_a.join("A2.JPG");
+        let jpg_b = folder_b.join("B.JPG");
+        fs::write(&jpg_a1, b"a1").expect("write a1");
+        fs::write(&jpg_a2, b"a2").expect("write a2");
+        fs::write(&jpg_b, b"b").expect("write b");
+        fs::write(folder_b.join("C.JPG"), b"c").expect("write unselected");
+
+        let plan = generate_plan_for_jpg_files(
+            &PlanOptions {
+                jpg_input: folder_a.clone(),
+                raw_input: None,
+                raw_from_jpg_parent_when_missing: false,
+                recursive: false,
+                include_hidden: false,
+                follow_symlinks: false,
+                template: "{orig_name}".to_string(),
+                dedupe_same_maker: true,
+                exclusions: Vec::new(),
+                max_filename_len: 240,
+                min_rating: None,
+                min_file_size: 0,
+                include_videos: false,
+                raw_primary: false,
+                primary_extensions: default_primary_extensions(),
+                raw_extensions: default_raw_extensions(),
+                strict_metadata: false,
+                changed_only: false,
+                rename_companions: false,
+                only_source: None,
+                metadata_cache: false,
+                maker_aliases: Default::default(),
+                dedupe_model_maker: false,
+                date_zone: Default::default(),
+                time_shifts: Vec::new(),
+                prefer_gps_time: false,
+                extra_exif_tags: Vec::new(),
+                raw_time_tolerance: None,
+                match_raw_by_unique_id: false,
+            },
+            &[folder_a.clone(), jpg_b.clone(), jpg_a1.clone()],
+        )
+        .expect("folders and files should be accepted together");
+
+        assert_eq!(plan.jpg_roots, vec![folder_a, folder_b]);
+        let originals: Vec<_> = plan
+            .candidates
+            .iter()
+            .map(|candidate| candidate.original_path.clone())
+            .collect();
+        assert_eq!(originals, vec![jpg_a1, jpg_a2, jpg_b]);
+    }
+
     #[test]
     fn generate_plan_for_jpg_files_raw_parent_uses_each_jpg_parent() {
         let temp = tempdir().expect("tempdir");
@@ -1273,10 +4602,30 @@ mod tests {
                 raw_from_jpg_parent_when_missing: true,
                 recursive: false,
                 include_hidden: false,
+                follow_symlinks: false,
                 template: "{camera_maker}_{orig_name}".to_string(),
                 dedupe_same_maker: true,
                 exclusions: Vec::new(),
                 max_filename_len: 240,
+                min_rating: None,
+                min_file_size: 0,
+                include_videos: false,
+                raw_primary: false,
+                primary_extensions: default_primary_extensions(),
+                raw_extensions: default_raw_extensions(),
+                strict_metadata: false,
+                changed_only: false,
+                rename_companions: false,
+                only_source: None,
+                metadata_cache: false,
+                maker_aliases: Default::default(),
+                dedupe_model_maker: false,
+                date_zone: Default::default(),
+                time_shifts: Vec::new(),
+                prefer_gps_time: false,
+                extra_exif_tags: Vec::new(),
+                raw_time_tolerance: None,
+                match_raw_by_unique_id: false,
             },
             &[jpg_a.clone(), jpg_b.clone()],
         )
@@ -1314,10 +4663,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: false,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
@@ -1353,10 +4722,30 @@ mod tests {
             raw_from_jpg_parent_when_missing: false,
             recursive: true,
             include_hidden: false,
+            follow_symlinks: false,
             template: "{orig_name}".to_string(),
             dedupe_same_maker: true,
             exclusions: Vec::new(),
             max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
         })
         .expect("plan generation should succeed");
 
@@ -1372,23 +4761,92 @@ mod tests {
         assert_eq!(plan.stats.skipped_hidden, 1);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn generate_plan_recursive_follows_symlinked_directories_when_enabled() {
+        let temp = tempdir().expect("tempdir");
+        let jpg_root = temp.path().join("jpg");
+        let outside = temp.path().join("library").join("2025");
+        fs::create_dir_all(&jpg_root).expect("jpg root");
+        fs::create_dir_all(&outside).expect("outside dir");
+        fs::write(outside.join("LINKED.JPG"), b"linked").expect("linked jpg");
+        std::os::unix::fs::symlink(&outside, jpg_root.join("2025")).expect("symlink");
+
+        let mut options = PlanOptions {
+            jpg_input: jpg_root,
+            raw_input: None,
+            raw_from_jpg_parent_when_missing: false,
+            recursive: true,
+            include_hidden: false,
+            follow_symlinks: false,
+            template: "{orig_name}".to_string(),
+            dedupe_same_maker: true,
+            exclusions: Vec::new(),
+            max_filename_len: 240,
+            min_rating: None,
+            min_file_size: 0,
+            include_videos: false,
+            raw_primary: false,
+            primary_extensions: default_primary_extensions(),
+            raw_extensions: default_raw_extensions(),
+            strict_metadata: false,
+            changed_only: false,
+            rename_companions: false,
+            only_source: None,
+            metadata_cache: false,
+            maker_aliases: Default::default(),
+            dedupe_model_maker: false,
+            date_zone: Default::default(),
+            time_shifts: Vec::new(),
+            prefer_gps_time: false,
+            extra_exif_tags: Vec::new(),
+            raw_time_tolerance: None,
+            match_raw_by_unique_id: false,
+        };
+        let plan = generate_plan(&options).expect("plan generation should succeed");
+        assert!(plan.candidates.is_empty());
+
+        options.follow_symlinks = true;
+        let plan = generate_plan(&options).expect("plan generation should succeed");
+        assert_eq!(plan.candidates.len(), 1);
+        assert!(plan.follow_symlinks);
+        assert_eq!(
+            plan.candidates[0]
+                .original_path
+                .file_name()
+                .and_then(|v| v.to_str()),
+            Some("LINKED.JPG")
+        );
+    }
+
     #[test]
     fn metadata_source_label_uses_raw_extension_for_raw_exif() {
         let raw_path = PathBuf::from("/tmp/session/DSC00001.RAF");
-        let label = metadata_source_label(MetadataSource::RawExif, Some(&raw_path));
+        let jpg_path = PathBuf::from("/tmp/session/DSC00001.JPG");
+        let label = metadata_source_label(MetadataSource::RawExif, Some(&raw_path), &jpg_path);
         assert_eq!(label, "raf");
     }
 
     #[test]
     fn metadata_source_label_returns_xmp_for_combined_source() {
         let raw_path = PathBuf::from("/tmp/session/DSC00001.DNG");
-        let label = metadata_source_label(MetadataSource::XmpAndRawExif, Some(&raw_path));
+        let jpg_path = PathBuf::from("/tmp/session/DSC00001.JPG");
+        let label =
+            metadata_source_label(MetadataSource::XmpAndRawExif, Some(&raw_path), &jpg_path);
         assert_eq!(label, "xmp");
     }
 
     #[test]
     fn metadata_source_label_returns_jpg_for_fallback_source() {
-        let label = metadata_source_label(MetadataSource::FallbackFileModified, None);
+        let jpg_path = PathBuf::from("/tmp/session/DSC00001.JPG");
+        let label = metadata_source_label(MetadataSource::FallbackFileModified, None, &jpg_path);
         assert_eq!(label, "jpg");
     }
+
+    #[test]
+    fn metadata_source_label_returns_tiff_for_tiff_input() {
+        let tiff_path = PathBuf::from("/tmp/session/DSC00001.TIF");
+        let label = metadata_source_label(MetadataSource::JpgExif, None, &tiff_path);
+        assert_eq!(label, "tiff");
+    }
 }
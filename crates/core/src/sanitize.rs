@@ -96,13 +96,22 @@ pub fn sanitize_filename(value: &str) -> String {
     out
 }
 
+// ファイルシステムの上限と同じ単位で数える (Windows は UTF-16、それ以外は UTF-8 のバイト数)
+pub fn filename_len(value: &str) -> usize {
+    if cfg!(windows) {
+        value.encode_utf16().count()
+    } else {
+        value.len()
+    }
+}
+
 pub fn truncate_filename_if_needed(
     filename_without_ext: &str,
     extension_with_dot: &str,
     limit: usize,
 ) -> String {
-    let ext_len = extension_with_dot.chars().count();
-    if filename_without_ext.chars().count() + ext_len <= limit {
+    let ext_len = filename_len(extension_with_dot);
+    if filename_len(filename_without_ext) + ext_len <= limit {
         return filename_without_ext.to_string();
     }
 
@@ -110,14 +119,20 @@ pub fn truncate_filename_if_needed(
     while tokens.len() > 1 {
         tokens.pop();
         let candidate = tokens.join("_");
-        if candidate.chars().count() + ext_len <= limit {
+        if filename_len(&candidate) + ext_len <= limit {
             return candidate;
         }
     }
 
+    // 多バイト文字の途中で切らないよう1文字ずつ詰める
+    let budget = limit.saturating_sub(ext_len);
+    let mut used = 0;
     filename_without_ext
         .chars()
-        .take(limit.saturating_sub(ext_len))
+        .take_while(|ch| {
+            used += filename_len(ch.encode_utf8(&mut [0; 4]));
+            used <= budget
+        })
         .collect()
 }
 
@@ -456,6 +471,25 @@ mod tests {
         assert_eq!(value, "A_B_C");
     }
 
+    #[test]
+    fn truncate_drops_trailing_tokens_first() {
+        let value = truncate_filename_if_needed("20240101_X-T5_XF23mm", ".JPG", 18);
+        assert_eq!(value, "20240101_X-T5");
+    }
+
+    #[test]
+    fn truncate_counts_filesystem_units_for_multibyte_names() {
+        let name = "写真".repeat(100);
+        let value = truncate_filename_if_needed(&name, ".JPG", 240);
+        assert!(filename_len(&value) + 4 <= 240);
+        assert!(name.starts_with(&value));
+        if cfg!(windows) {
+            assert_eq!(value.chars().count(), 200);
+        } else {
+            assert_eq!(value.chars().count(), 78);
+        }
+    }
+
     #[test]
     fn exclusions_handle_unicode_casefold_without_panicking() {
         let value = apply_exclusions("İ_IMG_İ".to_string(), &["İ".to_string()]);
@@ -1,12 +1,14 @@
 use crate::metadata::PhotoMetadata;
 use chrono::Datelike;
 use chrono::Timelike;
+use std::collections::BTreeMap;
 use thiserror::Error;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TemplatePart {
     Literal(String),
     Token(Token),
+    Separator,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -23,9 +25,45 @@ pub enum Token {
     LensMake,
     LensModel,
     FilmSim,
+    DynamicRange,
+    GrainEffect,
+    ColorChrome,
+    WbShift,
+    FocalLengthEq,
+    ExposureComp,
+    Flash,
+    Label,
+    Keywords,
+    Title,
     OrigName,
 }
 
+pub const TEMPLATE_TOKENS: &[&str] = &[
+    "date",
+    "year",
+    "month",
+    "day",
+    "hour",
+    "minute",
+    "second",
+    "camera_maker",
+    "camera_model",
+    "lens_maker",
+    "lens_model",
+    "film_sim",
+    "dynamic_range",
+    "grain_effect",
+    "color_chrome",
+    "wb_shift",
+    "focal_length_eq",
+    "exposure_comp",
+    "flash",
+    "label",
+    "keywords",
+    "title",
+    "orig_name",
+];
+
 #[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum TemplateError {
     #[error("テンプレートが空です")]
@@ -36,6 +74,8 @@ pub enum TemplateError {
     InvalidFilenameChar(char),
     #[error("未対応トークンです: {0}")]
     UnknownToken(String),
+    #[error("フォルダ区切り(/)の前後が空です")]
+    EmptyPathSegment,
 }
 
 pub fn validate_template(input: &str) -> Result<(), TemplateError> {
@@ -78,6 +118,12 @@ pub fn parse_template(input: &str) -> Result<Vec<TemplatePart>, TemplateError> {
                 parts.push(TemplatePart::Token(parse_token(&token)?));
             }
             '}' => return Err(TemplateError::UnbalancedBraces),
+            '/' => {
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(TemplatePart::Separator);
+            }
             _ => {
                 if is_disallowed_filename_char(ch) {
                     return Err(TemplateError::InvalidFilenameChar(ch));
@@ -94,28 +140,59 @@ pub fn parse_template(input: &str) -> Result<Vec<TemplatePart>, TemplateError> {
     if parts.is_empty() {
         return Err(TemplateError::Empty);
     }
+    if split_template_segments(&parts).any(|segment| segment.is_empty()) {
+        return Err(TemplateError::EmptyPathSegment);
+    }
 
     Ok(parts)
 }
 
+pub fn split_template_segments(
+    parts: &[TemplatePart],
+) -> impl Iterator<Item = &[TemplatePart]> + '_ {
+    parts.split(|part| *part == TemplatePart::Separator)
+}
+
+static NO_MAKER_ALIASES: BTreeMap<String, String> = BTreeMap::new();
+
+// メーカー名の表記と、{lens_maker} / {camera_model} でカメラメーカー名を重ねないかどうか
+#[derive(Debug, Clone, Copy)]
+pub struct MakerNameOptions<'a> {
+    pub dedupe_same_maker: bool,
+    pub dedupe_model_maker: bool,
+    pub aliases: &'a BTreeMap<String, String>,
+}
+
+impl Default for MakerNameOptions<'_> {
+    fn default() -> Self {
+        Self {
+            dedupe_same_maker: true,
+            dedupe_model_maker: false,
+            aliases: &NO_MAKER_ALIASES,
+        }
+    }
+}
+
 pub fn render_template(parts: &[TemplatePart], metadata: &PhotoMetadata) -> String {
-    render_template_with_options(parts, metadata, true)
+    render_template_with_options(parts, metadata, MakerNameOptions::default())
 }
 
 pub fn render_template_with_options(
     parts: &[TemplatePart],
     metadata: &PhotoMetadata,
-    dedupe_same_maker: bool,
+    maker: MakerNameOptions<'_>,
 ) -> String {
+    let maker_aliases = maker.aliases;
     let same_maker = same_maker(
-        metadata.normalized_camera_make(),
-        metadata.normalized_lens_make(),
-    ) && dedupe_same_maker;
+        metadata.normalized_camera_make(maker_aliases),
+        metadata.normalized_lens_make(maker_aliases),
+    ) && maker.dedupe_same_maker;
 
     let mut output = String::new();
     for part in parts {
         match part {
             TemplatePart::Literal(s) => output.push_str(&normalize_literal_connector(s)),
+            TemplatePart::Separator => output.push('/'),
             TemplatePart::Token(token) => {
                 let value = match token {
                     Token::Date => format_date(metadata),
@@ -126,21 +203,22 @@ pub fn render_template_with_options(
                     Token::Minute => format!("{:02}", metadata.date.minute()),
                     Token::Second => format!("{:02}", metadata.date.second()),
                     Token::CameraMake => metadata
-                        .normalized_camera_make()
+                        .normalized_camera_make(maker_aliases)
                         .unwrap_or_default()
                         .to_string(),
-                    Token::CameraModel => metadata
-                        .camera_model
-                        .as_deref()
-                        .unwrap_or_default()
-                        .trim()
-                        .to_string(),
+                    Token::CameraModel => if maker.dedupe_model_maker {
+                        metadata.camera_model_without_maker(maker_aliases)
+                    } else {
+                        metadata.camera_model.as_deref().map(str::trim)
+                    }
+                    .unwrap_or_default()
+                    .to_string(),
                     Token::LensMake => {
                         if same_maker {
                             String::new()
                         } else {
                             metadata
-                                .normalized_lens_make()
+                                .normalized_lens_make(maker_aliases)
                                 .unwrap_or_default()
                                 .to_string()
                         }
@@ -157,6 +235,30 @@ pub fn render_template_with_options(
                         .unwrap_or_default()
                         .trim()
                         .to_string(),
+                    Token::DynamicRange => optional_field(&metadata.dynamic_range),
+                    Token::GrainEffect => optional_field(&metadata.grain_effect),
+                    Token::ColorChrome => optional_field(&metadata.color_chrome_effect),
+                    Token::WbShift => optional_field(&metadata.wb_shift),
+                    Token::FocalLengthEq => optional_field(&metadata.focal_length_eq),
+                    Token::ExposureComp => optional_field(&metadata.exposure_compensation),
+                    // 発光した時だけ "Flash" を入れる
+                    Token::Flash => {
+                        if metadata.flash_fired == Some(true) {
+                            "Flash".to_string()
+                        } else {
+                            String::new()
+                        }
+                    }
+                    Token::Label => optional_field(&metadata.label),
+                    // キーワードは "-" でつなぐ
+                    Token::Keywords => metadata
+                        .keywords
+                        .iter()
+                        .map(|keyword| keyword.trim())
+                        .filter(|keyword| !keyword.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("-"),
+                    Token::Title => metadata.title(None).unwrap_or_default().trim().to_string(),
                     Token::OrigName => metadata.original_name.clone(),
                 };
                 output.push_str(&normalize_token_value(&value));
@@ -181,11 +283,25 @@ fn parse_token(token: &str) -> Result<Token, TemplateError> {
         "lens_maker" => Ok(Token::LensMake),
         "lens_model" => Ok(Token::LensModel),
         "film_sim" => Ok(Token::FilmSim),
+        "dynamic_range" => Ok(Token::DynamicRange),
+        "grain_effect" => Ok(Token::GrainEffect),
+        "color_chrome" => Ok(Token::ColorChrome),
+        "wb_shift" => Ok(Token::WbShift),
+        "focal_length_eq" => Ok(Token::FocalLengthEq),
+        "exposure_comp" => Ok(Token::ExposureComp),
+        "flash" => Ok(Token::Flash),
+        "label" => Ok(Token::Label),
+        "keywords" => Ok(Token::Keywords),
+        "title" => Ok(Token::Title),
         "orig_name" => Ok(Token::OrigName),
         other => Err(TemplateError::UnknownToken(other.to_string())),
     }
 }
 
+fn optional_field(value: &Option<String>) -> String {
+    value.as_deref().unwrap_or_default().trim().to_string()
+}
+
 fn same_maker(camera_make: Option<&str>, lens_make: Option<&str>) -> bool {
     match (camera_make, lens_make) {
         (Some(camera), Some(lens)) => camera.eq_ignore_ascii_case(lens),
@@ -231,12 +347,26 @@ mod tests {
     fn metadata() -> PhotoMetadata {
         PhotoMetadata {
             source: MetadataSource::JpgExif,
-            date: Local::now(),
+            date: Local::now().fixed_offset(),
             camera_make: Some("FUJIFILM".to_string()),
             camera_model: Some("X-T5".to_string()),
             lens_make: Some("fujifilm".to_string()),
             lens_model: Some("XF33mmF1.4".to_string()),
             film_sim: Some("Classic Chrome".to_string()),
+            rating: None,
+            dynamic_range: None,
+            grain_effect: None,
+            color_chrome_effect: None,
+            wb_shift: None,
+            focal_length_eq: None,
+            exposure_compensation: None,
+            flash_fired: None,
+            image_unique_id: None,
+            title: Default::default(),
+            description: Default::default(),
+            label: None,
+            keywords: Vec::new(),
+            extra_tags: Default::default(),
             original_name: "IMG_0001".to_string(),
             jpg_path: PathBuf::from("IMG_0001.JPG"),
         }
@@ -248,6 +378,13 @@ mod tests {
         assert!(!parsed.is_empty());
     }
 
+    #[test]
+    fn template_tokens_are_all_parseable() {
+        for token in TEMPLATE_TOKENS {
+            assert!(parse_token(token).is_ok(), "{token}");
+        }
+    }
+
     #[test]
     fn parse_template_invalid_unknown() {
         let err = parse_template("{foo}").expect_err("must fail");
@@ -272,11 +409,37 @@ mod tests {
         assert_eq!(err, TemplateError::InvalidFilenameChar('/'));
     }
 
+    #[test]
+    fn parse_template_splits_folder_segments() {
+        let parsed = parse_template("{year}/{month}/{date}_{orig_name}").expect("must parse");
+        let segments = split_template_segments(&parsed).collect::<Vec<_>>();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0], &[TemplatePart::Token(Token::Year)]);
+
+        let rendered = render_template(&parsed, &metadata());
+        assert_eq!(rendered.matches('/').count(), 2);
+    }
+
+    #[test]
+    fn parse_template_rejects_empty_folder_segment() {
+        for template in ["/{orig_name}", "{year}//{orig_name}", "{year}/"] {
+            let err = parse_template(template).expect_err("must fail");
+            assert_eq!(err, TemplateError::EmptyPathSegment);
+        }
+    }
+
+    fn maker_options(dedupe_same_maker: bool) -> MakerNameOptions<'static> {
+        MakerNameOptions {
+            dedupe_same_maker,
+            ..MakerNameOptions::default()
+        }
+    }
+
     #[test]
     fn render_dedupes_lens_maker() {
         let parsed =
             parse_template("{camera_maker}_{lens_maker}_{lens_model}").expect("must parse");
-        let rendered = render_template_with_options(&parsed, &metadata(), true);
+        let rendered = render_template_with_options(&parsed, &metadata(), maker_options(true));
         assert_eq!(rendered, "FUJIFILM__XF33mmF1.4");
     }
 
@@ -284,7 +447,7 @@ mod tests {
     fn render_keeps_lens_maker_when_dedupe_off() {
         let parsed =
             parse_template("{camera_maker}_{lens_maker}_{lens_model}").expect("must parse");
-        let rendered = render_template_with_options(&parsed, &metadata(), false);
+        let rendered = render_template_with_options(&parsed, &metadata(), maker_options(false));
         assert_eq!(rendered, "FUJIFILM_fujifilm_XF33mmF1.4");
     }
 
@@ -301,14 +464,96 @@ mod tests {
         m.lens_model = Some("XF35mm F1.4 R".to_string());
         m.film_sim = Some("Classic Chrome".to_string());
         let parsed = parse_template("{lens_model}_{film_sim}").expect("must parse");
-        let rendered = render_template_with_options(&parsed, &m, true);
+        let rendered = render_template_with_options(&parsed, &m, maker_options(true));
         assert_eq!(rendered, "XF35mm-F1.4-R_Classic-Chrome");
     }
 
+    #[test]
+    fn render_dedupes_maker_inside_camera_model_when_enabled() {
+        let mut m = metadata();
+        m.camera_make = Some("NIKON CORPORATION".to_string());
+        m.camera_model = Some("NIKON Z 6_2".to_string());
+        let parsed = parse_template("{camera_maker}_{camera_model}").expect("must parse");
+        let options = MakerNameOptions {
+            dedupe_model_maker: true,
+            ..MakerNameOptions::default()
+        };
+        assert_eq!(
+            render_template_with_options(&parsed, &m, options),
+            "Nikon_Z-6_2"
+        );
+        assert_eq!(
+            render_template_with_options(&parsed, &m, maker_options(true)),
+            "Nikon_NIKON-Z-6_2"
+        );
+    }
+
+    #[test]
+    fn render_fujifilm_recipe_tokens() {
+        let mut m = metadata();
+        m.dynamic_range = Some("DR400".to_string());
+        m.grain_effect = Some("Weak Small".to_string());
+        m.color_chrome_effect = Some("Strong".to_string());
+        let parsed = parse_template("{dynamic_range}_{grain_effect}_{color_chrome}_{wb_shift}")
+            .expect("must parse");
+        let rendered = render_template_with_options(&parsed, &m, maker_options(true));
+        assert_eq!(rendered, "DR400_Weak-Small_Strong_");
+    }
+
+    #[test]
+    fn render_label_keywords_and_title_tokens() {
+        let mut m = metadata();
+        let parsed = parse_template("{label}_{keywords}_{title}").expect("must parse");
+        m.label = Some("Red".to_string());
+        m.keywords = vec!["harbor".to_string(), "night".to_string()];
+        m.title
+            .insert("x-default".to_string(), "Evening walk".to_string());
+        assert_eq!(
+            render_template_with_options(&parsed, &m, maker_options(true)),
+            "Red_harbor-night_Evening-walk"
+        );
+    }
+
+    #[test]
+    fn render_focal_length_eq_token() {
+        let mut m = metadata();
+        let parsed = parse_template("{focal_length_eq}_{orig_name}").expect("must parse");
+        assert_eq!(
+            render_template_with_options(&parsed, &m, maker_options(true)),
+            "_IMG_0001"
+        );
+        m.focal_length_eq = Some("53mm".to_string());
+        assert_eq!(
+            render_template_with_options(&parsed, &m, maker_options(true)),
+            "53mm_IMG_0001"
+        );
+    }
+
+    #[test]
+    fn render_flash_only_when_fired() {
+        let mut m = metadata();
+        m.exposure_compensation = Some("-0.7EV".to_string());
+        let parsed = parse_template("{exposure_comp}_{flash}_{orig_name}").expect("must parse");
+        assert_eq!(
+            render_template_with_options(&parsed, &m, maker_options(true)),
+            "-0.7EV__IMG_0001"
+        );
+        m.flash_fired = Some(false);
+        assert_eq!(
+            render_template_with_options(&parsed, &m, maker_options(true)),
+            "-0.7EV__IMG_0001"
+        );
+        m.flash_fired = Some(true);
+        assert_eq!(
+            render_template_with_options(&parsed, &m, maker_options(true)),
+            "-0.7EV_Flash_IMG_0001"
+        );
+    }
+
     #[test]
     fn render_normalizes_literal_separator_to_underscore() {
         let parsed = parse_template("{date} - {orig_name}").expect("must parse");
-        let rendered = render_template_with_options(&parsed, &metadata(), true);
+        let rendered = render_template_with_options(&parsed, &metadata(), maker_options(true));
         assert!(rendered.contains("_"));
         assert!(!rendered.contains(" - "));
     }
@@ -317,7 +562,7 @@ mod tests {
     fn render_supports_split_date_tokens() {
         let parsed = parse_template("{year}{month}{day}{hour}{minute}{second}_{orig_name}")
             .expect("must parse");
-        let rendered = render_template_with_options(&parsed, &metadata(), true);
+        let rendered = render_template_with_options(&parsed, &metadata(), maker_options(true));
         assert!(rendered.ends_with("_IMG_0001"));
         assert_eq!(rendered.len(), 14 + "_IMG_0001".len());
     }
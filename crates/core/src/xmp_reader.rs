@@ -1,13 +1,21 @@
-use crate::exif_reader::normalize_film_simulation_from_camera_profile;
-use crate::metadata::PartialMetadata;
+use crate::exif_reader::{
+    format_exposure_compensation, format_focal_length,
+    normalize_film_simulation_from_camera_profile, normalize_image_unique_id, parse_flash_fired,
+};
+use crate::metadata::{local_date, parse_rating, PartialMetadata};
 use anyhow::{Context, Result};
-use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
-use std::collections::HashMap;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
 
+const DEFAULT_LANG: &str = "x-default";
+
+// 名前空間は見ずにタグ名だけで照合する。Capture One や DxO は同じ項目を Adobe と別の名前空間
+// (tiff: と exif:、aux: と exifEX: など) や別名で書くことがある
 const TARGET_XMP_KEYS: &[&str] = &[
     "datetimeoriginal",
+    "datetimedigitized",
     "createdate",
     "datecreated",
     "make",
@@ -19,30 +27,95 @@ const TARGET_XMP_KEYS: &[&str] = &[
     "filmsimulation",
     "filmmode",
     "filmsimulationname",
+    "rating",
+    "ratingpercent",
+    "label",
+    "focallengthin35mmfilm",
+    "focallengthin35mmformat",
+    "exposurebiasvalue",
+    "exposurecompensation",
+    "fired",
+    "flash",
+    "imageuniqueid",
+    "gpstimestamp",
 ];
 
 pub fn read_xmp_metadata(path: &Path) -> Result<PartialMetadata> {
     let xml = fs::read_to_string(path)
         .with_context(|| format!("XMPを開けませんでした: {}", path.display()))?;
-    let values = collect_tag_values(&xml);
+    Ok(parse_xmp_metadata(&xml))
+}
+
+// Lightroom / Camera Raw が記録する元のRAWファイル名。読めない場合は None
+pub(crate) fn read_xmp_raw_file_name(path: &Path) -> Option<String> {
+    let xml = fs::read_to_string(path).ok()?;
+    extract_tag_value(&xml, "crs:RawFileName")
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
 
-    let date = pick_value(&values, &["datetimeoriginal", "createdate", "datecreated"])
-        .as_deref()
-        .and_then(parse_date);
+pub(crate) fn parse_xmp_metadata(xml: &str) -> PartialMetadata {
+    let values = collect_tag_values(xml);
+
+    let date = pick_value(
+        &values,
+        &[
+            "datetimeoriginal",
+            "datetimedigitized",
+            "createdate",
+            "datecreated",
+        ],
+    )
+    .as_deref()
+    .and_then(parse_date);
     let camera_make = pick_value(&values, &["make"]);
     let camera_model = pick_value(&values, &["model"]);
     let lens_make = pick_value(&values, &["lensmake"]);
     let lens_model = pick_value(&values, &["lensmodel", "lens"]);
-    let film_sim = pick_film_simulation(&xml, &values);
-
-    Ok(PartialMetadata {
+    let film_sim = pick_film_simulation(xml, &values);
+    let rating = pick_value(&values, &["rating"])
+        .and_then(|raw| parse_rating(&raw))
+        .or_else(|| {
+            pick_value(&values, &["ratingpercent"]).and_then(|raw| rating_from_percent(&raw))
+        });
+    let label = pick_value(&values, &["label"]);
+    let focal_length_eq = pick_value(
+        &values,
+        &["focallengthin35mmfilm", "focallengthin35mmformat"],
+    )
+    .and_then(|raw| format_focal_length(&raw));
+    let exposure_compensation = pick_value(&values, &["exposurebiasvalue", "exposurecompensation"])
+        .and_then(|raw| format_exposure_compensation(&raw));
+    // exif:Flash は通常は構造体で、発光の有無は exif:Fired に入る。EXIF の値をそのまま書くソフトもある
+    let flash_fired = pick_value(&values, &["fired"])
+        .or_else(|| pick_value(&values, &["flash"]).filter(|raw| !raw.starts_with('<')))
+        .and_then(|raw| parse_flash_fired(&raw));
+    // サイドカーの xmpMM:DocumentID はサイドカー自身に振られた値なので、カメラの値だけを読む
+    let image_unique_id =
+        pick_value(&values, &["imageuniqueid"]).and_then(|raw| normalize_image_unique_id(&raw));
+    // exif:GPSTimeStamp は日付込みの UTC 時刻 ("2026-02-08T01:20:30Z")
+    let gps_date = pick_value(&values, &["gpstimestamp"])
+        .and_then(|raw| DateTime::parse_from_rfc3339(raw.trim()).ok());
+
+    PartialMetadata {
         date,
         camera_make: normalize(camera_make),
         camera_model: normalize(camera_model),
         lens_make: normalize(lens_make),
         lens_model: normalize(lens_model),
         film_sim: normalize(film_sim),
-    })
+        rating,
+        focal_length_eq,
+        exposure_compensation,
+        flash_fired,
+        image_unique_id,
+        title: extract_lang_alt(xml, "dc:title"),
+        description: extract_lang_alt(xml, "dc:description"),
+        label: normalize(label),
+        keywords: extract_list(xml, "dc:subject"),
+        gps_date,
+        ..PartialMetadata::default()
+    }
 }
 
 fn pick_value(values: &HashMap<String, String>, keys: &[&str]) -> Option<String> {
@@ -136,6 +209,107 @@ fn extract_crs_camera_profile(xml: &str) -> Option<String> {
     None
 }
 
+// dc:title などの rdf:Alt を xml:lang -> 本文で返す。rdf:li を使わず値だけを書く形式は x-default とみなす
+fn extract_lang_alt(xml: &str, tag_name: &str) -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    let Some(block) = extract_element_block(xml, tag_name) else {
+        if let Some(text) = extract_attribute_value(xml, tag_name)
+            .map(|raw| html_unescape_basic(raw.trim()))
+            .filter(|text| !text.is_empty())
+        {
+            values.insert(DEFAULT_LANG.to_string(), text);
+        }
+        return values;
+    };
+
+    let mut cursor = 0usize;
+    while let Some(open_rel) = block[cursor..].find("<rdf:li") {
+        let open = cursor + open_rel;
+        let Some(tag_end_rel) = block[open..].find('>') else {
+            break;
+        };
+        let tag_end = open + tag_end_rel;
+        let Some(close_rel) = block[tag_end + 1..].find("</rdf:li>") else {
+            break;
+        };
+        let close = tag_end + 1 + close_rel;
+        let lang = extract_attribute_value(&block[open..tag_end], "xml:lang")
+            .map(|lang| lang.trim().to_string())
+            .filter(|lang| !lang.is_empty())
+            .unwrap_or_else(|| DEFAULT_LANG.to_string());
+        let text = html_unescape_basic(block[tag_end + 1..close].trim());
+        if !text.is_empty() {
+            values.entry(lang).or_insert(text);
+        }
+        cursor = close + "</rdf:li>".len();
+    }
+
+    let text = block.trim();
+    if values.is_empty() && !text.is_empty() && !text.starts_with('<') {
+        values.insert(DEFAULT_LANG.to_string(), html_unescape_basic(text));
+    }
+    values
+}
+
+// dc:subject などの rdf:Bag / rdf:Seq を並び順のまま返す。重複は最初の1つだけ残す
+fn extract_list(xml: &str, tag_name: &str) -> Vec<String> {
+    let mut values = Vec::<String>::new();
+    let Some(block) = extract_element_block(xml, tag_name) else {
+        return extract_attribute_value(xml, tag_name)
+            .map(|raw| html_unescape_basic(raw.trim()))
+            .filter(|text| !text.is_empty())
+            .into_iter()
+            .collect();
+    };
+
+    let mut cursor = 0usize;
+    while let Some(open_rel) = block[cursor..].find("<rdf:li") {
+        let open = cursor + open_rel;
+        let Some(tag_end_rel) = block[open..].find('>') else {
+            break;
+        };
+        let tag_end = open + tag_end_rel;
+        let Some(close_rel) = block[tag_end + 1..].find("</rdf:li>") else {
+            break;
+        };
+        let close = tag_end + 1 + close_rel;
+        let text = html_unescape_basic(block[tag_end + 1..close].trim());
+        if !text.is_empty() && !values.contains(&text) {
+            values.push(text);
+        }
+        cursor = close + "</rdf:li>".len();
+    }
+
+    let text = block.trim();
+    if values.is_empty() && !text.is_empty() && !text.starts_with('<') {
+        values.push(html_unescape_basic(text));
+    }
+    values
+}
+
+// 開始タグと終了タグの間をそのまま返す
+fn extract_element_block<'a>(xml: &'a str, tag_name: &str) -> Option<&'a str> {
+    let open_prefix = format!("<{tag_name}");
+    let mut cursor = 0usize;
+    while let Some(open_rel) = xml[cursor..].find(&open_prefix) {
+        let open = cursor + open_rel;
+        let after = &xml[open + open_prefix.len()..];
+        // dc:titleX のような別名のタグは飛ばす
+        if !after.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            cursor = open + open_prefix.len();
+            continue;
+        }
+        let tag_end = open + xml[open..].find('>')?;
+        if xml[..tag_end].ends_with('/') {
+            return None;
+        }
+        let close_tag = format!("</{tag_name}>");
+        let close = tag_end + 1 + xml[tag_end + 1..].find(&close_tag)?;
+        return Some(&xml[tag_end + 1..close]);
+    }
+    None
+}
+
 fn extract_tag_value(haystack: &str, tag_name: &str) -> Option<String> {
     extract_attribute_value(haystack, tag_name)
         .or_else(|| extract_element_text_value(haystack, tag_name))
@@ -331,13 +505,33 @@ fn normalize(value: Option<String>) -> Option<String> {
 }
 
 fn normalize_tag_name(tag: &str) -> String {
-    tag.rsplit(':')
-        .next()
-        .unwrap_or_default()
+    let (prefix, name) = tag.rsplit_once(':').unwrap_or(("", tag));
+    let key = name
         .chars()
         .filter(|c| c.is_ascii_alphanumeric())
         .collect::<String>()
-        .to_ascii_lowercase()
+        .to_ascii_lowercase();
+    // MicrosoftPhoto:Rating (Capture One なども書く) は 1〜99 の百分率なので xmp:Rating と分ける
+    if key == "rating" && prefix.eq_ignore_ascii_case("MicrosoftPhoto") {
+        return "ratingpercent".to_string();
+    }
+    key
+}
+
+// Windows の星の数との対応 (1, 25, 50, 75, 99)
+fn rating_from_percent(raw: &str) -> Option<i8> {
+    let percent = raw.trim().parse::<f64>().ok()?;
+    if !percent.is_finite() {
+        return None;
+    }
+    Some(match percent {
+        p if p <= 0.0 => 0,
+        p if p < 13.0 => 1,
+        p if p < 38.0 => 2,
+        p if p < 63.0 => 3,
+        p if p < 88.0 => 4,
+        _ => 5,
+    })
 }
 
 fn html_unescape_basic(input: &str) -> String {
@@ -349,7 +543,15 @@ fn html_unescape_basic(input: &str) -> String {
         .replace("&apos;", "'")
 }
 
-fn parse_date(input: &str) -> Option<DateTime<Local>> {
+fn parse_date(input: &str) -> Option<DateTime<FixedOffset>> {
+    let input = input.trim();
+    // Capture One などは UTC を "Z" で書く。撮影地の時刻は残っていないため、EXIF の日時と同じくPCのタイムゾーンで扱う
+    if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
+        if input.ends_with(['Z', 'z']) {
+            return Some(dt.with_timezone(&Local).fixed_offset());
+        }
+        return Some(dt);
+    }
     let candidates = [
         "%Y:%m:%d %H:%M:%S",
         "%Y-%m-%d %H:%M:%S",
@@ -357,14 +559,16 @@ fn parse_date(input: &str) -> Option<DateTime<Local>> {
         "%Y-%m-%dT%H:%M:%S%.f",
         "%Y-%m-%dT%H:%M:%S%:z",
         "%Y-%m-%dT%H:%M:%S%.f%:z",
+        "%Y-%m-%dT%H:%M%:z",
+        "%Y-%m-%dT%H:%M",
     ];
 
     for fmt in candidates {
         if let Ok(dt) = DateTime::parse_from_str(input, fmt) {
-            return Some(dt.with_timezone(&Local));
+            return Some(dt);
         }
         if let Ok(naive) = NaiveDateTime::parse_from_str(input, fmt) {
-            if let Some(local) = Local.from_local_datetime(&naive).single() {
+            if let Some(local) = local_date(&naive) {
                 return Some(local);
             }
         }
@@ -376,7 +580,7 @@ fn parse_date(input: &str) -> Option<DateTime<Local>> {
 #[cfg(test)]
 mod tests {
     use super::read_xmp_metadata;
-    use chrono::{Datelike, Timelike};
+    use chrono::{Datelike, Local, TimeZone, Timelike, Utc};
     use std::fs;
     use tempfile::tempdir;
 
@@ -403,6 +607,90 @@ mod tests {
         assert_eq!(meta.lens_model.as_deref(), Some("XF35mm F1.4 R"));
     }
 
+    #[test]
+    fn read_xmp_metadata_reads_language_alternatives() {
+        let temp = tempdir().expect("tempdir");
+        let xmp_path = temp.path().join("IMG_0001.xmp");
+        fs::write(
+            &xmp_path,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description>
+<dc:title><rdf:Alt><rdf:li xml:lang="x-default">Harbor &amp; Lights</rdf:li><rdf:li xml:lang="ja-JP">港の灯り</rdf:li></rdf:Alt></dc:title>
+<dc:description>Evening walk</dc:description>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("write xmp");
+
+        let meta = read_xmp_metadata(&xmp_path).expect("read xmp");
+        assert_eq!(
+            meta.title.get("x-default").map(String::as_str),
+            Some("Harbor & Lights")
+        );
+        assert_eq!(
+            meta.title.get("ja-JP").map(String::as_str),
+            Some("港の灯り")
+        );
+        assert_eq!(
+            meta.description.get("x-default").map(String::as_str),
+            Some("Evening walk")
+        );
+    }
+
+    #[test]
+    fn read_xmp_metadata_reads_capture_one_style_fields() {
+        let temp = tempdir().expect("tempdir");
+        let xmp_path = temp.path().join("IMG_0004.xmp");
+        fs::write(
+            &xmp_path,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description tiff:Make="Canon" tiff:Model="Canon EOS R5" MicrosoftPhoto:Rating="75" exif:Flash="16">
+<exif:DateTimeDigitized>2026-02-08T01:20:30Z</exif:DateTimeDigitized>
+<exifEX:LensModel>RF24-70mm F2.8 L IS USM</exifEX:LensModel>
+<exif:ExposureCompensation>-0.3</exif:ExposureCompensation>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("write xmp");
+
+        let meta = read_xmp_metadata(&xmp_path).expect("read xmp");
+        let date = meta.date.expect("date");
+        // UTC の時刻をPCのタイムゾーンの時刻に直す (Asia/Tokyo なら 10:20:30)
+        let expected = Utc
+            .with_ymd_and_hms(2026, 2, 8, 1, 20, 30)
+            .single()
+            .expect("date")
+            .with_timezone(&Local);
+        assert_eq!(date, expected);
+        assert_eq!(date.offset(), expected.offset());
+        assert_eq!(date.hour(), expected.hour());
+        assert_eq!(meta.camera_make.as_deref(), Some("Canon"));
+        assert_eq!(meta.camera_model.as_deref(), Some("Canon EOS R5"));
+        assert_eq!(meta.lens_model.as_deref(), Some("RF24-70mm F2.8 L IS USM"));
+        assert_eq!(meta.rating, Some(4));
+        assert_eq!(meta.flash_fired, Some(false));
+        assert_eq!(meta.exposure_compensation.as_deref(), Some("-0.3EV"));
+    }
+
+    #[test]
+    fn read_xmp_metadata_reads_label_and_keywords() {
+        let temp = tempdir().expect("tempdir");
+        let xmp_path = temp.path().join("IMG_0003.xmp");
+        fs::write(
+            &xmp_path,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating="4" xmp:Label="Red">
+<dc:subject><rdf:Bag><rdf:li>harbor</rdf:li><rdf:li>night</rdf:li><rdf:li>harbor</rdf:li></rdf:Bag></dc:subject>
+<dc:title><rdf:Seq><rdf:li>Harbor</rdf:li></rdf:Seq></dc:title>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("write xmp");
+
+        let meta = read_xmp_metadata(&xmp_path).expect("read xmp");
+        assert_eq!(meta.rating, Some(4));
+        assert_eq!(meta.label.as_deref(), Some("Red"));
+        assert_eq!(meta.keywords, vec!["harbor", "night"]);
+        assert_eq!(
+            meta.title.get("x-default").map(String::as_str),
+            Some("Harbor")
+        );
+    }
+
     #[test]
     fn read_xmp_metadata_supports_element_text_values() {
         let temp = tempdir().expect("tempdir");
@@ -474,4 +762,18 @@ mod tests {
         let meta = read_xmp_metadata(&xmp_path).expect("read xmp");
         assert_eq!(meta.film_sim.as_deref(), Some("REALA ACE"));
     }
+
+    #[test]
+    fn read_xmp_metadata_reads_rating() {
+        let temp = tempdir().expect("tempdir");
+        let xmp_path = temp.path().join("IMG_0007.xmp");
+        fs::write(
+            &xmp_path,
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="4" /></rdf:RDF></x:xmpmeta>"#,
+        )
+        .expect("write xmp");
+
+        let meta = read_xmp_metadata(&xmp_path).expect("read xmp");
+        assert_eq!(meta.rating, Some(4));
+    }
 }
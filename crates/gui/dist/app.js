@@ -10,6 +10,16 @@ const TOKENS = [
   { token: "{lens_maker}", label: "レンズメーカー名" },
   { token: "{lens_model}", label: "レンズ名" },
   { token: "{film_sim}", label: "フィルムシミュレーション名" },
+  { token: "{dynamic_range}", label: "ダイナミックレンジ" },
+  { token: "{grain_effect}", label: "グレイン" },
+  { token: "{color_chrome}", label: "カラークローム" },
+  { token: "{wb_shift}", label: "WBシフト" },
+  { token: "{focal_length_eq}", label: "35mm換算焦点距離" },
+  { token: "{exposure_comp}", label: "露出補正" },
+  { token: "{flash}", label: "フラッシュ発光" },
+  { token: "{label}", label: "カラーラベル" },
+  { token: "{keywords}", label: "キーワード" },
+  { token: "{title}", label: "タイトル" },
   { token: "{orig_name}", label: "元ファイル名" },
 ];
 
@@ -357,6 +367,7 @@ function buildLogEntriesFromPlan(plan, changedEmoji) {
     original: basename(row.original_path),
     target: basename(row.target_path),
     source: resolveLogSourceLabel(row),
+    raws: (row.raw_paths || []).map(basename),
   }));
 }
 
@@ -388,6 +399,13 @@ function appendSingleConvertLogEntry(entry) {
   targetLine.textContent = `→ ${entry.target}${sourceSuffix}`;
   li.appendChild(originalLine);
   li.appendChild(targetLine);
+  // 同名の RAW が複数形式ある場合だけ一覧を出す
+  if (entry.raws && entry.raws.length > 1) {
+    const rawLine = document.createElement("span");
+    rawLine.className = "convert-log-target-line";
+    rawLine.textContent = `RAW: ${entry.raws.join(", ")}`;
+    li.appendChild(rawLine);
+  }
   el.convertLog.appendChild(li);
 }
 
@@ -526,7 +544,10 @@ async function onApply() {
       },
     });
     renderConvertLogEntries(buildLogEntriesFromPlan(plan, "✅"));
-    setMessage(`変換完了: ${result.applied}件`, false);
+    const exiftoolNote = plan.exiftool_missing
+      ? "（exiftool が見つからないため、フィルムシミュレーション等を読めていない可能性があります）"
+      : "";
+    setMessage(`変換完了: ${result.applied}件${exiftoolNote}`, false);
     const appliedCount = Number(result.applied) || 0;
     const changedCount = Array.isArray(plan?.candidates)
       ? plan.candidates.filter((row) => row.changed).length
@@ -2,17 +2,23 @@
 
 use chrono::{DateTime, Local, Utc};
 use fphoto_renamer_core::{
-    apply_plan_with_options, generate_plan, load_config, render_preview_sample, save_config,
-    undo_last, validate_template, ApplyOptions, MetadataSource, PhotoMetadata, PlanOptions,
-    RenamePlan,
+    apply_plan_subset, apply_plan_with_options, cleanup_backups, generate_plan_chunked,
+    list_undo_sessions, load_config, pending_apply, preview_undo, recover_interrupted_apply,
+    redo_last, render_preview_sample, save_config, undo_last_files, undo_last_with_options,
+    undo_session, validate_template, ApplyMode, ApplyOptions, BackupFormat, CollisionPolicy,
+    DateZone, MakerNameOptions, MetadataSource, PhotoMetadata, PlanControl, PlanOptions,
+    ProgressSink, RecoverAction, RenamePlan, SourceFilter, UndoOptions, DEFAULT_MAX_FILENAME_LEN,
 };
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 #[cfg(target_os = "macos")]
 use tauri::menu::{AboutMetadata, Menu, PredefinedMenuItem, Submenu};
 use tauri::path::BaseDirectory;
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 
+const PLAN_BATCH_SIZE: usize = 200;
 #[cfg(target_os = "macos")]
 const DEFAULT_ABOUT_COPYRIGHT: &str = "Copyright (c) 2026 Kelly-jp. All rights reserved.";
 
@@ -25,11 +31,37 @@ struct PlanRequest {
     raw_parent_if_missing: bool,
     recursive: bool,
     include_hidden: bool,
+    #[serde(default)]
+    follow_symlinks: bool,
     template: String,
     #[serde(default = "default_true")]
     dedupe_same_maker: bool,
     exclusions: Vec<String>,
     max_filename_len: Option<usize>,
+    #[serde(default)]
+    min_rating: Option<i8>,
+    #[serde(default)]
+    min_file_size: u64,
+    #[serde(default)]
+    include_videos: bool,
+    #[serde(default)]
+    raw_primary: bool,
+    #[serde(default)]
+    primary_extensions: Option<Vec<String>>,
+    #[serde(default)]
+    raw_extensions: Option<Vec<String>>,
+    #[serde(default)]
+    raw_time_tolerance_ms: Option<u64>,
+    #[serde(default)]
+    match_raw_by_unique_id: bool,
+    #[serde(default)]
+    strict_metadata: bool,
+    #[serde(default)]
+    changed_only: bool,
+    #[serde(default)]
+    rename_companions: bool,
+    #[serde(default)]
+    only_source: Option<SourceFilter>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -83,40 +115,244 @@ struct ApplyRequest {
     plan: RenamePlan,
     #[serde(default)]
     backup_originals: bool,
+    #[serde(default = "default_true")]
+    reject_stale_plan: bool,
+    #[serde(default)]
+    copy_to: Option<String>,
+    #[serde(default)]
+    hardlink_to: Option<String>,
+    #[serde(default)]
+    continue_on_error: bool,
+    #[serde(default)]
+    collision_policy: CollisionPolicy,
+    #[serde(default)]
+    selected: Option<Vec<usize>>,
+    #[serde(default)]
+    backup_dir: Option<String>,
+    #[serde(default)]
+    backup_format: BackupFormat,
+    #[serde(default)]
+    clear_readonly: bool,
+    #[serde(default)]
+    set_file_times: bool,
 }
 
 struct AppState {
     launched_at_utc: DateTime<Utc>,
+    plan_cancel: AtomicBool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PlanProgressPayload {
+    phase: &'static str,
+    done: usize,
+    total: Option<usize>,
+}
+
+struct GuiPlanProgress<R: tauri::Runtime> {
+    app: tauri::AppHandle<R>,
+}
+
+impl<R: tauri::Runtime> ProgressSink for GuiPlanProgress<R> {
+    fn files_scanned(&self, scanned_files: usize) {
+        let _ = self.app.emit(
+            "plan-progress",
+            PlanProgressPayload {
+                phase: "scan",
+                done: scanned_files,
+                total: None,
+            },
+        );
+    }
+
+    fn metadata_read(&self, done: usize, total: usize) {
+        let _ = self.app.emit(
+            "plan-progress",
+            PlanProgressPayload {
+                phase: "metadata",
+                done,
+                total: Some(total),
+            },
+        );
+    }
 }
 
 #[tauri::command]
-fn generate_plan_cmd(request: PlanRequest) -> Result<RenamePlan, String> {
+fn cancel_plan_cmd(state: tauri::State<'_, AppState>) {
+    state.plan_cancel.store(true, Ordering::Relaxed);
+}
+
+#[tauri::command(async)]
+fn generate_plan_cmd<R: tauri::Runtime>(
+    app: tauri::AppHandle<R>,
+    state: tauri::State<'_, AppState>,
+    request: PlanRequest,
+) -> Result<RenamePlan, String> {
+    let config = load_config().map_err(|err| err.to_string())?;
+    let primary_extensions = request
+        .primary_extensions
+        .unwrap_or(config.primary_extensions);
+    let raw_extensions = request.raw_extensions.unwrap_or(config.raw_extensions);
+
     let options = PlanOptions {
         jpg_input: request.jpg_input.into(),
         raw_input: request.raw_input.map(Into::into),
         raw_from_jpg_parent_when_missing: request.raw_parent_if_missing,
         recursive: request.recursive,
         include_hidden: request.include_hidden,
+        follow_symlinks: request.follow_symlinks,
         template: request.template,
         dedupe_same_maker: request.dedupe_same_maker,
         exclusions: request.exclusions,
-        max_filename_len: request.max_filename_len.unwrap_or(240),
+        max_filename_len: request.max_filename_len.unwrap_or(DEFAULT_MAX_FILENAME_LEN),
+        min_rating: request.min_rating,
+        min_file_size: request.min_file_size,
+        include_videos: request.include_videos,
+        raw_primary: request.raw_primary,
+        primary_extensions,
+        raw_extensions,
+        strict_metadata: request.strict_metadata,
+        changed_only: request.changed_only,
+        rename_companions: request.rename_companions,
+        only_source: request.only_source,
+        metadata_cache: true,
+        maker_aliases: config.maker_aliases,
+        extra_exif_tags: config.extra_exif_tags,
+        dedupe_model_maker: config.dedupe_model_maker,
+        date_zone: DateZone::default(),
+        time_shifts: Vec::new(),
+        prefer_gps_time: false,
+        raw_time_tolerance: request.raw_time_tolerance_ms.map(Duration::from_millis),
+        match_raw_by_unique_id: request.match_raw_by_unique_id,
     };
 
-    generate_plan(&options).map_err(|err| err.to_string())
+    state.plan_cancel.store(false, Ordering::Relaxed);
+    let progress = GuiPlanProgress { app: app.clone() };
+    let mut candidates = Vec::new();
+    let mut plan = generate_plan_chunked(
+        &options,
+        PlanControl {
+            progress: Some(&progress),
+            cancel: Some(&state.plan_cancel),
+        },
+        PLAN_BATCH_SIZE,
+        |batch| {
+            let _ = app.emit("plan-candidates", &batch);
+            candidates.extend(batch);
+            Ok(())
+        },
+    )
+    .map_err(|err| err.to_string())?;
+    plan.candidates = candidates;
+    Ok(plan)
+}
+
+fn non_empty_path(value: &Option<String>) -> Option<PathBuf> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+fn apply_mode(request: &ApplyRequest) -> ApplyMode {
+    if let Some(output_dir) = non_empty_path(&request.copy_to) {
+        return ApplyMode::CopyTo(output_dir);
+    }
+    if let Some(output_dir) = non_empty_path(&request.hardlink_to) {
+        return ApplyMode::HardlinkTo(output_dir);
+    }
+    ApplyMode::Rename
 }
 
 #[tauri::command]
 fn apply_plan_cmd(request: ApplyRequest) -> Result<fphoto_renamer_core::ApplyResult, String> {
     let options = ApplyOptions {
-        backup_originals: request.backup_originals,
+        backup_originals: request.backup_originals || non_empty_path(&request.backup_dir).is_some(),
+        reject_stale_plan: request.reject_stale_plan,
+        mode: apply_mode(&request),
+        continue_on_error: request.continue_on_error,
+        collision_policy: request.collision_policy,
+        backup_dir: non_empty_path(&request.backup_dir),
+        backup_format: request.backup_format,
+        backup_retention: load_config()
+            .map(|config| config.backup_retention)
+            .unwrap_or_default(),
+        clear_readonly: request.clear_readonly,
+        set_file_times: request.set_file_times,
+    };
+    match &request.selected {
+        Some(indices) => apply_plan_subset(&request.plan, indices, &options),
+        None => apply_plan_with_options(&request.plan, &options),
+    }
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn cleanup_backups_cmd() -> Result<fphoto_renamer_core::BackupCleanupResult, String> {
+    let retention = load_config()
+        .map_err(|err| err.to_string())?
+        .backup_retention;
+    cleanup_backups(&retention).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn pending_apply_cmd() -> Result<Option<fphoto_renamer_core::PendingApply>, String> {
+    pending_apply().map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn recover_apply_cmd(rollback: bool) -> Result<fphoto_renamer_core::RecoverResult, String> {
+    let action = if rollback {
+        RecoverAction::Rollback
+    } else {
+        RecoverAction::Complete
     };
-    apply_plan_with_options(&request.plan, &options).map_err(|err| err.to_string())
+    recover_interrupted_apply(action).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn undo_last_cmd(allow_modified: Option<bool>) -> Result<fphoto_renamer_core::UndoResult, String> {
+    undo_last_with_options(&UndoOptions {
+        allow_modified: allow_modified.unwrap_or(false),
+    })
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn undo_files_cmd(
+    files: Vec<String>,
+    allow_modified: Option<bool>,
+) -> Result<fphoto_renamer_core::UndoResult, String> {
+    let files: Vec<PathBuf> = files.into_iter().map(PathBuf::from).collect();
+    undo_last_files(
+        &files,
+        &UndoOptions {
+            allow_modified: allow_modified.unwrap_or(false),
+        },
+    )
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn preview_undo_cmd() -> Result<fphoto_renamer_core::UndoPreview, String> {
+    preview_undo().map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn redo_last_cmd() -> Result<fphoto_renamer_core::RedoResult, String> {
+    redo_last().map_err(|err| err.to_string())
 }
 
 #[tauri::command]
-fn undo_last_cmd() -> Result<fphoto_renamer_core::UndoResult, String> {
-    undo_last().map_err(|err| err.to_string())
+fn list_undo_sessions_cmd() -> Result<Vec<fphoto_renamer_core::UndoSession>, String> {
+    list_undo_sessions().map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn undo_session_cmd(id: String) -> Result<fphoto_renamer_core::UndoResult, String> {
+    undo_session(&id).map_err(|err| err.to_string())
 }
 
 #[tauri::command]
@@ -126,13 +362,18 @@ fn validate_template_cmd(template: String) -> Result<(), String> {
 
 #[tauri::command]
 fn render_sample_cmd(request: SampleRequest) -> Result<String, String> {
+    let config = load_config().map_err(|err| err.to_string())?;
     render_preview_sample(
         &request.template,
-        request.dedupe_same_maker,
+        MakerNameOptions {
+            dedupe_same_maker: request.dedupe_same_maker,
+            dedupe_model_maker: config.dedupe_model_maker,
+            aliases: &config.maker_aliases,
+        },
         &request.exclusions,
         &request.metadata,
         &request.extension_with_dot,
-        request.max_filename_len.unwrap_or(240),
+        request.max_filename_len.unwrap_or(DEFAULT_MAX_FILENAME_LEN),
     )
     .map_err(|err| err.to_string())
 }
@@ -143,13 +384,18 @@ fn render_fixed_sample_cmd(
     request: FixedSampleRequest,
 ) -> Result<String, String> {
     let metadata = fixed_sample_metadata(state.launched_at_utc.with_timezone(&Local));
+    let config = load_config().map_err(|err| err.to_string())?;
     render_preview_sample(
         &request.template,
-        request.dedupe_same_maker,
+        MakerNameOptions {
+            dedupe_same_maker: request.dedupe_same_maker,
+            dedupe_model_maker: config.dedupe_model_maker,
+            aliases: &config.maker_aliases,
+        },
         &request.exclusions,
         &metadata,
         ".JPG",
-        request.max_filename_len.unwrap_or(240),
+        request.max_filename_len.unwrap_or(DEFAULT_MAX_FILENAME_LEN),
     )
     .map_err(|err| err.to_string())
 }
@@ -222,6 +468,7 @@ fn main() {
     tauri::Builder::default()
         .manage(AppState {
             launched_at_utc: Utc::now(),
+            plan_cancel: AtomicBool::new(false),
         })
         .setup(|app| {
             configure_exiftool_path(app.handle());
@@ -231,8 +478,17 @@ fn main() {
         })
         .invoke_handler(tauri::generate_handler![
             generate_plan_cmd,
+            cancel_plan_cmd,
             apply_plan_cmd,
             undo_last_cmd,
+            undo_files_cmd,
+            preview_undo_cmd,
+            redo_last_cmd,
+            list_undo_sessions_cmd,
+            undo_session_cmd,
+            pending_apply_cmd,
+            cleanup_backups_cmd,
+            recover_apply_cmd,
             validate_template_cmd,
             render_sample_cmd,
             render_fixed_sample_cmd,
@@ -392,12 +648,26 @@ fn default_true() -> bool {
 fn fixed_sample_metadata(launched_at: DateTime<Local>) -> PhotoMetadata {
     PhotoMetadata {
         source: MetadataSource::JpgExif,
-        date: launched_at,
+        date: launched_at.fixed_offset(),
         camera_make: Some("FUJIFILM".to_string()),
         camera_model: Some("X-H2".to_string()),
         lens_make: Some("FUJIFILM".to_string()),
         lens_model: Some("XF35mm F1.4 R".to_string()),
         film_sim: Some("PROVIA".to_string()),
+        rating: None,
+        dynamic_range: None,
+        grain_effect: None,
+        color_chrome_effect: None,
+        wb_shift: None,
+        focal_length_eq: Some("53mm".to_string()),
+        exposure_compensation: None,
+        flash_fired: None,
+        image_unique_id: None,
+        title: Default::default(),
+        description: Default::default(),
+        label: None,
+        keywords: Vec::new(),
+        extra_tags: Default::default(),
         original_name: "DSC00001".to_string(),
         jpg_path: PathBuf::from("DSC00001.JPG"),
     }